@@ -10,6 +10,8 @@ const URL: &str = "https://repo1.maven.org/maven2/org/openapitools/openapi-gener
 
 const FILE_NAME: &str = "openapi-generator-cli-7.5.0.jar";
 
+const SPEC: &str = "../../api.yaml";
+
 async fn download_cli() -> PathBuf {
 	let jar = URL;
 	let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
@@ -29,10 +31,7 @@ async fn download_cli() -> PathBuf {
 		"Should be able to download generator"
 	);
 
-	let mut stream = StreamReader::new(
-		res.bytes_stream()
-			.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e)),
-	);
+	let mut stream = StreamReader::new(res.bytes_stream().map_err(std::io::Error::other));
 	let mut file = tokio::fs::File::create(&out_file).await.unwrap();
 	tokio::io::copy(&mut stream, &mut file).await.unwrap();
 	println!("Downloaded to {:?}", file);
@@ -41,17 +40,129 @@ async fn download_cli() -> PathBuf {
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
-	println!("cargo::rerun-if-changed=../../api.yaml");
+	println!("cargo::rerun-if-changed={SPEC}");
 	let file = download_cli().await;
 	let out_lib = Path::new(&env::var("OUT_DIR").unwrap()).join("generated");
 	let status = std::process::Command::new("java")
 		.arg("-jar")
 		.arg(file)
-		.args(["generate", "-i", "../../api.yaml", "-g", "rust", "-o"])
+		.args(["generate", "-i", SPEC, "-g", "rust", "-o"])
 		.arg(out_lib)
 		.status()
 		.unwrap();
 	if !status.success() {
 		panic!("Failed to generate")
 	}
+	#[cfg(feature = "server")]
+	server::generate(
+		Path::new(SPEC),
+		&Path::new(&env::var("OUT_DIR").unwrap()).join("server.rs"),
+	);
+}
+
+///Operations trait of the server, generated from the spec paths
+///
+///The handlers are named like the client functions, the path and method. The trait only names the
+///operations, each handler keeps its own axum extractors and responses.
+#[cfg(feature = "server")]
+mod server {
+	use std::fmt::Write;
+	use std::path::Path;
+
+	use serde_yaml::Value;
+
+	const METHODS: [(&str, &str); 6] = [
+		("get", "Get"),
+		("head", "Head"),
+		("post", "Post"),
+		("put", "Put"),
+		("patch", "Patch"),
+		("delete", "Delete"),
+	];
+
+	struct Operation {
+		///Path in the axum syntax, like `/job/:job_id`
+		path: String,
+		method: &'static str,
+		name: String,
+		doc: String,
+	}
+
+	fn operations(spec: &Value) -> Vec<Operation> {
+		let paths = spec["paths"].as_mapping().expect("Spec should have paths");
+		let mut operations = Vec::new();
+		for (path, item) in paths {
+			let path = path.as_str().expect("Paths should be strings");
+			for (key, method) in METHODS {
+				let Some(operation) = item.get(key) else {
+					continue;
+				};
+				let description = operation
+					.get("description")
+					.or(item.get("description"))
+					.and_then(Value::as_str);
+				let mut doc = format!("`{} {path}`", key.to_uppercase());
+				if let Some(description) = description {
+					write!(doc, ": {}", description.lines().next().unwrap_or_default()).unwrap();
+				}
+				let words = path.split(['/', '{', '}']).filter(|word| !word.is_empty());
+				let name = words.chain([key]).collect::<Vec<_>>().join("_");
+				operations.push(Operation {
+					path: path.replace('{', ":").replace('}', ""),
+					method,
+					name,
+					doc,
+				});
+			}
+		}
+		operations
+	}
+
+	pub fn generate(spec: &Path, out: &Path) {
+		let spec = std::fs::read_to_string(spec).expect("Should read the spec");
+		let spec: Value = serde_yaml::from_str(&spec).expect("Spec should be valid yaml");
+		let operations = operations(&spec);
+		let mut code = String::from(
+			"///Http method of an operation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Method {
+	Get,
+	Head,
+	Post,
+	Put,
+	Patch,
+	Delete,
+}
+
+///Handlers of every operation of the spec, named like the client functions
+pub trait Operations {
+	///Handler of an operation, routed to its path and method
+	type Handler;
+",
+		);
+		for operation in &operations {
+			writeln!(code, "\t///{}", operation.doc).unwrap();
+			writeln!(code, "\tfn {}(&self) -> Self::Handler;", operation.name).unwrap();
+		}
+		code.push_str(
+			"}
+
+///Handler of every operation, with its path in the `/job/:job_id` syntax and its method
+pub fn operations<O: Operations + ?Sized>(
+	operations: &O,
+) -> Vec<(&'static str, Method, O::Handler)> {
+	vec![
+",
+		);
+		for operation in &operations {
+			writeln!(
+				code,
+				"\t\t({:?}, Method::{}, operations.{}()),",
+				operation.path, operation.method, operation.name
+			)
+			.unwrap();
+		}
+		code.push_str("\t]\n}\n");
+		std::fs::write(out, code).expect("Should write the server operations");
+	}
 }
@@ -1,13 +1,25 @@
 #[macro_use]
 extern crate serde_derive;
 
+///Revision of the worker api, increased on changes that break workers or servers of older revisions
+pub const API_REVISION: i32 = 2;
+///Oldest worker api revision still supported by the server
+pub const MIN_WORKER_API_REVISION: i32 = 1;
+
 #[allow(unused_imports)]
 pub mod models {
 	include!(concat!(env!("OUT_DIR"), "/generated/src/models/mod.rs"));
 }
 
+///Client functions, the `wasm` feature builds them for the browser, sending the requests with fetch
 #[cfg(feature = "client")]
-#[allow(unused_imports)]
+#[allow(unused_imports, clippy::too_many_arguments)]
 pub mod apis {
 	include!(concat!(env!("OUT_DIR"), "/generated/src/apis/mod.rs"));
 }
+
+///Operations of the spec, the server implements a handler for each of them
+#[cfg(feature = "server")]
+pub mod server {
+	include!(concat!(env!("OUT_DIR"), "/server.rs"));
+}
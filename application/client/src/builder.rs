@@ -0,0 +1,396 @@
+//! Worker construction, for binaries embedding the worker instead of running the CLI
+//!
+//! The builder checks the ffmpeg binaries and the server compatibility, then logs in. The returned
+//! [Worker] runs the tasks allocated with its api configuration:
+//! ```no_run
+//! # async fn embed() -> Result<(), client::builder::BuildError> {
+//! use client::transport::Transport;
+//! use client::TaskRunner;
+//!
+//! let worker = client::ClientBuilder::new("https://encoder.example.com/api")
+//!     .password("worker password")
+//!     .ffmpeg("/usr/local/bin/ffmpeg")
+//!     .concurrency(2)
+//!     .build()
+//!     .await?;
+//! if let Ok(Some(task)) = worker.allocate_task(&task::Resources::default()).await {
+//!     worker.run(task).await;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use api::apis::configuration::{ApiKey, Configuration};
+
+use crate::cache::SourceCache;
+use crate::encoder::Encoder;
+use crate::gpu::Devices;
+use crate::limits::Limits;
+use crate::work::{jitter, RECONNECT_DELAY};
+use crate::{binary_version, Worker};
+
+#[derive(Debug)]
+pub enum BuildError {
+	///The server url can't be parsed
+	InvalidUrl(String),
+	///No password was set
+	MissingCredentials,
+	///The ffmpeg or ffprobe binary does not run
+	Binary(PathBuf, io::Error),
+	///The http client could not be created with the options
+	Http(reqwest::Error),
+	///The server could not be reached, or refused the credentials
+	Login(String),
+	///The server does not support this worker api revision
+	Incompatible(String),
+}
+
+impl Display for BuildError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match self {
+			BuildError::InvalidUrl(url) => write!(f, "Invalid server url: {url}"),
+			BuildError::MissingCredentials => f.write_str("A password is required"),
+			BuildError::Binary(path, err) => write!(f, "Could not run {}: {err}", path.display()),
+			BuildError::Http(err) => write!(f, "Could not create the http client: {err}"),
+			BuildError::Login(err) | BuildError::Incompatible(err) => f.write_str(err),
+		}
+	}
+}
+
+impl std::error::Error for BuildError {}
+
+///Configures a [Worker], logged in to the server
+pub struct ClientBuilder {
+	server: String,
+	password: Option<String>,
+	name: Option<String>,
+	ffmpeg: PathBuf,
+	ffprobe: PathBuf,
+	concurrency: u32,
+	cpus: Option<f64>,
+	threads: Option<u32>,
+	nice: Option<i32>,
+	devices: Vec<String>,
+	download_inputs: bool,
+	pipe_inputs: bool,
+	source_cache: Option<(PathBuf, u64)>,
+	max_download_rate: Option<u64>,
+	max_upload_rate: Option<u64>,
+	verify_output: bool,
+	task_timeout: Option<Duration>,
+	request_timeout: Option<Duration>,
+	connect_timeout: Option<Duration>,
+	retry_login: bool,
+	http: Option<reqwest::ClientBuilder>,
+	encoders: BTreeMap<String, Box<dyn Encoder>>,
+}
+
+impl ClientBuilder {
+	///Worker for the server api base url, like `http://localhost:8888/api`
+	pub fn new(server: impl Into<String>) -> Self {
+		Self {
+			server: server.into(),
+			password: None,
+			name: None,
+			ffmpeg: "ffmpeg".into(),
+			ffprobe: "ffprobe".into(),
+			concurrency: 1,
+			cpus: None,
+			threads: None,
+			nice: None,
+			devices: Vec::new(),
+			download_inputs: false,
+			pipe_inputs: false,
+			source_cache: None,
+			max_download_rate: None,
+			max_upload_rate: None,
+			verify_output: false,
+			task_timeout: None,
+			request_timeout: None,
+			connect_timeout: None,
+			retry_login: false,
+			http: None,
+			encoders: BTreeMap::new(),
+		}
+	}
+
+	///Password to register the worker with the server
+	pub fn password(mut self, password: impl Into<String>) -> Self {
+		self.password = Some(password.into());
+		self
+	}
+
+	///Name identifying the worker on the server, kept when it logs in again
+	pub fn name(mut self, name: impl Into<String>) -> Self {
+		self.name = Some(name.into());
+		self
+	}
+
+	///ffmpeg binary used to encode, `ffmpeg` on the path by default
+	pub fn ffmpeg(mut self, ffmpeg: impl Into<PathBuf>) -> Self {
+		self.ffmpeg = ffmpeg.into();
+		self
+	}
+
+	///ffprobe binary used to analyse the inputs, `ffprobe` on the path by default
+	pub fn ffprobe(mut self, ffprobe: impl Into<PathBuf>) -> Self {
+		self.ffprobe = ffprobe.into();
+		self
+	}
+
+	///Tasks the caller runs at the same time, the CPUs are split between them
+	pub fn concurrency(mut self, tasks: u32) -> Self {
+		self.concurrency = tasks.max(1);
+		self
+	}
+
+	///CPUs the worker may use, such as its cgroup quota
+	pub fn cpus(mut self, cpus: f64) -> Self {
+		self.cpus = Some(cpus);
+		self
+	}
+
+	///Threads used by each ffmpeg process, overrides the ones derived from the CPUs
+	pub fn threads(mut self, threads: u32) -> Self {
+		self.threads = Some(threads);
+		self
+	}
+
+	///Niceness of the ffmpeg processes, from -20 to 19
+	pub fn nice(mut self, nice: i32) -> Self {
+		self.nice = Some(nice);
+		self
+	}
+
+	///Hardware acceleration devices to decode the inputs on, each task uses one at a time
+	pub fn devices(mut self, devices: Vec<String>) -> Self {
+		self.devices = devices;
+		self
+	}
+
+	///Download the inputs to temporary files before transcoding, verifying their checksum
+	pub fn download_inputs(mut self, download: bool) -> Self {
+		self.download_inputs = download;
+		self
+	}
+
+	///Stream single inputs to ffmpeg stdin, instead of passing the url and token in its arguments
+	pub fn pipe_inputs(mut self, pipe: bool) -> Self {
+		self.pipe_inputs = pipe;
+		self
+	}
+
+	///Keep the downloaded job inputs in the directory for the next tasks of the same job, up to
+	///the capacity in bytes
+	pub fn source_cache(mut self, dir: impl Into<PathBuf>, capacity: u64) -> Self {
+		self.source_cache = Some((dir.into(), capacity));
+		self
+	}
+
+	///Limit for the inputs downloaded by the worker, in bytes per second
+	pub fn max_download_rate(mut self, rate: u64) -> Self {
+		self.max_download_rate = Some(rate);
+		self
+	}
+
+	///Limit for the output uploads, in bytes per second
+	pub fn max_upload_rate(mut self, rate: u64) -> Self {
+		self.max_upload_rate = Some(rate);
+		self
+	}
+
+	///Remux each output locally before uploading it, failing the task if it is corrupted
+	pub fn verify_output(mut self, verify: bool) -> Self {
+		self.verify_output = verify;
+		self
+	}
+
+	///Time a task may run before ffmpeg is killed, the task failed and its allocation released
+	pub fn task_timeout(mut self, timeout: Duration) -> Self {
+		self.task_timeout = Some(timeout);
+		self
+	}
+
+	///Time each server request may take, including the input downloads and output uploads
+	pub fn request_timeout(mut self, timeout: Duration) -> Self {
+		self.request_timeout = Some(timeout);
+		self
+	}
+
+	///Time to wait for the connection to the server
+	pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+		self.connect_timeout = Some(timeout);
+		self
+	}
+
+	///Retry the login until it succeeds, instead of failing the build, incompatible servers still fail
+	pub fn retry_login(mut self, retry: bool) -> Self {
+		self.retry_login = retry;
+		self
+	}
+
+	///Http client options, like the TLS certificates, the timeouts are added to it
+	pub fn http(mut self, http: reqwest::ClientBuilder) -> Self {
+		self.http = Some(http);
+		self
+	}
+
+	///Encode the video codec with the backend, instead of the ffmpeg encoder
+	pub fn encoder(mut self, codec: impl Into<String>, encoder: impl Encoder + 'static) -> Self {
+		self.encoders.insert(codec.into(), Box::new(encoder));
+		self
+	}
+
+	fn http_client(&mut self) -> Result<reqwest::Client, BuildError> {
+		let mut http = self.http.take().unwrap_or_default();
+		if let Some(timeout) = self.request_timeout {
+			http = http.timeout(timeout);
+		}
+		if let Some(timeout) = self.connect_timeout {
+			http = http.connect_timeout(timeout);
+		}
+		http.build().map_err(BuildError::Http)
+	}
+
+	///Check the binaries and the server, then login
+	pub async fn build(mut self) -> Result<Worker, BuildError> {
+		if self.server.parse::<reqwest::Url>().is_err() {
+			return Err(BuildError::InvalidUrl(self.server));
+		}
+		let password = self.password.take().ok_or(BuildError::MissingCredentials)?;
+		for binary in [&self.ffmpeg, &self.ffprobe] {
+			let version = binary_version(binary)
+				.await
+				.map_err(|err| BuildError::Binary(binary.clone(), err))?;
+			println!("Using {}: {version}", binary.display());
+		}
+		let mut config = Configuration {
+			client: self.http_client()?,
+			base_path: self.server,
+			..Default::default()
+		};
+		let token = loop {
+			match login(&config, &password, self.name.as_deref()).await {
+				Ok(token) => break token,
+				Err(BuildError::Login(err)) if self.retry_login => {
+					eprintln!("{err}, retrying");
+					tokio::time::sleep(jitter(RECONNECT_DELAY)).await;
+				}
+				Err(err) => return Err(err),
+			}
+		};
+		config.api_key = Some(ApiKey {
+			key: token,
+			prefix: None,
+		});
+		Ok(Worker {
+			config,
+			ffmpeg: self.ffmpeg,
+			ffprobe: self.ffprobe,
+			download_inputs: self.download_inputs,
+			pipe_inputs: self.pipe_inputs,
+			max_download_rate: self.max_download_rate,
+			max_upload_rate: self.max_upload_rate,
+			task_timeout: self.task_timeout,
+			verify_output: self.verify_output,
+			limits: Limits::per_task(self.threads, self.nice, self.cpus, self.concurrency),
+			devices: (!self.devices.is_empty()).then(|| Devices::new(self.devices)),
+			source_cache: self
+				.source_cache
+				.map(|(dir, capacity)| SourceCache::new(dir, capacity)),
+			validations: Default::default(),
+			metrics: Default::default(),
+			encoders: self.encoders,
+		})
+	}
+}
+
+///Refuse servers that do not support this worker api revision
+fn check_compatibility(server: &api::models::Compatibility) -> Result<(), String> {
+	if api::API_REVISION < server.min_worker_api_revision {
+		return Err(format!(
+			"Worker api revision {} is no longer supported by server {}, update the worker to revision {} or newer",
+			api::API_REVISION, server.server_version, server.min_worker_api_revision
+		));
+	}
+	if api::API_REVISION > server.api_revision {
+		return Err(format!(
+			"Server {} implements api revision {}, older than the worker revision {}, update the server",
+			server.server_version,
+			server.api_revision,
+			api::API_REVISION
+		));
+	}
+	Ok(())
+}
+
+///Check the server version and compatibility, then login with the worker name, returning the token
+pub async fn login(
+	config: &Configuration,
+	password: &str,
+	name: Option<&str>,
+) -> Result<String, BuildError> {
+	let server_version = api::apis::default_api::version_get(config)
+		.await
+		.map_err(|err| BuildError::Login(format!("Could not get server version: {err}")))?;
+	println!("Server: {}, version {:?}", config.base_path, server_version);
+	let compatibility = api::apis::default_api::version_compatibility_get(config)
+		.await
+		.map_err(|err| BuildError::Login(format!("Could not get server compatibility: {err}")))?;
+	check_compatibility(&compatibility).map_err(BuildError::Incompatible)?;
+	api::apis::default_api::login_get(config, password, name)
+		.await
+		.map_err(|err| BuildError::Login(format!("Could not login: {err}")))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn compatibility(
+		api_revision: i32,
+		min_worker_api_revision: i32,
+	) -> api::models::Compatibility {
+		api::models::Compatibility {
+			server_version: "1.0.0".to_string(),
+			api_revision,
+			min_worker_api_revision,
+		}
+	}
+
+	#[test]
+	fn compatible_with_server_of_same_revision() {
+		let server = compatibility(api::API_REVISION, api::MIN_WORKER_API_REVISION);
+		assert_eq!(check_compatibility(&server), Ok(()))
+	}
+
+	#[test]
+	fn incompatible_with_older_server() {
+		let server = compatibility(api::API_REVISION - 1, 0);
+		assert!(check_compatibility(&server).is_err())
+	}
+
+	#[tokio::test]
+	async fn build_with_invalid_url_fails() {
+		let err = ClientBuilder::new("not a url")
+			.password("secret")
+			.build()
+			.await
+			.err();
+		assert!(matches!(err, Some(BuildError::InvalidUrl(_))))
+	}
+
+	#[tokio::test]
+	async fn build_without_password_fails() {
+		let err = ClientBuilder::new("http://localhost:8888/api")
+			.build()
+			.await
+			.err();
+		assert!(matches!(err, Some(BuildError::MissingCredentials)))
+	}
+}
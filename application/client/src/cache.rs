@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::OnceCell;
+use uuid::Uuid;
+
+///A downloaded source, the file is removed when the last reference is dropped
+pub struct Cached {
+	path: PathBuf,
+	///Size of the file, once downloaded
+	size: OnceCell<u64>,
+}
+
+impl Cached {
+	pub fn path(&self) -> &Path {
+		&self.path
+	}
+}
+
+impl Drop for Cached {
+	fn drop(&mut self) {
+		let _ = std::fs::remove_file(&self.path);
+	}
+}
+
+///Job id and input index on the job
+type SourceKey = (Uuid, u32);
+
+///Job inputs downloaded by the worker, shared by the tasks of the same job
+///
+///The least recently used sources are evicted when the size exceeds the capacity,
+///tasks still using them keep the file until they finish
+pub struct SourceCache {
+	dir: PathBuf,
+	///Maximum size of the cached files, in bytes
+	capacity: u64,
+	///Ordered from the least to the most recently used
+	entries: Mutex<Vec<(SourceKey, Arc<Cached>)>>,
+}
+
+impl SourceCache {
+	pub fn new(dir: PathBuf, capacity: u64) -> Self {
+		Self {
+			dir,
+			capacity,
+			entries: Default::default(),
+		}
+	}
+
+	///Get the job input, identified by its index on the job, downloading it if not cached
+	///
+	///Concurrent requests for the same input wait for a single download
+	pub async fn get<F, Fut>(&self, job: Uuid, index: u32, download: F) -> io::Result<Arc<Cached>>
+	where
+		F: FnOnce(PathBuf) -> Fut,
+		Fut: Future<Output = io::Result<()>>,
+	{
+		let entry = self.touch(job, index);
+		entry
+			.size
+			.get_or_try_init(|| async {
+				tokio::fs::create_dir_all(&self.dir).await?;
+				download(entry.path.clone()).await?;
+				Ok::<_, io::Error>(tokio::fs::metadata(&entry.path).await?.len())
+			})
+			.await?;
+		self.evict();
+		Ok(entry)
+	}
+
+	///Find or create the entry, marking it as the most recently used
+	fn touch(&self, job: Uuid, index: u32) -> Arc<Cached> {
+		let mut entries = self.entries.lock().unwrap();
+		let entry = match entries.iter().position(|(key, _)| *key == (job, index)) {
+			Some(position) => entries.remove(position).1,
+			None => Arc::new(Cached {
+				path: self.dir.join(format!("{job}-{index}")),
+				size: OnceCell::new(),
+			}),
+		};
+		entries.push(((job, index), entry.clone()));
+		entry
+	}
+
+	///Drop the least recently used entries until the downloaded size fits the capacity
+	fn evict(&self) {
+		let mut entries = self.entries.lock().unwrap();
+		let mut total: u64 = entries
+			.iter()
+			.filter_map(|(_, entry)| entry.size.get())
+			.sum();
+		while total > self.capacity && entries.len() > 1 {
+			let (_, evicted) = entries.remove(0);
+			total -= evicted.size.get().copied().unwrap_or_default();
+		}
+	}
+}
+
+///Arguments of the test encode, and if it runs two passes
+type ValidationKey = (Vec<String>, bool);
+
+///Outcome of the job options validations, by the arguments of the test encode and the pass count
+///
+///Only the outcomes that depend on the options are kept, not failures like ffmpeg not starting
+#[derive(Default)]
+pub struct Validations(Mutex<HashMap<ValidationKey, Result<(), String>>>);
+
+impl Validations {
+	pub fn get(&self, args: &[String], two_pass: bool) -> Option<Result<(), String>> {
+		let validations = self.0.lock().unwrap();
+		validations.get(&(args.to_vec(), two_pass)).cloned()
+	}
+
+	pub fn insert(&self, args: Vec<String>, two_pass: bool, outcome: Result<(), String>) {
+		self.0.lock().unwrap().insert((args, two_pass), outcome);
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn cache(capacity: u64) -> SourceCache {
+		let dir = std::env::temp_dir().join(format!("segmented-cache-{}", Uuid::new_v4()));
+		SourceCache::new(dir, capacity)
+	}
+
+	async fn get(cache: &SourceCache, job: Uuid, index: u32) -> Arc<Cached> {
+		cache
+			.get(job, index, |path| tokio::fs::write(path, [0; 6]))
+			.await
+			.unwrap()
+	}
+
+	#[tokio::test]
+	async fn cached_source_is_not_downloaded_again() {
+		let cache = cache(100);
+		let job = Uuid::new_v4();
+		let first = get(&cache, job, 0).await;
+		let again = cache
+			.get(job, 0, |_| async {
+				Err(io::Error::other("Downloaded again"))
+			})
+			.await;
+		let _ = std::fs::remove_dir_all(&cache.dir);
+		assert_eq!(again.unwrap().path(), first.path())
+	}
+
+	#[tokio::test]
+	async fn evicts_the_least_recently_used() {
+		let cache = cache(12);
+		let job = Uuid::new_v4();
+		let first = get(&cache, job, 0).await.path().to_owned();
+		let second = get(&cache, job, 1).await.path().to_owned();
+		get(&cache, job, 0).await;
+		let third = get(&cache, job, 2).await.path().to_owned();
+		let exists = [&first, &second, &third].map(|path| path.exists());
+		let _ = std::fs::remove_dir_all(&cache.dir);
+		assert_eq!(exists, [true, false, true])
+	}
+
+	#[tokio::test]
+	async fn evicted_source_kept_while_used() {
+		let cache = cache(6);
+		let job = Uuid::new_v4();
+		let used = get(&cache, job, 0).await;
+		get(&cache, job, 1).await;
+		let exists = used.path().exists();
+		drop(used);
+		let _ = std::fs::remove_dir_all(&cache.dir);
+		assert!(exists)
+	}
+}
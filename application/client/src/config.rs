@@ -0,0 +1,126 @@
+//! Worker configuration
+//!
+//! The configuration file uses toml, every field is optional and the command line arguments take
+//! precedence:
+//! ```toml
+//! server = "https://encoder.example.com/api"
+//! password = "worker password"
+//! name = "encoder-1"
+//! jobs = 2
+//! ffmpeg = "/usr/local/bin/ffmpeg"
+//! ffprobe = "/usr/local/bin/ffprobe"
+//! cache_dir = "/var/cache/segmented-worker"
+//!
+//! [encoders.libx265]
+//! command = ["x265", "--y4m", "--crf", "24", "--input", "-", "--output", "-"]
+//! format = "hevc"
+//! framerate = "25"
+//! ```
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::encoder::External;
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+	///Server api base url
+	pub server: Option<String>,
+	///Password to register the worker with the server
+	pub password: Option<String>,
+	///Name identifying the worker on the server
+	pub name: Option<String>,
+	///Number of tasks to run concurrently, at least 1
+	pub jobs: Option<u32>,
+	pub ffmpeg: Option<PathBuf>,
+	pub ffprobe: Option<PathBuf>,
+	///Directory for the cached job inputs
+	pub cache_dir: Option<PathBuf>,
+	///Standalone encoders replacing ffmpeg, by video codec
+	pub encoders: BTreeMap<String, External>,
+}
+
+impl Config {
+	pub fn from_file(path: &Path) -> io::Result<Self> {
+		let content = std::fs::read_to_string(path)?;
+		Self::parse(&content)
+	}
+
+	fn parse(content: &str) -> io::Result<Self> {
+		let config: Self = toml::from_str(content)
+			.map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+		if config.jobs == Some(0) {
+			let err = "jobs must be at least 1";
+			return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+		}
+		Ok(config)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn parse_empty_config_is_default() {
+		let config = Config::parse("").unwrap();
+		assert_eq!(config, Config::default())
+	}
+
+	#[test]
+	fn parse_worker_fields() {
+		let config = Config::parse(
+			r#"
+			server = "https://example.com/api"
+			password = "secret"
+			name = "encoder-1"
+			jobs = 4
+			ffmpeg = "/opt/ffmpeg"
+			cache_dir = "/var/cache/worker"
+			"#,
+		)
+		.unwrap();
+		assert_eq!(config.server.as_deref(), Some("https://example.com/api"));
+		assert_eq!(config.password.as_deref(), Some("secret"));
+		assert_eq!(config.name.as_deref(), Some("encoder-1"));
+		assert_eq!(config.jobs, Some(4));
+		assert_eq!(config.ffmpeg, Some(PathBuf::from("/opt/ffmpeg")));
+		assert_eq!(config.ffprobe, None);
+		assert_eq!(config.cache_dir, Some(PathBuf::from("/var/cache/worker")));
+	}
+
+	#[test]
+	fn parse_encoder_by_codec() {
+		let config = Config::parse(
+			r#"
+			[encoders.libsvtav1]
+			command = ["SvtAv1EncApp", "-i", "stdin", "-b", "stdout"]
+			format = "ivf"
+			"#,
+		)
+		.unwrap();
+		let encoder = External {
+			command: ["SvtAv1EncApp", "-i", "stdin", "-b", "stdout"]
+				.map(String::from)
+				.to_vec(),
+			format: "ivf".to_string(),
+			framerate: None,
+		};
+		assert_eq!(config.encoders.get("libsvtav1"), Some(&encoder));
+	}
+
+	#[test]
+	fn parse_zero_jobs_invalid_data() {
+		let err = Config::parse("jobs = 0").unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::InvalidData)
+	}
+
+	#[test]
+	fn parse_unknown_field_invalid_data() {
+		let err = Config::parse("server_url = \"\"").unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::InvalidData)
+	}
+}
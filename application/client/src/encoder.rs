@@ -0,0 +1,399 @@
+//! Video encoder backends for the transcodes
+//!
+//! ffmpeg encodes every codec by default. The worker configuration can replace it, for a codec,
+//! with a standalone encoder like `SvtAv1EncApp` or `x265`:
+//! ```toml
+//! [encoders.libsvtav1]
+//! command = ["SvtAv1EncApp", "--preset", "6", "-i", "stdin", "-b", "stdout"]
+//! format = "ivf"
+//! ```
+//! ffmpeg decodes and filters the video, with the job video parameters, and pipes it to the
+//! encoder as y4m. The encoder settings come from the command. Another ffmpeg muxes the encoded
+//! stream with the audio, read again from the input.
+//!
+//! Raw streams like `hevc` have no timestamps, the encoder needs the `framerate` of the decoded
+//! video to use them:
+//! ```toml
+//! [encoders.libx265]
+//! command = ["x265", "--y4m", "--input", "-", "--output", "-"]
+//! format = "hevc"
+//! framerate = "24000/1001"
+//! ```
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::process::Stdio;
+
+use serde::Deserialize;
+use tokio::io::AsyncRead;
+use tokio::process::Command;
+use tokio::sync::watch;
+
+use task::{Input, Instance};
+
+pub use crate::ffmpeg_runner::Progress;
+use crate::limits::Limits;
+use crate::{audio_args, ffmpeg_runner, range_args, transcode_args, ClientError};
+
+///Transcode of a task, with the ffmpeg arguments reading each input
+pub struct Transcode<'a> {
+	pub task: &'a Instance,
+	pub sources: Vec<Vec<String>>,
+	///ffmpeg output arguments, like the pass of a two-pass encode
+	pub extra: Vec<String>,
+	///Input piped to ffmpeg, read by the `pipe:0` source
+	pub stdin: Option<Box<dyn AsyncRead + Unpin + Send>>,
+}
+
+///Running transcode, writing the output container stream
+pub struct Encoding {
+	pub output: Box<dyn AsyncRead + Unpin + Send>,
+	///Resolves once every process of the transcode exited, failing if any of them did
+	pub exit: Pin<Box<dyn Future<Output = Result<(), ClientError>> + Send>>,
+	pub progress: watch::Receiver<Option<Progress>>,
+}
+
+///Backend encoding the video of the transcodes
+///
+///Only ffmpeg runs two-pass encodes and reads the inputs from stdin, the tasks using other
+///backends read the inputs from the server or from the downloaded files
+pub trait Encoder: Send + Sync {
+	///Start the transcode, the output is read while it runs
+	fn spawn(
+		&self,
+		ffmpeg: &Path,
+		transcode: Transcode,
+		limits: &Limits,
+	) -> Result<Encoding, ClientError>;
+}
+
+///Encodes with the ffmpeg encoder of the codec
+pub struct Ffmpeg;
+
+impl Encoder for Ffmpeg {
+	fn spawn(
+		&self,
+		ffmpeg: &Path,
+		transcode: Transcode,
+		limits: &Limits,
+	) -> Result<Encoding, ClientError> {
+		let Transcode {
+			task,
+			sources,
+			extra,
+			stdin,
+		} = transcode;
+		let args = transcode_args(task, sources)?.into_iter().chain(extra);
+		let (output, exit, progress) =
+			ffmpeg_runner::run_to_stream(ffmpeg, args, task.container(), limits, stdin)?;
+		Ok(Encoding {
+			output: Box::new(output),
+			exit: Box::pin(async move { exit.await?.result() }),
+			progress,
+		})
+	}
+}
+
+///Runs ffmpeg with its own arguments, like the concat of a merge, instead of the transcode ones
+pub(crate) struct Remux(pub Vec<String>);
+
+impl Encoder for Remux {
+	fn spawn(
+		&self,
+		ffmpeg: &Path,
+		transcode: Transcode,
+		limits: &Limits,
+	) -> Result<Encoding, ClientError> {
+		let container = transcode.task.container();
+		let (output, exit, progress) = ffmpeg_runner::run_to_stream(
+			ffmpeg,
+			self.0.clone(),
+			container,
+			limits,
+			transcode.stdin,
+		)?;
+		Ok(Encoding {
+			output: Box::new(output),
+			exit: Box::pin(async move { exit.await?.result() }),
+			progress,
+		})
+	}
+}
+
+///ffmpeg formats of raw video streams, read at 25 fps unless the framerate is given
+const RAW_FORMATS: [&str; 8] = [
+	"h264",
+	"hevc",
+	"vvc",
+	"obu",
+	"m4v",
+	"mpegvideo",
+	"mjpeg",
+	"rawvideo",
+];
+
+///Standalone encoder reading y4m on stdin and writing the encoded video to stdout
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct External {
+	///Encoder binary, followed by its arguments
+	pub command: Vec<String>,
+	///ffmpeg format of the encoded video, like `ivf` or `hevc`
+	pub format: String,
+	///Frame rate of the decoded video, like `30000/1001`, required by the raw formats
+	#[serde(default)]
+	pub framerate: Option<String>,
+}
+
+impl External {
+	///ffmpeg arguments reading the encoded video from stdin, raw formats need the framerate
+	fn encoded_input(&self) -> Result<Vec<String>, ClientError> {
+		let framerate = match (RAW_FORMATS.contains(&self.format.as_str()), &self.framerate) {
+			(true, Some(framerate)) => vec!["-framerate".to_string(), framerate.clone()],
+			(true, None) => {
+				let reason = format!(
+					"Encoder format {} has no timestamps, the framerate is required",
+					self.format
+				);
+				return Err(ClientError::InvalidTask(reason));
+			}
+			(false, _) => Vec::new(),
+		};
+		Ok(["-f".to_string(), self.format.clone()]
+			.into_iter()
+			.chain(framerate)
+			.chain(["-i".to_string(), "pipe:0".to_string()])
+			.collect())
+	}
+}
+
+impl Encoder for External {
+	fn spawn(
+		&self,
+		ffmpeg: &Path,
+		transcode: Transcode,
+		limits: &Limits,
+	) -> Result<Encoding, ClientError> {
+		let task = transcode.task;
+		let (Some((program, args)), None) = (self.command.split_first(), transcode.stdin) else {
+			let reason = "Standalone encoders need a command and can't read from stdin";
+			return Err(ClientError::InvalidTask(reason.to_string()));
+		};
+		let ([input], [source]) = (task.inputs.as_slice(), transcode.sources.as_slice()) else {
+			let reason = "Standalone encoders transcode a single input";
+			return Err(ClientError::InvalidTask(reason.to_string()));
+		};
+		let mux = mux_args(task, input, source, self.encoded_input()?);
+		let decode = ffmpeg_runner::y4m_command(ffmpeg, decode_args(task, input, source), limits);
+		let (decoded, decoder, progress) = ffmpeg_runner::spawn_piped(decode, None)?;
+		let decoded: Stdio = decoded.try_into()?;
+		let mut encoder = Command::new(program);
+		limits.apply(&mut encoder);
+		let mut encoder = encoder
+			.args(args)
+			.stdin(decoded)
+			.stdout(Stdio::piped())
+			.stderr(Stdio::null())
+			.kill_on_drop(true)
+			.spawn()?;
+		let encoded = encoder.stdout.take().expect("encoder stdout is piped");
+		let (output, muxer, _) = ffmpeg_runner::run_to_stream(
+			ffmpeg,
+			mux,
+			task.container(),
+			limits,
+			Some(Box::new(encoded)),
+		)?;
+		let program = program.clone();
+		let exit = async move {
+			decoder.await?.result()?;
+			let status = encoder.wait().await?;
+			if !status.success() {
+				return Err(ClientError::Ffmpeg {
+					exit_code: status.code(),
+					stderr: format!("Encoder {program} failed"),
+				});
+			}
+			muxer.await?.result()
+		};
+		Ok(Encoding {
+			output: Box::new(output),
+			exit: Box::pin(exit),
+			progress,
+		})
+	}
+}
+
+///Decode the video of the input, applying the job video parameters the decoder knows, like filters
+fn decode_args(task: &Instance, input: &Input, source: &[String]) -> Vec<String> {
+	let (input_range, output_range) = match input.accurate_seek {
+		true => (Vec::new(), range_args(input)),
+		false => (range_args(input), Vec::new()),
+	};
+	let rendition = task.rendition().map(|rendition| rendition.params.clone());
+	let map = format!("0:v:{}", input.video_stream.unwrap_or(0));
+	input_range
+		.into_iter()
+		.chain(task.job_options.input_params.iter().cloned())
+		.chain(source.iter().cloned())
+		.chain(["-map".to_string(), map])
+		.chain(output_range)
+		.chain(task.job_options.video.params.iter().cloned())
+		.chain(rendition.into_iter().flatten())
+		.collect()
+}
+
+///Mux the encoded video, read by the encoded input arguments, with the audio of the input read again
+fn mux_args(
+	task: &Instance,
+	input: &Input,
+	source: &[String],
+	encoded: Vec<String>,
+) -> Vec<String> {
+	let audio_map = match input.audio_stream {
+		Some(stream) => format!("0:a:{stream}"),
+		None => "0:a:0?".to_string(),
+	};
+	let audio = task.job_options.audio.clone().map(audio_args);
+	//The range is applied to the input, the video on stdin is already cut
+	range_args(input)
+		.into_iter()
+		.chain(task.job_options.input_params.iter().cloned())
+		.chain(source.iter().cloned())
+		.chain(encoded)
+		.chain(["-map", "1:v:0", "-map"].map(String::from))
+		.chain([audio_map, "-c:v".to_string(), "copy".to_string()])
+		.chain(audio.into_iter().flatten())
+		.collect()
+}
+
+#[cfg(test)]
+mod test {
+	use task::{Container, JobOptions, Options, Output, Recipe, Resources};
+	use uuid::Uuid;
+
+	use super::*;
+
+	fn encoder(format: &str, framerate: Option<&str>) -> External {
+		External {
+			command: vec!["x265".to_string()],
+			format: format.to_string(),
+			framerate: framerate.map(String::from),
+		}
+	}
+
+	fn task(input: Input) -> Instance {
+		Instance {
+			job_id: Uuid::new_v4(),
+			task_id: Uuid::new_v4(),
+			inputs: vec![input],
+			recipe: Recipe::Transcode(Vec::new()),
+			output: Output::default(),
+			resources: Resources::default(),
+			job_options: JobOptions {
+				video: Options {
+					codec: Some("libx265".to_string()),
+					params: vec!["-vf".to_string(), "scale=-2:720".to_string()],
+				},
+				audio: Some(Options {
+					codec: Some("none".to_string()),
+					params: Vec::new(),
+				}),
+				container: Container::default(),
+				..Default::default()
+			},
+		}
+	}
+
+	fn input(accurate_seek: bool) -> Input {
+		Input {
+			start: Some(10.0),
+			end: Some(20.0),
+			accurate_seek,
+			..Input::source()
+		}
+	}
+
+	fn source() -> Vec<String> {
+		vec!["-i".to_string(), "input.mkv".to_string()]
+	}
+
+	#[test]
+	fn decode_args_seek_the_input_with_the_video_params() {
+		let input = input(false);
+		let args = decode_args(&task(input.clone()), &input, &source());
+		let expected = [
+			"-ss",
+			"10",
+			"-to",
+			"20",
+			"-i",
+			"input.mkv",
+			"-map",
+			"0:v:0",
+			"-vf",
+			"scale=-2:720",
+		];
+		assert_eq!(args, expected.map(String::from))
+	}
+
+	#[test]
+	fn decode_args_accurate_seek_cuts_the_output() {
+		let input = input(true);
+		let args = decode_args(&task(input.clone()), &input, &source());
+		let expected = [
+			"-i",
+			"input.mkv",
+			"-map",
+			"0:v:0",
+			"-ss",
+			"10",
+			"-to",
+			"20",
+			"-vf",
+			"scale=-2:720",
+		];
+		assert_eq!(args, expected.map(String::from))
+	}
+
+	#[test]
+	fn mux_args_read_the_raw_stream_at_the_framerate() {
+		let input = input(false);
+		let encoded = encoder("hevc", Some("24000/1001")).encoded_input().unwrap();
+		let args = mux_args(&task(input.clone()), &input, &source(), encoded);
+		let expected = [
+			"-ss",
+			"10",
+			"-to",
+			"20",
+			"-i",
+			"input.mkv",
+			"-f",
+			"hevc",
+			"-framerate",
+			"24000/1001",
+			"-i",
+			"pipe:0",
+			"-map",
+			"1:v:0",
+			"-map",
+			"0:a:0?",
+			"-c:v",
+			"copy",
+			"-an",
+		];
+		assert_eq!(args, expected.map(String::from))
+	}
+
+	#[test]
+	fn mux_args_timestamped_format_without_framerate() {
+		let encoded = encoder("ivf", Some("25")).encoded_input().unwrap();
+		assert_eq!(encoded, ["-f", "ivf", "-i", "pipe:0"].map(String::from))
+	}
+
+	#[test]
+	fn raw_format_without_framerate_is_invalid() {
+		let res = encoder("hevc", None).encoded_input();
+		assert!(matches!(res, Err(ClientError::InvalidTask(_))))
+	}
+}
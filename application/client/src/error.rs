@@ -0,0 +1,162 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+
+use reqwest::StatusCode;
+
+///Why a worker operation failed, telling apart the failures worth retrying
+#[derive(Debug)]
+pub enum ClientError {
+	///The server request failed, with the response status when there was one
+	Http {
+		status: Option<StatusCode>,
+		message: String,
+	},
+	///ffmpeg failed, without exit code when it was killed by a signal
+	Ffmpeg {
+		exit_code: Option<i32>,
+		stderr: String,
+	},
+	///A server response, or the output of a tool, could not be parsed
+	Parse(String),
+	///The server refused the worker token
+	Auth,
+	///The task can't run on this worker, like a job using an unknown encoder
+	InvalidTask(String),
+	///Reading or writing a local file failed
+	Io(io::Error),
+}
+
+impl ClientError {
+	///Error for a response with an unexpected status
+	pub fn status(status: StatusCode, message: impl Into<String>) -> Self {
+		match status {
+			StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => ClientError::Auth,
+			status => ClientError::Http {
+				status: Some(status),
+				message: message.into(),
+			},
+		}
+	}
+
+	///Failures that may not happen again, like lost connections or server errors
+	pub fn is_transient(&self) -> bool {
+		match self {
+			ClientError::Http { status: None, .. } => true,
+			ClientError::Http {
+				status: Some(status),
+				..
+			} => {
+				status.is_server_error()
+					|| *status == StatusCode::TOO_MANY_REQUESTS
+					|| *status == StatusCode::REQUEST_TIMEOUT
+			}
+			_ => false,
+		}
+	}
+}
+
+impl Display for ClientError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ClientError::Http {
+				status: Some(status),
+				message,
+			} => write!(f, "{message}: {status}"),
+			ClientError::Http {
+				status: None,
+				message,
+			} => f.write_str(message),
+			ClientError::Ffmpeg { exit_code, stderr } => {
+				match exit_code {
+					Some(code) => write!(f, "ffmpeg returned exit code {code}")?,
+					None => f.write_str("ffmpeg was killed")?,
+				}
+				match stderr.is_empty() {
+					true => Ok(()),
+					false => write!(f, ":\n{stderr}"),
+				}
+			}
+			ClientError::Parse(err) => write!(f, "Invalid data: {err}"),
+			ClientError::Auth => f.write_str("The server refused the worker token"),
+			ClientError::InvalidTask(err) => write!(f, "Invalid task: {err}"),
+			ClientError::Io(err) => write!(f, "{err}"),
+		}
+	}
+}
+
+impl std::error::Error for ClientError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			ClientError::Io(err) => Some(err),
+			_ => None,
+		}
+	}
+}
+
+impl From<io::Error> for ClientError {
+	fn from(value: io::Error) -> Self {
+		ClientError::Io(value)
+	}
+}
+
+impl From<reqwest::Error> for ClientError {
+	fn from(value: reqwest::Error) -> Self {
+		match value.status() {
+			Some(status) => ClientError::status(status, value.to_string()),
+			None => ClientError::Http {
+				status: None,
+				message: value.to_string(),
+			},
+		}
+	}
+}
+
+impl<T> From<api::apis::Error<T>> for ClientError {
+	fn from(value: api::apis::Error<T>) -> Self {
+		match value {
+			api::apis::Error::Reqwest(err) => err.into(),
+			api::apis::Error::Serde(err) => ClientError::Parse(err.to_string()),
+			api::apis::Error::Io(err) => ClientError::Io(err),
+			api::apis::Error::ResponseError(res) => ClientError::status(res.status, res.content),
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn refused_token_is_auth() {
+		let err = ClientError::status(StatusCode::UNAUTHORIZED, "Bad authorization");
+		assert!(matches!(err, ClientError::Auth));
+		assert!(!err.is_transient())
+	}
+
+	#[test]
+	fn server_errors_are_transient() {
+		let unavailable = ClientError::status(StatusCode::SERVICE_UNAVAILABLE, "");
+		let rejected = ClientError::status(StatusCode::CONFLICT, "");
+		assert!(unavailable.is_transient());
+		assert!(!rejected.is_transient())
+	}
+
+	#[test]
+	fn incomplete_request_is_transient() {
+		let err = ClientError::status(StatusCode::REQUEST_TIMEOUT, "Upload rejected");
+		assert!(err.is_transient())
+	}
+
+	#[test]
+	fn ffmpeg_failure_has_the_log() {
+		let err = ClientError::Ffmpeg {
+			exit_code: Some(1),
+			stderr: "Unknown encoder".to_string(),
+		};
+		assert_eq!(
+			err.to_string(),
+			"ffmpeg returned exit code 1:\nUnknown encoder"
+		);
+		assert!(!err.is_transient())
+	}
+}
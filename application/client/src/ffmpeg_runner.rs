@@ -1,63 +1,307 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet, VecDeque};
 use std::ffi::OsStr;
 use std::future::Future;
+use std::path::Path;
 use std::process::{ExitStatus, Stdio};
 
 use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
 use tokio::process::{ChildStdout, Command};
 use tokio::sync::mpsc::{channel, Receiver};
+use tokio::sync::{oneshot, watch};
+
+use task::Container;
+
+use crate::limits::Limits;
+use crate::ClientError;
+
+///Lines of the ffmpeg log kept to explain failures
+const LOG_TAIL_LINES: usize = 10;
 
 struct Status(pub BTreeMap<String, String>);
 
-fn status_adapter(stream: impl AsyncRead + Unpin + Send + 'static) -> Receiver<Status> {
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Progress {
+	///Seconds of encoded output
+	pub out_time: f64,
+	///Encoding speed, relative to realtime
+	pub speed: Option<f64>,
+	///Frames encoded per second
+	pub fps: Option<f64>,
+}
+
+impl Status {
+	fn progress(&self) -> Option<Progress> {
+		let out_time = self.0.get("out_time_us")?.parse::<i64>().ok()?;
+		let speed = self
+			.0
+			.get("speed")
+			.and_then(|speed| speed.trim().trim_end_matches('x').parse().ok());
+		let fps = self.0.get("fps").and_then(|fps| fps.trim().parse().ok());
+		Some(Progress {
+			out_time: out_time.max(0) as f64 / 1_000_000.0,
+			speed,
+			fps,
+		})
+	}
+}
+
+///Output of a finished ffmpeg process
+pub(crate) struct Exit {
+	pub status: ExitStatus,
+	///Last lines logged by ffmpeg
+	pub log: String,
+}
+
+impl Exit {
+	///Fails unless ffmpeg succeeded, with the end of the ffmpeg log
+	pub fn result(self) -> Result<(), ClientError> {
+		match self.status.success() {
+			true => Ok(()),
+			false => Err(ClientError::Ffmpeg {
+				exit_code: self.status.code(),
+				stderr: self.log,
+			}),
+		}
+	}
+}
+
+///Key and value of a progress line, other lines are ffmpeg log messages
+fn progress_entry(line: &str) -> Option<(&str, &str)> {
+	let (name, value) = line.split_once('=')?;
+	let is_key = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+	is_key.then_some((name, value))
+}
+
+///Parse the progress on the stream, the log tail is sent when the stream ends
+fn status_adapter(
+	stream: impl AsyncRead + Unpin + Send + 'static,
+) -> (Receiver<Status>, oneshot::Receiver<String>) {
 	let mut stream = BufReader::new(stream);
 	let (sender, receiver) = channel(32);
+	let (log_sender, log_receiver) = oneshot::channel();
 	tokio::spawn(async move {
 		let mut status = BTreeMap::new();
+		let mut log = VecDeque::with_capacity(LOG_TAIL_LINES);
 		loop {
 			let mut line = String::new();
-			if stream.read_line(&mut line).await.is_err() {
+			if !matches!(stream.read_line(&mut line).await, Ok(1..)) {
 				break;
 			}
-			if let Some((name, value)) = line.split_once('=') {
-				status.insert(name.into(), value.trim_end().into());
-			}
-			let is_complete = line.starts_with("progress=");
-			if is_complete {
-				if sender.send(Status(status)).await.is_err() {
-					break;
+			let Some((name, value)) = progress_entry(&line) else {
+				if log.len() == LOG_TAIL_LINES {
+					log.pop_front();
 				}
+				log.push_back(line.trim_end().to_string());
+				continue;
+			};
+			status.insert(name.into(), value.trim_end().into());
+			if name == "progress" {
+				let _ = sender.send(Status(status)).await;
 				status = BTreeMap::new();
 			}
 		}
+		let _ = log_sender.send(Vec::from(log).join("\n"));
 	});
-	receiver
+	(receiver, log_receiver)
 }
 
+///ffmpeg command with the progress and errors on stderr, the output arguments are added by the caller
+fn progress_command<I, S>(ffmpeg: &Path, args: I, limits: &Limits) -> Command
+where
+	I: IntoIterator<Item = S>,
+	S: AsRef<OsStr>,
+{
+	let mut ffmpeg = Command::new(ffmpeg);
+	ffmpeg.args(args);
+	ffmpeg.args(limits.args());
+	limits.apply(&mut ffmpeg);
+	ffmpeg.args(["-progress", "pipe:2", "-nostats", "-v", "error"]);
+	ffmpeg
+}
+
+///ffmpeg command writing the container stream to stdout, with the progress and errors on stderr
+///
+///Mp4 is fragmented, as stdout is not seekable
+pub(crate) fn command<I, S>(
+	ffmpeg: &Path,
+	args: I,
+	container: Container,
+	limits: &Limits,
+) -> Command
+where
+	I: IntoIterator<Item = S>,
+	S: AsRef<OsStr>,
+{
+	let mut ffmpeg = progress_command(ffmpeg, args, limits);
+	if container == Container::Mp4 {
+		ffmpeg.args(["-movflags", "frag_keyframe+empty_moov+default_base_moof"]);
+	}
+	ffmpeg.args(["-f", container.format(), "-"]);
+	ffmpeg
+}
+
+///ffmpeg command writing the decoded video to stdout as y4m, for the standalone encoders
+pub(crate) fn y4m_command<I, S>(ffmpeg: &Path, args: I, limits: &Limits) -> Command
+where
+	I: IntoIterator<Item = S>,
+	S: AsRef<OsStr>,
+{
+	let mut ffmpeg = progress_command(ffmpeg, args, limits);
+	//High bit depth formats are not part of the y4m specification
+	ffmpeg.args(["-strict", "-1", "-f", "yuv4mpegpipe", "-"]);
+	ffmpeg
+}
+
+///Remux the file without writing the result, failing on the first error
+///
+///Detects truncated or corrupted outputs before they are uploaded
+pub(crate) async fn verify(ffmpeg: &Path, path: &Path, limits: &Limits) -> Result<(), ClientError> {
+	let input = [OsStr::new("-xerror"), OsStr::new("-i"), path.as_os_str()];
+	let copy = ["-map", "0", "-c", "copy"].map(OsStr::new);
+	run_to_null(ffmpeg, input.into_iter().chain(copy), limits).await
+}
+
+///Run ffmpeg discarding the output, fails with the logged errors
+pub(crate) async fn run_to_null<I, S>(
+	ffmpeg: &Path,
+	args: I,
+	limits: &Limits,
+) -> Result<(), ClientError>
+where
+	I: IntoIterator<Item = S>,
+	S: AsRef<OsStr>,
+{
+	run_to(ffmpeg, args, &["-f", "null", "-"].map(OsStr::new), limits).await
+}
+
+///Run ffmpeg writing the first frame of the output to the path as a jpeg image
+pub(crate) async fn run_to_image<I, S>(
+	ffmpeg: &Path,
+	args: I,
+	path: &Path,
+	limits: &Limits,
+) -> Result<(), ClientError>
+where
+	I: IntoIterator<Item = S>,
+	S: AsRef<OsStr>,
+{
+	let image = ["-frames:v", "1", "-c:v", "mjpeg", "-f", "image2", "-y"].map(OsStr::new);
+	let output: Vec<&OsStr> = image.into_iter().chain([path.as_os_str()]).collect();
+	run_to(ffmpeg, args, &output, limits).await
+}
+
+///Run ffmpeg with the output arguments, fails with the logged errors
+async fn run_to<I, S>(
+	ffmpeg: &Path,
+	args: I,
+	output_args: &[&OsStr],
+	limits: &Limits,
+) -> Result<(), ClientError>
+where
+	I: IntoIterator<Item = S>,
+	S: AsRef<OsStr>,
+{
+	let mut ffmpeg = Command::new(ffmpeg);
+	limits.apply(&mut ffmpeg);
+	let output = ffmpeg
+		.args(["-v", "error"])
+		.args(args)
+		.args(limits.args())
+		.args(output_args)
+		.stdin(Stdio::null())
+		.stdout(Stdio::null())
+		.kill_on_drop(true)
+		.output()
+		.await?;
+	let errors = String::from_utf8_lossy(&output.stderr);
+	match output.status.success() && errors.trim().is_empty() {
+		true => Ok(()),
+		false => Err(ClientError::Ffmpeg {
+			exit_code: output.status.code(),
+			stderr: errors.trim().to_string(),
+		}),
+	}
+}
+
+///Names of the encoders supported by ffmpeg
+pub(crate) async fn encoders(ffmpeg: &Path) -> std::io::Result<HashSet<String>> {
+	let output = Command::new(ffmpeg)
+		.args(["-hide_banner", "-encoders"])
+		.stdin(Stdio::null())
+		.kill_on_drop(true)
+		.output()
+		.await?;
+	if !output.status.success() {
+		return Err(std::io::Error::other(format!(
+			"ffmpeg -encoders returned: {}",
+			output.status
+		)));
+	}
+	//Each encoder is listed after the legend, as `<flags> <name> <description>`
+	let list = String::from_utf8_lossy(&output.stdout);
+	let encoders = list
+		.lines()
+		.skip_while(|line| !line.trim_start().starts_with("---"))
+		.skip(1)
+		.filter_map(|line| line.split_whitespace().nth(1))
+		.map(String::from)
+		.collect();
+	Ok(encoders)
+}
+
+///Run ffmpeg with the output on the returned pipe, writing the optional input to its stdin
 pub(crate) fn run_to_stream<I, S>(
+	ffmpeg: &Path,
 	args: I,
-) -> (
+	container: Container,
+	limits: &Limits,
+	stdin: Option<Box<dyn AsyncRead + Unpin + Send>>,
+) -> std::io::Result<(
 	ChildStdout,
-	impl Future<Output = std::io::Result<ExitStatus>>,
-)
+	impl Future<Output = std::io::Result<Exit>>,
+	watch::Receiver<Option<Progress>>,
+)>
 where
 	I: IntoIterator<Item = S>,
 	S: AsRef<OsStr>,
 {
-	let mut ffmpeg = Command::new("ffmpeg");
-	ffmpeg.args(args);
-	ffmpeg.args(["-progress", "pipe:2", "-nostats", "-v", "quiet"]);
-	ffmpeg.args(["-f", "matroska", "-"]);
+	spawn_piped(command(ffmpeg, args, container, limits), stdin)
+}
+
+///Run the ffmpeg command, which writes to stdout and the progress to stderr
+pub(crate) fn spawn_piped(
+	mut ffmpeg: Command,
+	stdin: Option<Box<dyn AsyncRead + Unpin + Send>>,
+) -> std::io::Result<(
+	ChildStdout,
+	impl Future<Output = std::io::Result<Exit>>,
+	watch::Receiver<Option<Progress>>,
+)> {
 	ffmpeg
 		.stderr(Stdio::piped())
 		.stdout(Stdio::piped())
-		.stdin(Stdio::null());
+		.stdin(match stdin {
+			Some(_) => Stdio::piped(),
+			None => Stdio::null(),
+		})
+		.kill_on_drop(true);
 	println!("ffmpeg command: {:?}", ffmpeg);
-	let mut child = ffmpeg.spawn().unwrap();
-	let output = child.stdout.take().unwrap();
-	let progress = child.stderr.take().unwrap();
-	let status = async move { child.wait().await };
-	let parsed_progress = status_adapter(progress);
+	let mut child = ffmpeg.spawn()?;
+	if let (Some(mut input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+		tokio::spawn(async move {
+			//ffmpeg may close stdin before the end, when it stops reading early
+			let _ = tokio::io::copy(&mut input, &mut pipe).await;
+		});
+	}
+	let output = child.stdout.take().expect("ffmpeg stdout is piped");
+	let progress = child.stderr.take().expect("ffmpeg stderr is piped");
+	let (parsed_progress, log) = status_adapter(progress);
+	let status = async move {
+		let status = child.wait().await?;
+		let log = log.await.unwrap_or_default();
+		Ok(Exit { status, log })
+	};
+	let (progress_sender, progress_receiver) = watch::channel(None);
 	tokio::spawn(async move {
 		let mut stream = parsed_progress;
 		loop {
@@ -73,7 +317,10 @@ where
 						|| (key.as_str(), val.as_str()) == ("progress", "end")
 				})
 				.for_each(|(_, val)| println!("Time: {val}"));
+			if let Some(progress) = status.progress() {
+				progress_sender.send_replace(Some(progress));
+			}
 		}
 	});
-	(output, status)
+	Ok((output, status, progress_receiver))
 }
@@ -0,0 +1,199 @@
+use std::io;
+use std::path::Path;
+use std::process::Stdio;
+
+use tokio::process::Command;
+
+use task::MediaProbe;
+
+pub(crate) struct Probe {
+	///Duration in seconds
+	pub duration: f64,
+	///Keyframe timestamps of the first video stream, in seconds
+	pub keyframes: Vec<f64>,
+}
+
+///Options of the source, the job input params go before it, like they do for ffmpeg
+pub(crate) struct Source<'a> {
+	pub url: &'a str,
+	///Only sent to remote inputs
+	pub authorization: Option<&'a str>,
+	pub input_params: &'a [String],
+}
+
+fn command(ffprobe: &Path, source: &Source, args: &[&str]) -> Command {
+	let mut command = Command::new(ffprobe);
+	command.args(["-v", "error"]);
+	command.args(args);
+	if let Some(authorization) = source.authorization {
+		command.arg("-headers");
+		command.arg(format!("Authorization: {authorization}"));
+	}
+	command.args(source.input_params);
+	command.arg(source.url);
+	command
+}
+
+async fn run(ffprobe: &Path, source: &Source<'_>, args: &[&str]) -> io::Result<String> {
+	let mut ffprobe = command(ffprobe, source, args);
+	ffprobe
+		.stdin(Stdio::null())
+		.stderr(Stdio::inherit())
+		.kill_on_drop(true);
+	let output = ffprobe.output().await?;
+	if !output.status.success() {
+		return Err(io::Error::other(format!(
+			"ffprobe returned: {}",
+			output.status
+		)));
+	}
+	String::from_utf8(output.stdout).map_err(io::Error::other)
+}
+
+///Duration of the input in seconds
+pub(crate) async fn duration(ffprobe: &Path, source: &Source<'_>) -> io::Result<f64> {
+	let args = ["-of", "csv=p=0", "-show_entries", "format=duration"];
+	let duration = run(ffprobe, source, &args).await?;
+	duration
+		.trim()
+		.parse()
+		.map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid duration"))
+}
+
+///Duration of the input, and the keyframes of its video stream, by index among the video streams
+pub(crate) async fn probe(
+	ffprobe: &Path,
+	source: &Source<'_>,
+	video_stream: u32,
+) -> io::Result<Probe> {
+	let duration = duration(ffprobe, source).await?;
+	let stream = format!("v:{video_stream}");
+	let packets = run(
+		ffprobe,
+		source,
+		&[
+			"-of",
+			"csv=p=0",
+			"-select_streams",
+			&stream,
+			"-show_entries",
+			"packet=pts_time,flags",
+		],
+	)
+	.await?;
+	let mut keyframes: Vec<f64> = packets
+		.lines()
+		.filter_map(|line| line.split_once(','))
+		.filter(|(_, flags)| flags.starts_with('K'))
+		.filter_map(|(time, _)| time.parse().ok())
+		.collect();
+	keyframes.sort_by(f64::total_cmp);
+	Ok(Probe {
+		duration,
+		keyframes,
+	})
+}
+
+///Format and streams of the input
+pub(crate) async fn metadata(ffprobe: &Path, source: &Source<'_>) -> io::Result<MediaProbe> {
+	let output = run(
+		ffprobe,
+		source,
+		&["-of", "json", "-show_entries", task::probe::SHOW_ENTRIES],
+	)
+	.await?;
+	task::probe::parse(output.as_bytes())
+		.map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+///Timestamps of the scene changes scoring above the threshold, in seconds
+///
+///ffprobe can't filter a remote input, so ffmpeg decodes the video stream with the scene detection
+///filter, printing the selected frames
+pub(crate) async fn scene_changes(
+	ffmpeg: &Path,
+	source: &Source<'_>,
+	video_stream: u32,
+	threshold: f64,
+) -> io::Result<Vec<f64>> {
+	let mut ffmpeg = Command::new(ffmpeg);
+	ffmpeg.args(["-v", "error"]);
+	if let Some(authorization) = source.authorization {
+		ffmpeg.arg("-headers");
+		ffmpeg.arg(format!("Authorization: {authorization}"));
+	}
+	ffmpeg.args(source.input_params);
+	ffmpeg.args(["-i", source.url, "-map"]);
+	ffmpeg.arg(format!("0:v:{video_stream}"));
+	ffmpeg.arg("-vf");
+	ffmpeg.arg(format!(
+		"select='gt(scene,{threshold})',metadata=mode=print:file=-"
+	));
+	ffmpeg.args(["-f", "null", "-"]);
+	ffmpeg
+		.stdin(Stdio::null())
+		.stderr(Stdio::inherit())
+		.kill_on_drop(true);
+	let output = ffmpeg.output().await?;
+	if !output.status.success() {
+		return Err(io::Error::other(format!(
+			"ffmpeg returned: {}",
+			output.status
+		)));
+	}
+	let output = String::from_utf8(output.stdout).map_err(io::Error::other)?;
+	Ok(parse_scene_changes(&output))
+}
+
+///Read the `pts_time` of the frames printed by the metadata filter
+fn parse_scene_changes(output: &str) -> Vec<f64> {
+	let mut scenes: Vec<f64> = output
+		.lines()
+		.filter(|line| line.starts_with("frame:"))
+		.filter_map(|line| {
+			line.split_whitespace()
+				.find_map(|field| field.strip_prefix("pts_time:"))
+		})
+		.filter_map(|time| time.parse().ok())
+		.collect();
+	scenes.sort_by(f64::total_cmp);
+	scenes
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn parse_scene_changes_reads_frame_times() {
+		let output = "frame:0    pts:120     pts_time:4.8\n\
+			lavfi.scene_score=0.512\n\
+			frame:1    pts:50      pts_time:2\n\
+			lavfi.scene_score=0.731\n";
+		assert_eq!(parse_scene_changes(output), vec![2.0, 4.8])
+	}
+
+	#[test]
+	fn command_places_input_params_before_the_input() {
+		let input_params = ["-analyzeduration".to_string(), "100M".to_string()];
+		let source = Source {
+			url: "http://server/input",
+			authorization: Some("key"),
+			input_params: &input_params,
+		};
+		let command = command(Path::new("ffprobe"), &source, &["-of", "json"]);
+		let args: Vec<_> = command.as_std().get_args().collect();
+		let expected = [
+			"-v",
+			"error",
+			"-of",
+			"json",
+			"-headers",
+			"Authorization: key",
+			"-analyzeduration",
+			"100M",
+			"http://server/input",
+		];
+		assert_eq!(args, expected)
+	}
+}
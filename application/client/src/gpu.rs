@@ -0,0 +1,96 @@
+use std::sync::Mutex;
+
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+///Hardware acceleration devices, each used by a single task at a time
+pub struct Devices {
+	free: Mutex<Vec<String>>,
+	available: Semaphore,
+	count: usize,
+}
+
+///A device reserved for a task, returned to the pool when dropped
+pub struct Lease<'a> {
+	devices: &'a Devices,
+	device: Option<String>,
+	_permit: SemaphorePermit<'a>,
+}
+
+impl Devices {
+	///Devices as accepted by ffmpeg `-hwaccel_device`, such as a gpu index or a render node
+	pub fn new(devices: Vec<String>) -> Self {
+		Self {
+			available: Semaphore::new(devices.len()),
+			count: devices.len(),
+			free: Mutex::new(devices),
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		self.count
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.count == 0
+	}
+
+	///Wait for a free device
+	pub async fn acquire(&self) -> Lease<'_> {
+		let permit = self
+			.available
+			.acquire()
+			.await
+			.expect("Device semaphore is never closed");
+		let device = self.free.lock().unwrap().pop();
+		Lease {
+			devices: self,
+			device,
+			_permit: permit,
+		}
+	}
+}
+
+impl Lease<'_> {
+	pub fn device(&self) -> &str {
+		self.device.as_deref().unwrap_or_default()
+	}
+
+	///ffmpeg input arguments decoding on the device
+	pub fn input_args(&self) -> [String; 4] {
+		[
+			"-hwaccel".to_string(),
+			"auto".to_string(),
+			"-hwaccel_device".to_string(),
+			self.device().to_string(),
+		]
+	}
+}
+
+impl Drop for Lease<'_> {
+	fn drop(&mut self) {
+		if let Some(device) = self.device.take() {
+			self.devices.free.lock().unwrap().push(device);
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[tokio::test]
+	async fn leased_device_is_not_shared() {
+		let devices = Devices::new(vec!["0".to_string(), "1".to_string()]);
+		let first = devices.acquire().await;
+		let second = devices.acquire().await;
+		assert_ne!(first.device(), second.device())
+	}
+
+	#[tokio::test]
+	async fn dropped_lease_returns_device() {
+		let devices = Devices::new(vec!["0".to_string()]);
+		drop(devices.acquire().await);
+		let lease = devices.acquire().await;
+		assert_eq!(lease.device(), "0")
+	}
+}
@@ -1,146 +1,1473 @@
+use std::collections::BTreeMap;
 use std::io;
-use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use reqwest::header::AUTHORIZATION;
-use reqwest::{Body, StatusCode};
-use tokio::process::ChildStdout;
-use tokio_util::codec::{BytesCodec, FramedRead};
+use base64::prelude::*;
+use reqwest::StatusCode;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::sync::watch;
 use uuid::Uuid;
 
 use api::apis::configuration::Configuration;
-use task::{Input, Instance, Recipe, Status, TaskSource};
+pub use builder::ClientBuilder;
+use encoder::{Encoder, Encoding, Transcode};
+pub use error::ClientError;
+use limits::Limits;
+use task::segment::AnalysisArtifact;
+use task::{
+	AnalysisOptions, Container, Input, Instance, JobOptions, Options, Output, Recipe, Status,
+	Thumbnail,
+};
+use transport::Transport;
 
+pub mod builder;
+pub mod cache;
+pub mod config;
+pub mod encoder;
+mod error;
 mod ffmpeg_runner;
+mod ffprobe;
+pub mod gpu;
+pub mod limits;
+pub mod metrics;
+pub mod state;
+mod throttle;
+pub mod transport;
+pub mod work;
 
+///Minimum interval between progress reports
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+///Upload attempts before failing the task
+const UPLOAD_ATTEMPTS: u32 = 5;
+///Wait before the first upload retry, doubled on each attempt
+const UPLOAD_RETRY_DELAY: Duration = Duration::from_secs(1);
+///Time without sending any byte before the upload attempt is abandoned
+const UPLOAD_STALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+///Bytes of the output sent to the server, including the ones it had before the attempt
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct UploadProgress {
+	pub sent: u64,
+	pub total: u64,
+}
+
+impl UploadProgress {
+	pub fn percent(&self) -> f64 {
+		match self.total {
+			0 => 100.0,
+			total => self.sent as f64 * 100.0 / total as f64,
+		}
+	}
+}
+
+///Runs the tasks, using the transport to reach the server
 #[allow(async_fn_in_trait)]
-pub trait TaskRunner {
+pub trait TaskRunner: Transport {
+	fn ffmpeg(&self) -> &Path;
+	fn ffprobe(&self) -> &Path;
+	///Download the transcode inputs to local files, instead of letting ffmpeg read them from the server
+	fn download_inputs(&self) -> bool {
+		false
+	}
+	///Stream single input transcodes to ffmpeg stdin, keeping the credentials out of its arguments
+	fn pipe_inputs(&self) -> bool {
+		false
+	}
+	///Cache for the downloaded job inputs, shared by the tasks of the same job
+	fn source_cache(&self) -> Option<&cache::SourceCache> {
+		None
+	}
+	///Outcome of the job options already validated, kept for the worker lifetime
+	fn validations(&self) -> Option<&cache::Validations> {
+		None
+	}
+	///Counters for the tasks run and the outputs uploaded
+	fn metrics(&self) -> Option<&metrics::Metrics> {
+		None
+	}
+	///Wall-clock limit for running a task, after which it is failed and released
+	fn task_timeout(&self) -> Option<Duration> {
+		None
+	}
+	///Time without sending any byte before an upload attempt is abandoned
+	fn upload_stall_timeout(&self) -> Duration {
+		UPLOAD_STALL_TIMEOUT
+	}
+	///Remux the local output before uploading, failing the task if it is not readable
+	fn verify_output(&self) -> bool {
+		false
+	}
+	///Threads and priority of the ffmpeg processes
+	fn limits(&self) -> Limits {
+		Limits::default()
+	}
+	///Devices to decode the transcode inputs, each task uses a device on its own
+	fn devices(&self) -> Option<&gpu::Devices> {
+		None
+	}
+	///Backend replacing ffmpeg to encode the video codec
+	fn encoder(&self, _codec: &str) -> Option<&dyn Encoder> {
+		None
+	}
 	fn get_input_url(&self, job: Uuid, task: Uuid, idx: u32) -> String;
 	fn get_output_url(&self, job: Uuid, task: Uuid) -> String;
 	fn get_input_creds(&self) -> String;
 	fn get_output_creds(&self) -> String {
 		self.get_input_creds()
 	}
-	async fn upload_stdout(&self, stdout: ChildStdout, id: (Uuid, Uuid)) -> io::Result<StatusCode>;
-	async fn mark_task_complete(&self, job: Uuid, task: Uuid) -> Result<(), ()>;
-
-	async fn add_task_to_job(&self, job: Uuid, task: TaskSource) -> Result<(), ()>;
+	fn get_upload_url(&self, job: Uuid, task: Uuid) -> String {
+		format!("{}/upload", self.get_output_url(job, task))
+	}
+	///Called while uploading, at most once per progress interval, the default logs the progress
+	fn upload_progressed(&self, id: (Uuid, Uuid), progress: UploadProgress) {
+		println!(
+			"Task {} uploaded {} of {} bytes ({:.1}%)",
+			id.1,
+			progress.sent,
+			progress.total,
+			progress.percent()
+		);
+	}
+	async fn mark_task_complete(&self, job: Uuid, task: Uuid) -> Result<(), ClientError> {
+		if let Some(metrics) = self.metrics() {
+			metrics.task_completed();
+		}
+		self.set_task_status(job, task, Status::Finished).await
+	}
+	async fn mark_task_failed(
+		&self,
+		job: Uuid,
+		task: Uuid,
+		reason: &str,
+	) -> Result<(), ClientError> {
+		if let Some(metrics) = self.metrics() {
+			metrics.task_failed();
+		}
+		let status = Status::Failed(Some(reason.to_string()));
+		self.set_task_status(job, task, status).await
+	}
 
-	async fn run_analysis(&self, task: Instance, _option: Option<f64>) -> Result<(), ()> {
-		let source = TaskSource {
-			inputs: vec![Input::source()],
-			recipe: Recipe::Transcode(Default::default()),
+	///Split the source in segments, to be transcoded in parallel, then merged
+	async fn run_analysis(
+		&self,
+		task: Instance,
+		options: AnalysisOptions,
+	) -> Result<(), ClientError> {
+		let id = (task.job_id, task.task_id);
+		let input = self.get_input_url(id.0, id.1, 0);
+		let creds = self.get_input_creds();
+		let input_params = &task.job_options.input_params;
+		let source = ffprobe::Source {
+			url: &input,
+			authorization: Some(&creds),
+			input_params,
+		};
+		//The segments are cut at the keyframes of the selected video stream
+		let stream = task.inputs.first().and_then(|input| input.video_stream);
+		let stream = stream.unwrap_or(0);
+		let probe = ffprobe::probe(self.ffprobe(), &source, stream).await?;
+		self.probe_and_report(&input, Some(&creds), input_params, id)
+			.await;
+		let cuts = match options.scene_threshold {
+			Some(threshold) => {
+				ffprobe::scene_changes(self.ffmpeg(), &source, stream, threshold).await?
+			}
+			None => probe.keyframes,
+		};
+		//The server splits the clip, and creates the transcodes of the segments, from the artifact
+		let artifact = AnalysisArtifact {
+			duration: probe.duration,
+			cuts,
 		};
-		self.add_task_to_job(task.job_id, source).await
+		let path = std::env::temp_dir().join(format!("segmented-analysis-{}.json", id.1));
+		let res = async {
+			let json =
+				serde_json::to_vec(&artifact).map_err(|err| ClientError::Parse(err.to_string()))?;
+			tokio::fs::write(&path, json).await?;
+			self.upload_output(&path, id).await
+		}
+		.await;
+		let _ = tokio::fs::remove_file(&path).await;
+		res?;
+		self.mark_task_complete(id.0, id.1).await
 	}
-	async fn run_transcode(&self, task: Instance, _extra_options: Vec<String>) -> Result<(), ()> {
-		let inputs = task
-			.inputs
-			.into_iter()
-			.flat_map(|input| {
-				let source = [
+	async fn run_transcode(
+		&self,
+		task: Instance,
+		extra_options: Vec<String>,
+	) -> Result<(), ClientError> {
+		if let Err(err) = self.validate(&task.job_options).await {
+			let reason = format!("Invalid job options: {err}");
+			eprintln!("{reason}");
+			return self
+				.mark_task_failed(task.job_id, task.task_id, &reason)
+				.await
+				.and(Err(err));
+		}
+		//Only ffmpeg reads the input from stdin, the other backends read it twice
+		let codec = task.job_options.video.codec.as_deref().unwrap_or_default();
+		if self.pipe_inputs() && task.inputs.len() == 1 && self.encoder(codec).is_none() {
+			let sources = vec![vec!["-i".to_string(), "pipe:0".to_string()]];
+			return self.transcode(task, sources, Some(0), extra_options).await;
+		}
+		if !self.download_inputs() && !self.pipe_inputs() {
+			let sources = self.remote_sources(&task);
+			return self.transcode(task, sources, None, extra_options).await;
+		}
+		let dir = std::env::temp_dir().join(format!("segmented-transcode-{}", task.task_id));
+		tokio::fs::create_dir_all(&dir).await?;
+		let res = async {
+			let mut sources = Vec::with_capacity(task.inputs.len());
+			let mut cached = Vec::new();
+			let (job_id, task_id) = (task.job_id, task.task_id);
+			for (idx, input) in task.inputs.iter().enumerate() {
+				let idx = idx as u32;
+				let path = match self.source_cache() {
+					Some(cache) => cache
+						.get(job_id, input.index, |path| async move {
+							self.download_input(job_id, task_id, idx, &path).await
+						})
+						.await
+						.map(|entry| {
+							let path = entry.path().to_path_buf();
+							cached.push(entry);
+							path
+						}),
+					None => {
+						let path = dir.join(idx.to_string());
+						self.download_input(task.job_id, task.task_id, idx, &path)
+							.await
+							.map(|()| path)
+					}
+				};
+				let path = match path {
+					Ok(path) => path,
+					Err(err) => {
+						eprintln!("Failed to download input {idx}: {err}");
+						let reason = format!("Failed to download input {idx}");
+						let _ = self
+							.mark_task_failed(task.job_id, task.task_id, &reason)
+							.await;
+						return Err(err.into());
+					}
+				};
+				sources.push(vec!["-i".to_string(), path.to_string_lossy().into_owned()]);
+			}
+			let res = self.transcode(task, sources, None, extra_options).await;
+			drop(cached);
+			res
+		}
+		.await;
+		let _ = tokio::fs::remove_dir_all(&dir).await;
+		res
+	}
+	///Encode a generated clip with the job options, failing fast on unknown encoders or parameters
+	///
+	///The outcome is reused for the next tasks with the same options
+	async fn validate(&self, options: &JobOptions) -> Result<(), ClientError> {
+		let args = validation_args(options);
+		let validations = self.validations();
+		if let Some(outcome) = validations.and_then(|cached| cached.get(&args, options.two_pass)) {
+			return outcome.map_err(ClientError::InvalidTask);
+		}
+		let res = self.validate_uncached(options).await;
+		let outcome = match &res {
+			Ok(()) => Some(Ok(())),
+			Err(ClientError::InvalidTask(reason)) => Some(Err(reason.clone())),
+			Err(err @ ClientError::Ffmpeg { .. }) => Some(Err(err.to_string())),
+			Err(_) => None,
+		};
+		if let (Some(validations), Some(outcome)) = (validations, outcome) {
+			validations.insert(args, options.two_pass, outcome);
+		}
+		res
+	}
+	async fn validate_uncached(&self, options: &JobOptions) -> Result<(), ClientError> {
+		let video = options
+			.video
+			.codec
+			.as_deref()
+			.ok_or(ClientError::InvalidTask("Missing video codec".to_string()))?;
+		if self.encoder(video).is_some() {
+			return match options.two_pass {
+				true => Err(ClientError::InvalidTask(format!(
+					"Two-pass encodes of {video} need the ffmpeg encoder"
+				))),
+				false => Ok(()),
+			};
+		}
+		let encoders = ffmpeg_runner::encoders(self.ffmpeg()).await?;
+		let audio = options
+			.audio
+			.as_ref()
+			.and_then(|audio| audio.codec.as_deref())
+			.filter(|codec| !matches!(*codec, "copy" | "none"));
+		for codec in [video].into_iter().chain(audio) {
+			if !encoders.contains(codec) {
+				let reason = format!("Unknown encoder '{codec}'");
+				return Err(ClientError::InvalidTask(reason));
+			}
+		}
+		ffmpeg_runner::run_to_null(self.ffmpeg(), validation_args(options), &self.limits()).await
+	}
+	///Transcode the inputs, each read with the matching ffmpeg source arguments
+	///
+	///The `stdin` input is streamed to ffmpeg, on every pass
+	async fn transcode(
+		&self,
+		task: Instance,
+		sources: Vec<Vec<String>>,
+		stdin: Option<u32>,
+		_extra_options: Vec<String>,
+	) -> Result<(), ClientError> {
+		let lease = match self.devices() {
+			//A task hinting no gpu is left to the cpu, keeping the devices for the others
+			Some(devices) if task.resources.gpus != Some(0) => Some(devices.acquire().await),
+			_ => None,
+		};
+		let sources = match &lease {
+			Some(lease) => sources
+				.into_iter()
+				.map(|source| lease.input_args().into_iter().chain(source).collect())
+				.collect(),
+			None => sources,
+		};
+		let id = (task.job_id, task.task_id);
+		let codec = task.job_options.video.codec.as_deref().unwrap_or_default();
+		let encoder = self.encoder(codec).unwrap_or(&encoder::Ffmpeg);
+		if !task.job_options.two_pass {
+			return self
+				.run_and_upload(&task, encoder, sources, Vec::new(), stdin)
+				.await;
+		}
+		let dir = std::env::temp_dir().join(format!("segmented-passlog-{}", task.task_id));
+		tokio::fs::create_dir_all(&dir).await?;
+		let passlog = dir.join("pass").to_string_lossy().into_owned();
+		let first = pass_args(&transcode_args(&task, sources.clone())?, "1", &passlog);
+		let second = pass_args(&[], "2", &passlog);
+		let res = match self.first_pass(first, &task, stdin).await {
+			Ok(()) => {
+				self.run_and_upload(&task, encoder, sources, second, stdin)
+					.await
+			}
+			Err(err) => self
+				.mark_task_failed(id.0, id.1, &format!("First pass failed: {err}"))
+				.await
+				.and(Err(err)),
+		};
+		let _ = tokio::fs::remove_dir_all(&dir).await;
+		res
+	}
+	///Concatenate the inputs in the recipe order, without reencoding
+	async fn run_merge(&self, task: Instance, order: Vec<u32>) -> Result<(), ClientError> {
+		let dir = std::env::temp_dir().join(format!("segmented-merge-{}", task.task_id));
+		tokio::fs::create_dir_all(&dir).await?;
+		//The job source following the merged inputs provides the subtitles and attachments
+		let source = match task.inputs.len() > order.len() {
+			true => task.inputs.last().zip(self.remote_sources(&task).pop()),
+			false => None,
+		};
+		let res = async {
+			let mut list = String::new();
+			for (position, idx) in order.into_iter().enumerate() {
+				let path = dir.join(format!(
+					"{position}.{}",
+					task.job_options.container.extension()
+				));
+				self.download_input(task.job_id, task.task_id, idx, &path)
+					.await?;
+				list.push_str(&concat_entry(&path));
+			}
+			let list_path = dir.join("list.txt");
+			tokio::fs::write(&list_path, list).await?;
+			let args = concat_args(&list_path, source, &task.job_options.input_params);
+			let remux = encoder::Remux(args);
+			self.run_and_upload(&task, &remux, Vec::new(), Vec::new(), None)
+				.await
+		}
+		.await;
+		let _ = tokio::fs::remove_dir_all(&dir).await;
+		res
+	}
+	///Extract the poster frame, or the sprite sheet, of the input as a jpeg image
+	async fn run_thumbnail(&self, task: Instance, thumbnail: Thumbnail) -> Result<(), ClientError> {
+		let id = (task.job_id, task.task_id);
+		let output = std::env::temp_dir().join(format!("segmented-thumbnail-{}.jpg", id.1));
+		let res = async {
+			let input = task
+				.inputs
+				.first()
+				.ok_or_else(|| ClientError::InvalidTask("Thumbnail without input".to_string()))?;
+			//The sprite frames are spread over the input range, probed if it has no end
+			let end = match (thumbnail, input.end) {
+				(Thumbnail::Sprite { .. }, None) => {
+					let url = self.get_input_url(id.0, id.1, 0);
+					let creds = self.get_input_creds();
+					let source = ffprobe::Source {
+						url: &url,
+						authorization: Some(&creds),
+						input_params: &task.job_options.input_params,
+					};
+					ffprobe::duration(self.ffprobe(), &source).await?
+				}
+				(_, end) => end.unwrap_or_default(),
+			};
+			let duration = end - input.start.unwrap_or_default();
+			let source = self.remote_sources(&task).swap_remove(0);
+			let input_params = &task.job_options.input_params;
+			let args = thumbnail_args(input, input_params, source, thumbnail, duration);
+			let limits = self.limits().for_task(&task.resources);
+			ffmpeg_runner::run_to_image(self.ffmpeg(), args, &output, &limits).await?;
+			self.upload_output(&output, id).await
+		}
+		.await;
+		let _ = tokio::fs::remove_file(&output).await;
+		match res {
+			Ok(()) => self.mark_task_complete(id.0, id.1).await,
+			Err(err) => {
+				eprintln!("{err}");
+				self.mark_task_failed(id.0, id.1, &err.to_string())
+					.await
+					.and(Err(err))
+			}
+		}
+	}
+	///Encode with the backend, adding the `extra` output arguments, then upload the output
+	async fn run_and_upload(
+		&self,
+		task: &Instance,
+		encoder: &dyn Encoder,
+		sources: Vec<Vec<String>>,
+		extra: Vec<String>,
+		stdin: Option<u32>,
+	) -> Result<(), ClientError> {
+		let id = (task.job_id, task.task_id);
+		let container = task.container();
+		let output = std::env::temp_dir().join(format!(
+			"segmented-output-{}{}.{}",
+			id.1,
+			file_name_suffix(&task.output),
+			container.extension()
+		));
+		let res = async {
+			self.encode(task, encoder, sources, extra, &output, stdin)
+				.await?;
+			if self.verify_output() {
+				let limits = self.limits().for_task(&task.resources);
+				if let Err(err) = ffmpeg_runner::verify(self.ffmpeg(), &output, &limits).await {
+					eprintln!("Output verification failed");
+					return Err(err);
+				}
+			}
+			self.probe_and_report(&output.to_string_lossy(), None, &[], id)
+				.await;
+			self.upload_output(&output, id).await
+		}
+		.await;
+		let _ = tokio::fs::remove_file(&output).await;
+		let res = match res {
+			Ok(()) => self.mark_task_complete(id.0, id.1).await,
+			Err(err) => {
+				eprintln!("{err}");
+				self.mark_task_failed(id.0, id.1, &err.to_string())
+					.await
+					.and(Err(err))
+			}
+		};
+		println!("Task result: {:?}", res);
+		res
+	}
+	///Report the metadata of the input to the server, failures only skip the report
+	async fn probe_and_report(
+		&self,
+		input: &str,
+		authorization: Option<&str>,
+		input_params: &[String],
+		id: (Uuid, Uuid),
+	) {
+		let source = ffprobe::Source {
+			url: input,
+			authorization,
+			input_params,
+		};
+		let metadata = match ffprobe::metadata(self.ffprobe(), &source).await {
+			Ok(metadata) => metadata,
+			Err(err) => {
+				eprintln!("Failed to probe metadata: {err}");
+				return;
+			}
+		};
+		if self.report_probe(id.0, id.1, metadata).await.is_err() {
+			eprintln!("Failed to report metadata");
+		}
+	}
+	///Encode with the backend, saving the output to the path
+	async fn encode(
+		&self,
+		task: &Instance,
+		encoder: &dyn Encoder,
+		sources: Vec<Vec<String>>,
+		extra: Vec<String>,
+		path: &Path,
+		stdin: Option<u32>,
+	) -> Result<(), ClientError> {
+		let id = (task.job_id, task.task_id);
+		let limits = self.limits().for_task(&task.resources);
+		let mut file = tokio::fs::File::create(path).await?;
+		let transcode = Transcode {
+			task,
+			sources,
+			extra,
+			stdin: self.stdin_input(id, stdin).await?,
+		};
+		let Encoding {
+			mut output,
+			exit,
+			progress,
+		} = encoder.spawn(self.ffmpeg(), transcode, &limits)?;
+		let (copied, _) = tokio::join!(
+			async {
+				tokio::io::copy(&mut output, &mut file).await?;
+				file.flush().await
+			},
+			forward_progress(self, progress, id)
+		);
+		exit.await?;
+		println!("Encode of task {} finished", id.1);
+		Ok(copied?)
+	}
+	///Run the first pass of a two-pass encode, only the passlog is kept
+	async fn first_pass(
+		&self,
+		args: Vec<String>,
+		task: &Instance,
+		stdin: Option<u32>,
+	) -> Result<(), ClientError> {
+		let args = args.into_iter().chain(["-an".to_string()]);
+		let input = self.stdin_input((task.job_id, task.task_id), stdin).await?;
+		let (mut pipe, out, _) = ffmpeg_runner::run_to_stream(
+			self.ffmpeg(),
+			args,
+			Container::default(),
+			&self.limits().for_task(&task.resources),
+			input,
+		)?;
+		let copied = tokio::io::copy(&mut pipe, &mut tokio::io::sink()).await;
+		let exit = out.await?;
+		println!("ffmpeg first pass returned: {}", exit.status);
+		exit.result()?;
+		copied?;
+		Ok(())
+	}
+	///Open the input streamed to ffmpeg stdin, if any
+	async fn stdin_input(
+		&self,
+		id: (Uuid, Uuid),
+		stdin: Option<u32>,
+	) -> Result<Option<Box<dyn AsyncRead + Unpin + Send>>, ClientError> {
+		let Some(idx) = stdin else {
+			return Ok(None);
+		};
+		match self.open_input(id.0, id.1, idx).await {
+			Ok(input) => Ok(Some(input)),
+			Err(err) => {
+				eprintln!("Failed to open input {idx}");
+				Err(err.into())
+			}
+		}
+	}
+	///Upload the output, retrying transient failures with exponential backoff
+	///
+	///Each attempt resumes from the last byte received by the server
+	async fn upload_output(&self, path: &Path, id: (Uuid, Uuid)) -> Result<(), ClientError> {
+		let size = tokio::fs::metadata(path).await?.len();
+		let file = tokio::fs::File::open(path).await?;
+		let digest = BASE64_STANDARD.encode(sha256_of(file).await?);
+		let mut delay = UPLOAD_RETRY_DELAY;
+		let start = Instant::now();
+		let mut attempt = 1;
+		loop {
+			match upload_attempt(self, path, size, &digest, id).await {
+				Ok(()) => {
+					if let Some(metrics) = self.metrics() {
+						metrics.uploaded(size, start.elapsed());
+					}
+					return Ok(());
+				}
+				Err(err) if !err.is_transient() || attempt == UPLOAD_ATTEMPTS => {
+					eprintln!("Upload failed: {err}");
+					return Err(err);
+				}
+				Err(err) => eprintln!("Upload attempt {attempt} failed: {err}"),
+			}
+			tokio::time::sleep(delay).await;
+			delay *= 2;
+			attempt += 1;
+		}
+	}
+	///ffmpeg source arguments reading the task inputs from the server
+	fn remote_sources(&self, task: &Instance) -> Vec<Vec<String>> {
+		(0..task.inputs.len() as u32)
+			.map(|idx| {
+				vec![
 					"-headers".to_string(),
 					format!("Authorization: {}", self.get_input_creds()),
 					"-i".to_string(),
-					self.get_input_url(task.job_id, task.task_id, input.index),
-				];
-				let start = input
-					.start
-					.map(|start| ["-ss".to_string(), start.to_string()]);
-				let end = input.end.map(|end| ["-to".to_string(), end.to_string()]);
-				let args: Vec<String> = start
-					.into_iter()
-					.flatten()
-					.chain(end.into_iter().flatten())
-					.chain(source.into_iter())
-					.collect();
-				args
+					self.get_input_url(task.job_id, task.task_id, idx),
+				]
 			})
-			.collect::<Vec<_>>();
-		let codec = [
-			"-c:v".to_string(),
-			task.job_options
-				.video
-				.codec
-				.expect("Should have a video codec"),
-		];
-		let params = task.job_options.video.params.into_iter();
-		let args = inputs.into_iter().chain(codec).chain(params);
-		let (pipe, out) = ffmpeg_runner::run_to_stream(args);
-		let upload_res = self.upload_stdout(pipe, (task.job_id, task.task_id)).await;
-		let status = out.await.expect("Failed to run ffmpeg").code().unwrap();
-		upload_res.unwrap();
-		println!("ffmpeg returned: {status}");
-		let res = self.mark_task_complete(task.job_id, task.task_id).await;
-		println!("Mark task complete: {:?}", res);
+			.collect()
+	}
+	///Print the commands the task would run and where the output would be uploaded
+	fn dry_run(&self, task: &Instance) -> Result<(), ClientError> {
+		let commands = match &task.recipe {
+			Recipe::Analysis(_) => {
+				let input = self.get_input_url(task.job_id, task.task_id, 0);
+				println!("ffprobe {} on {input}", self.ffprobe().display());
+				println!("Analysis adds tasks to the job, nothing is uploaded");
+				return Ok(());
+			}
+			Recipe::Transcode(_) => {
+				let args = transcode_args(task, self.remote_sources(task))?;
+				match task.job_options.two_pass {
+					true => ["1", "2"]
+						.map(|pass| pass_args(&args, pass, "<passlog>"))
+						.to_vec(),
+					false => vec![args],
+				}
+			}
+			Recipe::Merge(order) => {
+				let source = (task.inputs.len() > order.len())
+					.then(|| task.inputs.last().zip(self.remote_sources(task).pop()))
+					.flatten();
+				let list = Path::new("<downloaded inputs list>");
+				vec![concat_args(list, source, &task.job_options.input_params)]
+			}
+			Recipe::Thumbnail(thumbnail) => {
+				if let (Some(input), Some(source)) =
+					(task.inputs.first(), self.remote_sources(task).pop())
+				{
+					let duration = input.end.unwrap_or_default() - input.start.unwrap_or_default();
+					let input_params = &task.job_options.input_params;
+					let args = thumbnail_args(input, input_params, source, *thumbnail, duration);
+					println!("{} {args:?}", self.ffmpeg().display());
+				}
+				println!("Output: {}", self.get_upload_url(task.job_id, task.task_id));
+				return Ok(());
+			}
+		};
+		for args in commands {
+			let container = task.container();
+			let limits = self.limits().for_task(&task.resources);
+			println!(
+				"{:?}",
+				ffmpeg_runner::command(self.ffmpeg(), args, container, &limits)
+			);
+		}
+		println!("Output: {}", self.get_upload_url(task.job_id, task.task_id));
 		Ok(())
 	}
-
+	///Run the task, failing it if the timeout expires
+	///
+	///Dropping the task kills the running ffmpeg
 	async fn run(&self, task: Instance) {
-		let _ = match task.recipe.clone() {
-			Recipe::Analysis(analysis) => self.run_analysis(task, analysis).await,
-			Recipe::Transcode(extra_options) => self.run_transcode(task, extra_options).await,
-			Recipe::Merge(_) => unimplemented!("Merge task is not implemented"),
+		let id = (task.job_id, task.task_id);
+		let run = async {
+			let res = match task.recipe.clone() {
+				Recipe::Analysis(analysis) => self.run_analysis(task, analysis).await,
+				Recipe::Transcode(extra_options) => self.run_transcode(task, extra_options).await,
+				Recipe::Merge(order) => self.run_merge(task, order).await,
+				Recipe::Thumbnail(thumbnail) => self.run_thumbnail(task, thumbnail).await,
+			};
+			if let Err(err) = res {
+				eprintln!("Task {} failed: {err}", id.1);
+			}
 		};
+		let timeout = match self.task_timeout() {
+			Some(timeout) => timeout,
+			None => return run.await,
+		};
+		if tokio::time::timeout(timeout, run).await.is_err() {
+			eprintln!("Task {} timed out after {timeout:?}", id.1);
+			let reason = format!("Timed out after {} seconds", timeout.as_secs());
+			//A failed task keeps its allocation, it is only released if the failure was not reported
+			if let Err(err) = self.mark_task_failed(id.0, id.1, &reason).await {
+				eprintln!("Could not report the timeout of task {}: {err}", id.1);
+				let res = self.cancel_task(id.0, id.1).await;
+				println!("Released task {}: {res:?}", id.1);
+			}
+		}
+	}
+}
+
+///ffmpeg arguments to transcode the inputs, each read with the matching source arguments
+fn transcode_args(task: &Instance, sources: Vec<Vec<String>>) -> Result<Vec<String>, ClientError> {
+	let inputs = task.inputs.iter().zip(sources).flat_map(|(input, source)| {
+		let range = (!input.accurate_seek).then(|| range_args(input));
+		range
+			.into_iter()
+			.flatten()
+			.chain(task.job_options.input_params.iter().cloned())
+			.chain(source)
+	});
+	let maps = map_args(&task.inputs);
+	//Accurate seeking applies the range to the output, validated to have a single input
+	let accurate = task
+		.inputs
+		.iter()
+		.filter(|input| input.accurate_seek)
+		.flat_map(range_args);
+	let video = &task.job_options.video;
+	let codec = video
+		.codec
+		.clone()
+		.ok_or(ClientError::InvalidTask("Missing video codec".to_string()))?;
+	let codec = ["-c:v".to_string(), codec];
+	let audio = task
+		.job_options
+		.audio
+		.clone()
+		.map(audio_args)
+		.unwrap_or_default();
+	let rendition = task
+		.rendition()
+		.map(|rendition| rendition.params.clone())
+		.unwrap_or_default();
+	Ok(inputs
+		.chain(maps)
+		.chain(accurate)
+		.chain(codec)
+		.chain(video.params.iter().cloned())
+		.chain(rendition)
+		.chain(audio)
+		.collect())
+}
+
+///`-ss` and `-to` arguments of the input range
+fn range_args(input: &Input) -> Vec<String> {
+	let start = input
+		.start
+		.map(|start| ["-ss".to_string(), start.to_string()]);
+	let end = input.end.map(|end| ["-to".to_string(), end.to_string()]);
+	start
+		.into_iter()
+		.flatten()
+		.chain(end.into_iter().flatten())
+		.collect()
+}
+
+///Suffix of the output file name, keeping only characters safe in a file name
+fn file_name_suffix(output: &Output) -> String {
+	output
+		.suffix
+		.iter()
+		.flat_map(|suffix| suffix.chars())
+		.filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+		.collect()
+}
+
+///Map the selected streams, empty to let ffmpeg pick one stream of each type
+fn map_args(inputs: &[Input]) -> Vec<String> {
+	let selected = inputs
+		.iter()
+		.any(|input| input.video_stream.is_some() || input.audio_stream.is_some());
+	if !selected {
+		return Vec::new();
+	}
+	inputs
+		.iter()
+		.enumerate()
+		.flat_map(|(idx, input)| {
+			let video = format!("{idx}:v:{}", input.video_stream.unwrap_or(0));
+			let audio = match input.audio_stream {
+				Some(stream) => format!("{idx}:a:{stream}"),
+				None => format!("{idx}:a:0?"),
+			};
+			["-map".to_string(), video, "-map".to_string(), audio]
+		})
+		.collect()
+}
+
+///Arguments encoding a short generated clip with the job codecs and parameters
+fn validation_args(options: &JobOptions) -> Vec<String> {
+	let video = [
+		"-f",
+		"lavfi",
+		"-i",
+		"testsrc=size=320x240:rate=25:duration=0.2",
+	];
+	let audio_source = ["-f", "lavfi", "-i", "anullsrc=duration=0.2"];
+	let audio = options.audio.clone().map(audio_args);
+	let codec = ["-c:v".to_string()]
+		.into_iter()
+		.chain(options.video.codec.clone());
+	video
+		.into_iter()
+		.chain(
+			audio
+				.is_some()
+				.then_some(audio_source)
+				.into_iter()
+				.flatten(),
+		)
+		.map(String::from)
+		.chain(codec)
+		.chain(options.video.params.iter().cloned())
+		.chain(audio.into_iter().flatten())
+		.collect()
+}
+
+///Arguments for one pass of a two-pass encode
+fn pass_args(args: &[String], pass: &str, passlog: &str) -> Vec<String> {
+	args.iter()
+		.cloned()
+		.chain(["-pass", pass, "-passlogfile", passlog].map(String::from))
+		.collect()
+}
+
+///Concatenate the files in the list without reencoding
+///
+///The subtitles and attachments are copied from the source, when given, instead of the list.
+///The source is read in its input range, with the job input params
+fn concat_args(
+	list: &Path,
+	source: Option<(&Input, Vec<String>)>,
+	input_params: &[String],
+) -> Vec<String> {
+	let maps = match source {
+		Some(_) => [
+			"-map", "0:v", "-map", "0:a?", "-map", "1:s?", "-map", "1:t?",
+		]
+		.as_slice(),
+		None => &[],
+	};
+	let source = source.map(|(input, source)| {
+		range_args(input)
+			.into_iter()
+			.chain(input_params.iter().cloned())
+			.chain(source)
+	});
+	["-f", "concat", "-safe", "0", "-i"]
+		.into_iter()
+		.map(String::from)
+		.chain([list.to_string_lossy().to_string()])
+		.chain(source.into_iter().flatten())
+		.chain(maps.iter().map(|arg| arg.to_string()))
+		.chain(["-c".to_string(), "copy".to_string()])
+		.collect()
+}
+
+///Extract the thumbnail from the first video stream of the source
+///
+///The sprite sheet tiles frames evenly spaced over the duration of the input range
+fn thumbnail_args(
+	input: &Input,
+	input_params: &[String],
+	source: Vec<String>,
+	thumbnail: Thumbnail,
+	duration: f64,
+) -> Vec<String> {
+	let filter = match thumbnail {
+		Thumbnail::Poster => "scale=640:-2".to_string(),
+		Thumbnail::Sprite { columns, rows } => {
+			let fps = f64::from(columns * rows) / duration.max(1.0);
+			format!("fps={fps},scale=160:-2,tile={columns}x{rows}")
+		}
+	};
+	range_args(input)
+		.into_iter()
+		.chain(input_params.iter().cloned())
+		.chain(source)
+		.chain(["-map", "0:v:0", "-an", "-vf"].map(String::from))
+		.chain([filter])
+		.collect()
+}
+
+///Audio arguments, the `none` codec drops the audio
+fn audio_args(options: Options) -> Vec<String> {
+	match options.codec.as_deref() {
+		Some("none") => vec!["-an".to_string()],
+		codec => codec
+			.map(|codec| ["-c:a".to_string(), codec.to_string()])
+			.into_iter()
+			.flatten()
+			.chain(options.params)
+			.collect(),
+	}
+}
+
+///Send the bytes the server does not have yet, then finish the upload
+///
+///The upload is only finished after the server reports having all the bytes
+async fn upload_attempt<R: TaskRunner + ?Sized>(
+	runner: &R,
+	path: &Path,
+	size: u64,
+	digest: &str,
+	id: (Uuid, Uuid),
+) -> Result<(), ClientError> {
+	let offset = runner.upload_offset(id).await?;
+	if offset > size {
+		let message = format!("Server has {offset} of {size} bytes");
+		return Err(ClientError::status(StatusCode::CONFLICT, message));
+	}
+	if offset < size {
+		let (sender, receiver) = watch::channel(UploadProgress {
+			sent: offset,
+			total: size,
+		});
+		tokio::select! {
+			res = runner.upload_from(path, id, offset, sender) => res?,
+			stalled = watch_upload(runner, receiver, id) => return Err(stalled),
+		}
+		let received = runner.upload_offset(id).await?;
+		if received != size {
+			return Err(ClientError::Http {
+				status: None,
+				message: format!("Server received {received} of {size} bytes"),
+			});
+		}
+	}
+	runner.finish_upload(id, digest).await
+}
+
+///Report the upload progress, returns an error once no bytes were sent for the stall timeout
+///
+///Once every byte is sent the server response is left to the request timeout, the watch never
+///returns
+async fn watch_upload<R: TaskRunner + ?Sized>(
+	runner: &R,
+	progress: watch::Receiver<UploadProgress>,
+	id: (Uuid, Uuid),
+) -> ClientError {
+	let stall_timeout = runner.upload_stall_timeout();
+	let mut interval = tokio::time::interval(PROGRESS_INTERVAL.min(stall_timeout));
+	let mut sent = progress.borrow().sent;
+	let mut last_sent = Instant::now();
+	loop {
+		interval.tick().await;
+		let current = *progress.borrow();
+		if current.sent != sent {
+			sent = current.sent;
+			last_sent = Instant::now();
+			runner.upload_progressed(id, current);
+		}
+		if current.sent >= current.total {
+			return std::future::pending().await;
+		}
+		if last_sent.elapsed() >= stall_timeout {
+			return ClientError::Http {
+				status: None,
+				message: format!(
+					"Upload stalled at {} of {} bytes",
+					current.sent, current.total
+				),
+			};
+		}
+	}
+}
+
+///Sha-256 of the content
+async fn sha256_of(mut read: impl AsyncRead + Unpin) -> io::Result<[u8; 32]> {
+	let mut hasher = Sha256::new();
+	let mut buf = vec![0; 64 * 1024];
+	loop {
+		let len = read.read(&mut buf).await?;
+		if len == 0 {
+			break;
+		}
+		hasher.update(&buf[..len]);
+	}
+	Ok(hasher.finalize().into())
+}
+
+///Send the ffmpeg progress to the server, until ffmpeg exits
+async fn forward_progress<R: TaskRunner + ?Sized>(
+	runner: &R,
+	mut progress: watch::Receiver<Option<ffmpeg_runner::Progress>>,
+	id: (Uuid, Uuid),
+) {
+	let mut interval = tokio::time::interval(PROGRESS_INTERVAL);
+	loop {
+		interval.tick().await;
+		if progress.changed().await.is_err() {
+			return;
+		}
+		let current = *progress.borrow_and_update();
+		if let Some(current) = current {
+			if let (Some(metrics), Some(fps)) = (runner.metrics(), current.fps) {
+				metrics.encode_fps(fps);
+			}
+			let _ = runner
+				.report_progress(id.0, id.1, current.out_time, current.speed)
+				.await;
+		}
 	}
 }
 
-impl TaskRunner for Configuration {
+///Check that the binary runs, returning the first line of its version
+pub async fn binary_version(binary: &Path) -> io::Result<String> {
+	let output = tokio::process::Command::new(binary)
+		.arg("-version")
+		.stdin(Stdio::null())
+		.output()
+		.await?;
+	if !output.status.success() {
+		return Err(io::Error::other(format!(
+			"{} -version returned: {}",
+			binary.display(),
+			output.status
+		)));
+	}
+	let version = String::from_utf8_lossy(&output.stdout);
+	Ok(version.lines().next().unwrap_or_default().to_string())
+}
+
+///Line for the ffmpeg concat demuxer list
+fn concat_entry(path: &Path) -> String {
+	let path = path.to_string_lossy().replace('\'', "'\\''");
+	format!("file '{path}'\n")
+}
+
+///Runs the tasks using the server api and the configured ffmpeg binaries
+pub struct Worker {
+	pub config: Configuration,
+	pub ffmpeg: PathBuf,
+	pub ffprobe: PathBuf,
+	pub download_inputs: bool,
+	///Stream single inputs to ffmpeg stdin
+	pub pipe_inputs: bool,
+	///Limit for the inputs downloaded by the worker, in bytes per second
+	pub max_download_rate: Option<u64>,
+	///Limit for the output upload, in bytes per second
+	pub max_upload_rate: Option<u64>,
+	///Limit for running each task
+	pub task_timeout: Option<Duration>,
+	///Remux the output locally before uploading it
+	pub verify_output: bool,
+	pub limits: Limits,
+	///Hardware acceleration devices shared by the tasks
+	pub devices: Option<gpu::Devices>,
+	///Keep the downloaded job inputs for the next tasks of the job
+	pub source_cache: Option<cache::SourceCache>,
+	pub validations: cache::Validations,
+	pub metrics: Arc<metrics::Metrics>,
+	///Backends replacing ffmpeg, by video codec
+	pub encoders: BTreeMap<String, Box<dyn Encoder>>,
+}
+
+impl TaskRunner for Worker {
+	fn ffmpeg(&self) -> &Path {
+		&self.ffmpeg
+	}
+
+	fn ffprobe(&self) -> &Path {
+		&self.ffprobe
+	}
+
+	fn download_inputs(&self) -> bool {
+		self.download_inputs
+	}
+
+	fn pipe_inputs(&self) -> bool {
+		self.pipe_inputs
+	}
+
+	fn task_timeout(&self) -> Option<Duration> {
+		self.task_timeout
+	}
+
+	fn verify_output(&self) -> bool {
+		self.verify_output
+	}
+
+	fn limits(&self) -> Limits {
+		self.limits
+	}
+
+	fn devices(&self) -> Option<&gpu::Devices> {
+		self.devices.as_ref()
+	}
+
+	fn source_cache(&self) -> Option<&cache::SourceCache> {
+		self.source_cache.as_ref()
+	}
+
+	fn validations(&self) -> Option<&cache::Validations> {
+		Some(&self.validations)
+	}
+
+	fn encoder(&self, codec: &str) -> Option<&dyn Encoder> {
+		self.encoders.get(codec).map(|encoder| encoder.as_ref())
+	}
+
+	fn metrics(&self) -> Option<&metrics::Metrics> {
+		Some(&self.metrics)
+	}
+
 	fn get_input_url(&self, job: Uuid, task: Uuid, idx: u32) -> String {
-		format!("{}/job/{}/task/{}/input/{}", self.base_path, job, task, idx)
+		format!(
+			"{}/job/{}/task/{}/input/{}",
+			self.config.base_path, job, task, idx
+		)
 	}
 
 	fn get_output_url(&self, job: Uuid, task: Uuid) -> String {
-		format!("{}/job/{}/task/{}/output", self.base_path, job, task)
+		format!("{}/job/{}/task/{}/output", self.config.base_path, job, task)
 	}
 
 	fn get_input_creds(&self) -> String {
-		self.api_key
+		self.config
+			.api_key
 			.as_ref()
 			.map(|k| k.key.to_string())
 			.unwrap_or_default()
 	}
+}
 
-	async fn upload_stdout(&self, stdout: ChildStdout, id: (Uuid, Uuid)) -> io::Result<StatusCode> {
-		let stream = FramedRead::new(stdout, BytesCodec::new());
-		let body = Body::wrap_stream(stream);
-		self.client
-			.put(self.get_output_url(id.0, id.1))
-			.header(AUTHORIZATION.as_str(), self.get_output_creds())
-			.body(body)
-			.send()
-			.await
-			.map(|res| res.status())
-			.map_err(|e| io::Error::new(ErrorKind::Other, e))
-	}
-
-	async fn mark_task_complete(&self, job: Uuid, task: Uuid) -> Result<(), ()> {
-		let res = api::apis::worker_api::job_job_id_task_task_id_status_post(
-			self,
-			&job.to_string(),
-			&task.to_string(),
-			Some(Status::Finished.into()),
-		)
-		.await;
-		res.or(Err(()))
+#[cfg(test)]
+mod test {
+	use task::Resources;
+
+	use super::*;
+	use crate::transport::MemoryServer;
+
+	///Outputs the downloaded input reversed, without running ffmpeg
+	struct ReverseEncoder;
+
+	impl Encoder for ReverseEncoder {
+		fn spawn(
+			&self,
+			_ffmpeg: &Path,
+			transcode: Transcode,
+			_limits: &Limits,
+		) -> Result<Encoding, ClientError> {
+			let path = transcode.sources[0]
+				.last()
+				.expect("Source has the input path");
+			let mut content = std::fs::read(path)?;
+			content.reverse();
+			Ok(Encoding {
+				output: Box::new(io::Cursor::new(content)),
+				exit: Box::pin(async { Ok(()) }),
+				progress: watch::channel(None).1,
+			})
+		}
 	}
 
-	async fn add_task_to_job(&self, job: Uuid, task: TaskSource) -> Result<(), ()> {
-		let recipe = match task.recipe {
-			Recipe::Transcode(t) => Some(t),
-			_ => None,
+	struct FailingEncoder;
+
+	impl Encoder for FailingEncoder {
+		fn spawn(
+			&self,
+			_ffmpeg: &Path,
+			_transcode: Transcode,
+			_limits: &Limits,
+		) -> Result<Encoding, ClientError> {
+			let err = ClientError::Ffmpeg {
+				exit_code: Some(1),
+				stderr: "Invalid frame".to_string(),
+			};
+			Ok(Encoding {
+				output: Box::new(tokio::io::empty()),
+				exit: Box::pin(async { Err(err) }),
+				progress: watch::channel(None).1,
+			})
+		}
+	}
+
+	///Never finishes the encode
+	struct HangingEncoder;
+
+	impl Encoder for HangingEncoder {
+		fn spawn(
+			&self,
+			_ffmpeg: &Path,
+			_transcode: Transcode,
+			_limits: &Limits,
+		) -> Result<Encoding, ClientError> {
+			Ok(Encoding {
+				output: Box::new(tokio::io::empty()),
+				exit: Box::pin(std::future::pending()),
+				progress: watch::channel(None).1,
+			})
+		}
+	}
+
+	impl TaskRunner for MemoryServer {
+		fn ffmpeg(&self) -> &Path {
+			Path::new("ffmpeg-is-not-run")
+		}
+
+		fn ffprobe(&self) -> &Path {
+			Path::new("ffprobe-is-not-run")
 		}
-		.ok_or(())?;
-		let parsed = api::models::TaskRequest {
-			inputs: vec![Input::source().into()],
-			recipe: Box::new(api::models::TaskRequestRecipe::TranscodeTask(Box::new(
-				api::models::TranscodeTask {
-					options: recipe.into(),
+
+		fn download_inputs(&self) -> bool {
+			true
+		}
+
+		fn task_timeout(&self) -> Option<Duration> {
+			self.task_timeout
+		}
+
+		fn upload_stall_timeout(&self) -> Duration {
+			self.upload_stall_timeout.unwrap_or(UPLOAD_STALL_TIMEOUT)
+		}
+
+		fn validations(&self) -> Option<&cache::Validations> {
+			Some(&self.validations)
+		}
+
+		fn encoder(&self, codec: &str) -> Option<&dyn Encoder> {
+			match codec {
+				"reverse" => Some(&ReverseEncoder),
+				"failing" => Some(&FailingEncoder),
+				"hanging" => Some(&HangingEncoder),
+				_ => None,
+			}
+		}
+
+		fn get_input_url(&self, job: Uuid, task: Uuid, idx: u32) -> String {
+			format!("memory://job/{job}/task/{task}/input/{idx}")
+		}
+
+		fn get_output_url(&self, job: Uuid, task: Uuid) -> String {
+			format!("memory://job/{job}/task/{task}/output")
+		}
+
+		fn get_input_creds(&self) -> String {
+			String::new()
+		}
+	}
+
+	fn transcode_task(codec: &str) -> Instance {
+		Instance {
+			job_id: Uuid::new_v4(),
+			task_id: Uuid::new_v4(),
+			inputs: vec![Input {
+				index: 0,
+				start: None,
+				end: None,
+				..Default::default()
+			}],
+			recipe: Recipe::Transcode(Vec::new()),
+			output: Output::default(),
+			resources: Resources::default(),
+			job_options: JobOptions {
+				video: Options {
+					codec: Some(codec.to_string()),
+					params: Vec::new(),
 				},
-			))),
+				audio: None,
+				two_pass: false,
+				container: Container::default(),
+				renditions: Vec::new(),
+				..Default::default()
+			},
+		}
+	}
+
+	#[tokio::test]
+	async fn transcode_uploads_the_encoded_input() {
+		let server = MemoryServer {
+			inputs: vec![b"segment".to_vec()],
+			..Default::default()
+		};
+		let res = server
+			.run_transcode(transcode_task("reverse"), Vec::new())
+			.await;
+		assert!(res.is_ok());
+		assert_eq!(*server.output.lock().unwrap(), Some(b"tnemges".to_vec()));
+		let statuses = server.statuses.lock().unwrap();
+		assert!(matches!(statuses.as_slice(), [Status::Finished]))
+	}
+
+	#[tokio::test]
+	async fn failed_encode_fails_the_task_with_the_log() {
+		let server = MemoryServer {
+			inputs: vec![b"segment".to_vec()],
+			..Default::default()
+		};
+		let res = server
+			.run_transcode(transcode_task("failing"), Vec::new())
+			.await;
+		assert!(matches!(res, Err(ClientError::Ffmpeg { .. })));
+		assert_eq!(*server.output.lock().unwrap(), None);
+		let statuses = server.statuses.lock().unwrap();
+		assert!(matches!(
+			statuses.as_slice(),
+			[Status::Failed(Some(reason))] if reason.contains("Invalid frame")
+		))
+	}
+
+	#[tokio::test]
+	async fn timed_out_task_is_failed_not_released() {
+		let server = MemoryServer {
+			inputs: vec![b"segment".to_vec()],
+			task_timeout: Some(Duration::from_millis(10)),
+			..Default::default()
+		};
+		server.run(transcode_task("hanging")).await;
+		let statuses = server.statuses.lock().unwrap();
+		assert!(matches!(
+			statuses.as_slice(),
+			[Status::Failed(Some(reason))] if reason.starts_with("Timed out")
+		));
+		assert!(server.released.lock().unwrap().is_empty())
+	}
+
+	#[tokio::test]
+	async fn missing_input_fails_the_task() {
+		let server = MemoryServer::default();
+		let res = server
+			.run_transcode(transcode_task("reverse"), Vec::new())
+			.await;
+		assert!(matches!(res, Err(ClientError::Io(_))));
+		let statuses = server.statuses.lock().unwrap();
+		assert!(matches!(statuses.as_slice(), [Status::Failed(Some(_))]))
+	}
+
+	#[tokio::test]
+	async fn invalid_options_are_not_validated_again() {
+		let server = MemoryServer {
+			inputs: vec![b"segment".to_vec()],
+			..Default::default()
+		};
+		let task = transcode_task("reverse");
+		let args = validation_args(&task.job_options);
+		let reason = "Unknown encoder 'reverse'".to_string();
+		server.validations.insert(args, false, Err(reason));
+		let res = server.run_transcode(task, Vec::new()).await;
+		assert!(matches!(res, Err(ClientError::InvalidTask(_))));
+		assert_eq!(*server.output.lock().unwrap(), None);
+	}
+
+	#[tokio::test]
+	async fn two_pass_needs_the_ffmpeg_encoder() {
+		let server = MemoryServer::default();
+		let mut task = transcode_task("reverse");
+		task.job_options.two_pass = true;
+		let res = server.run_transcode(task, Vec::new()).await;
+		assert!(matches!(res, Err(ClientError::InvalidTask(_))));
+		assert_eq!(*server.output.lock().unwrap(), None);
+		let args = validation_args(&transcode_task("reverse").job_options);
+		let cached = server.validations.get(&args, true);
+		assert!(matches!(cached, Some(Err(_))))
+	}
+
+	async fn upload_file(content: &[u8]) -> PathBuf {
+		let path = std::env::temp_dir().join(format!("segmented-upload-{}", Uuid::new_v4()));
+		tokio::fs::write(&path, content).await.unwrap();
+		path
+	}
+
+	#[tokio::test]
+	async fn upload_waiting_for_the_response_is_not_stalled() {
+		let server = MemoryServer {
+			upload_stall_timeout: Some(Duration::from_millis(20)),
+			upload_delay: Some(Duration::from_millis(200)),
+			..Default::default()
+		};
+		let path = upload_file(b"segment").await;
+		let res = server
+			.upload_output(&path, (Uuid::new_v4(), Uuid::new_v4()))
+			.await;
+		tokio::fs::remove_file(&path).await.unwrap();
+		assert!(res.is_ok(), "{res:?}");
+		assert_eq!(*server.output.lock().unwrap(), Some(b"segment".to_vec()));
+	}
+
+	#[tokio::test]
+	async fn upload_without_bytes_sent_stalls() {
+		let server = MemoryServer {
+			upload_stall_timeout: Some(Duration::from_millis(20)),
+			upload_hangs: true,
+			..Default::default()
+		};
+		let path = upload_file(b"segment").await;
+		let id = (Uuid::new_v4(), Uuid::new_v4());
+		let res = upload_attempt(&server, &path, 7, "digest", id).await;
+		tokio::fs::remove_file(&path).await.unwrap();
+		assert!(
+			matches!(res, Err(ClientError::Http { message, .. }) if message.contains("stalled"))
+		)
+	}
+
+	#[test]
+	fn transcode_without_video_codec_is_invalid() {
+		let mut task = transcode_task("libx264");
+		task.job_options.video.codec = None;
+		let res = transcode_args(&task, vec![Vec::new()]);
+		assert!(matches!(res, Err(ClientError::InvalidTask(_))))
+	}
+
+	#[test]
+	fn map_args_empty_without_selected_streams() {
+		assert!(map_args(&[Input::source(), Input::source()]).is_empty())
+	}
+
+	#[test]
+	fn map_args_maps_the_selected_streams_of_each_input() {
+		let selected = Input {
+			video_stream: Some(1),
+			audio_stream: Some(2),
+			..Input::source()
 		};
-		api::apis::worker_api::job_job_id_task_post(self, &job.to_string(), Some(parsed))
-			.await
-			.or(Err(()))
-			.and(Ok(()))
+		let args = map_args(&[selected, Input::source()]);
+		let expected = ["0:v:1", "0:a:2", "1:v:0", "1:a:0?"]
+			.into_iter()
+			.flat_map(|stream| ["-map", stream]);
+		assert!(args.iter().eq(expected))
+	}
+
+	#[test]
+	fn transcode_maps_the_selected_audio_stream() {
+		let mut task = transcode_task("libx264");
+		task.inputs[0].audio_stream = Some(1);
+		let args = transcode_args(&task, vec![Vec::new()]).unwrap();
+		let map = args.windows(2).any(|pair| pair == ["-map", "0:a:1"]);
+		assert!(map, "{args:?}")
+	}
+
+	#[test]
+	fn concat_args_read_the_source_with_the_input_params() {
+		let input_params = vec!["-analyzeduration".to_string(), "100M".to_string()];
+		let source = vec!["-i".to_string(), "source".to_string()];
+		let input = Input::source();
+		let args = concat_args(Path::new("list.txt"), Some((&input, source)), &input_params);
+		let expected = ["list.txt", "-analyzeduration", "100M", "-i", "source"];
+		assert!(args.windows(5).any(|window| window == expected), "{args:?}")
+	}
+
+	#[test]
+	fn concat_args_read_the_source_in_its_range() {
+		let source = vec!["-i".to_string(), "source".to_string()];
+		let input = Input {
+			start: Some(30.0),
+			end: Some(90.0),
+			..Input::source()
+		};
+		let args = concat_args(Path::new("list.txt"), Some((&input, source)), &[]);
+		let expected = ["list.txt", "-ss", "30", "-to", "90", "-i", "source"];
+		assert!(args.windows(7).any(|window| window == expected), "{args:?}")
+	}
+
+	#[test]
+	fn thumbnail_args_read_the_source_with_the_input_params() {
+		let input_params = vec!["-hwaccel".to_string(), "auto".to_string()];
+		let source = vec!["-i".to_string(), "source".to_string()];
+		let args = thumbnail_args(
+			&Input::source(),
+			&input_params,
+			source,
+			Thumbnail::Poster,
+			0.0,
+		);
+		assert_eq!(args[..4], ["-hwaccel", "auto", "-i", "source"])
 	}
 }
@@ -0,0 +1,175 @@
+use std::sync::Mutex;
+
+use tokio::process::Command;
+
+use task::Resources;
+
+///Resources used by each ffmpeg process, so the worker can share the machine with other workloads
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Limits {
+	///Threads used by ffmpeg, takes precedence over the cpu limit
+	pub threads: Option<u32>,
+	///Niceness of the ffmpeg processes
+	pub nice: Option<i32>,
+	///CPUs available to each ffmpeg process, rounded up to a whole number of threads
+	pub cpus: Option<f64>,
+}
+
+impl Limits {
+	///Split the CPUs available to the worker between its concurrent tasks
+	pub fn per_task(threads: Option<u32>, nice: Option<i32>, cpus: Option<f64>, jobs: u32) -> Self {
+		Self {
+			threads,
+			nice,
+			cpus: cpus.map(|cpus| cpus / jobs.max(1) as f64),
+		}
+	}
+
+	///Use the CPUs hinted by the task, the configured threads still take precedence
+	pub fn for_task(&self, hint: &Resources) -> Self {
+		Self {
+			cpus: hint.cpus.or(self.cpus),
+			..*self
+		}
+	}
+
+	fn thread_count(&self) -> Option<u32> {
+		self.threads
+			.or_else(|| self.cpus.map(|cpus| cpus.ceil().max(1.0) as u32))
+	}
+
+	///ffmpeg output arguments limiting the threads
+	pub fn args(&self) -> Vec<String> {
+		match self.thread_count() {
+			Some(threads) => vec!["-threads".to_string(), threads.to_string()],
+			None => Vec::new(),
+		}
+	}
+
+	///Lower the priority of the process when it is started
+	pub(crate) fn apply(&self, command: &mut Command) {
+		#[cfg(unix)]
+		if let Some(nice) = self.nice {
+			//SAFETY: setpriority is async-signal-safe, so it can run between fork and exec
+			unsafe {
+				command.pre_exec(
+					move || match libc::setpriority(libc::PRIO_PROCESS, 0, nice) {
+						0 => Ok(()),
+						_ => Err(std::io::Error::last_os_error()),
+					},
+				);
+			}
+		}
+		#[cfg(not(unix))]
+		let _ = command;
+	}
+}
+
+///Resources of the worker, shared by its concurrent tasks so it only requests the tasks that fit
+#[derive(Debug, Default)]
+pub struct Budget {
+	total: Resources,
+	used: Mutex<Used>,
+}
+
+#[derive(Debug, Default)]
+struct Used {
+	cpus: f64,
+	memory: u64,
+	gpus: u32,
+}
+
+impl Budget {
+	///Unset totals are not limited
+	pub fn new(total: Resources) -> Self {
+		Self {
+			total,
+			used: Default::default(),
+		}
+	}
+
+	///Capacity not reserved by the running tasks
+	pub fn free(&self) -> Resources {
+		let used = self.used.lock().unwrap();
+		Resources {
+			cpus: self.total.cpus.map(|cpus| (cpus - used.cpus).max(0.0)),
+			memory: self
+				.total
+				.memory
+				.map(|memory| memory.saturating_sub(used.memory)),
+			gpus: self.total.gpus.map(|gpus| gpus.saturating_sub(used.gpus)),
+		}
+	}
+
+	///Reserve the resources hinted by a task, until they are released
+	pub fn reserve(&self, hint: &Resources) {
+		let mut used = self.used.lock().unwrap();
+		used.cpus += hint.cpus.unwrap_or_default();
+		used.memory += hint.memory.unwrap_or_default();
+		used.gpus += hint.gpus.unwrap_or_default();
+	}
+
+	pub fn release(&self, hint: &Resources) {
+		let mut used = self.used.lock().unwrap();
+		used.cpus = (used.cpus - hint.cpus.unwrap_or_default()).max(0.0);
+		used.memory = used.memory.saturating_sub(hint.memory.unwrap_or_default());
+		used.gpus = used.gpus.saturating_sub(hint.gpus.unwrap_or_default());
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn no_limits_no_args() {
+		assert!(Limits::default().args().is_empty())
+	}
+
+	#[test]
+	fn cpus_split_between_jobs_rounded_up() {
+		let limits = Limits::per_task(None, None, Some(5.0), 2);
+		assert_eq!(limits.args(), ["-threads", "3"])
+	}
+
+	#[test]
+	fn task_cpus_replace_the_split_cpus() {
+		let limits = Limits::per_task(None, None, Some(8.0), 4);
+		let hint = Resources {
+			cpus: Some(4.0),
+			..Default::default()
+		};
+		assert_eq!(limits.for_task(&hint).args(), ["-threads", "4"]);
+		assert_eq!(
+			limits.for_task(&Resources::default()).args(),
+			["-threads", "2"]
+		)
+	}
+
+	#[test]
+	fn budget_free_excludes_reserved() {
+		let budget = Budget::new(Resources {
+			cpus: Some(8.0),
+			memory: None,
+			gpus: Some(1),
+		});
+		let hint = Resources {
+			cpus: Some(6.0),
+			memory: Some(1 << 30),
+			gpus: Some(1),
+		};
+		budget.reserve(&hint);
+		let free = budget.free();
+		assert_eq!(free.cpus, Some(2.0));
+		assert_eq!(free.memory, None);
+		assert_eq!(free.gpus, Some(0));
+		budget.release(&hint);
+		assert_eq!(budget.free().cpus, Some(8.0))
+	}
+
+	#[test]
+	fn threads_take_precedence_over_cpus() {
+		let limits = Limits::per_task(Some(8), None, Some(2.0), 1);
+		assert_eq!(limits.args(), ["-threads", "8"])
+	}
+}
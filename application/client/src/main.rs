@@ -1,76 +1,339 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 
 use clap::Parser;
+use tokio_util::sync::CancellationToken;
 
-use api::apis::configuration::ApiKey;
-use api::apis::Error;
-use client::TaskRunner;
-use task::Instance;
+use client::builder::ClientBuilder;
+use client::limits::Budget;
+use client::state::InFlight;
+use client::transport::Transport;
+use client::work::WorkOptions;
+use client::{TaskRunner, Worker};
+use task::{Instance, Resources};
 
 #[derive(Parser, Debug)]
 #[command()]
 struct Args {
-	///Server api base url
-	#[arg(short, long, default_value = "http://localhost:8888/api")]
-	server: String,
+	///Toml configuration file, the arguments take precedence over it
+	#[arg(long, env = "CLIENT_CONFIG")]
+	config: Option<PathBuf>,
+	///Server api base url [default: http://localhost:8888/api]
+	#[arg(short, long)]
+	server: Option<String>,
 	///Password to register worker with server
 	#[arg(long, env = "CLIENT_PASSWORD")]
-	password: String,
+	password: Option<String>,
+	///Name identifying the worker on the server, kept across logins [default: the host name]
+	#[arg(long, env = "CLIENT_NAME")]
+	name: Option<String>,
+	///ffmpeg binary used to encode [default: ffmpeg]
+	#[arg(long, env = "CLIENT_FFMPEG")]
+	ffmpeg: Option<PathBuf>,
+	///ffprobe binary used to analyse the inputs [default: ffprobe]
+	#[arg(long, env = "CLIENT_FFPROBE")]
+	ffprobe: Option<PathBuf>,
+	///Number of tasks to run concurrently [default: one per device, or 1]
+	#[arg(short, long, env = "CLIENT_JOBS", value_parser = clap::value_parser!(u32).range(1..))]
+	jobs: Option<u32>,
+	///Directory for the cached job inputs [default: a new directory in the system temporary one]
+	#[arg(long, env = "CLIENT_CACHE_DIR")]
+	cache_dir: Option<PathBuf>,
+	///Retry connecting to the server forever, instead of exiting when it is unavailable
+	#[arg(long, env = "CLIENT_DAEMON")]
+	daemon: bool,
+	///Seconds to wait for running tasks on shutdown, before killing them and releasing their allocation
+	#[arg(long, env = "CLIENT_SHUTDOWN_TIMEOUT", default_value_t = 60)]
+	shutdown_timeout: u64,
+	///Download the inputs to temporary files before transcoding, verifying their checksum
+	#[arg(long, env = "CLIENT_DOWNLOAD_INPUTS")]
+	download_inputs: bool,
+	///Keep the downloaded job inputs for the next tasks of the same job, up to this many bytes
+	#[arg(long, env = "CLIENT_SOURCE_CACHE_SIZE", requires = "download_inputs")]
+	source_cache_size: Option<u64>,
+	///Stream the input to ffmpeg stdin, instead of passing the url and token in its arguments
+	///
+	///Tasks with several inputs are downloaded to temporary files, ffprobe still reads from the server
+	#[arg(long, env = "CLIENT_PIPE_INPUTS")]
+	pipe_inputs: bool,
+	///Maximum download rate in bytes per second, ffmpeg reading the inputs from the server is not limited
+	#[arg(long, env = "CLIENT_MAX_DOWNLOAD_RATE", value_parser = clap::value_parser!(u64).range(1..))]
+	max_download_rate: Option<u64>,
+	///Maximum upload rate in bytes per second
+	#[arg(long, env = "CLIENT_MAX_UPLOAD_RATE", value_parser = clap::value_parser!(u64).range(1..))]
+	max_upload_rate: Option<u64>,
+	///Seconds a task may run before ffmpeg is killed, the task failed and its allocation released
+	#[arg(long, env = "CLIENT_TASK_TIMEOUT", value_parser = clap::value_parser!(u64).range(1..))]
+	task_timeout: Option<u64>,
+	///Remux each output locally before uploading it, failing the task if it is truncated or corrupted
+	#[arg(long, env = "CLIENT_VERIFY_OUTPUT")]
+	verify_output: bool,
+	///Threads used by each ffmpeg process, overrides the ones derived from --cpus
+	#[arg(long, env = "CLIENT_THREADS", value_parser = clap::value_parser!(u32).range(1..))]
+	threads: Option<u32>,
+	///Niceness of the ffmpeg processes, from -20 to 19
+	#[arg(long, env = "CLIENT_NICE", allow_negative_numbers = true, value_parser = clap::value_parser!(i32).range(-20..=19))]
+	nice: Option<i32>,
+	///CPUs the worker may use, such as its cgroup quota, split between the concurrent tasks
+	#[arg(long, env = "CLIENT_CPUS")]
+	cpus: Option<f64>,
+	///Memory the tasks may use, in bytes, tasks hinting more than the free memory are not requested
+	#[arg(long, env = "CLIENT_MEMORY")]
+	memory: Option<u64>,
+	///GPU index to decode the inputs on, each task uses a single device at a time
+	#[arg(long, env = "CLIENT_GPU_INDEX", value_delimiter = ',')]
+	gpu_index: Vec<u32>,
+	///Hardware acceleration device to decode the inputs on, such as a render node
+	#[arg(long, env = "CLIENT_HWACCEL_DEVICE", value_delimiter = ',')]
+	hwaccel_device: Vec<String>,
+	///PEM certificate trusted to verify the server, in addition to the system ones
+	///
+	///ffmpeg and ffprobe reading from the server use their own TLS settings
+	#[arg(long, env = "CLIENT_CA_CERT")]
+	ca_cert: Option<PathBuf>,
+	///Accept any server certificate, only intended for development
+	#[arg(long)]
+	insecure_skip_verify: bool,
+	///PEM certificate presented to the server, for mutual TLS
+	#[arg(long, env = "CLIENT_CERT", requires = "client_key")]
+	client_cert: Option<PathBuf>,
+	///PKCS#8 PEM private key of the client certificate
+	#[arg(long, env = "CLIENT_KEY", requires = "client_cert")]
+	client_key: Option<PathBuf>,
+	///File listing the allocated tasks, the tasks left by a previous run are released on start
+	#[arg(long, env = "CLIENT_STATE_FILE")]
+	state_file: Option<PathBuf>,
+	///Address to serve the worker metrics on, in the prometheus text format
+	#[arg(long, env = "CLIENT_METRICS_ADDRESS")]
+	metrics_address: Option<SocketAddr>,
+	///Print the commands of the task described by the json file, `-` for stdin, instead of running
+	///tasks. No task is allocated
+	#[arg(long, value_name = "TASK")]
+	dry_run: Option<PathBuf>,
 }
 
-async fn run_task(config: &api::apis::configuration::Configuration, task: Instance) {
-	println!("Task: {:#?}", task);
-	config.run(task).await;
+///Read a task description, in the allocation response format
+fn read_task(path: &Path) -> Result<Instance, String> {
+	let json = match path.to_str() {
+		Some("-") => std::io::read_to_string(std::io::stdin()),
+		_ => std::fs::read_to_string(path),
+	}
+	.map_err(|err| err.to_string())?;
+	let task: api::models::Task = serde_json::from_str(&json).map_err(|err| err.to_string())?;
+	Instance::try_from(task).or(Err("Unsupported task".to_string()))
 }
 
-async fn work_loop(config: &api::apis::configuration::Configuration) -> bool {
-	println!("Requesting task...");
-	let api_task = api::apis::worker_api::allocate_task_get(config).await;
-	match api_task {
-		Err(Error::ResponseError(e)) => {
-			if 503 == e.status.as_u16() {
-				println!("No tasks available");
-				tokio::time::sleep(Duration::from_secs(5)).await;
-				true
-			} else {
-				eprintln!("Unexpected error: {:?}", e);
-				false
-			}
-		}
-		Ok(api_task) => {
-			match Instance::try_from(api_task) {
-				Ok(task) => run_task(config, task).await,
-				Err(e) => eprintln!("Failed to parse task: {e:?}"),
-			}
-			true
-		}
-		Err(e) => {
-			eprintln!("Could not finish request: {:?}", e);
-			false
+///Release the tasks left allocated by a previous run
+async fn release_previous(worker: &Worker, path: &Path) {
+	let previous = match client::state::read(path) {
+		Ok(previous) => previous,
+		Err(err) => {
+			eprintln!("Could not read the state file: {err}");
+			return;
 		}
+	};
+	for (job_id, task_id) in previous {
+		let res = worker.cancel_task(job_id, task_id).await;
+		println!("Released task {task_id} from the previous run: {res:?}");
 	}
 }
 
+///Print the commands of the described task, exits with an error if they can not be built
+fn dry_run(worker: &Worker, description: &Path) {
+	let res = read_task(description)
+		.map_err(|err| format!("Invalid task description: {err}"))
+		.and_then(|task| worker.dry_run(&task).map_err(|err| err.to_string()));
+	if let Err(err) = res {
+		eprintln!("{err}");
+		std::process::exit(1);
+	}
+}
+
+///Wait for SIGINT or SIGTERM
+async fn shutdown_signal() {
+	let ctrl_c = tokio::signal::ctrl_c();
+	#[cfg(unix)]
+	let terminate = async {
+		tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+			.expect("failed to install SIGTERM handler")
+			.recv()
+			.await
+	};
+	#[cfg(not(unix))]
+	let terminate = std::future::pending::<Option<()>>();
+	tokio::select! {
+		_ = ctrl_c => {}
+		_ = terminate => {}
+	}
+}
+
+///Host name of the machine, to name the worker
+#[cfg(unix)]
+fn host_name() -> Option<String> {
+	let mut buf = [0u8; 256];
+	//SAFETY: the buffer length is passed, gethostname writes at most that many bytes
+	let res = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) };
+	if res != 0 {
+		return None;
+	}
+	let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+	String::from_utf8(buf[..len].to_vec())
+		.ok()
+		.filter(|name| !name.is_empty())
+}
+
+#[cfg(not(unix))]
+fn host_name() -> Option<String> {
+	std::env::var("COMPUTERNAME").ok()
+}
+
+///Http client options with the TLS settings
+fn http_client(args: &Args) -> Result<reqwest::ClientBuilder, String> {
+	let read = |path: &Path| {
+		std::fs::read(path).map_err(|err| format!("Could not read {}: {err}", path.display()))
+	};
+	let mut builder = reqwest::Client::builder();
+	if let Some(path) = &args.ca_cert {
+		let cert = reqwest::Certificate::from_pem(&read(path)?)
+			.map_err(|err| format!("Invalid CA certificate: {err}"))?;
+		builder = builder.add_root_certificate(cert);
+	}
+	if let (Some(cert), Some(key)) = (&args.client_cert, &args.client_key) {
+		let identity = reqwest::Identity::from_pkcs8_pem(&read(cert)?, &read(key)?)
+			.map_err(|err| format!("Invalid client certificate: {err}"))?;
+		builder = builder.identity(identity);
+	}
+	if args.insecure_skip_verify {
+		eprintln!("Server certificates are not verified");
+		builder = builder.danger_accept_invalid_certs(true);
+	}
+	Ok(builder)
+}
+
 #[tokio::main]
 async fn main() {
 	let args = Args::parse();
-	let base = args
+	let http = http_client(&args).unwrap_or_else(|err| {
+		eprintln!("{err}");
+		std::process::exit(1);
+	});
+	let file = args
+		.config
+		.as_deref()
+		.map(client::config::Config::from_file);
+	let file = match file {
+		Some(Ok(file)) => file,
+		Some(Err(err)) => {
+			eprintln!("Failed to read the config file: {err}");
+			std::process::exit(2);
+		}
+		None => Default::default(),
+	};
+	let server = args
 		.server
-		.parse::<reqwest::Url>()
-		.expect("Should be valid uri");
-	let mut config = api::apis::configuration::Configuration {
-		base_path: args.server,
-		..Default::default()
+		.or(file.server)
+		.unwrap_or("http://localhost:8888/api".to_string());
+	let Some(password) = args.password.or(file.password) else {
+		eprintln!("A password is required, on the arguments or the config file");
+		std::process::exit(2);
 	};
-	let server_version = api::apis::default_api::version_get(&config).await.unwrap();
-	println!("Server: {}, version {:?}", base, server_version);
-	let token = api::apis::default_api::login_get(&config, &args.password)
-		.await
-		.unwrap();
-	println!("Login successful, token: {token}");
-	config.api_key = Some(ApiKey {
-		key: token,
-		prefix: None,
+	let name = args.name.or(file.name).or_else(host_name);
+	let ffmpeg = args.ffmpeg.or(file.ffmpeg).unwrap_or("ffmpeg".into());
+	let ffprobe = args.ffprobe.or(file.ffprobe).unwrap_or("ffprobe".into());
+	let devices: Vec<String> = (args.gpu_index.iter().map(u32::to_string))
+		.chain(args.hwaccel_device)
+		.collect();
+	let jobs = args
+		.jobs
+		.or(file.jobs)
+		.unwrap_or(devices.len() as u32)
+		.max(1);
+	let budget = Budget::new(Resources {
+		cpus: args.cpus,
+		memory: args.memory,
+		gpus: (!devices.is_empty()).then_some(devices.len() as u32),
+	});
+	let cache_dir = args.cache_dir.or(file.cache_dir).unwrap_or_else(|| {
+		std::env::temp_dir().join(format!("segmented-source-cache-{}", std::process::id()))
+	});
+	let mut builder = ClientBuilder::new(server)
+		.password(password)
+		.ffmpeg(ffmpeg)
+		.ffprobe(ffprobe)
+		.concurrency(jobs)
+		.devices(devices)
+		.download_inputs(args.download_inputs)
+		.pipe_inputs(args.pipe_inputs)
+		.verify_output(args.verify_output)
+		.retry_login(args.daemon)
+		.http(http);
+	if let Some(name) = name {
+		builder = builder.name(name);
+	}
+	if let Some(cpus) = args.cpus {
+		builder = builder.cpus(cpus);
+	}
+	if let Some(threads) = args.threads {
+		builder = builder.threads(threads);
+	}
+	if let Some(nice) = args.nice {
+		builder = builder.nice(nice);
+	}
+	if let Some(capacity) = args.source_cache_size {
+		builder = builder.source_cache(cache_dir, capacity);
+	}
+	if let Some(rate) = args.max_download_rate {
+		builder = builder.max_download_rate(rate);
+	}
+	if let Some(rate) = args.max_upload_rate {
+		builder = builder.max_upload_rate(rate);
+	}
+	if let Some(timeout) = args.task_timeout {
+		builder = builder.task_timeout(Duration::from_secs(timeout));
+	}
+	for (codec, encoder) in file.encoders {
+		builder = builder.encoder(codec, encoder);
+	}
+	let worker = builder.build().await.unwrap_or_else(|err| {
+		eprintln!("{err}");
+		std::process::exit(1);
 	});
-	while work_loop(&config).await {}
+	println!("Login successful");
+	if let Some(description) = &args.dry_run {
+		dry_run(&worker, description);
+		return;
+	}
+	if let Some(address) = args.metrics_address {
+		let listener = match tokio::net::TcpListener::bind(address).await {
+			Ok(listener) => listener,
+			Err(err) => {
+				eprintln!("Could not listen for metrics on {address}: {err}");
+				std::process::exit(1);
+			}
+		};
+		println!("Metrics on http://{address}/metrics");
+		tokio::spawn(client::metrics::serve(listener, worker.metrics.clone()));
+	}
+	if let Some(path) = &args.state_file {
+		release_previous(&worker, path).await;
+	}
+	let shutdown = CancellationToken::new();
+	tokio::spawn({
+		let shutdown = shutdown.clone();
+		async move {
+			shutdown_signal().await;
+			println!("Shutting down, no new tasks will be requested");
+			shutdown.cancel();
+		}
+	});
+	let options = WorkOptions {
+		jobs,
+		budget,
+		daemon: args.daemon,
+		in_flight: InFlight::new(args.state_file),
+		shutdown_timeout: Duration::from_secs(args.shutdown_timeout),
+	};
+	Arc::new(worker).run_forever(options, shutdown).await;
 }
@@ -0,0 +1,155 @@
+use std::fmt::Write;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+///Bytes of the request read before answering
+const MAX_REQUEST: usize = 8 * 1024;
+
+///Counters of the work done by the worker, exposed in the prometheus text format
+#[derive(Default)]
+pub struct Metrics {
+	completed: AtomicU64,
+	failed: AtomicU64,
+	///Last encoding frame rate reported by ffmpeg, as the f64 bits
+	fps: AtomicU64,
+	uploaded_bytes: AtomicU64,
+	///Time spent uploading, in milliseconds
+	upload_millis: AtomicU64,
+}
+
+impl Metrics {
+	pub fn task_completed(&self) {
+		self.completed.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn task_failed(&self) {
+		self.failed.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn encode_fps(&self, fps: f64) {
+		self.fps.store(fps.to_bits(), Ordering::Relaxed);
+	}
+
+	pub fn uploaded(&self, bytes: u64, elapsed: Duration) {
+		self.uploaded_bytes.fetch_add(bytes, Ordering::Relaxed);
+		self.upload_millis
+			.fetch_add(elapsed.as_millis() as u64, Ordering::Relaxed);
+	}
+
+	///Metrics in the prometheus text format
+	pub fn render(&self) -> String {
+		let upload_seconds = self.upload_millis.load(Ordering::Relaxed) as f64 / 1000.0;
+		let metrics = [
+			(
+				"segmented_worker_tasks_completed_total",
+				"counter",
+				"Tasks finished successfully",
+				self.completed.load(Ordering::Relaxed) as f64,
+			),
+			(
+				"segmented_worker_tasks_failed_total",
+				"counter",
+				"Tasks reported as failed",
+				self.failed.load(Ordering::Relaxed) as f64,
+			),
+			(
+				"segmented_worker_encode_fps",
+				"gauge",
+				"Last encoding frame rate reported by ffmpeg",
+				f64::from_bits(self.fps.load(Ordering::Relaxed)),
+			),
+			(
+				"segmented_worker_uploaded_bytes_total",
+				"counter",
+				"Bytes of output uploaded to the server",
+				self.uploaded_bytes.load(Ordering::Relaxed) as f64,
+			),
+			(
+				"segmented_worker_upload_seconds_total",
+				"counter",
+				"Time spent uploading outputs",
+				upload_seconds,
+			),
+		];
+		let mut body = String::new();
+		for (name, kind, help, value) in metrics {
+			let _ = write!(
+				body,
+				"# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n"
+			);
+		}
+		body
+	}
+}
+
+///Answer every request on the listener with the metrics
+pub async fn serve(listener: TcpListener, metrics: Arc<Metrics>) -> io::Result<()> {
+	loop {
+		let (stream, _) = listener.accept().await?;
+		let metrics = metrics.clone();
+		tokio::spawn(async move {
+			if let Err(err) = respond(stream, &metrics).await {
+				eprintln!("Failed to send metrics: {err}");
+			}
+		});
+	}
+}
+
+async fn respond(mut stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+	let mut request = Vec::new();
+	let mut buf = [0; 1024];
+	while !request.ends_with(b"\r\n\r\n") && request.len() < MAX_REQUEST {
+		let len = stream.read(&mut buf).await?;
+		if len == 0 {
+			break;
+		}
+		request.extend_from_slice(&buf[..len]);
+	}
+	let body = metrics.render();
+	let response = format!(
+		"HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+		body.len()
+	);
+	stream.write_all(response.as_bytes()).await?;
+	stream.shutdown().await
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn render_has_the_counters() {
+		let metrics = Metrics::default();
+		metrics.task_completed();
+		metrics.task_completed();
+		metrics.uploaded(10, Duration::from_millis(1500));
+		let body = metrics.render();
+		assert!(body.contains("\nsegmented_worker_tasks_completed_total 2\n"));
+		assert!(body.contains("\nsegmented_worker_uploaded_bytes_total 10\n"));
+		assert!(body.contains("\nsegmented_worker_upload_seconds_total 1.5\n"))
+	}
+
+	#[tokio::test]
+	async fn serve_answers_with_the_metrics() {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let address = listener.local_addr().unwrap();
+		let metrics = Arc::new(Metrics::default());
+		metrics.task_failed();
+		tokio::spawn(serve(listener, metrics));
+		let mut stream = TcpStream::connect(address).await.unwrap();
+		stream
+			.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+			.await
+			.unwrap();
+		let mut response = String::new();
+		stream.read_to_string(&mut response).await.unwrap();
+		assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+		assert!(response.contains("\nsegmented_worker_tasks_failed_total 1\n"))
+	}
+}
@@ -0,0 +1,128 @@
+use std::collections::HashSet;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use uuid::Uuid;
+
+///Tasks allocated to the worker, optionally saved to a file after each change
+///
+///The file lists one `job_id task_id` pair per line, so a restarted worker can release them
+pub struct InFlight {
+	tasks: Mutex<HashSet<(Uuid, Uuid)>>,
+	path: Option<PathBuf>,
+}
+
+impl InFlight {
+	pub fn new(path: Option<PathBuf>) -> Self {
+		Self {
+			tasks: Default::default(),
+			path,
+		}
+	}
+
+	pub fn insert(&self, id: (Uuid, Uuid)) {
+		let mut tasks = self.tasks.lock().unwrap();
+		tasks.insert(id);
+		self.save(&tasks);
+	}
+
+	pub fn remove(&self, id: &(Uuid, Uuid)) {
+		let mut tasks = self.tasks.lock().unwrap();
+		tasks.remove(id);
+		self.save(&tasks);
+	}
+
+	///Remove and return all the tasks
+	pub fn drain(&self) -> Vec<(Uuid, Uuid)> {
+		let mut tasks = self.tasks.lock().unwrap();
+		let drained = tasks.drain().collect();
+		self.save(&tasks);
+		drained
+	}
+
+	///Replace the file content, through a temporary file so it is never partially written
+	fn save(&self, tasks: &HashSet<(Uuid, Uuid)>) {
+		let Some(path) = &self.path else {
+			return;
+		};
+		let content: String = tasks
+			.iter()
+			.map(|(job, task)| format!("{job} {task}\n"))
+			.collect();
+		let mut temporary = path.as_os_str().to_owned();
+		temporary.push(format!(".{}.tmp", Uuid::new_v4()));
+		let temporary = PathBuf::from(temporary);
+		let res =
+			std::fs::write(&temporary, content).and_then(|()| std::fs::rename(&temporary, path));
+		if let Err(err) = res {
+			eprintln!("Failed to save the allocated tasks: {err}");
+		}
+	}
+}
+
+///Read the tasks saved by a previous run, a missing file has no tasks
+pub fn read(path: &Path) -> io::Result<Vec<(Uuid, Uuid)>> {
+	let content = match std::fs::read_to_string(path) {
+		Ok(content) => content,
+		Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+		Err(err) => return Err(err),
+	};
+	content
+		.lines()
+		.filter(|line| !line.trim().is_empty())
+		.map(|line| {
+			let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid task entry");
+			let (job, task) = line.split_once(' ').ok_or_else(invalid)?;
+			let job = job.parse().map_err(|_| invalid())?;
+			let task = task.trim().parse().map_err(|_| invalid())?;
+			Ok((job, task))
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn state_dir() -> PathBuf {
+		let dir = std::env::temp_dir().join(format!("segmented-state-{}", Uuid::new_v4()));
+		std::fs::create_dir_all(&dir).unwrap();
+		dir
+	}
+
+	#[test]
+	fn read_has_the_saved_tasks() {
+		let dir = state_dir();
+		let path = dir.join("state");
+		let (kept, removed) = (
+			(Uuid::new_v4(), Uuid::new_v4()),
+			(Uuid::new_v4(), Uuid::new_v4()),
+		);
+		let tasks = InFlight::new(Some(path.clone()));
+		tasks.insert(kept);
+		tasks.insert(removed);
+		tasks.remove(&removed);
+		let read = read(&path);
+		let files = std::fs::read_dir(&dir).unwrap().count();
+		std::fs::remove_dir_all(&dir).unwrap();
+		assert_eq!(read.unwrap(), [kept]);
+		assert_eq!(files, 1, "The temporary file should be renamed")
+	}
+
+	#[test]
+	fn read_missing_file_has_no_tasks() {
+		let path = std::env::temp_dir().join(format!("segmented-missing-{}", Uuid::new_v4()));
+		assert!(read(&path).unwrap().is_empty())
+	}
+
+	#[test]
+	fn read_invalid_entry_is_invalid_data() {
+		let dir = state_dir();
+		let path = dir.join("state");
+		std::fs::write(&path, "not a task\n").unwrap();
+		let read = read(&path);
+		std::fs::remove_dir_all(&dir).unwrap();
+		assert_eq!(read.unwrap_err().kind(), io::ErrorKind::InvalidData)
+	}
+}
@@ -0,0 +1,74 @@
+use std::time::Duration;
+
+use futures_util::{Stream, StreamExt};
+use tokio::time::Instant;
+
+///Keeps the average transfer rate under the limit, in bytes per second
+pub(crate) struct Limiter {
+	rate: Option<u64>,
+	start: Instant,
+	transferred: u64,
+}
+
+impl Limiter {
+	pub(crate) fn new(rate: Option<u64>) -> Self {
+		Self {
+			rate,
+			start: Instant::now(),
+			transferred: 0,
+		}
+	}
+
+	///Account for the transferred bytes, waiting until the average rate is under the limit
+	pub(crate) async fn consume(&mut self, bytes: usize) {
+		let Some(rate) = self.rate else {
+			return;
+		};
+		self.transferred += bytes as u64;
+		let due = Duration::from_secs_f64(self.transferred as f64 / rate as f64);
+		tokio::time::sleep_until(self.start + due).await;
+	}
+}
+
+///Throttle a stream of chunks, used to limit the upload body and the piped inputs
+pub(crate) fn stream<S, B, E>(stream: S, rate: Option<u64>) -> impl Stream<Item = Result<B, E>>
+where
+	S: Stream<Item = Result<B, E>> + Unpin,
+	B: AsRef<[u8]>,
+{
+	futures_util::stream::unfold(
+		(stream, Limiter::new(rate)),
+		|(mut stream, mut limiter)| async move {
+			let item = stream.next().await?;
+			if let Ok(chunk) = &item {
+				limiter.consume(chunk.as_ref().len()).await;
+			}
+			Some((item, (stream, limiter)))
+		},
+	)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn chunks() -> impl Stream<Item = Result<Vec<u8>, ()>> + Unpin {
+		futures_util::stream::iter([Ok(vec![0; 100]), Err(()), Ok(vec![0; 100])])
+	}
+
+	#[tokio::test]
+	async fn unlimited_stream_does_not_wait() {
+		let start = Instant::now();
+		let items: Vec<_> = stream(chunks(), None).collect().await;
+		assert_eq!(items.len(), 3);
+		assert!(start.elapsed() < Duration::from_millis(100))
+	}
+
+	#[tokio::test]
+	async fn limited_stream_keeps_the_rate() {
+		let start = Instant::now();
+		let items: Vec<_> = stream(chunks(), Some(1000)).collect().await;
+		assert_eq!(items, [Ok(vec![0; 100]), Err(()), Ok(vec![0; 100])]);
+		assert!(start.elapsed() >= Duration::from_millis(200))
+	}
+}
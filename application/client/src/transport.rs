@@ -0,0 +1,438 @@
+//! Server operations of the worker
+//!
+//! The [Worker] talks to the server api with reqwest. The tasks only use the [Transport] trait,
+//! so they can also run against the in-memory server of the tests.
+use std::io;
+use std::io::SeekFrom;
+use std::path::Path;
+
+use base64::prelude::*;
+use futures_util::TryStreamExt;
+use reqwest::header::AUTHORIZATION;
+use reqwest::{Body, StatusCode};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::watch;
+use tokio_util::codec::{BytesCodec, FramedRead};
+use tokio_util::io::StreamReader;
+use uuid::Uuid;
+
+use task::{Instance, MediaProbe, Resources, Status, TaskSource};
+
+use crate::{throttle, ClientError, TaskRunner, UploadProgress, Worker};
+
+///Header with the bytes received by the resumable upload
+const UPLOAD_OFFSET: &str = "upload-offset";
+///Header requesting the input checksum
+const WANT_DIGEST: &str = "want-digest";
+///Header with the input checksum
+const DIGEST: &str = "digest";
+
+#[allow(async_fn_in_trait)]
+pub trait Transport {
+	///Allocate a task fitting the capacity, None when the server has no task for the worker
+	async fn allocate_task(&self, capacity: &Resources) -> Result<Option<Instance>, ClientError>;
+	///Save the task input to a local file
+	async fn download_input(&self, job: Uuid, task: Uuid, idx: u32, path: &Path) -> io::Result<()>;
+	///Read the task input from the server
+	async fn open_input(
+		&self,
+		job: Uuid,
+		task: Uuid,
+		idx: u32,
+	) -> io::Result<Box<dyn AsyncRead + Unpin + Send>>;
+	///Bytes of the output already received by the server
+	async fn upload_offset(&self, id: (Uuid, Uuid)) -> Result<u64, ClientError>;
+	///Send the file content, starting at the offset, adding the bytes sent to the progress
+	async fn upload_from(
+		&self,
+		path: &Path,
+		id: (Uuid, Uuid),
+		offset: u64,
+		progress: watch::Sender<UploadProgress>,
+	) -> Result<(), ClientError>;
+	///Use the uploaded content as the task output, the server checks it against the sha-256 digest
+	async fn finish_upload(&self, id: (Uuid, Uuid), digest: &str) -> Result<(), ClientError>;
+	async fn set_task_status(
+		&self,
+		job: Uuid,
+		task: Uuid,
+		status: Status,
+	) -> Result<(), ClientError>;
+	///Release the task allocation, so another worker can run it
+	async fn cancel_task(&self, job: Uuid, task: Uuid) -> Result<(), ClientError>;
+	///Report the encoded seconds and the speed relative to realtime
+	async fn report_progress(
+		&self,
+		job: Uuid,
+		task: Uuid,
+		out_time: f64,
+		speed: Option<f64>,
+	) -> Result<(), ClientError>;
+	///Report the metadata of the task output, or of the job source for the analysis
+	async fn report_probe(
+		&self,
+		job: Uuid,
+		task: Uuid,
+		probe: MediaProbe,
+	) -> Result<(), ClientError>;
+	///Returns the index of the new task
+	async fn add_task_to_job(&self, job: Uuid, task: TaskSource) -> Result<u32, ClientError>;
+}
+
+impl Transport for Worker {
+	async fn allocate_task(&self, capacity: &Resources) -> Result<Option<Instance>, ClientError> {
+		let task = api::apis::worker_api::allocate_task_get(
+			&self.config,
+			capacity.cpus,
+			capacity
+				.memory
+				.map(|memory| memory.try_into().unwrap_or(i64::MAX)),
+			capacity
+				.gpus
+				.map(|gpus| gpus.try_into().unwrap_or(i32::MAX)),
+		)
+		.await;
+		match task {
+			Ok(task) => Instance::try_from(task)
+				.map(Some)
+				.or(Err(ClientError::Parse("Unsupported task".to_string()))),
+			Err(api::apis::Error::ResponseError(res))
+				if res.status == StatusCode::SERVICE_UNAVAILABLE =>
+			{
+				Ok(None)
+			}
+			Err(err) => Err(err.into()),
+		}
+	}
+
+	async fn set_task_status(
+		&self,
+		job: Uuid,
+		task: Uuid,
+		status: Status,
+	) -> Result<(), ClientError> {
+		api::apis::worker_api::job_job_id_task_task_id_status_post(
+			&self.config,
+			&job.to_string(),
+			&task.to_string(),
+			Some(status.into()),
+		)
+		.await
+		.map_err(ClientError::from)
+	}
+
+	async fn upload_offset(&self, id: (Uuid, Uuid)) -> Result<u64, ClientError> {
+		let res = self
+			.config
+			.client
+			.head(self.get_upload_url(id.0, id.1))
+			.header(AUTHORIZATION.as_str(), self.get_output_creds())
+			.send()
+			.await?;
+		if !res.status().is_success() {
+			return Err(ClientError::status(
+				res.status(),
+				"Upload offset request failed",
+			));
+		}
+		res.headers()
+			.get(UPLOAD_OFFSET)
+			.and_then(|val| val.to_str().ok())
+			.and_then(|val| val.parse().ok())
+			.ok_or(ClientError::Parse("Missing upload offset".to_string()))
+	}
+
+	async fn upload_from(
+		&self,
+		path: &Path,
+		id: (Uuid, Uuid),
+		offset: u64,
+		progress: watch::Sender<UploadProgress>,
+	) -> Result<(), ClientError> {
+		let mut file = tokio::fs::File::open(path).await?;
+		file.seek(SeekFrom::Start(offset)).await?;
+		let stream = FramedRead::new(file, BytesCodec::new());
+		let sent = throttle::stream(stream, self.max_upload_rate).inspect_ok(move |chunk| {
+			progress.send_modify(|progress| progress.sent += chunk.len() as u64)
+		});
+		let body = Body::wrap_stream(sent);
+		let res = self
+			.config
+			.client
+			.patch(self.get_upload_url(id.0, id.1))
+			.header(AUTHORIZATION.as_str(), self.get_output_creds())
+			.header(UPLOAD_OFFSET, offset)
+			.body(body)
+			.send()
+			.await?;
+		match res.status().is_success() {
+			true => Ok(()),
+			false => Err(ClientError::status(res.status(), "Upload rejected")),
+		}
+	}
+
+	async fn finish_upload(&self, id: (Uuid, Uuid), digest: &str) -> Result<(), ClientError> {
+		let res = self
+			.config
+			.client
+			.post(self.get_upload_url(id.0, id.1))
+			.header(AUTHORIZATION.as_str(), self.get_output_creds())
+			.header(DIGEST, format!("sha-256={digest}"))
+			.send()
+			.await?;
+		match res.status().is_success() {
+			true => Ok(()),
+			false => Err(ClientError::status(res.status(), "Upload not accepted")),
+		}
+	}
+
+	async fn download_input(&self, job: Uuid, task: Uuid, idx: u32, path: &Path) -> io::Result<()> {
+		let mut res = self
+			.config
+			.client
+			.get(self.get_input_url(job, task, idx))
+			.header(AUTHORIZATION.as_str(), self.get_input_creds())
+			.header(WANT_DIGEST, "sha-256")
+			.send()
+			.await
+			.and_then(|res| res.error_for_status())
+			.map_err(io::Error::other)?;
+		let expected = res
+			.headers()
+			.get(DIGEST)
+			.and_then(|value| value.to_str().ok())
+			.and_then(|value| value.strip_prefix("sha-256="))
+			.map(str::to_string);
+		let mut hasher = Sha256::new();
+		let mut file = tokio::fs::File::create(path).await?;
+		let mut limiter = throttle::Limiter::new(self.max_download_rate);
+		while let Some(chunk) = res.chunk().await.map_err(io::Error::other)? {
+			limiter.consume(chunk.len()).await;
+			hasher.update(&chunk);
+			file.write_all(&chunk).await?;
+		}
+		file.flush().await?;
+		match expected {
+			Some(expected) if expected != BASE64_STANDARD.encode(hasher.finalize()) => Err(
+				io::Error::new(io::ErrorKind::InvalidData, "Input digest mismatch"),
+			),
+			_ => Ok(()),
+		}
+	}
+
+	async fn open_input(
+		&self,
+		job: Uuid,
+		task: Uuid,
+		idx: u32,
+	) -> io::Result<Box<dyn AsyncRead + Unpin + Send>> {
+		let res = self
+			.config
+			.client
+			.get(self.get_input_url(job, task, idx))
+			.header(AUTHORIZATION.as_str(), self.get_input_creds())
+			.send()
+			.await
+			.and_then(|res| res.error_for_status())
+			.map_err(io::Error::other)?;
+		let stream = Box::pin(res.bytes_stream().map_err(io::Error::other));
+		let throttled = throttle::stream(stream, self.max_download_rate);
+		Ok(Box::new(StreamReader::new(Box::pin(throttled))))
+	}
+
+	async fn report_progress(
+		&self,
+		job: Uuid,
+		task: Uuid,
+		out_time: f64,
+		speed: Option<f64>,
+	) -> Result<(), ClientError> {
+		api::apis::worker_api::job_job_id_task_task_id_progress_post(
+			&self.config,
+			&job.to_string(),
+			&task.to_string(),
+			Some(api::models::TaskProgress { out_time, speed }),
+		)
+		.await
+		.map_err(ClientError::from)
+	}
+
+	async fn report_probe(
+		&self,
+		job: Uuid,
+		task: Uuid,
+		probe: MediaProbe,
+	) -> Result<(), ClientError> {
+		api::apis::worker_api::job_job_id_task_task_id_probe_put(
+			&self.config,
+			&job.to_string(),
+			&task.to_string(),
+			Some(probe.into()),
+		)
+		.await
+		.map_err(ClientError::from)
+	}
+
+	async fn cancel_task(&self, job: Uuid, task: Uuid) -> Result<(), ClientError> {
+		api::apis::worker_api::job_job_id_task_task_id_delete(
+			&self.config,
+			&job.to_string(),
+			&task.to_string(),
+		)
+		.await
+		.map_err(ClientError::from)
+	}
+
+	async fn add_task_to_job(&self, job: Uuid, task: TaskSource) -> Result<u32, ClientError> {
+		let parsed = task.try_into().map_err(|_| {
+			ClientError::InvalidTask("Only transcodes and merges can be added".to_string())
+		})?;
+		let idx = api::apis::worker_api::job_job_id_task_post(
+			&self.config,
+			&job.to_string(),
+			Some(parsed),
+		)
+		.await?;
+		idx.try_into()
+			.map_err(|_| ClientError::Parse(format!("Invalid task index {idx}")))
+	}
+}
+
+///Server kept in memory for the tests, recording what the worker sent
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct MemoryServer {
+	///Allocated from the last one
+	pub tasks: std::sync::Mutex<Vec<Instance>>,
+	pub inputs: Vec<Vec<u8>>,
+	pub uploaded: std::sync::Mutex<Vec<u8>>,
+	///Upload accepted as the task output
+	pub output: std::sync::Mutex<Option<Vec<u8>>>,
+	pub statuses: std::sync::Mutex<Vec<Status>>,
+	pub released: std::sync::Mutex<Vec<Uuid>>,
+	pub task_timeout: Option<std::time::Duration>,
+	pub upload_stall_timeout: Option<std::time::Duration>,
+	///Wait before answering the upload, after receiving its bytes
+	pub upload_delay: Option<std::time::Duration>,
+	///The upload never receives any byte
+	pub upload_hangs: bool,
+	pub validations: crate::cache::Validations,
+}
+
+#[cfg(test)]
+impl MemoryServer {
+	fn input(&self, idx: u32) -> io::Result<Vec<u8>> {
+		self.inputs
+			.get(idx as usize)
+			.cloned()
+			.ok_or(io::Error::from(io::ErrorKind::NotFound))
+	}
+}
+
+#[cfg(test)]
+impl Transport for MemoryServer {
+	async fn allocate_task(&self, _capacity: &Resources) -> Result<Option<Instance>, ClientError> {
+		Ok(self.tasks.lock().unwrap().pop())
+	}
+
+	async fn download_input(
+		&self,
+		_job: Uuid,
+		_task: Uuid,
+		idx: u32,
+		path: &Path,
+	) -> io::Result<()> {
+		tokio::fs::write(path, self.input(idx)?).await
+	}
+
+	async fn open_input(
+		&self,
+		_job: Uuid,
+		_task: Uuid,
+		idx: u32,
+	) -> io::Result<Box<dyn AsyncRead + Unpin + Send>> {
+		Ok(Box::new(io::Cursor::new(self.input(idx)?)))
+	}
+
+	async fn upload_offset(&self, _id: (Uuid, Uuid)) -> Result<u64, ClientError> {
+		Ok(self.uploaded.lock().unwrap().len() as u64)
+	}
+
+	async fn upload_from(
+		&self,
+		path: &Path,
+		_id: (Uuid, Uuid),
+		offset: u64,
+		progress: watch::Sender<UploadProgress>,
+	) -> Result<(), ClientError> {
+		if self.upload_hangs {
+			return std::future::pending().await;
+		}
+		let content = tokio::fs::read(path).await?;
+		{
+			let mut uploaded = self.uploaded.lock().unwrap();
+			if offset != uploaded.len() as u64 {
+				return Err(ClientError::status(StatusCode::CONFLICT, "Wrong offset"));
+			}
+			let sent = &content[offset as usize..];
+			uploaded.extend_from_slice(sent);
+			progress.send_modify(|progress| progress.sent += sent.len() as u64);
+		}
+		if let Some(delay) = self.upload_delay {
+			tokio::time::sleep(delay).await;
+		}
+		Ok(())
+	}
+
+	async fn finish_upload(&self, _id: (Uuid, Uuid), digest: &str) -> Result<(), ClientError> {
+		let uploaded = self.uploaded.lock().unwrap().clone();
+		if BASE64_STANDARD.encode(Sha256::digest(&uploaded)) != digest {
+			return Err(ClientError::status(
+				StatusCode::BAD_REQUEST,
+				"Digest mismatch",
+			));
+		}
+		*self.output.lock().unwrap() = Some(uploaded);
+		Ok(())
+	}
+
+	async fn set_task_status(
+		&self,
+		_job: Uuid,
+		_task: Uuid,
+		status: Status,
+	) -> Result<(), ClientError> {
+		self.statuses.lock().unwrap().push(status);
+		Ok(())
+	}
+
+	async fn cancel_task(&self, _job: Uuid, task: Uuid) -> Result<(), ClientError> {
+		self.released.lock().unwrap().push(task);
+		Ok(())
+	}
+
+	async fn report_progress(
+		&self,
+		_job: Uuid,
+		_task: Uuid,
+		_out_time: f64,
+		_speed: Option<f64>,
+	) -> Result<(), ClientError> {
+		Ok(())
+	}
+
+	async fn report_probe(
+		&self,
+		_job: Uuid,
+		_task: Uuid,
+		_probe: MediaProbe,
+	) -> Result<(), ClientError> {
+		Ok(())
+	}
+
+	async fn add_task_to_job(&self, _job: Uuid, _task: TaskSource) -> Result<u32, ClientError> {
+		let reason = "The memory server does not add tasks";
+		Err(ClientError::InvalidTask(reason.to_string()))
+	}
+}
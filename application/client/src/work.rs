@@ -0,0 +1,237 @@
+//! The work loop of the worker, requesting tasks while it has capacity until shutdown
+//!
+//! Binaries embedding the worker run it like the CLI does:
+//! ```no_run
+//! # async fn embed() -> Result<(), client::builder::BuildError> {
+//! use std::sync::Arc;
+//!
+//! use client::work::WorkOptions;
+//! use tokio_util::sync::CancellationToken;
+//!
+//! let worker = client::ClientBuilder::new("https://encoder.example.com/api")
+//!     .password("worker password")
+//!     .build()
+//!     .await?;
+//! Arc::new(worker)
+//!     .run_forever(WorkOptions::default(), CancellationToken::new())
+//!     .await;
+//! # Ok(())
+//! # }
+//! ```
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::header::AUTHORIZATION;
+use tokio::sync::{watch, Semaphore};
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use task::{Instance, Resources};
+
+use crate::limits::Budget;
+use crate::state::InFlight;
+use crate::transport::Transport;
+use crate::{ClientError, TaskRunner, Worker};
+
+///Wait before requesting a task again, when none was available
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+///Wait before reconnecting to the server or to the availability events
+pub(crate) const RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+///How the work loop requests and runs the tasks
+pub struct WorkOptions {
+	///Tasks run at the same time
+	pub jobs: u32,
+	///Resources shared by the running tasks, only tasks fitting the free ones are requested
+	pub budget: Budget,
+	///Keep requesting tasks while the server is unavailable, instead of stopping
+	pub daemon: bool,
+	///Allocated tasks, released when they don't finish before the shutdown timeout
+	pub in_flight: InFlight,
+	///Time the running tasks have to finish once shutdown is requested
+	pub shutdown_timeout: Duration,
+}
+
+impl Default for WorkOptions {
+	fn default() -> Self {
+		Self {
+			jobs: 1,
+			budget: Budget::new(Resources::default()),
+			daemon: false,
+			in_flight: InFlight::new(None),
+			shutdown_timeout: Duration::from_secs(60),
+		}
+	}
+}
+
+///Random delay between half and one and a half times the base
+pub(crate) fn jitter(base: Duration) -> Duration {
+	let random = (Uuid::new_v4().as_u128() % 1000) as f64 / 1000.0;
+	base.mul_f64(0.5 + random)
+}
+
+async fn run_task(worker: &Worker, task: Instance) {
+	println!("Task: {:#?}", task);
+	worker.run(task).await;
+}
+
+///Request a task fitting the free capacity, returns None when no task could be allocated, Err if
+///the worker should stop
+///
+///Without tasks, waits for an availability event or the jittered retry delay
+async fn request_task(
+	worker: &Worker,
+	capacity: &Resources,
+	available: &mut watch::Receiver<u64>,
+	shutdown: &CancellationToken,
+) -> Result<Option<Instance>, ClientError> {
+	println!("Requesting task...");
+	available.borrow_and_update();
+	match worker.allocate_task(capacity).await {
+		Ok(Some(task)) => Ok(Some(task)),
+		Ok(None) => {
+			println!("No tasks available");
+			tokio::select! {
+				Ok(()) = available.changed() => {}
+				_ = tokio::time::sleep(jitter(RETRY_DELAY)) => {}
+				_ = shutdown.cancelled() => {}
+			}
+			Ok(None)
+		}
+		Err(ClientError::Parse(err)) => {
+			eprintln!("Failed to parse task: {err}");
+			Ok(None)
+		}
+		Err(err) => {
+			eprintln!("Could not request a task: {err}");
+			Err(err)
+		}
+	}
+}
+
+///Follow the server availability events, counting them on the channel
+///
+///Reconnects after a delay when the stream fails, the retry delay is used meanwhile
+async fn follow_availability(
+	worker: Arc<Worker>,
+	available: watch::Sender<u64>,
+	shutdown: CancellationToken,
+) {
+	loop {
+		let err = tokio::select! {
+			err = availability_events(&worker, &available) => err,
+			_ = shutdown.cancelled() => return,
+		};
+		eprintln!("Availability events unavailable: {err}");
+		tokio::select! {
+			_ = tokio::time::sleep(jitter(RECONNECT_DELAY)) => {}
+			_ = shutdown.cancelled() => return,
+		}
+	}
+}
+
+///Read the availability event stream, until it fails
+async fn availability_events(worker: &Worker, available: &watch::Sender<u64>) -> String {
+	let res = worker
+		.config
+		.client
+		.get(format!("{}/allocate_task/events", worker.config.base_path))
+		.header(AUTHORIZATION, worker.get_input_creds())
+		.send()
+		.await
+		.and_then(|res| res.error_for_status());
+	let mut res = match res {
+		Ok(res) => res,
+		Err(err) => return err.to_string(),
+	};
+	let mut buffer = String::new();
+	loop {
+		let chunk = match res.chunk().await {
+			Ok(Some(chunk)) => chunk,
+			Ok(None) => return "stream closed".to_string(),
+			Err(err) => return err.to_string(),
+		};
+		buffer.push_str(&String::from_utf8_lossy(&chunk));
+		while let Some(end) = buffer.find('\n') {
+			let line: String = buffer.drain(..=end).collect();
+			if line.trim_end() == "event: available" {
+				available.send_modify(|count| *count += 1);
+			}
+		}
+	}
+}
+
+impl Worker {
+	///Run up to `jobs` tasks at the same time, until a request fails or shutdown is requested
+	///
+	///On shutdown no task is requested, the running ones are killed and released if they don't
+	///finish in the shutdown timeout
+	pub async fn run_forever(self: Arc<Self>, options: WorkOptions, shutdown: CancellationToken) {
+		let WorkOptions {
+			jobs,
+			budget,
+			daemon,
+			in_flight,
+			shutdown_timeout,
+		} = options;
+		let (budget, in_flight) = (Arc::new(budget), Arc::new(in_flight));
+		let (sender, mut available) = watch::channel(0);
+		tokio::spawn(follow_availability(self.clone(), sender, shutdown.clone()));
+		let slots = Arc::new(Semaphore::new(jobs.max(1) as usize));
+		let mut running = JoinSet::new();
+		loop {
+			let permit = tokio::select! {
+				permit = slots.clone().acquire_owned() => permit.unwrap(),
+				_ = shutdown.cancelled() => break,
+			};
+			while running.try_join_next().is_some() {}
+			if shutdown.is_cancelled() {
+				break;
+			}
+			match request_task(&self, &budget.free(), &mut available, &shutdown).await {
+				Ok(Some(task)) => {
+					let worker = self.clone();
+					let in_flight = in_flight.clone();
+					let budget = budget.clone();
+					let id = (task.job_id, task.task_id);
+					let resources = task.resources.clone();
+					in_flight.insert(id);
+					budget.reserve(&resources);
+					running.spawn(async move {
+						run_task(&worker, task).await;
+						budget.release(&resources);
+						in_flight.remove(&id);
+						drop(permit);
+					});
+				}
+				Ok(None) => {}
+				Err(ClientError::Auth) => {
+					eprintln!("The server refused the worker token, stopping");
+					break;
+				}
+				Err(_) if daemon => {
+					tokio::select! {
+						_ = tokio::time::sleep(jitter(RECONNECT_DELAY)) => {}
+						_ = shutdown.cancelled() => {}
+					}
+				}
+				Err(_) => break,
+			}
+		}
+		if running.is_empty() {
+			return;
+		}
+		println!("Waiting for {} running tasks", running.len());
+		let finish = async { while running.join_next().await.is_some() {} };
+		if tokio::time::timeout(shutdown_timeout, finish).await.is_ok() {
+			return;
+		}
+		running.abort_all();
+		while running.join_next().await.is_some() {}
+		for (job_id, task_id) in in_flight.drain() {
+			let res = self.cancel_task(job_id, task_id).await;
+			println!("Released task {task_id}: {res:?}");
+		}
+	}
+}
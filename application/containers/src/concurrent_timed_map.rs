@@ -0,0 +1,182 @@
+//! [ConcurrentTimedMap], a [TimedMap](crate::TimedMap) that can be shared between threads
+
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
+
+use crate::timed_map::{timestamp_now, AtomicTimestamp, TimedMapEntry};
+
+const DEFAULT_SHARDS: usize = 16;
+
+type Shard<Key, Val> = RwLock<HashMap<Key, TimedMapEntry<Val>>>;
+
+/// A map that let you remove entries after some time without updates.
+/// The entries are split between shards, each with its own lock, so the operations only need `&self`
+pub struct ConcurrentTimedMap<Key, Val>
+where
+	Key: Eq + Hash,
+{
+	shards: Box<[Shard<Key, Val>]>,
+	hasher: RandomState,
+}
+
+impl<Key, Val> Default for ConcurrentTimedMap<Key, Val>
+where
+	Key: Eq + Hash,
+{
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<Key, Val> ConcurrentTimedMap<Key, Val>
+where
+	Key: Eq + Hash,
+{
+	/// Creates an empty ConcurrentTimedMap.
+	pub fn new() -> Self {
+		Self::with_shards(DEFAULT_SHARDS)
+	}
+
+	/// Creates an empty ConcurrentTimedMap, with at least one shard.
+	pub fn with_shards(shards: usize) -> Self {
+		Self {
+			shards: (0..shards.max(1)).map(|_| Default::default()).collect(),
+			hasher: RandomState::new(),
+		}
+	}
+
+	fn shard<Q>(&self, key: &Q) -> &Shard<Key, Val>
+	where
+		Q: Hash + ?Sized,
+	{
+		let hash = self.hasher.hash_one(key);
+		&self.shards[hash as usize % self.shards.len()]
+	}
+
+	fn read(shard: &Shard<Key, Val>) -> RwLockReadGuard<'_, HashMap<Key, TimedMapEntry<Val>>> {
+		shard.read().unwrap_or_else(|err| err.into_inner())
+	}
+
+	fn write(shard: &Shard<Key, Val>) -> RwLockWriteGuard<'_, HashMap<Key, TimedMapEntry<Val>>> {
+		shard.write().unwrap_or_else(|err| err.into_inner())
+	}
+
+	///Returns true if the map contains no elements.
+	pub fn is_empty(&self) -> bool {
+		self.shards.iter().all(|shard| Self::read(shard).is_empty())
+	}
+
+	/// Insert new element into map, and set its timestamp to current time.
+	pub fn insert(&self, key: Key, value: Val) {
+		let now = AtomicTimestamp::now();
+		Self::write(self.shard(&key)).insert(key, TimedMapEntry(now, value));
+	}
+
+	/// Removes the key from the map, and returns its value.
+	pub fn remove<Q>(&self, key: &Q) -> Option<Val>
+	where
+		Key: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		Self::write(self.shard(key)).remove(key).map(|val| val.1)
+	}
+
+	/// Returns a copy of the stored element, and update it's timestamp to now.
+	pub fn get<Q>(&self, key: &Q) -> Option<Val>
+	where
+		Key: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+		Val: Clone,
+	{
+		Self::read(self.shard(key)).get(key).map(|entry| {
+			entry.0.set_to_now();
+			entry.1.clone()
+		})
+	}
+
+	/// Removes all elements that have not been accessed for a period greater than [duration](Duration).
+	pub fn timeout(&self, duration: Duration) {
+		let expired = timestamp_now() - duration;
+		for shard in self.shards.iter() {
+			Self::write(shard).retain(|_, entry| entry.0.load() >= expired)
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::sync::Arc;
+	use std::thread::sleep;
+	use std::time::Duration;
+
+	use super::ConcurrentTimedMap;
+
+	const TEST_TIMEOUT: Duration = Duration::from_millis(10);
+
+	#[test]
+	fn new_concurrent_timed_map_is_empty() {
+		let map: ConcurrentTimedMap<String, String> = ConcurrentTimedMap::new();
+		assert!(map.is_empty())
+	}
+
+	#[test]
+	fn concurrent_timed_map_get_after_insert() {
+		let map = ConcurrentTimedMap::new();
+		map.insert("KEY".to_string(), "Value".to_string());
+		let got = map.get("KEY").expect("Should get the stored value");
+		assert_eq!(got, "Value")
+	}
+
+	#[test]
+	fn concurrent_timed_map_remove_returns_the_removed_value() {
+		let map = ConcurrentTimedMap::new();
+		map.insert("KEY", "Value");
+		assert_eq!(map.remove("KEY"), Some("Value"));
+		assert!(map.is_empty(), "Element should be removed")
+	}
+
+	#[test]
+	fn concurrent_timed_map_timeout_with_delay_will_remove() {
+		let map = ConcurrentTimedMap::new();
+		map.insert("KEY", "Value");
+
+		sleep(TEST_TIMEOUT);
+		map.timeout(TEST_TIMEOUT);
+
+		assert!(map.is_empty(), "Timeout should remove after the sleep")
+	}
+
+	#[test]
+	fn concurrent_timed_map_timeout_after_get_should_not_remove() {
+		let map = ConcurrentTimedMap::new();
+		map.insert("KEY", "Value");
+
+		sleep(TEST_TIMEOUT);
+		map.get("KEY").expect("Should get");
+		map.timeout(TEST_TIMEOUT);
+
+		assert!(!map.is_empty(), "Get should update the timestamp")
+	}
+
+	#[test]
+	fn concurrent_timed_map_insert_from_threads() {
+		let map = Arc::new(ConcurrentTimedMap::with_shards(4));
+		let threads: Vec<_> = (0..8)
+			.map(|key| {
+				let map = map.clone();
+				std::thread::spawn(move || map.insert(key, key * 2))
+			})
+			.collect();
+		threads
+			.into_iter()
+			.for_each(|thread| thread.join().unwrap());
+
+		for key in 0..8 {
+			assert_eq!(map.get(&key), Some(key * 2))
+		}
+	}
+}
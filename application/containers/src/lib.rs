@@ -1,5 +1,9 @@
-pub use timed_map::TimedMap;
+pub use concurrent_timed_map::ConcurrentTimedMap;
+pub use timed_map::{TimedEntry, TimedMap, TimedMapSnapshot, TimedMapStats};
+pub use timed_set::TimedSet;
 pub use weak_map::{WeakMap, WeakMapEntryArc};
 
+mod concurrent_timed_map;
 mod timed_map;
+mod timed_set;
 mod weak_map;
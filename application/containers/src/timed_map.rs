@@ -1,16 +1,20 @@
 use std::borrow::Borrow;
-use std::collections::HashMap;
+use std::collections::{hash_map, HashMap};
 use std::hash::Hash;
 use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
 use std::time::{Duration, SystemTime};
 
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+
 /// Type to store [Duration] atomically. May be slightly of due to concurrency
-struct AtomicTimestamp(AtomicU64, AtomicU32);
+pub(crate) struct AtomicTimestamp(AtomicU64, AtomicU32);
 
-fn timestamp_now() -> Duration {
+pub(crate) fn timestamp_now() -> Duration {
 	SystemTime::now()
 		.duration_since(SystemTime::UNIX_EPOCH)
-		.unwrap_or(Default::default())
+		.unwrap_or_default()
 }
 
 impl AtomicTimestamp {
@@ -20,12 +24,14 @@ impl AtomicTimestamp {
 	fn u64u32_to_duration(secs: u64, nanos: u32) -> Duration {
 		Duration::new(secs, nanos)
 	}
-	fn now() -> Self {
-		let now = timestamp_now();
-		let (secs, nanos) = Self::duration_to_u64u32(&now);
+	pub(crate) fn now() -> Self {
+		Self::from_duration(&timestamp_now())
+	}
+	fn from_duration(duration: &Duration) -> Self {
+		let (secs, nanos) = Self::duration_to_u64u32(duration);
 		Self(AtomicU64::from(secs), AtomicU32::from(nanos))
 	}
-	fn load(&self) -> Duration {
+	pub(crate) fn load(&self) -> Duration {
 		let secs = self.0.load(Ordering::Acquire);
 		let nanos = self.1.load(Ordering::Acquire);
 		Self::u64u32_to_duration(secs, nanos)
@@ -37,12 +43,43 @@ impl AtomicTimestamp {
 		self.1.store(nanos, Ordering::Release);
 	}
 
-	fn set_to_now(&self) {
+	pub(crate) fn set_to_now(&self) {
 		self.store(&timestamp_now());
 	}
 }
 
-struct TimedMapEntry<Value>(AtomicTimestamp, Value);
+pub(crate) struct TimedMapEntry<Value>(pub(crate) AtomicTimestamp, pub(crate) Value);
+
+/// Counters of the [TimedMap] accesses and evictions.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TimedMapStats {
+	/// Lookups that found the key
+	pub hits: u64,
+	/// Lookups that did not find the key
+	pub misses: u64,
+	/// Elements removed by [timeout](TimedMap::timeout)
+	pub evictions: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+	hits: AtomicU64,
+	misses: AtomicU64,
+	evictions: AtomicU64,
+}
+
+impl Counters {
+	fn lookup<T>(&self, found: Option<T>) -> Option<T> {
+		let counter = match found {
+			Some(_) => &self.hits,
+			None => &self.misses,
+		};
+		counter.fetch_add(1, Ordering::Relaxed);
+		found
+	}
+}
+
+type EvictHook<Key, Val> = Box<dyn Fn(Key, Val) + Send + Sync>;
 
 /// A map that let you remove entries after some time without updates.
 /// This struct won't remove elements automatically, but only when requested
@@ -51,6 +88,17 @@ where
 	Key: Eq + Hash,
 {
 	map: HashMap<Key, TimedMapEntry<Val>>,
+	on_evict: Option<EvictHook<Key, Val>>,
+	counters: Counters,
+}
+
+impl<Key, Val> Default for TimedMap<Key, Val>
+where
+	Key: Eq + Hash,
+{
+	fn default() -> Self {
+		Self::new()
+	}
 }
 
 impl<Key, Val> TimedMap<Key, Val>
@@ -61,9 +109,16 @@ where
 	pub fn new() -> Self {
 		TimedMap {
 			map: HashMap::new(),
+			on_evict: None,
+			counters: Counters::default(),
 		}
 	}
 
+	/// Sets the function called with each entry removed by [timeout](TimedMap::timeout).
+	pub fn on_evict(&mut self, hook: impl Fn(Key, Val) + Send + Sync + 'static) {
+		self.on_evict = Some(Box::new(hook));
+	}
+
 	///Returns true if the map contains no elements.
 	pub fn is_empty(&self) -> bool {
 		self.map.is_empty()
@@ -90,25 +145,193 @@ where
 		Key: Borrow<Q>,
 		Q: Hash + Eq + ?Sized,
 	{
-		self.map.get(key).map(|entry| {
+		let found = self.map.get(key).map(|entry| {
 			entry.0.set_to_now();
 			&entry.1
-		})
+		});
+		self.counters.lookup(found)
+	}
+
+	/// Returns a mutable reference to the stored element, and update it's timestamp to now.
+	pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut Val>
+	where
+		Key: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		let found = self.map.get_mut(key).map(|entry| {
+			entry.0.set_to_now();
+			&mut entry.1
+		});
+		self.counters.lookup(found)
+	}
+
+	/// Returns true if the map contains the key, and update it's timestamp to now.
+	pub fn contains_key<Q>(&self, key: &Q) -> bool
+	where
+		Key: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		self.get(key).is_some()
+	}
+
+	/// Iterates over the elements with the time since their last access, without updating their timestamps.
+	pub fn iter(&self) -> impl Iterator<Item = (&Key, &Val, Duration)> {
+		let now = timestamp_now();
+		self.map
+			.iter()
+			.map(move |(key, entry)| (key, &entry.1, now.saturating_sub(entry.0.load())))
+	}
+
+	/// Gets the entry of the key, for in-place manipulation.
+	pub fn entry(&mut self, key: Key) -> TimedEntry<'_, Key, Val> {
+		TimedEntry(self.map.entry(key))
 	}
 
 	/// Removes all elements that have not been accessed for a period greater than [duration](Duration).
 	pub fn timeout(&mut self, duration: Duration) {
 		let expired = timestamp_now() - duration;
-		self.map.retain(|_, entry| entry.0.load() >= expired)
+		let before = self.map.len();
+		match &self.on_evict {
+			None => self.map.retain(|_, entry| entry.0.load() >= expired),
+			Some(on_evict) => {
+				let (kept, evicted): (HashMap<_, _>, HashMap<_, _>) = std::mem::take(&mut self.map)
+					.into_iter()
+					.partition(|(_, entry)| entry.0.load() >= expired);
+				self.map = kept;
+				for (key, entry) in evicted {
+					on_evict(key, entry.1);
+				}
+			}
+		}
+		let evicted = (before - self.map.len()) as u64;
+		self.counters
+			.evictions
+			.fetch_add(evicted, Ordering::Relaxed);
+	}
+
+	/// Returns the counters of hits and misses on lookups, and of evicted elements.
+	pub fn stats(&self) -> TimedMapStats {
+		TimedMapStats {
+			hits: self.counters.hits.load(Ordering::Relaxed),
+			misses: self.counters.misses.load(Ordering::Relaxed),
+			evictions: self.counters.evictions.load(Ordering::Relaxed),
+		}
+	}
+}
+
+/// Copy of the [TimedMap] values and timestamps, that can be serialized to restore the map later.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TimedMapSnapshot<Key, Val> {
+	/// Entries with the time of their last access, since the unix epoch
+	entries: Vec<(Key, Val, Duration)>,
+}
+
+impl<Key, Val> TimedMap<Key, Val>
+where
+	Key: Eq + Hash + Clone,
+	Val: Clone,
+{
+	/// Copies the elements and their timestamps, without updating them.
+	pub fn snapshot(&self) -> TimedMapSnapshot<Key, Val> {
+		let entries = self
+			.map
+			.iter()
+			.map(|(key, entry)| (key.clone(), entry.1.clone(), entry.0.load()))
+			.collect();
+		TimedMapSnapshot { entries }
+	}
+}
+
+impl<Key, Val> From<TimedMapSnapshot<Key, Val>> for TimedMap<Key, Val>
+where
+	Key: Eq + Hash,
+{
+	/// Restores the map, keeping the snapshot timestamps so the time while stopped counts for the timeout.
+	fn from(snapshot: TimedMapSnapshot<Key, Val>) -> Self {
+		let map = snapshot
+			.entries
+			.into_iter()
+			.map(|(key, value, accessed)| {
+				(
+					key,
+					TimedMapEntry(AtomicTimestamp::from_duration(&accessed), value),
+				)
+			})
+			.collect();
+		TimedMap {
+			map,
+			on_evict: None,
+			counters: Counters::default(),
+		}
+	}
+}
+
+/// A view into a single entry of a [TimedMap], accessing the value updates it's timestamp to now.
+pub struct TimedEntry<'a, Key, Val>(hash_map::Entry<'a, Key, TimedMapEntry<Val>>);
+
+impl<'a, Key, Val> TimedEntry<'a, Key, Val> {
+	pub fn key(&self) -> &Key {
+		self.0.key()
+	}
+
+	/// Modifies the value, if the entry is occupied.
+	pub fn and_modify(self, modify: impl FnOnce(&mut Val)) -> Self {
+		Self(self.0.and_modify(|entry| {
+			entry.0.set_to_now();
+			modify(&mut entry.1)
+		}))
+	}
+
+	/// Inserts the value if the entry is vacant, and returns a mutable reference to the stored value.
+	pub fn or_insert(self, default: Val) -> &'a mut Val {
+		self.or_insert_with(|| default)
+	}
+
+	/// Inserts the result of the function if the entry is vacant, and returns a mutable reference to the stored value.
+	pub fn or_insert_with(self, default: impl FnOnce() -> Val) -> &'a mut Val {
+		let entry = self
+			.0
+			.or_insert_with(|| TimedMapEntry(AtomicTimestamp::now(), default()));
+		entry.0.set_to_now();
+		&mut entry.1
+	}
+}
+
+impl<Key, Val> TimedMap<Key, Val>
+where
+	Key: Eq + Hash + Send + Sync + 'static,
+	Val: Send + Sync + 'static,
+{
+	/// Spawns a task that calls [timeout](TimedMap::timeout) with the [ttl](Duration) every [interval](Duration).
+	/// The task stops when the map is dropped.
+	pub fn spawn_evictor(
+		map: &Arc<RwLock<Self>>,
+		interval: Duration,
+		ttl: Duration,
+	) -> JoinHandle<()> {
+		let map = Arc::downgrade(map);
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(interval);
+			loop {
+				interval.tick().await;
+				let Some(map) = map.upgrade() else {
+					return;
+				};
+				map.write()
+					.unwrap_or_else(|err| err.into_inner())
+					.timeout(ttl);
+			}
+		})
 	}
 }
 
 #[cfg(test)]
 mod test {
+	use std::sync::{Arc, Mutex, RwLock};
 	use std::thread::sleep;
 	use std::time::Duration;
 
-	use super::TimedMap;
+	use super::{TimedMap, TimedMapSnapshot, TimedMapStats};
 
 	const TEST_TIMEOUT: Duration = Duration::from_millis(10);
 
@@ -175,7 +398,7 @@ mod test {
 		let mut map = TimedMap::new();
 		let key = 123456;
 		let value = 789;
-		map.insert(key.clone(), value.clone());
+		map.insert(key, value);
 		let got = map.get(&key).expect("Should get the stored value");
 		assert_eq!(got, &value)
 	}
@@ -224,4 +447,154 @@ mod test {
 			"Timeout should not remove because the get updates the timestamp"
 		)
 	}
+
+	#[test]
+	fn new_timed_map_get_mut_modifies_value() {
+		let mut map = TimedMap::new();
+		map.insert("KEY", 1);
+		*map.get_mut("KEY").expect("Should get the stored value") += 1;
+		assert_eq!(map.get("KEY"), Some(&2))
+	}
+
+	#[test]
+	fn new_timed_map_contains_key_after_insert() {
+		let mut map = TimedMap::new();
+		map.insert("KEY", "Value");
+		assert!(map.contains_key("KEY"));
+		assert!(!map.contains_key("Other key"))
+	}
+
+	#[test]
+	fn new_timed_map_entry_inserts_then_modifies() {
+		let mut map = TimedMap::new();
+		map.entry("KEY")
+			.and_modify(|count| *count += 1)
+			.or_insert(1);
+		map.entry("KEY")
+			.and_modify(|count| *count += 1)
+			.or_insert(1);
+		assert_eq!(map.get("KEY"), Some(&2))
+	}
+
+	#[test]
+	fn new_timed_map_timeout_after_entry_should_not_remove() {
+		let mut map = TimedMap::new();
+		map.insert("KEY", 1);
+
+		sleep(TEST_TIMEOUT);
+		map.entry("KEY").or_insert(2);
+		map.timeout(TEST_TIMEOUT);
+
+		assert!(!map.is_empty(), "Entry should update the timestamp")
+	}
+
+	#[test]
+	fn new_timed_map_iter_has_time_since_access() {
+		let mut map = TimedMap::new();
+		map.insert("KEY", "Value");
+		sleep(TEST_TIMEOUT);
+
+		let entries: Vec<_> = map.iter().collect();
+		assert_eq!(entries.len(), 1);
+		let (key, value, idle) = entries[0];
+		assert_eq!((*key, *value), ("KEY", "Value"));
+		assert!(idle >= TEST_TIMEOUT, "Should be idle since the insert")
+	}
+
+	#[test]
+	fn new_timed_map_iter_does_not_update_timestamp() {
+		let mut map = TimedMap::new();
+		map.insert("KEY", "Value");
+
+		sleep(TEST_TIMEOUT);
+		assert_eq!(map.iter().count(), 1);
+		map.timeout(TEST_TIMEOUT);
+
+		assert!(map.is_empty(), "Iter should not update the timestamp")
+	}
+
+	#[test]
+	fn new_timed_map_restored_from_serialized_snapshot_has_values() {
+		let mut map = TimedMap::new();
+		map.insert("KEY".to_string(), 123);
+
+		let json = serde_json::to_string(&map.snapshot()).unwrap();
+		let snapshot: TimedMapSnapshot<String, i32> = serde_json::from_str(&json).unwrap();
+		let restored = TimedMap::from(snapshot);
+
+		assert_eq!(restored.get("KEY"), Some(&123))
+	}
+
+	#[test]
+	fn new_timed_map_restored_keeps_timestamps() {
+		let mut map = TimedMap::new();
+		map.insert("KEY", "Value");
+		let snapshot = map.snapshot();
+
+		sleep(TEST_TIMEOUT);
+		let mut restored = TimedMap::from(snapshot);
+		restored.timeout(TEST_TIMEOUT);
+
+		assert!(restored.is_empty(), "Should keep the insert timestamp")
+	}
+
+	#[test]
+	fn new_timed_map_stats_count_hits_misses_and_evictions() {
+		let mut map = TimedMap::new();
+		map.insert("KEY", "Value");
+		map.insert("Other key", "Other value");
+		map.get("KEY");
+		map.get("Missing");
+		assert!(!map.contains_key("Missing"));
+
+		sleep(TEST_TIMEOUT);
+		map.timeout(TEST_TIMEOUT);
+
+		let expected = TimedMapStats {
+			hits: 1,
+			misses: 2,
+			evictions: 2,
+		};
+		assert_eq!(map.stats(), expected)
+	}
+
+	#[test]
+	fn timeout_calls_on_evict_with_removed_entries() {
+		let evicted = Arc::new(Mutex::new(Vec::new()));
+		let mut map = TimedMap::new();
+		let hook_evicted = evicted.clone();
+		map.on_evict(move |key, value| hook_evicted.lock().unwrap().push((key, value)));
+		map.insert("KEY", "Value");
+
+		sleep(TEST_TIMEOUT);
+		map.insert("Other key", "Other value");
+		map.timeout(TEST_TIMEOUT);
+
+		assert_eq!(*evicted.lock().unwrap(), [("KEY", "Value")]);
+		assert!(map.get("Other key").is_some(), "Should keep the new entry")
+	}
+
+	#[tokio::test]
+	async fn spawn_evictor_removes_expired() {
+		let map = Arc::new(RwLock::new(TimedMap::new()));
+		map.write().unwrap().insert("KEY", "Value");
+
+		TimedMap::spawn_evictor(&map, TEST_TIMEOUT, TEST_TIMEOUT);
+		tokio::time::sleep(TEST_TIMEOUT * 5).await;
+
+		assert!(map.read().unwrap().is_empty(), "Evictor should remove")
+	}
+
+	#[tokio::test]
+	async fn spawn_evictor_stops_after_map_dropped() {
+		let map: Arc<RwLock<TimedMap<&str, &str>>> = Default::default();
+
+		let evictor = TimedMap::spawn_evictor(&map, TEST_TIMEOUT, TEST_TIMEOUT);
+		drop(map);
+
+		tokio::time::timeout(TEST_TIMEOUT * 10, evictor)
+			.await
+			.expect("Evictor should stop")
+			.unwrap();
+	}
 }
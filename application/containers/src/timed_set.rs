@@ -0,0 +1,127 @@
+use std::borrow::Borrow;
+use std::hash::Hash;
+use std::time::Duration;
+
+use crate::TimedMap;
+
+/// A set that let you remove elements after some time without updates.
+/// This struct won't remove elements automatically, but only when requested
+pub struct TimedSet<Key>
+where
+	Key: Eq + Hash,
+{
+	map: TimedMap<Key, ()>,
+}
+
+impl<Key> Default for TimedSet<Key>
+where
+	Key: Eq + Hash,
+{
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<Key> TimedSet<Key>
+where
+	Key: Eq + Hash,
+{
+	/// Creates an empty TimedSet.
+	pub fn new() -> Self {
+		TimedSet {
+			map: TimedMap::new(),
+		}
+	}
+
+	///Returns true if the set contains no elements.
+	pub fn is_empty(&self) -> bool {
+		self.map.is_empty()
+	}
+
+	/// Insert the element, and set its timestamp to current time.
+	/// Returns true if the element was not present.
+	pub fn insert(&mut self, key: Key) -> bool {
+		let mut inserted = false;
+		self.map.entry(key).or_insert_with(|| inserted = true);
+		inserted
+	}
+
+	/// Removes the element, returns true if it was present.
+	pub fn remove<Q>(&mut self, key: &Q) -> bool
+	where
+		Key: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		self.map.remove(key).is_some()
+	}
+
+	/// Returns true if the set contains the element, and update it's timestamp to now.
+	pub fn contains<Q>(&self, key: &Q) -> bool
+	where
+		Key: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		self.map.contains_key(key)
+	}
+
+	/// Removes all elements that have not been accessed for a period greater than [duration](Duration).
+	pub fn timeout(&mut self, duration: Duration) {
+		self.map.timeout(duration)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::thread::sleep;
+	use std::time::Duration;
+
+	use super::TimedSet;
+
+	const TEST_TIMEOUT: Duration = Duration::from_millis(10);
+
+	#[test]
+	fn new_timed_set_is_empty() {
+		let set: TimedSet<String> = TimedSet::new();
+		assert!(set.is_empty())
+	}
+
+	#[test]
+	fn timed_set_insert_returns_if_new() {
+		let mut set = TimedSet::new();
+		assert!(set.insert("KEY"), "First insert should be new");
+		assert!(!set.insert("KEY"), "Second insert should not be new");
+		assert!(set.contains("KEY"))
+	}
+
+	#[test]
+	fn timed_set_remove_returns_if_present() {
+		let mut set = TimedSet::new();
+		set.insert("KEY");
+		assert!(set.remove("KEY"));
+		assert!(!set.remove("KEY"));
+		assert!(set.is_empty())
+	}
+
+	#[test]
+	fn timed_set_timeout_with_delay_will_remove() {
+		let mut set = TimedSet::new();
+		set.insert("KEY");
+
+		sleep(TEST_TIMEOUT);
+		set.timeout(TEST_TIMEOUT);
+
+		assert!(set.is_empty(), "Timeout should remove after the sleep")
+	}
+
+	#[test]
+	fn timed_set_timeout_after_contains_should_not_remove() {
+		let mut set = TimedSet::new();
+		set.insert("KEY");
+
+		sleep(TEST_TIMEOUT);
+		assert!(set.contains("KEY"));
+		set.timeout(TEST_TIMEOUT);
+
+		assert!(!set.is_empty(), "Contains should update the timestamp")
+	}
+}
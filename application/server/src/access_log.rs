@@ -0,0 +1,341 @@
+//! Access log, one line per request, written apart from the application logs
+//!
+//! The file is rotated when it exceeds the configured size or age, the previous files are kept as
+//! `<path>.1` (the newest) up to `<path>.<keep>`
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::header;
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::Router;
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use crate::config::{AccessLogConfig, AccessLogFormat};
+
+///Lines waiting for the writer, new ones are dropped when it falls behind
+const LINE_BUFFER: usize = 4096;
+
+#[derive(Serialize)]
+struct Entry<'a> {
+	///Seconds since the unix epoch
+	time: f64,
+	remote: Option<String>,
+	method: &'a str,
+	path: &'a str,
+	version: String,
+	status: u16,
+	bytes: Option<u64>,
+	duration_ms: f64,
+}
+
+struct Current {
+	file: File,
+	size: u64,
+	///When the file was created, or opened if the filesystem does not record it
+	created: SystemTime,
+}
+
+pub struct AccessLog {
+	path: PathBuf,
+	format: AccessLogFormat,
+	max_size: Option<u64>,
+	max_age: Option<Duration>,
+	keep: u32,
+	current: Current,
+}
+
+///Sends the formatted lines of the requests to the writer thread
+#[derive(Clone)]
+struct Lines {
+	format: AccessLogFormat,
+	sender: mpsc::Sender<String>,
+}
+
+impl AccessLog {
+	///Open the configured log, appending to the existing file
+	pub fn open(path: &Path, config: &AccessLogConfig) -> io::Result<Self> {
+		Ok(Self {
+			path: path.to_path_buf(),
+			format: config.format,
+			max_size: config.max_size,
+			max_age: config.max_age.map(Duration::from_secs),
+			keep: config.keep,
+			current: Self::open_current(path)?,
+		})
+	}
+
+	fn open_current(path: &Path) -> io::Result<Current> {
+		let file = OpenOptions::new().create(true).append(true).open(path)?;
+		let metadata = file.metadata()?;
+		Ok(Current {
+			file,
+			size: metadata.len(),
+			created: metadata.created().unwrap_or_else(|_| SystemTime::now()),
+		})
+	}
+
+	fn rotated(&self, idx: u32) -> PathBuf {
+		let mut path = self.path.clone().into_os_string();
+		path.push(format!(".{idx}"));
+		path.into()
+	}
+
+	fn rotate(&mut self) -> io::Result<()> {
+		if self.keep == 0 {
+			std::fs::remove_file(&self.path)?;
+		} else {
+			for idx in (1..self.keep).rev() {
+				match std::fs::rename(self.rotated(idx), self.rotated(idx + 1)) {
+					Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+					_ => {}
+				}
+			}
+			std::fs::rename(&self.path, self.rotated(1))?;
+		}
+		self.current = Self::open_current(&self.path)?;
+		Ok(())
+	}
+
+	fn write(&mut self, line: &str) -> io::Result<()> {
+		let size = self.current.size;
+		let too_big = self
+			.max_size
+			.is_some_and(|max| size + line.len() as u64 > max);
+		let age = SystemTime::now()
+			.duration_since(self.current.created)
+			.unwrap_or_default();
+		let too_old = self.max_age.is_some_and(|max| age >= max);
+		if size > 0 && (too_big || too_old) {
+			self.rotate()?;
+		}
+		self.current.file.write_all(line.as_bytes())?;
+		self.current.size += line.len() as u64;
+		Ok(())
+	}
+
+	///Write the lines until every sender is dropped
+	fn run(mut self, mut lines: mpsc::Receiver<String>) {
+		while let Some(line) = lines.blocking_recv() {
+			if let Err(err) = self.write(&line) {
+				warn!(%err, "Failed to write the access log");
+			}
+		}
+	}
+
+	///Record every request handled by the router, the file is written by a separate thread
+	pub fn layer(self, router: Router) -> Router {
+		let (sender, receiver) = mpsc::channel(LINE_BUFFER);
+		let lines = Lines {
+			format: self.format,
+			sender,
+		};
+		std::thread::Builder::new()
+			.name("access-log".to_string())
+			.spawn(move || self.run(receiver))
+			.expect("Failed to start the access log writer");
+		router.layer(axum::middleware::from_fn_with_state(lines, record))
+	}
+}
+
+fn format_line(format: AccessLogFormat, entry: &Entry) -> io::Result<String> {
+	let mut line = match format {
+		AccessLogFormat::Common => common_line(entry),
+		AccessLogFormat::Json => serde_json::to_string(entry).map_err(io::Error::other)?,
+	};
+	line.push('\n');
+	Ok(line)
+}
+
+async fn record(
+	State(lines): State<Lines>,
+	remote: Option<ConnectInfo<SocketAddr>>,
+	request: Request,
+	next: Next,
+) -> Response {
+	let start = Instant::now();
+	let method = request.method().clone();
+	let path = request.uri().path().to_string();
+	let version = format!("{:?}", request.version());
+	let response = next.run(request).await;
+	let bytes = response
+		.headers()
+		.get(header::CONTENT_LENGTH)
+		.and_then(|len| len.to_str().ok()?.parse().ok());
+	let entry = Entry {
+		time: SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.unwrap_or_default()
+			.as_secs_f64(),
+		remote: remote.map(|ConnectInfo(addr)| addr.ip().to_string()),
+		method: method.as_str(),
+		path: &path,
+		version,
+		status: response.status().as_u16(),
+		bytes,
+		duration_ms: start.elapsed().as_secs_f64() * 1000.0,
+	};
+	let sent = format_line(lines.format, &entry)
+		.and_then(|line| lines.sender.try_send(line).map_err(io::Error::other));
+	if let Err(err) = sent {
+		warn!(%err, "Failed to record the access log");
+	}
+	response
+}
+
+fn common_line(entry: &Entry) -> String {
+	let remote = entry.remote.as_deref().unwrap_or("-");
+	let bytes = entry
+		.bytes
+		.map(|bytes| bytes.to_string())
+		.unwrap_or_else(|| "-".to_string());
+	format!(
+		"{remote} - - [{}] \"{} {} {}\" {} {bytes}",
+		common_time(entry.time as u64),
+		entry.method,
+		entry.path,
+		entry.version,
+		entry.status,
+	)
+}
+
+///Format the unix time as `10/Oct/2000:13:55:36 +0000`
+fn common_time(secs: u64) -> String {
+	const MONTHS: [&str; 12] = [
+		"Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+	];
+	let days = (secs / 86400) as i64;
+	let time = secs % 86400;
+	//Civil date from days since the epoch, from Howard Hinnant's date algorithms
+	let z = days + 719468;
+	let era = z.div_euclid(146097);
+	let doe = z.rem_euclid(146097);
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + i64::from(month <= 2);
+	format!(
+		"{day:02}/{}/{year}:{:02}:{:02}:{:02} +0000",
+		MONTHS[month as usize - 1],
+		time / 3600,
+		time / 60 % 60,
+		time % 60
+	)
+}
+
+#[cfg(test)]
+mod test {
+	use axum::routing::get;
+	use axum_test::TestServer;
+	use uuid::Uuid;
+
+	use super::*;
+
+	fn log_path() -> PathBuf {
+		std::env::temp_dir().join(format!("access-{}.log", Uuid::new_v4()))
+	}
+
+	fn entry() -> Entry<'static> {
+		Entry {
+			time: 971185536.0,
+			remote: Some("127.0.0.1".to_string()),
+			method: "GET",
+			path: "/api/job",
+			version: "HTTP/1.1".to_string(),
+			status: 200,
+			bytes: Some(2326),
+			duration_ms: 1.0,
+		}
+	}
+
+	#[test]
+	fn common_time_formats_utc() {
+		assert_eq!(common_time(971185536), "10/Oct/2000:13:45:36 +0000");
+		assert_eq!(common_time(951782400), "29/Feb/2000:00:00:00 +0000");
+	}
+
+	#[test]
+	fn common_line_format() {
+		let line = common_line(&entry());
+		assert_eq!(
+			line,
+			"127.0.0.1 - - [10/Oct/2000:13:45:36 +0000] \"GET /api/job HTTP/1.1\" 200 2326"
+		)
+	}
+
+	#[test]
+	fn rotate_when_size_exceeded() {
+		let path = log_path();
+		let config = AccessLogConfig {
+			max_size: Some(10),
+			..Default::default()
+		};
+		let mut log = AccessLog::open(&path, &config).unwrap();
+		let line = format_line(AccessLogFormat::Common, &entry()).unwrap();
+		log.write(&line).unwrap();
+		log.write(&line).unwrap();
+		let current = std::fs::read_to_string(&path).unwrap();
+		let rotated = std::fs::read_to_string(log.rotated(1)).unwrap();
+		std::fs::remove_file(&path).unwrap();
+		std::fs::remove_file(log.rotated(1)).unwrap();
+		assert_eq!(current.lines().count(), 1);
+		assert_eq!(rotated.lines().count(), 1);
+	}
+
+	#[test]
+	fn rotate_when_age_exceeded() {
+		let path = log_path();
+		let config = AccessLogConfig {
+			max_age: Some(0),
+			..Default::default()
+		};
+		let mut log = AccessLog::open(&path, &config).unwrap();
+		let line = format_line(AccessLogFormat::Common, &entry()).unwrap();
+		log.write(&line).unwrap();
+		log.write(&line).unwrap();
+		let current = std::fs::read_to_string(&path).unwrap();
+		let rotated = std::fs::read_to_string(log.rotated(1)).unwrap();
+		std::fs::remove_file(&path).unwrap();
+		std::fs::remove_file(log.rotated(1)).unwrap();
+		assert_eq!(current.lines().count(), 1);
+		assert_eq!(rotated.lines().count(), 1);
+	}
+
+	#[tokio::test]
+	async fn layer_writes_json_line_per_request() {
+		let path = log_path();
+		let config = AccessLogConfig {
+			format: AccessLogFormat::Json,
+			..Default::default()
+		};
+		let log = AccessLog::open(&path, &config).unwrap();
+		let router = log.layer(Router::new().route("/version", get(|| async { "1" })));
+		let server = TestServer::new(router).unwrap();
+		server.get("/version").await.assert_status_ok();
+		server.get("/missing").await;
+		drop(server);
+		let mut content = String::new();
+		for _ in 0..100 {
+			content = std::fs::read_to_string(&path).unwrap();
+			if content.lines().count() == 2 {
+				break;
+			}
+			tokio::time::sleep(Duration::from_millis(10)).await;
+		}
+		std::fs::remove_file(&path).unwrap();
+		let statuses: Vec<u64> = content
+			.lines()
+			.map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+			.map(|entry| entry["status"].as_u64().unwrap())
+			.collect();
+		assert_eq!(statuses, [200, 404]);
+	}
+}
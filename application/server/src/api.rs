@@ -1,22 +1,41 @@
 //! Api based on api.yaml spec
 
-use std::sync::Arc;
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock, RwLock};
 
 use axum::body::Body;
 use axum::extract::{FromRequestParts, State};
+use axum::handler::Handler;
 use axum::http::request::Parts;
 use axum::http::{header, HeaderMap, HeaderName, StatusCode};
-use axum::response::IntoResponse;
-use axum::routing::{get, post};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{on, MethodFilter, MethodRouter};
 use axum::{Json, Router};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tracing::{instrument, warn};
+use uuid::Uuid;
 
+use api::server::{operations, Method, Operations};
 use auth_module::AuthenticationHandler;
 use task::manager::Manager;
 use task::{Input, JobSource, Recipe, TaskSource};
 
-use crate::api::utils::parse::parse_job_options;
+use crate::api::utils::error::ErrorStatus;
+use crate::api::utils::parse::{
+	parse_analysis_options, parse_job_options, parse_priority, parse_streams, parse_trim,
+};
+use crate::config::QuotaConfig;
+use crate::logging::LogFilter;
+use crate::probe::probe;
+use crate::quota::{BodyLimit, Credential, Quotas};
 use crate::storage::{MemStorage, Storage};
+use crate::webhook::{Notifier, WebhookNotifier};
 
+mod admin;
 mod client;
 mod utils;
 mod worker;
@@ -25,7 +44,32 @@ pub trait AppState: Sync + Send {
 	fn manager(&self) -> &impl Manager;
 	fn auth_handler(&self) -> &impl AuthenticationHandler;
 	fn storage(&self) -> &impl Storage;
+	fn notifier(&self) -> &impl Notifier;
 	fn check_credential(&self, cred: &str) -> bool;
+	///Credential of the admin, when set the other tokens only access the jobs created with theirs
+	fn admin_credential(&self) -> Option<&str> {
+		None
+	}
+	///Filter of the server logs, when it can be changed at runtime
+	fn log_filter(&self) -> Option<&LogFilter> {
+		None
+	}
+	///Limits of each credential, when set
+	fn quotas(&self) -> Option<&Quotas> {
+		None
+	}
+	///ffprobe binary that validates the job sources, when set
+	fn ffprobe(&self) -> Option<&Path> {
+		None
+	}
+	///ffmpeg binary that joins the finished segments of the partial outputs, when set
+	fn ffmpeg(&self) -> Option<&Path> {
+		None
+	}
+	///Names the workers gave at login, when they are kept
+	fn worker_names(&self) -> Option<&WorkerNames> {
+		None
+	}
 }
 
 #[derive(Default)]
@@ -34,6 +78,13 @@ pub struct AppStateLocal {
 	_auth_handler: auth_module::LocalAuthenticator,
 	_manager: task::manager::LocalJobManager,
 	_storage: MemStorage,
+	_notifier: WebhookNotifier,
+	log_filter: Option<LogFilter>,
+	admin_credential: Option<String>,
+	quotas: Option<Quotas>,
+	ffprobe: Option<PathBuf>,
+	ffmpeg: Option<PathBuf>,
+	worker_names: WorkerNames,
 }
 
 impl AppState for AppStateLocal {
@@ -46,9 +97,30 @@ impl AppState for AppStateLocal {
 	fn storage(&self) -> &impl Storage {
 		&self._storage
 	}
+	fn notifier(&self) -> &impl Notifier {
+		&self._notifier
+	}
 	fn check_credential(&self, cred: &str) -> bool {
 		self.credential == cred
 	}
+	fn log_filter(&self) -> Option<&LogFilter> {
+		self.log_filter.as_ref()
+	}
+	fn admin_credential(&self) -> Option<&str> {
+		self.admin_credential.as_deref()
+	}
+	fn quotas(&self) -> Option<&Quotas> {
+		self.quotas.as_ref()
+	}
+	fn ffprobe(&self) -> Option<&Path> {
+		self.ffprobe.as_deref()
+	}
+	fn ffmpeg(&self) -> Option<&Path> {
+		self.ffmpeg.as_deref()
+	}
+	fn worker_names(&self) -> Option<&WorkerNames> {
+		Some(&self.worker_names)
+	}
 }
 
 impl AppStateLocal {
@@ -58,6 +130,88 @@ impl AppStateLocal {
 			..Default::default()
 		}
 	}
+
+	///Copy the stored files to the directory, restoring the ones already there
+	pub fn with_storage_dir(self, path: &Path) -> std::io::Result<AppStateLocal> {
+		Ok(AppStateLocal {
+			_storage: MemStorage::with_dir(path)?,
+			..self
+		})
+	}
+
+	///Persist the jobs on the journal file, restoring the ones already recorded
+	///
+	///Requires a [storage directory](Self::with_storage_dir), the jobs would lose their files otherwise
+	pub async fn with_journal(
+		self,
+		path: &std::path::Path,
+	) -> Result<AppStateLocal, task::TaskError> {
+		if !self._storage.is_persistent() {
+			let err = "The journal requires a storage directory";
+			let err = std::io::Error::new(std::io::ErrorKind::InvalidInput, err);
+			return Err(task::TaskError::StorageUnavailable(err));
+		}
+		Ok(AppStateLocal {
+			_manager: task::manager::LocalJobManager::with_journal(path).await?,
+			..self
+		})
+	}
+
+	///Separate the admin from the other logins, that only access the jobs created with their credential
+	pub fn with_admin_cred(self, cred: &str) -> AppStateLocal {
+		AppStateLocal {
+			admin_credential: Some(cred.into()),
+			..self
+		}
+	}
+
+	///Limit the jobs, queued tasks and stored bytes of each credential
+	pub fn with_quotas(self, config: QuotaConfig) -> AppStateLocal {
+		AppStateLocal {
+			quotas: Some(Quotas::new(config)),
+			..self
+		}
+	}
+
+	///Probe the job sources with ffprobe, rejecting the ones that are not a video
+	pub fn with_ffprobe(self, ffprobe: &Path) -> AppStateLocal {
+		AppStateLocal {
+			ffprobe: Some(ffprobe.into()),
+			..self
+		}
+	}
+
+	///Stream the partial outputs, joining their finished segments with ffmpeg
+	pub fn with_ffmpeg(self, ffmpeg: &Path) -> AppStateLocal {
+		AppStateLocal {
+			ffmpeg: Some(ffmpeg.into()),
+			..self
+		}
+	}
+
+	///Allow changing the log filter with the admin api
+	pub fn with_log_filter(self, log_filter: LogFilter) -> AppStateLocal {
+		AppStateLocal {
+			log_filter: Some(log_filter),
+			..self
+		}
+	}
+
+	///Use this secret to sign the job events sent to callbacks
+	pub fn with_webhook_secret(self, secret: &str) -> AppStateLocal {
+		AppStateLocal {
+			_notifier: WebhookNotifier::new(secret),
+			..self
+		}
+	}
+
+	///Send the job events to these hosts, even if they resolve to private addresses
+	pub fn with_webhook_allowed_hosts(self, hosts: &[String]) -> AppStateLocal {
+		AppStateLocal {
+			_notifier: self._notifier.allowing(hosts),
+			..self
+		}
+	}
 }
 
 struct AuthToken(String);
@@ -88,32 +242,247 @@ impl<S: AppState> FromRequestParts<Arc<S>> for AuthToken {
 	}
 }
 
+///Permission of the tokens logged in with the admin credential
+const ADMIN_PERMISSION: Uuid = Uuid::nil();
+
+impl AuthToken {
+	///Without an admin credential every token is an admin
+	async fn is_admin<S: AppState + ?Sized>(&self, state: &S) -> bool {
+		state.admin_credential().is_none()
+			|| state
+				.auth_handler()
+				.check(&self.0, ADMIN_PERMISSION)
+				.await
+				.unwrap_or_default()
+	}
+
+	///Credential the token logged in with, the user one while there is no admin credential
+	async fn credential<S: AppState + ?Sized>(&self, state: &S) -> Credential {
+		match state.admin_credential().is_some() && self.is_admin(state).await {
+			true => Credential::Admin,
+			false => Credential::User,
+		}
+	}
+
+	///The job was created with the credential of the token, or the token is an admin
+	async fn owns<S: AppState + ?Sized>(&self, state: &S, job: &JobSource) -> bool {
+		job.owner.as_deref() == Some(self.credential(state).await.owner())
+			|| self.is_admin(state).await
+	}
+}
+
+///Opaque id of the token, recorded and listed in place of the token itself
+///
+///The tokens may be guessable counters, so the id is keyed with a secret of the process
+fn token_id(token: &str) -> String {
+	static KEY: OnceLock<Uuid> = OnceLock::new();
+	let key = KEY.get_or_init(Uuid::new_v4);
+	let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).unwrap();
+	mac.update(token.as_bytes());
+	mac.finalize().into_bytes()[..8]
+		.iter()
+		.map(|byte| format!("{byte:02x}"))
+		.collect()
+}
+
+///Names the workers gave at login, by the id of their token
+///
+///A worker logs in again after a restart, so its allocations may be held by several tokens
+#[derive(Default)]
+pub struct WorkerNames(RwLock<BTreeMap<String, String>>);
+
+impl WorkerNames {
+	fn insert(&self, worker: String, name: String) {
+		let mut names = self.0.write().unwrap_or_else(|poison| poison.into_inner());
+		names.insert(worker, name);
+	}
+
+	///Name of the worker, or its id when it gave none
+	pub(crate) fn name_of(&self, worker: &str) -> String {
+		let names = self.0.read().unwrap_or_else(|poison| poison.into_inner());
+		names
+			.get(worker)
+			.cloned()
+			.unwrap_or_else(|| worker.to_string())
+	}
+}
+
+///Token of an admin, required by the admin api
+struct AdminToken;
+
+#[async_trait::async_trait]
+impl<S: AppState> FromRequestParts<Arc<S>> for AdminToken {
+	type Rejection = (StatusCode, &'static str);
+
+	async fn from_request_parts(
+		parts: &mut Parts,
+		state: &Arc<S>,
+	) -> Result<Self, Self::Rejection> {
+		let auth = AuthToken::from_request_parts(parts, state).await?;
+		match auth.is_admin(state.as_ref()).await {
+			true => Ok(AdminToken),
+			false => Err((StatusCode::FORBIDDEN, "Admin only")),
+		}
+	}
+}
+
+///Worker api revisions supported by this server
+fn compatibility() -> api::models::Compatibility {
+	api::models::Compatibility {
+		server_version: env!("CARGO_PKG_VERSION").to_string(),
+		api_revision: api::API_REVISION,
+		min_worker_api_revision: api::MIN_WORKER_API_REVISION,
+	}
+}
+
+///Handler of an operation, routed to the method of the spec
+type Endpoint<S> = Box<dyn FnOnce(MethodFilter) -> MethodRouter<Arc<S>>>;
+
+fn endpoint<S, H, T>(handler: H) -> Endpoint<S>
+where
+	S: AppState + 'static,
+	H: Handler<T, Arc<S>>,
+	T: 'static,
+{
+	Box::new(move |method| on(method, handler))
+}
+
+fn method_filter(method: Method) -> MethodFilter {
+	match method {
+		Method::Get => MethodFilter::GET,
+		Method::Head => MethodFilter::HEAD,
+		Method::Post => MethodFilter::POST,
+		Method::Put => MethodFilter::PUT,
+		Method::Patch => MethodFilter::PATCH,
+		Method::Delete => MethodFilter::DELETE,
+	}
+}
+
+async fn not_implemented() -> StatusCode {
+	StatusCode::NOT_IMPLEMENTED
+}
+
+///Handlers of the operations in api.yaml, the ones the server lacks respond with 501
+struct Handlers<S>(PhantomData<S>);
+
+impl<S: AppState + 'static> Operations for Handlers<S> {
+	type Handler = Endpoint<S>;
+
+	fn version_get(&self) -> Endpoint<S> {
+		endpoint(|| async { concat!("\"", env!("CARGO_PKG_VERSION"), "\"") })
+	}
+	fn version_compatibility_get(&self) -> Endpoint<S> {
+		endpoint(|| async { Json(compatibility()) })
+	}
+	fn login_get(&self) -> Endpoint<S> {
+		endpoint(login)
+	}
+	fn input_post(&self) -> Endpoint<S> {
+		endpoint(input_post)
+	}
+	fn job_get(&self) -> Endpoint<S> {
+		endpoint(client::get_job_list)
+	}
+	fn job_post(&self) -> Endpoint<S> {
+		endpoint(job_post)
+	}
+	fn job_job_id_info_get(&self) -> Endpoint<S> {
+		endpoint(client::job_info_get)
+	}
+	fn job_job_id_probe_get(&self) -> Endpoint<S> {
+		endpoint(client::job_probe_get)
+	}
+	fn job_job_id_events_get(&self) -> Endpoint<S> {
+		endpoint(client::job_events_get)
+	}
+	fn job_job_id_output_get(&self) -> Endpoint<S> {
+		endpoint(client::job_output_get)
+	}
+	fn job_job_id_thumbnail_get(&self) -> Endpoint<S> {
+		endpoint(client::thumbnail_get)
+	}
+	fn job_job_id_thumbnail_post(&self) -> Endpoint<S> {
+		endpoint(client::thumbnail_post)
+	}
+	fn job_job_id_delete(&self) -> Endpoint<S> {
+		endpoint(not_implemented)
+	}
+	fn job_job_id_input_get(&self) -> Endpoint<S> {
+		endpoint(not_implemented)
+	}
+	fn admin_allocations_get(&self) -> Endpoint<S> {
+		endpoint(admin::get_allocations)
+	}
+	fn admin_metrics_get(&self) -> Endpoint<S> {
+		endpoint(admin::get_metrics)
+	}
+	fn admin_stats_get(&self) -> Endpoint<S> {
+		endpoint(admin::get_stats)
+	}
+	fn admin_log_level_put(&self) -> Endpoint<S> {
+		endpoint(admin::put_log_level)
+	}
+	fn allocate_task_get(&self) -> Endpoint<S> {
+		endpoint(worker::allocate_task)
+	}
+	fn allocate_task_events_get(&self) -> Endpoint<S> {
+		endpoint(worker::allocate_task_events)
+	}
+	fn job_job_id_task_post(&self) -> Endpoint<S> {
+		endpoint(worker::task_post)
+	}
+	fn job_job_id_task_task_id_delete(&self) -> Endpoint<S> {
+		endpoint(worker::task_delete)
+	}
+	fn job_job_id_task_task_id_input_num_get(&self) -> Endpoint<S> {
+		endpoint(worker::get_task_input)
+	}
+	fn job_job_id_task_task_id_output_put(&self) -> Endpoint<S> {
+		endpoint(worker::put_task_output)
+	}
+	fn job_job_id_task_task_id_output_get(&self) -> Endpoint<S> {
+		endpoint(client::task_output_get)
+	}
+	fn job_job_id_task_task_id_output_upload_head(&self) -> Endpoint<S> {
+		endpoint(worker::output_upload_head)
+	}
+	fn job_job_id_task_task_id_output_upload_patch(&self) -> Endpoint<S> {
+		endpoint(worker::output_upload_patch)
+	}
+	fn job_job_id_task_task_id_output_upload_post(&self) -> Endpoint<S> {
+		endpoint(worker::output_upload_post)
+	}
+	fn job_job_id_task_task_id_status_post(&self) -> Endpoint<S> {
+		endpoint(worker::task_status_post)
+	}
+	fn job_job_id_task_task_id_progress_post(&self) -> Endpoint<S> {
+		endpoint(worker::task_progress_post)
+	}
+	fn job_job_id_task_task_id_probe_put(&self) -> Endpoint<S> {
+		endpoint(worker::task_probe_put)
+	}
+}
+
+///Route the operations of api.yaml, each path with the methods of the spec
 pub fn make_router<S: AppState + 'static>(state: Arc<S>) -> Router {
-	Router::<Arc<S>>::new()
-		.route(
-			"/version",
-			get(|| async { concat!("\"", env!("CARGO_PKG_VERSION"), "\"") }),
-		)
-		.route("/login", get(login))
-		.route("/job", get(client::get_job_list).post(job_post))
-		.route(
-			"/job/:job_id/task/:task_id/input/0",
-			get(worker::get_task_input),
-		)
-		.route(
-			"/job/:job_id/task/:task_id/output",
-			get(client::task_output_get).put(worker::put_task_output),
-		)
-		.route(
-			"/job/:job_id/task/:task_id/status",
-			post(worker::task_status_post),
-		)
-		.route("/job/:job_id/task", post(worker::task_post))
-		.route("/job/:job_id/output", get(client::job_output_get))
-		.route("/allocate_task", get(worker::allocate_task))
+	let mut routes = BTreeMap::<&str, MethodRouter<Arc<S>>>::new();
+	for (path, method, endpoint) in operations(&Handlers::<S>(PhantomData)) {
+		let handler = endpoint(method_filter(method));
+		let route = match routes.remove(path) {
+			Some(route) => route.merge(handler),
+			None => handler,
+		};
+		routes.insert(path, route);
+	}
+	routes
+		.into_iter()
+		.fold(Router::new(), |router, (path, route)| {
+			router.route(path, route)
+		})
 		.with_state(state)
 }
 
+#[instrument(skip_all)]
 async fn login<S: AppState>(
 	State(state): State<Arc<S>>,
 	header_map: HeaderMap,
@@ -123,49 +492,263 @@ async fn login<S: AppState>(
 		.map(|v| v.to_str())
 		.transpose()
 		.unwrap_or_default();
-	match credentials {
-		None => Err(StatusCode::BAD_REQUEST),
-		Some(provided) => match state.check_credential(provided) {
-			true => Ok(Json(state.auth_handler().new_token().await)),
-			false => Err(StatusCode::FORBIDDEN),
-		},
+	let provided = credentials.ok_or(StatusCode::BAD_REQUEST)?;
+	if state.admin_credential() == Some(provided) {
+		let token = state.auth_handler().new_token().await;
+		state
+			.auth_handler()
+			.add(&token, ADMIN_PERMISSION)
+			.await
+			.or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+		return Ok(Json(token));
 	}
+	if !state.check_credential(provided) {
+		return Err(StatusCode::FORBIDDEN);
+	}
+	let token = state.auth_handler().new_token().await;
+	let name = header_map
+		.get(HeaderName::from_static("worker_name"))
+		.and_then(|name| name.to_str().ok());
+	if let (Some(names), Some(name)) = (state.worker_names(), name) {
+		names.insert(token_id(&token), name.to_string());
+	}
+	Ok(Json(token))
+}
+
+///Declared length of the body, chunked bodies are limited while received
+fn content_length(headers: &HeaderMap) -> u64 {
+	headers
+		.get(header::CONTENT_LENGTH)
+		.and_then(|len| len.to_str().ok()?.parse().ok())
+		.unwrap_or(0)
 }
 
+#[instrument(skip_all)]
 async fn job_post<S: AppState>(
 	State(state): State<Arc<S>>,
-	_auth: AuthToken,
+	auth: AuthToken,
+	headers: HeaderMap,
+	body: Body,
+) -> Result<Response, Response> {
+	let (body, limit) = match state.quotas() {
+		Some(quotas) => {
+			let credential = auth.credential(state.as_ref()).await;
+			let usage = quotas
+				.usage(state.as_ref(), credential)
+				.await
+				.map_err(|err| err.response().into_response())?;
+			let input_id = headers
+				.get("input_id")
+				.and_then(|val| Uuid::parse_str(val.to_str().ok()?).ok());
+			//Inputs already used with the credential are counted on its usage
+			let requested = match input_id {
+				Some(input_id) if usage.inputs.contains(&input_id) => 0,
+				Some(input_id) => state.storage().file_size(input_id).await.unwrap_or(0),
+				None => content_length(&headers),
+			};
+			quotas
+				.check_job(&usage, requested)
+				.map_err(IntoResponse::into_response)?;
+			let (body, limit) = quotas.limit_body(&usage, body);
+			(body, limit)
+		}
+		None => (body, None),
+	};
+	let job_id = create_job(state.clone(), auth, headers, body)
+		.await
+		.map_err(
+			|status| match limit.as_ref().and_then(BodyLimit::exceeded) {
+				Some(exceeded) => exceeded.into_response(),
+				None => status.into_response(),
+			},
+		)?;
+	Ok((StatusCode::CREATED, job_id.to_string()).into_response())
+}
+
+///Output of the finished job used as the source, created by the same token
+async fn source_job_output<S: AppState>(
+	state: &S,
+	auth: &AuthToken,
+	source_job: Uuid,
+) -> Result<Uuid, StatusCode> {
+	let job = state
+		.manager()
+		.get_job(&source_job)
+		.await
+		.map_err(|err| err.status())?;
+	match job {
+		Some(job) if auth.owns(state, &job).await => {}
+		_ => return Err(StatusCode::NOT_FOUND),
+	}
+	state
+		.manager()
+		.get_job_output(&source_job, None)
+		.await
+		.map_err(|err| err.status())?
+		.ok_or(StatusCode::CONFLICT)
+}
+
+async fn create_job<S: AppState>(
+	state: Arc<S>,
+	auth: AuthToken,
 	headers: HeaderMap,
 	body: Body,
-) -> Result<impl IntoResponse, StatusCode> {
+) -> Result<Uuid, StatusCode> {
 	let options = parse_job_options(&headers)
-		.map(|opt| opt.video.codec.is_some().then_some(opt))
+		.map(|opt| (opt.video.codec.is_some() || opt.preset.is_some()).then_some(opt))
 		.ok()
 		.unwrap_or_default()
 		.ok_or(StatusCode::BAD_REQUEST)?;
+	let callback = headers
+		.get("callback_url")
+		.map(|val| val.to_str().map(String::from))
+		.transpose()
+		.or(Err(StatusCode::BAD_REQUEST))?;
+	let name = headers
+		.get("job_name")
+		.map(|val| String::from_utf8(val.as_bytes().to_vec()))
+		.transpose()
+		.or(Err(StatusCode::BAD_REQUEST))?;
+	let analysis = parse_analysis_options(&headers).or(Err(StatusCode::BAD_REQUEST))?;
+	let (start, end) = parse_trim(&headers).or(Err(StatusCode::BAD_REQUEST))?;
+	let (video_stream, audio_stream) = parse_streams(&headers).or(Err(StatusCode::BAD_REQUEST))?;
+	let priority = parse_priority(&headers).or(Err(StatusCode::BAD_REQUEST))?;
+	if callback
+		.as_deref()
+		.is_some_and(|callback| !state.notifier().accepts(callback))
+	{
+		return Err(StatusCode::BAD_REQUEST);
+	}
 
-	let input_id = state
-		.storage()
-		.body_to_new_file(body)
-		.await
-		.or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+	let input_id = headers
+		.get("input_id")
+		.map(|val| val.to_str().ok().and_then(|id| Uuid::parse_str(id).ok()))
+		.map(|id| id.ok_or(StatusCode::BAD_REQUEST))
+		.transpose()?;
+
+	let source_job = headers
+		.get("source_job")
+		.map(|val| val.to_str().ok().and_then(|id| Uuid::parse_str(id).ok()))
+		.map(|id| id.ok_or(StatusCode::BAD_REQUEST))
+		.transpose()?;
+
+	let input_id = match (source_job, input_id) {
+		(Some(_), Some(_)) => return Err(StatusCode::BAD_REQUEST),
+		(Some(source_job), None) => source_job_output(state.as_ref(), &auth, source_job).await?,
+		(None, Some(input_id)) => {
+			state
+				.storage()
+				.file_size(input_id)
+				.await
+				.map_err(|err| match err.kind() {
+					ErrorKind::NotFound => StatusCode::NOT_FOUND,
+					_ => StatusCode::INTERNAL_SERVER_ERROR,
+				})?;
+			input_id
+		}
+		(None, None) => state
+			.storage()
+			.body_to_new_file(body)
+			.await
+			.or(Err(StatusCode::INTERNAL_SERVER_ERROR))?,
+	};
+	let media = match state.ffprobe() {
+		Some(ffprobe) => {
+			let source = state
+				.storage()
+				.read_file(input_id)
+				.await
+				.or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+			match probe(ffprobe, source).await {
+				Ok(media) => Some(media),
+				Err(err) => {
+					//The body of a rejected job is not kept
+					if source_job.is_none() && headers.get("input_id").is_none() {
+						let _ = state.storage().delete_file(input_id).await;
+					}
+					return Err(match err.kind() {
+						ErrorKind::InvalidData | ErrorKind::TimedOut => {
+							StatusCode::UNPROCESSABLE_ENTITY
+						}
+						_ => {
+							warn!(%err, "Failed to probe the source");
+							StatusCode::INTERNAL_SERVER_ERROR
+						}
+					});
+				}
+			}
+		}
+		None => None,
+	};
 	let job_id = state
 		.manager()
-		.create_job(JobSource { input_id, options })
+		.create_job(JobSource {
+			callback,
+			name,
+			start,
+			end,
+			owner: Some(auth.credential(state.as_ref()).await.owner().to_string()),
+			media,
+			priority,
+			source_job,
+			..JobSource::new(input_id, options)
+		})
 		.await
-		.or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+		.map_err(|err| err.status())?;
 	state
 		.manager()
 		.add_task_to_job(
 			&job_id,
 			TaskSource {
-				inputs: vec![Input::source()],
-				recipe: Recipe::Analysis(None),
+				//The analysis only splits the clip of the source, with its selected streams
+				inputs: vec![Input {
+					start,
+					end,
+					video_stream,
+					audio_stream,
+					..Input::source()
+				}],
+				recipe: Recipe::Analysis(analysis),
+				..Default::default()
 			},
 		)
 		.await
 		.or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
-	Ok((StatusCode::CREATED, job_id.to_string()))
+	Ok(job_id)
+}
+
+#[instrument(skip_all)]
+async fn input_post<S: AppState>(
+	State(state): State<Arc<S>>,
+	auth: AuthToken,
+	headers: HeaderMap,
+	body: Body,
+) -> Result<impl IntoResponse, Response> {
+	let (body, credential, limit) = match state.quotas() {
+		Some(quotas) => {
+			let credential = auth.credential(state.as_ref()).await;
+			let usage = quotas
+				.usage(state.as_ref(), credential)
+				.await
+				.map_err(|err| err.response().into_response())?;
+			quotas
+				.check_bytes(&usage, content_length(&headers))
+				.map_err(IntoResponse::into_response)?;
+			let (body, limit) = quotas.limit_body(&usage, body);
+			(body, Some(credential), limit)
+		}
+		None => (body, None, None),
+	};
+	let input_id = state.storage().body_to_new_file(body).await.map_err(|_| {
+		match limit.as_ref().and_then(BodyLimit::exceeded) {
+			Some(exceeded) => exceeded.into_response(),
+			None => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+		}
+	})?;
+	if let (Some(quotas), Some(credential)) = (state.quotas(), credential) {
+		quotas.record_upload(credential, input_id);
+	}
+	Ok((StatusCode::CREATED, input_id.to_string()))
 }
 
 #[cfg(test)]
@@ -181,7 +764,7 @@ mod test {
 
 	use auth_module::AuthenticationHandler;
 	use task::manager::Manager;
-	use task::Recipe;
+	use task::{AnalysisOptions, Recipe};
 
 	use crate::api::{make_router, AppState, AppStateLocal};
 	use crate::storage::Storage;
@@ -222,6 +805,14 @@ mod test {
 		(server, state, token)
 	}
 
+	#[tokio::test]
+	async fn journal_without_storage_dir_refused() {
+		let path = std::env::temp_dir().join(format!("refused-{}.journal", Uuid::new_v4()));
+		let res = AppStateLocal::default().with_journal(&path).await;
+		assert!(res.is_err());
+		assert!(!path.exists());
+	}
+
 	#[tokio::test]
 	async fn get_version_ok() {
 		let server = test_server();
@@ -247,6 +838,30 @@ mod test {
 		assert!(!version.is_empty())
 	}
 
+	#[tokio::test]
+	async fn get_compatibility_has_current_revision() {
+		let server = test_server();
+		let compatibility: api::models::Compatibility =
+			server.get("/version/compatibility").await.json();
+		assert_eq!(compatibility.api_revision, api::API_REVISION);
+		assert!(compatibility.min_worker_api_revision <= api::API_REVISION);
+	}
+
+	#[tokio::test]
+	async fn method_missing_from_spec_not_allowed() {
+		let server = test_server();
+		let status = server.post("/version").await.status_code();
+		assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+	}
+
+	#[tokio::test]
+	async fn operation_without_handler_not_implemented() {
+		let server = test_server();
+		let path = format!("/job/{}", Uuid::new_v4());
+		let status = server.delete(&path).await.status_code();
+		assert_eq!(status, StatusCode::NOT_IMPLEMENTED);
+	}
+
 	#[tokio::test]
 	async fn get_login_without_auth_bad_request() {
 		let server = test_server();
@@ -388,69 +1003,416 @@ mod test {
 		assert!(Uuid::parse_str(&job_id).is_ok())
 	}
 
-	fn make_post_job_request(
-		server: TestServer,
-		token: HeaderValue,
-		options: task::Options,
-		body: Bytes,
-	) -> TestRequest {
-		let mut req = server
+	#[tokio::test]
+	async fn job_post_stores_callback_url() {
+		let (server, state, token) = test_server_state_auth().await;
+		let callback = "https://example.com/hook";
+		let job_id: Uuid = server
 			.post("/job")
 			.add_header(AUTHORIZATION, token)
 			.add_header(
 				HeaderName::from_static("video_codec"),
-				HeaderValue::from_str(options.codec.as_deref().unwrap_or("libx264")).unwrap(),
+				HeaderValue::from_static("libx264"),
 			)
-			.bytes(body);
-		let params = options
-			.params
-			.iter()
-			.map(String::as_str)
-			.map(HeaderValue::from_str)
-			.map(|x| x.unwrap());
-		for param in params {
-			req = req.add_header(HeaderName::from_static("video_param"), param);
-		}
-		req
+			.add_header(
+				HeaderName::from_static("callback_url"),
+				HeaderValue::from_static(callback),
+			)
+			.bytes(MKV_SAMPLE.as_slice().into())
+			.await
+			.text()
+			.parse()
+			.unwrap();
+		let job = state.manager().get_job(&job_id).await.unwrap().unwrap();
+		assert_eq!(job.callback.as_deref(), Some(callback))
 	}
 
 	#[tokio::test]
-	async fn job_post_creates_job_on_task_manager() {
-		let (server, state, token) = test_server_state_auth().await;
-		let job_options = task::Options {
-			codec: Some("libx264".to_string()),
-			params: vec![],
-		};
-		let job_id: Uuid =
-			make_post_job_request(server, token, job_options, MKV_SAMPLE.as_slice().into())
-				.await
-				.text()
-				.parse()
-				.unwrap();
-		let job = state.manager().get_job(&job_id).await.unwrap();
-		assert!(job.is_some())
+	async fn input_post_without_auth_forbidden() {
+		let server = test_server();
+		let status = server.post("/input").await.status_code();
+		assert_eq!(status, StatusCode::FORBIDDEN)
 	}
 
 	#[tokio::test]
-	async fn job_post_creates_job_with_same_codec() {
+	async fn input_post_stores_content() {
 		let (server, state, token) = test_server_state_auth().await;
-		let job_options = task::Options {
-			codec: Some("libx264".to_string()),
-			params: vec![],
-		};
-		let job_id: Uuid = make_post_job_request(
-			server,
-			token,
-			job_options.clone(),
-			MKV_SAMPLE.as_slice().into(),
-		)
-		.await
-		.text()
-		.parse()
-		.unwrap();
-		let job = state
-			.manager()
-			.get_job(&job_id)
+		let res = server
+			.post("/input")
+			.add_header(AUTHORIZATION, token)
+			.bytes(MKV_SAMPLE.as_slice().into())
+			.await;
+		assert_eq!(res.status_code(), StatusCode::CREATED);
+		let input_id: Uuid = res.text().parse().unwrap();
+		let mut content = Vec::new();
+		state
+			.storage()
+			.read_file(input_id)
+			.await
+			.unwrap()
+			.read_to_end(&mut content)
+			.await
+			.unwrap();
+		assert_eq!(content, MKV_SAMPLE)
+	}
+
+	fn post_job_with_input(server: &TestServer, token: HeaderValue, input_id: &str) -> TestRequest {
+		server
+			.post("/job")
+			.add_header(AUTHORIZATION, token)
+			.add_header(
+				HeaderName::from_static("video_codec"),
+				HeaderValue::from_static("libx264"),
+			)
+			.add_header(
+				HeaderName::from_static("input_id"),
+				HeaderValue::from_str(input_id).unwrap(),
+			)
+	}
+
+	#[tokio::test]
+	async fn jobs_created_from_the_same_input_share_it() {
+		let (server, state, token) = test_server_state_auth().await;
+		let input_id = server
+			.post("/input")
+			.add_header(AUTHORIZATION, token.clone())
+			.bytes(MKV_SAMPLE.as_slice().into())
+			.await
+			.text();
+		let mut jobs = Vec::new();
+		for _ in 0..2 {
+			let job_id: Uuid = post_job_with_input(&server, token.clone(), &input_id)
+				.await
+				.text()
+				.parse()
+				.unwrap();
+			jobs.push(job_id);
+		}
+		assert_ne!(jobs[0], jobs[1]);
+		for job_id in jobs {
+			let job = state.manager().get_job(&job_id).await.unwrap().unwrap();
+			assert_eq!(job.input_id.to_string(), input_id);
+		}
+	}
+
+	#[tokio::test]
+	async fn job_post_with_nonexistent_input_not_found() {
+		let (server, token) = test_server_auth().await;
+		let status = post_job_with_input(&server, token, &Uuid::nil().to_string())
+			.await
+			.status_code();
+		assert_eq!(status, StatusCode::NOT_FOUND)
+	}
+
+	#[tokio::test]
+	async fn job_post_with_invalid_input_id_bad_request() {
+		let (server, token) = test_server_auth().await;
+		let status = post_job_with_input(&server, token, "bad")
+			.await
+			.status_code();
+		assert_eq!(status, StatusCode::BAD_REQUEST)
+	}
+
+	#[tokio::test]
+	async fn job_post_stores_job_name() {
+		let (server, state, token) = test_server_state_auth().await;
+		let job_id: Uuid = server
+			.post("/job")
+			.add_header(AUTHORIZATION, token)
+			.add_header(
+				HeaderName::from_static("video_codec"),
+				HeaderValue::from_static("libx264"),
+			)
+			.add_header(
+				HeaderName::from_static("job_name"),
+				HeaderValue::from_static("my video"),
+			)
+			.bytes(MKV_SAMPLE.as_slice().into())
+			.await
+			.text()
+			.parse()
+			.unwrap();
+		let job = state.manager().get_job(&job_id).await.unwrap().unwrap();
+		assert_eq!(job.name.as_deref(), Some("my video"))
+	}
+
+	#[tokio::test]
+	async fn job_post_stores_priority() {
+		let (server, state, token) = test_server_state_auth().await;
+		let job_id: Uuid = server
+			.post("/job")
+			.add_header(AUTHORIZATION, token)
+			.add_header(
+				HeaderName::from_static("video_codec"),
+				HeaderValue::from_static("libx264"),
+			)
+			.add_header(
+				HeaderName::from_static("priority"),
+				HeaderValue::from_static("interactive"),
+			)
+			.bytes(MKV_SAMPLE.as_slice().into())
+			.await
+			.text()
+			.parse()
+			.unwrap();
+		let job = state.manager().get_job(&job_id).await.unwrap().unwrap();
+		assert_eq!(job.priority, task::Priority::Interactive)
+	}
+
+	#[tokio::test]
+	async fn job_post_with_unknown_priority_bad_request() {
+		let (server, token) = test_server_auth().await;
+		let status = server
+			.post("/job")
+			.add_header(AUTHORIZATION, token)
+			.add_header(
+				HeaderName::from_static("video_codec"),
+				HeaderValue::from_static("libx264"),
+			)
+			.add_header(
+				HeaderName::from_static("priority"),
+				HeaderValue::from_static("urgent"),
+			)
+			.bytes(MKV_SAMPLE.as_slice().into())
+			.await
+			.status_code();
+		assert_eq!(status, StatusCode::BAD_REQUEST)
+	}
+
+	#[tokio::test]
+	async fn job_post_analysis_uses_segment_duration() {
+		let (server, state, token) = test_server_state_auth().await;
+		server
+			.post("/job")
+			.add_header(AUTHORIZATION, token)
+			.add_header(
+				HeaderName::from_static("video_codec"),
+				HeaderValue::from_static("libx264"),
+			)
+			.add_header(
+				HeaderName::from_static("segment_duration"),
+				HeaderValue::from_static("25"),
+			)
+			.bytes(MKV_SAMPLE.as_slice().into())
+			.await
+			.assert_status(StatusCode::CREATED);
+		let task = state.manager().allocate_task("worker").await.unwrap();
+		let analysis = AnalysisOptions {
+			duration: Some(25.0),
+			..Default::default()
+		};
+		assert_eq!(task.unwrap().recipe, Recipe::Analysis(analysis))
+	}
+
+	#[tokio::test]
+	async fn job_post_analysis_reads_the_trimmed_clip() {
+		let (server, state, token) = test_server_state_auth().await;
+		let job_id: Uuid = server
+			.post("/job")
+			.add_header(AUTHORIZATION, token)
+			.add_header(
+				HeaderName::from_static("video_codec"),
+				HeaderValue::from_static("libx264"),
+			)
+			.add_header(
+				HeaderName::from_static("trim_start"),
+				HeaderValue::from_static("5"),
+			)
+			.add_header(
+				HeaderName::from_static("trim_end"),
+				HeaderValue::from_static("20"),
+			)
+			.bytes(MKV_SAMPLE.as_slice().into())
+			.await
+			.text()
+			.parse()
+			.unwrap();
+		let job = state.manager().get_job(&job_id).await.unwrap().unwrap();
+		assert_eq!((job.start, job.end), (Some(5.0), Some(20.0)));
+		let task = state
+			.manager()
+			.allocate_task("worker")
+			.await
+			.unwrap()
+			.unwrap();
+		assert_eq!(
+			(task.inputs[0].start, task.inputs[0].end),
+			(Some(5.0), Some(20.0))
+		)
+	}
+
+	#[tokio::test]
+	async fn job_post_analysis_reads_the_selected_streams() {
+		let (server, state, token) = test_server_state_auth().await;
+		server
+			.post("/job")
+			.add_header(AUTHORIZATION, token)
+			.add_header(
+				HeaderName::from_static("video_codec"),
+				HeaderValue::from_static("libx264"),
+			)
+			.add_header(
+				HeaderName::from_static("audio_stream"),
+				HeaderValue::from_static("1"),
+			)
+			.bytes(MKV_SAMPLE.as_slice().into())
+			.await
+			.assert_status(StatusCode::CREATED);
+		let task = state
+			.manager()
+			.allocate_task("worker")
+			.await
+			.unwrap()
+			.unwrap();
+		let input = &task.inputs[0];
+		assert_eq!((input.video_stream, input.audio_stream), (None, Some(1)))
+	}
+
+	#[tokio::test]
+	async fn job_post_with_negative_segment_duration_bad_request() {
+		let (server, token) = test_server_auth().await;
+		let status = server
+			.post("/job")
+			.add_header(AUTHORIZATION, token)
+			.add_header(
+				HeaderName::from_static("video_codec"),
+				HeaderValue::from_static("libx264"),
+			)
+			.add_header(
+				HeaderName::from_static("segment_duration"),
+				HeaderValue::from_static("-1"),
+			)
+			.bytes(MKV_SAMPLE.as_slice().into())
+			.await
+			.status_code();
+		assert_eq!(status, StatusCode::BAD_REQUEST)
+	}
+
+	#[tokio::test]
+	async fn job_post_with_invalid_callback_url_bad_request() {
+		let (server, token) = test_server_auth().await;
+		let status = server
+			.post("/job")
+			.add_header(AUTHORIZATION, token)
+			.add_header(
+				HeaderName::from_static("video_codec"),
+				HeaderValue::from_static("libx264"),
+			)
+			.add_header(
+				HeaderName::from_static("callback_url"),
+				HeaderValue::from_static("not a url"),
+			)
+			.bytes(MKV_SAMPLE.as_slice().into())
+			.await
+			.status_code();
+		assert_eq!(status, StatusCode::BAD_REQUEST)
+	}
+
+	#[tokio::test]
+	async fn job_post_with_preset_creates_job_with_expanded_codec() {
+		let (server, state, token) = test_server_state_auth().await;
+		let job_id: Uuid = server
+			.post("/job")
+			.add_header(AUTHORIZATION, token)
+			.add_header(
+				HeaderName::from_static("preset"),
+				HeaderValue::from_static("x264-medium"),
+			)
+			.bytes(MKV_SAMPLE.as_slice().into())
+			.await
+			.text()
+			.parse()
+			.unwrap();
+		let options = state
+			.manager()
+			.get_job(&job_id)
+			.await
+			.unwrap()
+			.unwrap()
+			.options;
+		assert_eq!(options.video.codec.as_deref(), Some("libx264"));
+		assert_eq!(options.preset, None)
+	}
+
+	#[tokio::test]
+	async fn job_post_with_unknown_preset_bad_request() {
+		let (server, token) = test_server_auth().await;
+		let status = server
+			.post("/job")
+			.add_header(AUTHORIZATION, token)
+			.add_header(
+				HeaderName::from_static("preset"),
+				HeaderValue::from_static("missing"),
+			)
+			.bytes(MKV_SAMPLE.as_slice().into())
+			.await
+			.status_code();
+		assert_eq!(status, StatusCode::BAD_REQUEST)
+	}
+
+	fn make_post_job_request(
+		server: TestServer,
+		token: HeaderValue,
+		options: task::Options,
+		body: Bytes,
+	) -> TestRequest {
+		let mut req = server
+			.post("/job")
+			.add_header(AUTHORIZATION, token)
+			.add_header(
+				HeaderName::from_static("video_codec"),
+				HeaderValue::from_str(options.codec.as_deref().unwrap_or("libx264")).unwrap(),
+			)
+			.bytes(body);
+		let params = options
+			.params
+			.iter()
+			.map(String::as_str)
+			.map(HeaderValue::from_str)
+			.map(|x| x.unwrap());
+		for param in params {
+			req = req.add_header(HeaderName::from_static("video_param"), param);
+		}
+		req
+	}
+
+	#[tokio::test]
+	async fn job_post_creates_job_on_task_manager() {
+		let (server, state, token) = test_server_state_auth().await;
+		let job_options = task::Options {
+			codec: Some("libx264".to_string()),
+			params: vec![],
+		};
+		let job_id: Uuid =
+			make_post_job_request(server, token, job_options, MKV_SAMPLE.as_slice().into())
+				.await
+				.text()
+				.parse()
+				.unwrap();
+		let job = state.manager().get_job(&job_id).await.unwrap();
+		assert!(job.is_some())
+	}
+
+	#[tokio::test]
+	async fn job_post_creates_job_with_same_codec() {
+		let (server, state, token) = test_server_state_auth().await;
+		let job_options = task::Options {
+			codec: Some("libx264".to_string()),
+			params: vec![],
+		};
+		let job_id: Uuid = make_post_job_request(
+			server,
+			token,
+			job_options.clone(),
+			MKV_SAMPLE.as_slice().into(),
+		)
+		.await
+		.text()
+		.parse()
+		.unwrap();
+		let job = state
+			.manager()
+			.get_job(&job_id)
 			.await
 			.unwrap()
 			.unwrap()
@@ -564,9 +1526,435 @@ mod test {
 		.text()
 		.parse()
 		.unwrap();
-		let task = state.manager().allocate_task().await.unwrap();
+		let task = state.manager().allocate_task("worker").await.unwrap();
 		assert!(task.is_some());
-		let task = task.unwrap().recipe;
-		assert!(matches!(task, Recipe::Analysis(_)))
+		let task = task.unwrap();
+		assert_eq!(task.job_id, job_id);
+		assert!(matches!(task.recipe, Recipe::Analysis(_)))
+	}
+
+	#[cfg(unix)]
+	#[tokio::test]
+	async fn job_post_rejected_by_ffprobe_unprocessable() {
+		let state = AppStateLocal::with_cred(TEST_CRED).with_ffprobe(std::path::Path::new("false"));
+		let (server, state, token) = test_server_state_auth_generic(Arc::new(state)).await;
+		let status = server
+			.post("/job")
+			.add_header(AUTHORIZATION, token)
+			.add_header(
+				HeaderName::from_static("video_codec"),
+				HeaderValue::from_static("libx264"),
+			)
+			.bytes(MKV_SAMPLE.as_slice().into())
+			.await
+			.status_code();
+		assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+		assert!(state.manager().get_job_list().await.unwrap().is_empty());
+		assert_eq!(state.storage().used_bytes().await.unwrap(), 0)
+	}
+
+	mod ownership {
+		use task::Input;
+
+		use crate::api::worker::test_util::MergeRecipe;
+
+		use super::*;
+
+		const ADMIN_CRED: &str = "admin password";
+
+		fn multi_tenant_server() -> TestServer {
+			let state = AppStateLocal::with_cred(TEST_CRED).with_admin_cred(ADMIN_CRED);
+			TestServer::new(make_router(Arc::new(state))).unwrap()
+		}
+
+		async fn login(server: &TestServer, cred: &'static str) -> HeaderValue {
+			let token: String = server
+				.get("/login")
+				.add_header(
+					HeaderName::from_static("credentials"),
+					HeaderValue::from_static(cred),
+				)
+				.await
+				.json();
+			token.parse().unwrap()
+		}
+
+		async fn post_job(server: &TestServer, token: HeaderValue) -> Uuid {
+			server
+				.post("/job")
+				.add_header(AUTHORIZATION, token)
+				.add_header(
+					HeaderName::from_static("video_codec"),
+					HeaderValue::from_static("libx264"),
+				)
+				.bytes(MKV_SAMPLE.as_slice().into())
+				.await
+				.text()
+				.parse()
+				.unwrap()
+		}
+
+		#[tokio::test]
+		async fn job_list_only_has_own_jobs() {
+			let server = multi_tenant_server();
+			let (user, admin) = (
+				login(&server, TEST_CRED).await,
+				login(&server, ADMIN_CRED).await,
+			);
+			post_job(&server, admin).await;
+			let job_id = post_job(&server, user.clone()).await;
+			let list: Vec<Uuid> = server
+				.get("/job")
+				.add_header(AUTHORIZATION, user)
+				.await
+				.json();
+			assert_eq!(list, [job_id]);
+		}
+
+		#[tokio::test]
+		async fn job_of_other_credential_not_found() {
+			let server = multi_tenant_server();
+			let (user, admin) = (
+				login(&server, TEST_CRED).await,
+				login(&server, ADMIN_CRED).await,
+			);
+			let job_id = post_job(&server, admin.clone()).await;
+			let info = |token: HeaderValue| {
+				server
+					.get(&format!("/job/{job_id}/info"))
+					.add_header(AUTHORIZATION, token)
+			};
+			info(admin).await.assert_status_ok();
+			info(user).await.assert_status(StatusCode::NOT_FOUND);
+		}
+
+		#[tokio::test]
+		async fn job_owned_after_login_again() {
+			let server = multi_tenant_server();
+			let job_id = post_job(&server, login(&server, TEST_CRED).await).await;
+			server
+				.get(&format!("/job/{job_id}/info"))
+				.add_header(AUTHORIZATION, login(&server, TEST_CRED).await)
+				.await
+				.assert_status_ok();
+		}
+
+		#[tokio::test]
+		async fn admin_sees_every_job() {
+			let server = multi_tenant_server();
+			let (alice, admin) = (
+				login(&server, TEST_CRED).await,
+				login(&server, ADMIN_CRED).await,
+			);
+			let job_id = post_job(&server, alice).await;
+			server
+				.get(&format!("/job/{job_id}/info"))
+				.add_header(AUTHORIZATION, admin.clone())
+				.await
+				.assert_status_ok();
+			let list: Vec<Uuid> = server
+				.get("/job")
+				.add_header(AUTHORIZATION, admin)
+				.await
+				.json();
+			assert_eq!(list, [job_id]);
+		}
+
+		#[tokio::test]
+		async fn job_owner_is_the_credential() {
+			let state = AppStateLocal::with_cred(TEST_CRED).with_admin_cred(ADMIN_CRED);
+			let state = Arc::new(state);
+			let server = TestServer::new(make_router(state.clone())).unwrap();
+			let token = login(&server, TEST_CRED).await;
+			let job_id = post_job(&server, token.clone()).await;
+			let job = state.manager().get_job(&job_id).await.unwrap().unwrap();
+			assert_eq!(job.owner.as_deref(), Some("user"))
+		}
+
+		#[tokio::test]
+		async fn task_routes_need_the_allocation_or_the_job() {
+			let server = multi_tenant_server();
+			let (owner, worker, other) = (
+				login(&server, ADMIN_CRED).await,
+				login(&server, TEST_CRED).await,
+				login(&server, TEST_CRED).await,
+			);
+			let job_id = post_job(&server, owner.clone()).await;
+			let task: api::models::Task = server
+				.get("/allocate_task")
+				.add_header(AUTHORIZATION, worker.clone())
+				.await
+				.json();
+			let progress = |token: HeaderValue| {
+				server
+					.post(&format!("/job/{job_id}/task/{}/progress", task.task_id))
+					.add_header(AUTHORIZATION, token)
+					.json(&api::models::TaskProgress {
+						out_time: 1.0,
+						speed: None,
+					})
+			};
+			progress(other.clone())
+				.await
+				.assert_status(StatusCode::FORBIDDEN);
+			progress(worker).await.assert_status(StatusCode::NO_CONTENT);
+			progress(owner.clone())
+				.await
+				.assert_status(StatusCode::NO_CONTENT);
+			let delete = |token: HeaderValue| {
+				server
+					.delete(&format!("/job/{job_id}/task/{}", task.task_id))
+					.add_header(AUTHORIZATION, token)
+			};
+			delete(other).await.assert_status(StatusCode::FORBIDDEN);
+			delete(owner).await.assert_status(StatusCode::NO_CONTENT);
+		}
+
+		#[tokio::test]
+		async fn task_post_to_job_of_other_credential_not_found() {
+			let server = multi_tenant_server();
+			let (owner, other) = (
+				login(&server, ADMIN_CRED).await,
+				login(&server, TEST_CRED).await,
+			);
+			let job_id = post_job(&server, owner.clone()).await;
+			let post = |token: HeaderValue| {
+				server
+					.post(&format!("/job/{job_id}/task"))
+					.add_header(AUTHORIZATION, token)
+					.json(&api::models::TaskRequest {
+						inputs: vec![Input::source().into()],
+						recipe: Box::new(MergeRecipe(vec![0]).into()),
+						output: None,
+						resources: None,
+					})
+			};
+			post(other).await.assert_status(StatusCode::NOT_FOUND);
+			post(owner).await.assert_status(StatusCode::CREATED);
+		}
+
+		#[tokio::test]
+		async fn admin_api_needs_admin_token() {
+			let server = multi_tenant_server();
+			let (user, admin) = (
+				login(&server, TEST_CRED).await,
+				login(&server, ADMIN_CRED).await,
+			);
+			let stats =
+				|token: HeaderValue| server.get("/admin/stats").add_header(AUTHORIZATION, token);
+			stats(user).await.assert_status(StatusCode::FORBIDDEN);
+			stats(admin).await.assert_status_ok();
+		}
+	}
+
+	mod chained_job {
+		use task::{Input, Status, TaskSource};
+
+		use crate::api::worker::test_util::create_job_source;
+
+		use super::*;
+
+		///Job with a transcode, finished unless `finish` is false, and the id of its output
+		async fn source_job(state: &AppStateLocal, finish: bool) -> (Uuid, Uuid) {
+			let manager = state.manager();
+			let job_id = manager
+				.create_job(create_job_source(Uuid::nil()))
+				.await
+				.unwrap();
+			let transcode = TaskSource {
+				inputs: vec![Input::source()],
+				recipe: Recipe::Transcode(Vec::new()),
+				..Default::default()
+			};
+			manager.add_task_to_job(&job_id, transcode).await.unwrap();
+			let instance = manager.allocate_task("worker").await.unwrap().unwrap();
+			let output = {
+				let file = state.storage().create_file().await.unwrap();
+				state.storage().store_file(file).await.unwrap()
+			};
+			if finish {
+				manager
+					.set_task_output(&job_id, &instance.task_id, output)
+					.await
+					.unwrap();
+				manager
+					.update_task_status(&job_id, &instance.task_id, Status::Finished)
+					.await
+					.unwrap();
+			}
+			(job_id, output)
+		}
+
+		fn post_chained(server: &TestServer, token: HeaderValue, source_job: Uuid) -> TestRequest {
+			server
+				.post("/job")
+				.add_header(AUTHORIZATION, token)
+				.add_header(
+					HeaderName::from_static("video_codec"),
+					HeaderValue::from_static("libx264"),
+				)
+				.add_header(
+					HeaderName::from_static("source_job"),
+					HeaderValue::from_str(&source_job.to_string()).unwrap(),
+				)
+		}
+
+		#[tokio::test]
+		async fn chained_job_encodes_the_source_job_output() {
+			let (server, state, token) = test_server_state_auth().await;
+			let (source, output) = source_job(&state, true).await;
+			let res = post_chained(&server, token, source).await;
+			res.assert_status(StatusCode::CREATED);
+			let job_id: Uuid = res.text().parse().unwrap();
+			let job = state.manager().get_job(&job_id).await.unwrap().unwrap();
+			assert_eq!(job.input_id, output);
+			assert_eq!(job.source_job, Some(source))
+		}
+
+		#[tokio::test]
+		async fn chained_to_unfinished_job_conflict() {
+			let (server, state, token) = test_server_state_auth().await;
+			let (source, _) = source_job(&state, false).await;
+			post_chained(&server, token, source)
+				.await
+				.assert_status(StatusCode::CONFLICT);
+		}
+
+		#[tokio::test]
+		async fn chained_to_missing_job_not_found() {
+			let (server, token) = test_server_auth().await;
+			post_chained(&server, token, Uuid::nil())
+				.await
+				.assert_status(StatusCode::NOT_FOUND);
+		}
+
+		#[tokio::test]
+		async fn chained_job_with_input_id_bad_request() {
+			let (server, state, token) = test_server_state_auth().await;
+			let (source, output) = source_job(&state, true).await;
+			post_chained(&server, token, source)
+				.add_header(
+					HeaderName::from_static("input_id"),
+					HeaderValue::from_str(&output.to_string()).unwrap(),
+				)
+				.await
+				.assert_status(StatusCode::BAD_REQUEST);
+		}
+	}
+
+	mod quota {
+		use axum::body::Body;
+		use axum::extract::State;
+		use axum::http::HeaderMap;
+		use axum::response::IntoResponse;
+
+		use super::*;
+
+		use crate::api::{input_post, AuthToken};
+		use crate::config::QuotaConfig;
+
+		async fn quota_server_auth(config: QuotaConfig) -> (TestServer, HeaderValue) {
+			let state = AppStateLocal::with_cred(TEST_CRED).with_quotas(config);
+			let (server, _, token) = test_server_state_auth_generic(Arc::new(state)).await;
+			(server, token)
+		}
+
+		fn post_job(server: &TestServer, token: HeaderValue) -> TestRequest {
+			server
+				.post("/job")
+				.add_header(AUTHORIZATION, token)
+				.add_header(
+					HeaderName::from_static("video_codec"),
+					HeaderValue::from_static("libx264"),
+				)
+				.bytes(MKV_SAMPLE.as_slice().into())
+		}
+
+		#[tokio::test]
+		async fn job_post_over_job_quota_too_many_requests() {
+			let (server, token) = quota_server_auth(QuotaConfig {
+				max_jobs: Some(1),
+				..Default::default()
+			})
+			.await;
+			post_job(&server, token.clone())
+				.await
+				.assert_status(StatusCode::CREATED);
+			let res = post_job(&server, token).await;
+			res.assert_status(StatusCode::TOO_MANY_REQUESTS);
+			assert!(res.text().contains("unfinished jobs"))
+		}
+
+		#[tokio::test]
+		async fn job_post_over_queued_task_quota_too_many_requests() {
+			let (server, token) = quota_server_auth(QuotaConfig {
+				max_queued_tasks: Some(1),
+				..Default::default()
+			})
+			.await;
+			post_job(&server, token.clone())
+				.await
+				.assert_status(StatusCode::CREATED);
+			let res = post_job(&server, token).await;
+			res.assert_status(StatusCode::TOO_MANY_REQUESTS);
+		}
+
+		#[tokio::test]
+		async fn input_post_over_byte_quota_insufficient_storage() {
+			let (server, token) = quota_server_auth(QuotaConfig {
+				max_bytes: Some(MKV_SAMPLE.len() as u64),
+				..Default::default()
+			})
+			.await;
+			let upload = || {
+				server
+					.post("/input")
+					.add_header(AUTHORIZATION, token.clone())
+					.bytes(MKV_SAMPLE.as_slice().into())
+			};
+			upload().await.assert_status(StatusCode::CREATED);
+			let res = upload().await;
+			res.assert_status(StatusCode::INSUFFICIENT_STORAGE);
+			assert!(res.text().contains("Storage quota exceeded"))
+		}
+
+		#[tokio::test]
+		async fn quota_shared_by_the_tokens_of_the_credential() {
+			let (server, token) = quota_server_auth(QuotaConfig {
+				max_jobs: Some(1),
+				..Default::default()
+			})
+			.await;
+			post_job(&server, token)
+				.await
+				.assert_status(StatusCode::CREATED);
+			let other: String = server
+				.get("/login")
+				.add_header(
+					HeaderName::from_static("credentials"),
+					HeaderValue::from_static(TEST_CRED),
+				)
+				.await
+				.json();
+			post_job(&server, other.parse().unwrap())
+				.await
+				.assert_status(StatusCode::TOO_MANY_REQUESTS);
+		}
+
+		#[tokio::test]
+		async fn chunked_input_over_byte_quota_insufficient_storage() {
+			let state = Arc::new(AppStateLocal::default().with_quotas(QuotaConfig {
+				max_bytes: Some(10),
+				..Default::default()
+			}));
+			let chunks = ["0123456789", "0"].map(Ok::<_, std::io::Error>);
+			let body = Body::from_stream(futures::stream::iter(chunks));
+			let token = AuthToken(state.auth_handler().new_token().await);
+			let res = input_post(State(state), token, HeaderMap::new(), body)
+				.await
+				.map(IntoResponse::into_response)
+				.unwrap_or_else(|res| res);
+			assert_eq!(res.status(), StatusCode::INSUFFICIENT_STORAGE);
+		}
 	}
 }
@@ -0,0 +1,393 @@
+//! Admin api
+//!
+//! Define the routes used to inspect the server state
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::Json;
+use tracing::{info, instrument};
+
+use task::manager::Manager;
+
+use crate::api::{AdminToken, AppState};
+use crate::storage::Storage;
+
+#[instrument(skip_all)]
+pub(super) async fn get_allocations<S: AppState>(
+	State(state): State<Arc<S>>,
+	_auth: AdminToken,
+) -> Result<Json<Vec<api::models::Allocation>>, StatusCode> {
+	state
+		.manager()
+		.get_allocated_tasks()
+		.await
+		.or(Err(StatusCode::INTERNAL_SERVER_ERROR))
+		.map(|allocations| allocations.into_iter().map(Into::into).collect())
+		.map(Json)
+}
+
+///Scheduler counters, in the prometheus text format
+#[instrument(skip_all)]
+pub(super) async fn get_metrics<S: AppState>(
+	State(state): State<Arc<S>>,
+	_auth: AdminToken,
+) -> impl IntoResponse {
+	let stats = state.manager().scheduler_stats();
+	let metrics = [
+		(
+			"segmented_scheduler_allocations_total",
+			"counter",
+			"Tasks allocated to workers",
+			stats.allocations as f64,
+		),
+		(
+			"segmented_scheduler_allocation_seconds_total",
+			"counter",
+			"Time spent allocating tasks",
+			stats.allocation_seconds,
+		),
+		(
+			"segmented_scheduler_ready_tasks",
+			"gauge",
+			"Tasks ready to be allocated",
+			stats.ready_tasks as f64,
+		),
+		(
+			"segmented_scheduler_queue_seconds_total",
+			"counter",
+			"Time the allocated tasks waited after becoming ready",
+			stats.queue_seconds,
+		),
+		(
+			"segmented_scheduler_tasks_completed_total",
+			"counter",
+			"Tasks finished by workers",
+			stats.completed as f64,
+		),
+		(
+			"segmented_scheduler_tasks_per_second",
+			"gauge",
+			"Finished tasks per second, since the server started",
+			stats.tasks_per_second,
+		),
+	];
+	let mut body = String::new();
+	for (name, kind, help, value) in metrics {
+		let _ = write!(
+			body,
+			"# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n"
+		);
+	}
+	([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
+
+///Jobs by state, active workers, recent throughput and storage usage
+#[instrument(skip_all)]
+pub(super) async fn get_stats<S: AppState>(
+	State(state): State<Arc<S>>,
+	_auth: AdminToken,
+) -> Result<Json<api::models::ClusterStats>, StatusCode> {
+	let manager = state.manager();
+	let allocations = manager
+		.get_allocated_tasks()
+		.await
+		.or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+	let jobs = manager
+		.get_job_list()
+		.await
+		.or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+	let mut counts = api::models::JobCounts::new(0, 0, 0, 0);
+	for job_id in jobs {
+		let output = manager
+			.get_job_output(&job_id, None)
+			.await
+			.or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+		let allocated: Vec<_> = allocations
+			.iter()
+			.filter(|alloc| alloc.job_id == job_id)
+			.collect();
+		let count = if output.is_some() {
+			&mut counts.finished
+		} else if allocated.iter().any(|alloc| alloc.failure.is_some()) {
+			&mut counts.failed
+		} else if !allocated.is_empty() {
+			&mut counts.running
+		} else {
+			&mut counts.pending
+		};
+		*count += 1;
+	}
+	//Counted by name, as a restarted worker holds allocations with each of its tokens
+	let workers: BTreeSet<String> = allocations
+		.iter()
+		.filter(|alloc| alloc.failure.is_none())
+		.map(|alloc| match state.worker_names() {
+			Some(names) => names.name_of(&alloc.worker),
+			None => alloc.worker.clone(),
+		})
+		.collect();
+	let storage_bytes = match state.storage().used_bytes().await {
+		Ok(bytes) => bytes,
+		Err(err) if err.kind() == std::io::ErrorKind::Unsupported => 0,
+		Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+	};
+	let completed = manager.scheduler_stats().completed_last_hour;
+	Ok(Json(api::models::ClusterStats::new(
+		counts,
+		workers.len().try_into().unwrap_or(i32::MAX),
+		completed.try_into().unwrap_or(i32::MAX),
+		storage_bytes.try_into().unwrap_or(i64::MAX),
+	)))
+}
+
+///Replace the log filter, using the `RUST_LOG` syntax
+#[instrument(skip_all)]
+pub(super) async fn put_log_level<S: AppState>(
+	State(state): State<Arc<S>>,
+	_auth: AdminToken,
+	directives: String,
+) -> Result<StatusCode, (StatusCode, String)> {
+	let log_filter = state.log_filter().ok_or((
+		StatusCode::NOT_IMPLEMENTED,
+		"Log filter can't be changed".to_string(),
+	))?;
+	let directives = directives.trim();
+	log_filter
+		.set(directives)
+		.map_err(|err| (StatusCode::BAD_REQUEST, err))?;
+	info!(directives, "Log filter changed");
+	Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod test {
+	use axum::http::header::AUTHORIZATION;
+	use axum::http::StatusCode;
+	use uuid::Uuid;
+
+	use task::manager::Manager;
+
+	use crate::api::AppState;
+
+	use super::super::worker::test_util::*;
+
+	#[tokio::test]
+	async fn allocations_requires_auth() {
+		let server = test_server();
+		let code = server.get("/admin/allocations").await.status_code();
+		assert_eq!(code, StatusCode::FORBIDDEN)
+	}
+
+	#[tokio::test]
+	async fn allocations_without_allocated_task_is_empty() {
+		let (server, _, auth) = app_with_job_and_analyse_task().await;
+		let allocations = server
+			.get("/admin/allocations")
+			.add_header(AUTHORIZATION, auth)
+			.await
+			.json::<Vec<api::models::Allocation>>();
+		assert!(allocations.is_empty())
+	}
+
+	#[tokio::test]
+	async fn allocations_lists_allocated_task() {
+		let (server, app, auth) = app_with_job_and_analyse_task().await;
+		let instance = app
+			.manager()
+			.allocate_task("worker")
+			.await
+			.unwrap()
+			.expect("Should have task");
+		let allocations = server
+			.get("/admin/allocations")
+			.add_header(AUTHORIZATION, auth)
+			.await
+			.json::<Vec<api::models::Allocation>>();
+		assert_eq!(allocations.len(), 1);
+		let allocation = &allocations[0];
+		assert_eq!(allocation.job_id.parse::<Uuid>().unwrap(), instance.job_id);
+		assert_eq!(
+			allocation.task_id.parse::<Uuid>().unwrap(),
+			instance.task_id
+		);
+		assert_eq!(allocation.task_idx, 0);
+		assert_eq!(allocation.worker, "worker");
+		assert!(allocation.age >= 0.0);
+	}
+
+	#[tokio::test]
+	async fn allocations_lists_worker_that_allocated_through_api() {
+		let (server, _, auth) = app_with_job_and_analyse_task().await;
+		server
+			.get("/allocate_task")
+			.add_header(AUTHORIZATION, auth.clone())
+			.await
+			.assert_status_ok();
+		let allocations = server
+			.get("/admin/allocations")
+			.add_header(AUTHORIZATION, auth.clone())
+			.await
+			.json::<Vec<api::models::Allocation>>();
+		let token = auth.to_str().unwrap();
+		assert_eq!(allocations[0].worker, crate::api::token_id(token));
+		assert_ne!(allocations[0].worker, token);
+	}
+
+	#[tokio::test]
+	async fn metrics_requires_auth() {
+		let server = test_server();
+		let code = server.get("/admin/metrics").await.status_code();
+		assert_eq!(code, StatusCode::FORBIDDEN)
+	}
+
+	#[tokio::test]
+	async fn metrics_count_allocated_task() {
+		let (server, app, auth) = app_with_job_and_analyse_task().await;
+		app.manager()
+			.allocate_task("worker")
+			.await
+			.unwrap()
+			.expect("Should have task");
+		let metrics = server
+			.get("/admin/metrics")
+			.add_header(AUTHORIZATION, auth)
+			.await
+			.text();
+		assert!(metrics.contains("segmented_scheduler_allocations_total 1\n"));
+		assert!(metrics.contains("segmented_scheduler_ready_tasks 0\n"));
+	}
+
+	#[tokio::test]
+	async fn stats_requires_auth() {
+		let server = test_server();
+		let code = server.get("/admin/stats").await.status_code();
+		assert_eq!(code, StatusCode::FORBIDDEN)
+	}
+
+	#[tokio::test]
+	async fn stats_count_running_job_and_its_worker() {
+		let (server, app, auth) = app_with_job_and_analyse_task().await;
+		app.manager()
+			.allocate_task("worker")
+			.await
+			.unwrap()
+			.expect("Should have task");
+		let stats = server
+			.get("/admin/stats")
+			.add_header(AUTHORIZATION, auth)
+			.await
+			.json::<api::models::ClusterStats>();
+		assert_eq!(stats.jobs.running, 1);
+		assert_eq!(stats.jobs.pending, 0);
+		assert_eq!(stats.active_workers, 1);
+		assert!(stats.storage_bytes > 0, "Job input should be stored");
+	}
+
+	#[tokio::test]
+	async fn stats_count_worker_once_across_logins() {
+		use axum::http::{HeaderName, HeaderValue};
+		use task::{Input, Recipe, TaskSource};
+		let (server, app, auth) = app_with_job_and_analyse_task().await;
+		let job_id = app.manager().get_job_list().await.unwrap()[0];
+		let transcode = TaskSource {
+			inputs: vec![Input::source()],
+			recipe: Recipe::Transcode(vec![]),
+			..Default::default()
+		};
+		app.manager()
+			.add_task_to_job(&job_id, transcode)
+			.await
+			.unwrap();
+		for _ in 0..2 {
+			let token: String = server
+				.get("/login")
+				.add_header(
+					HeaderName::from_static("credentials"),
+					HeaderValue::from_static(""),
+				)
+				.add_header(
+					HeaderName::from_static("worker_name"),
+					HeaderValue::from_static("render-1"),
+				)
+				.await
+				.json();
+			server
+				.get("/allocate_task")
+				.add_header(AUTHORIZATION, token.parse::<HeaderValue>().unwrap())
+				.await
+				.assert_status_ok();
+		}
+		let stats = server
+			.get("/admin/stats")
+			.add_header(AUTHORIZATION, auth)
+			.await
+			.json::<api::models::ClusterStats>();
+		assert_eq!(stats.jobs.running, 1);
+		assert_eq!(stats.active_workers, 1);
+	}
+
+	mod log_level {
+		use std::sync::Arc;
+
+		use tracing_subscriber::EnvFilter;
+
+		use crate::logging::LogFilter;
+		use crate::AppStateLocal;
+
+		use super::*;
+
+		#[tokio::test]
+		async fn put_requires_auth() {
+			let server = test_server();
+			let code = server.put("/admin/log_level").await.status_code();
+			assert_eq!(code, StatusCode::FORBIDDEN)
+		}
+
+		#[tokio::test]
+		async fn put_without_log_filter_not_implemented() {
+			let (server, auth) = test_server_auth().await;
+			let code = server
+				.put("/admin/log_level")
+				.add_header(AUTHORIZATION, auth)
+				.text("debug")
+				.await
+				.status_code();
+			assert_eq!(code, StatusCode::NOT_IMPLEMENTED)
+		}
+
+		#[tokio::test]
+		async fn put_replaces_filter() {
+			let (_layer, log_filter) = LogFilter::new(EnvFilter::new("info"));
+			let state = AppStateLocal::default().with_log_filter(log_filter);
+			let (server, app, auth) = test_server_state_auth_generic(Arc::new(state)).await;
+			let code = server
+				.put("/admin/log_level")
+				.add_header(AUTHORIZATION, auth)
+				.text("info,task::manager=debug")
+				.await
+				.status_code();
+			assert_eq!(code, StatusCode::NO_CONTENT);
+			let current = app.log_filter().and_then(LogFilter::current).unwrap();
+			assert!(current.contains("task::manager=debug"), "{current}");
+		}
+
+		#[tokio::test]
+		async fn put_invalid_filter_bad_request() {
+			let (_layer, log_filter) = LogFilter::new(EnvFilter::new("info"));
+			let state = AppStateLocal::default().with_log_filter(log_filter);
+			let (server, _, auth) = test_server_state_auth_generic(Arc::new(state)).await;
+			let code = server
+				.put("/admin/log_level")
+				.add_header(AUTHORIZATION, auth)
+				.text("task::manager=loud")
+				.await
+				.status_code();
+			assert_eq!(code, StatusCode::BAD_REQUEST)
+		}
+	}
+}
@@ -1,27 +1,130 @@
-use std::io::ErrorKind;
 use std::sync::Arc;
+use std::time::UNIX_EPOCH;
 
-use axum::extract::{Path, State};
-use axum::http::StatusCode;
+use axum::body::Body;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderValue, StatusCode};
 use axum::response::{IntoResponse, Response};
 use axum::Json;
+use serde::Deserialize;
+use tracing::{instrument, warn};
 use uuid::Uuid;
 
 use task::manager::Manager;
+use task::{Container, Input, Recipe, TaskSource, Thumbnail};
 
+use crate::api::utils::error::ErrorStatus;
 use crate::api::{AppState, AuthToken};
 use crate::storage::Storage;
 
 trait ClientApi: AppState {
-	async fn get_job_output(&self, job_id: Uuid) -> Result<Uuid, (StatusCode, &'static str)> {
-		self.manager()
-			.get_job_output(&job_id)
+	///Jobs of other credentials are not found, unless the token is an admin
+	async fn check_owner(
+		&self,
+		auth: &AuthToken,
+		job_id: Uuid,
+	) -> Result<(), (StatusCode, &'static str)> {
+		if auth.is_admin(self).await {
+			return Ok(());
+		}
+		let job = self
+			.manager()
+			.get_job(&job_id)
 			.await
-			.map_err(|err| match err.kind() {
-				ErrorKind::NotFound => (StatusCode::NOT_FOUND, "Job not found"),
-				_ => (StatusCode::INTERNAL_SERVER_ERROR, "Server error"),
-			})?
-			.ok_or((StatusCode::SERVICE_UNAVAILABLE, "Output not available yet"))
+			.map_err(|err| err.response())?;
+		match job {
+			Some(job) if auth.owns(self, &job).await => Ok(()),
+			_ => Err((StatusCode::NOT_FOUND, "Job not found")),
+		}
+	}
+
+	async fn get_job_output(
+		&self,
+		job_id: Uuid,
+		rendition: Option<String>,
+	) -> Result<Uuid, (StatusCode, &'static str)> {
+		let output = self
+			.manager()
+			.get_job_output(&job_id, rendition)
+			.await
+			.map_err(|err| err.response())?;
+		if let Some(output) = output {
+			return Ok(output);
+		}
+		let failure = self
+			.manager()
+			.get_job_failure(&job_id)
+			.await
+			.map_err(|err| err.response())?;
+		match failure {
+			Some(_) => Err((StatusCode::UNPROCESSABLE_ENTITY, "The job failed")),
+			None => Err((StatusCode::SERVICE_UNAVAILABLE, "Output not available yet")),
+		}
+	}
+
+	///Outputs of the finished segments at the start of the job output, or of the rendition
+	///
+	///Only outputs that are merged have segments, the list stops at the first unfinished segment
+	async fn get_leading_segments(
+		&self,
+		job_id: Uuid,
+		rendition: Option<String>,
+	) -> Result<Vec<Uuid>, (StatusCode, &'static str)> {
+		let manager = self.manager();
+		let job = manager
+			.get_job(&job_id)
+			.await
+			.map_err(|err| err.response())?
+			.ok_or((StatusCode::NOT_FOUND, "Job not found"))?;
+		let rendition = match rendition {
+			Some(name) => Some(
+				job.options
+					.renditions
+					.iter()
+					.position(|rendition| rendition.name == name)
+					.ok_or((StatusCode::NOT_FOUND, "Rendition not found"))? as u32,
+			),
+			None => None,
+		};
+		let mut last = None;
+		let mut idx = 0;
+		while let Some(task) = manager
+			.get_task_source(&job_id, idx)
+			.await
+			.map_err(|err| err.response())?
+		{
+			let matches = match rendition {
+				Some(_) => task.output.rendition == rendition,
+				None => !matches!(task.recipe, Recipe::Thumbnail(_)),
+			};
+			if matches {
+				last = Some(task);
+			}
+			idx += 1;
+		}
+		let Some(TaskSource {
+			inputs,
+			recipe: Recipe::Merge(order),
+			..
+		}) = last
+		else {
+			return Ok(Vec::new());
+		};
+		let mut segments = Vec::with_capacity(order.len());
+		for pos in order {
+			let Some(input) = inputs.get(pos as usize) else {
+				break;
+			};
+			let output = manager
+				.get_task_output(&job_id, input.index)
+				.await
+				.map_err(|err| err.response())?;
+			match output {
+				Some(output) => segments.push(output),
+				None => break,
+			}
+		}
+		Ok(segments)
 	}
 
 	async fn get_task_output(
@@ -32,25 +135,212 @@ trait ClientApi: AppState {
 		self.manager()
 			.get_allocated_task_output(&job_id, &task_id)
 			.await
-			.map_err(|err| match err.kind() {
-				ErrorKind::NotFound => (StatusCode::NOT_FOUND, "Job not found"),
-				_ => (StatusCode::INTERNAL_SERVER_ERROR, "Server error"),
-			})?
+			.map_err(|err| err.response())?
 			.ok_or((StatusCode::SERVICE_UNAVAILABLE, "Output not available yet"))
 	}
+
+	///Last task of the thumbnail, with whether it failed
+	async fn find_thumbnail(
+		&self,
+		job_id: Uuid,
+		thumbnail: Thumbnail,
+	) -> Result<Option<(u32, bool)>, (StatusCode, &'static str)> {
+		let manager = self.manager();
+		let mut last = None;
+		let mut idx = 0;
+		while let Some(task) = manager
+			.get_task_source(&job_id, idx)
+			.await
+			.map_err(|err| err.response())?
+		{
+			if task.recipe == Recipe::Thumbnail(thumbnail) {
+				last = Some(idx);
+			}
+			idx += 1;
+		}
+		let Some(idx) = last else {
+			return Ok(None);
+		};
+		let failure = manager
+			.get_task_failure(&job_id, idx)
+			.await
+			.map_err(|err| err.response())?;
+		Ok(Some((idx, failure.is_some())))
+	}
+
+	///Output of the job thumbnail task, none while it is running
+	async fn get_thumbnail(
+		&self,
+		job_id: Uuid,
+		thumbnail: Thumbnail,
+	) -> Result<Option<Uuid>, (StatusCode, &'static str)> {
+		let manager = self.manager();
+		manager
+			.get_job(&job_id)
+			.await
+			.map_err(|err| err.response())?
+			.ok_or((StatusCode::NOT_FOUND, "Job not found"))?;
+		match self.find_thumbnail(job_id, thumbnail).await? {
+			None => Err((StatusCode::NOT_FOUND, "Thumbnail not requested")),
+			Some((_, true)) => Err((StatusCode::UNPROCESSABLE_ENTITY, "The thumbnail failed")),
+			Some((idx, false)) => manager
+				.get_task_output(&job_id, idx)
+				.await
+				.map_err(|err| err.response()),
+		}
+	}
+
+	///Add the job thumbnail task, unless it was already requested and didn't fail
+	async fn request_thumbnail(
+		&self,
+		job_id: Uuid,
+		thumbnail: Thumbnail,
+	) -> Result<(), (StatusCode, &'static str)> {
+		let manager = self.manager();
+		let job = manager
+			.get_job(&job_id)
+			.await
+			.map_err(|err| err.response())?
+			.ok_or((StatusCode::NOT_FOUND, "Job not found"))?;
+		if let Some((_, false)) = self.find_thumbnail(job_id, thumbnail).await? {
+			return Ok(());
+		}
+		let end = job
+			.end
+			.or_else(|| job.media.as_ref().and_then(|media| media.duration));
+		let input = match thumbnail {
+			//The poster skips the first tenth of the clip, usually intros or black frames
+			Thumbnail::Poster => Input {
+				start: Some(match (job.start.unwrap_or(0.0), end) {
+					(start, Some(end)) => start + (end - start) / 10.0,
+					(start, None) => start,
+				}),
+				..Input::source()
+			},
+			Thumbnail::Sprite { .. } => Input {
+				start: job.start,
+				end,
+				..Input::source()
+			},
+		};
+		let task = TaskSource {
+			inputs: vec![input],
+			recipe: Recipe::Thumbnail(thumbnail),
+			..Default::default()
+		};
+		manager
+			.add_task_to_job(&job_id, task)
+			.await
+			.map_err(|err| err.response())?;
+		Ok(())
+	}
+
+	async fn get_job_info(
+		&self,
+		job_id: Uuid,
+	) -> Result<api::models::JobInfo, (StatusCode, &'static str)> {
+		let job = self
+			.manager()
+			.get_job(&job_id)
+			.await
+			.or(Err((StatusCode::INTERNAL_SERVER_ERROR, "Server error")))?
+			.ok_or((StatusCode::NOT_FOUND, "Job not found"))?;
+		let input_size = self.storage().file_size(job.input_id).await.or(Err((
+			StatusCode::INTERNAL_SERVER_ERROR,
+			"Input not available",
+		)))?;
+		let created_at = job
+			.created_at
+			.duration_since(UNIX_EPOCH)
+			.unwrap_or_default()
+			.as_secs_f64();
+		let video = job.media.as_ref().and_then(|media| media.video());
+		Ok(api::models::JobInfo {
+			job_id: job_id.to_string(),
+			name: job.name,
+			options: Box::new(job.options.into()),
+			input_size: input_size as i64,
+			created_at,
+			trim_start: job.start,
+			trim_end: job.end,
+			duration: job.media.as_ref().and_then(|media| media.duration),
+			width: video
+				.and_then(|stream| stream.width)
+				.map(|width| width as i32),
+			height: video
+				.and_then(|stream| stream.height)
+				.map(|height| height as i32),
+			priority: Some(job.priority.name().to_string()),
+			source_job: job.source_job.map(|id| id.to_string()),
+		})
+	}
+
+	///Metadata reported by the job analysis for the source, and by the task with the job output
+	///
+	///Until the analysis reports it, the source metadata is the probe of the server, when it has one
+	async fn get_job_probe(
+		&self,
+		job_id: Uuid,
+	) -> Result<api::models::JobProbe, (StatusCode, &'static str)> {
+		let manager = self.manager();
+		let job = manager
+			.get_job(&job_id)
+			.await
+			.map_err(|err| err.response())?
+			.ok_or((StatusCode::NOT_FOUND, "Job not found"))?;
+		let output = manager
+			.get_job_output(&job_id, None)
+			.await
+			.map_err(|err| err.response())?;
+		let (mut source, mut output_probe) = (None, None);
+		let mut idx = 0;
+		while let Some(task) = manager
+			.get_task_source(&job_id, idx)
+			.await
+			.map_err(|err| err.response())?
+		{
+			let analysis = matches!(task.recipe, Recipe::Analysis(_)) && source.is_none();
+			let has_output = output.is_some()
+				&& manager
+					.get_task_output(&job_id, idx)
+					.await
+					.map_err(|err| err.response())?
+					== output;
+			if analysis || has_output {
+				let probe = manager
+					.get_task_probe(&job_id, idx)
+					.await
+					.map_err(|err| err.response())?;
+				match analysis {
+					true => source = probe,
+					false => output_probe = probe,
+				}
+			}
+			idx += 1;
+		}
+		Ok(api::models::JobProbe {
+			source: source.or(job.media).map(|probe| Box::new(probe.into())),
+			output: output_probe.map(|probe| Box::new(probe.into())),
+		})
+	}
 }
 
 impl<T: AppState> ClientApi for T {}
 
+#[instrument(skip_all, fields(%job_id, %task_id))]
 pub(crate) async fn task_output_get<S: AppState>(
 	State(state): State<Arc<S>>,
-	_auth: AuthToken,
+	auth: AuthToken,
 	Path((job_id, task_id)): Path<(Uuid, Uuid)>,
 ) -> Result<Response, Response> {
+	state
+		.check_owner(&auth, job_id)
+		.await
+		.map_err(|err| err.into_response())?;
 	let stored = state
 		.get_task_output(job_id, task_id)
 		.await
-		.map_err(|s| s.into_response())?;
+		.map_err(|err| err.into_response())?;
 	let read = state.storage().read_file(stored).await.or(Err((
 		StatusCode::INTERNAL_SERVER_ERROR,
 		"Invalid file",
@@ -61,16 +351,49 @@ pub(crate) async fn task_output_get<S: AppState>(
 		.or(Err(StatusCode::INTERNAL_SERVER_ERROR.into_response()))?
 }
 
+#[derive(Deserialize)]
+pub(super) struct OutputQuery {
+	rendition: Option<String>,
+	///Stream the finished leading segments while the output is not available
+	#[serde(default)]
+	partial: bool,
+}
+
+#[instrument(skip_all, fields(%job_id))]
 pub(super) async fn job_output_get<S: AppState>(
 	State(state): State<Arc<S>>,
-	_auth: AuthToken,
+	auth: AuthToken,
 	Path(job_id): Path<Uuid>,
+	Query(query): Query<OutputQuery>,
 ) -> Result<Response, Response> {
-	let read = state
-		.get_job_output(job_id)
+	state
+		.check_owner(&auth, job_id)
+		.await
+		.map_err(|err| err.into_response())?;
+	let output = state.get_job_output(job_id, query.rendition.clone()).await;
+	let job = state
+		.manager()
+		.get_job(&job_id)
 		.await
-		.map_err(|e| e.into_response())?;
-	use crate::storage::Storage;
+		.or(Err(StatusCode::INTERNAL_SERVER_ERROR.into_response()))?;
+	let container = job
+		.as_ref()
+		.map(|job| job.options.container)
+		.unwrap_or_default();
+	let name = job
+		.and_then(|job| job.name)
+		.unwrap_or_else(|| job_id.to_string());
+	let name = match &query.rendition {
+		Some(rendition) => format!("{name}-{rendition}"),
+		None => name,
+	};
+	let read = match output {
+		Ok(read) => read,
+		Err((StatusCode::SERVICE_UNAVAILABLE, _)) if query.partial => {
+			return partial_output(state.as_ref(), job_id, query.rendition, &name).await;
+		}
+		Err(err) => return Err(err.into_response()),
+	};
 	let read = state
 		.storage()
 		.read_file(read)
@@ -79,19 +402,225 @@ pub(super) async fn job_output_get<S: AppState>(
 	let ranged = crate::api::utils::ranged::from_reader(read, None)
 		.await
 		.or(Err(StatusCode::INTERNAL_SERVER_ERROR.into_response()))?;
-	Ok(ranged.into_response())
+	let headers = [
+		(
+			header::CONTENT_TYPE,
+			HeaderValue::from_static(container.content_type()),
+		),
+		(
+			header::CONTENT_DISPOSITION,
+			attachment(&name, container.extension()),
+		),
+	];
+	Ok((headers, ranged).into_response())
+}
+
+///Stream the finished leading segments joined by ffmpeg, without a known length
+///
+///Segments are encoded to matroska, so the partial output is matroska whatever the job container
+async fn partial_output<S: AppState>(
+	state: &S,
+	job_id: Uuid,
+	rendition: Option<String>,
+	name: &str,
+) -> Result<Response, Response> {
+	let segments = state
+		.get_leading_segments(job_id, rendition)
+		.await
+		.map_err(|err| err.into_response())?;
+	if segments.is_empty() {
+		return Err((StatusCode::SERVICE_UNAVAILABLE, "Output not available yet").into_response());
+	}
+	let ffmpeg = state.ffmpeg().ok_or(
+		(
+			StatusCode::NOT_IMPLEMENTED,
+			"The server has no ffmpeg for partial outputs",
+		)
+			.into_response(),
+	)?;
+	let mut reads = Vec::with_capacity(segments.len());
+	for segment in segments {
+		let read = state
+			.storage()
+			.read_file(segment)
+			.await
+			.or(Err(StatusCode::INTERNAL_SERVER_ERROR.into_response()))?;
+		reads.push(read);
+	}
+	let stream = crate::remux::concat(ffmpeg, reads).await.map_err(|err| {
+		warn!(%err, "Failed to join the segments");
+		StatusCode::INTERNAL_SERVER_ERROR.into_response()
+	})?;
+	let body = Body::from_stream(stream);
+	let container = Container::Matroska;
+	let headers = [
+		(
+			header::CONTENT_TYPE,
+			HeaderValue::from_static(container.content_type()),
+		),
+		(
+			header::CONTENT_DISPOSITION,
+			attachment(&format!("{name}-partial"), container.extension()),
+		),
+	];
+	Ok((headers, body).into_response())
+}
+
+///Tiles of the sprite sheet, as columns and rows
+const SPRITE_GRID: (u32, u32) = (5, 5);
+
+#[derive(Deserialize)]
+pub(super) struct ThumbnailQuery {
+	#[serde(default)]
+	sprite: bool,
+}
+
+impl ThumbnailQuery {
+	fn thumbnail(&self) -> Thumbnail {
+		match self.sprite {
+			true => Thumbnail::Sprite {
+				columns: SPRITE_GRID.0,
+				rows: SPRITE_GRID.1,
+			},
+			false => Thumbnail::Poster,
+		}
+	}
+}
+
+///Generate the poster frame of the job source, or a sprite sheet, again if it failed
+#[instrument(skip_all, fields(%job_id))]
+pub(super) async fn thumbnail_post<S: AppState>(
+	State(state): State<Arc<S>>,
+	auth: AuthToken,
+	Path(job_id): Path<Uuid>,
+	Query(query): Query<ThumbnailQuery>,
+) -> Result<Response, Response> {
+	state
+		.check_owner(&auth, job_id)
+		.await
+		.map_err(|err| err.into_response())?;
+	state
+		.request_thumbnail(job_id, query.thumbnail())
+		.await
+		.map_err(|err| err.into_response())?;
+	Ok((StatusCode::ACCEPTED, "Thumbnail requested").into_response())
+}
+
+///Poster frame of the job source, or a sprite sheet of frames over the encoded clip
+#[instrument(skip_all, fields(%job_id))]
+pub(super) async fn thumbnail_get<S: AppState>(
+	State(state): State<Arc<S>>,
+	auth: AuthToken,
+	Path(job_id): Path<Uuid>,
+	Query(query): Query<ThumbnailQuery>,
+) -> Result<Response, Response> {
+	state
+		.check_owner(&auth, job_id)
+		.await
+		.map_err(|err| err.into_response())?;
+	let thumbnail = query.thumbnail();
+	let Some(output) = state
+		.get_thumbnail(job_id, thumbnail)
+		.await
+		.map_err(|err| err.into_response())?
+	else {
+		return Ok((StatusCode::ACCEPTED, "Thumbnail not ready").into_response());
+	};
+	let read = state
+		.storage()
+		.read_file(output)
+		.await
+		.or(Err(StatusCode::INTERNAL_SERVER_ERROR.into_response()))?;
+	let ranged = crate::api::utils::ranged::from_reader(read, None)
+		.await
+		.or(Err(StatusCode::INTERNAL_SERVER_ERROR.into_response()))?;
+	let headers = [(header::CONTENT_TYPE, HeaderValue::from_static("image/jpeg"))];
+	Ok((headers, ranged).into_response())
 }
 
+///Content-Disposition for the job output, with an ascii fallback when the name is not ascii
+fn attachment(name: &str, extension: &str) -> HeaderValue {
+	let file_name = format!("{name}.{extension}");
+	let ascii: String = file_name
+		.chars()
+		.map(|c| match c {
+			' '..='~' if c != '"' && c != '\\' && c != '/' => c,
+			_ => '_',
+		})
+		.collect();
+	let mut value = format!("attachment; filename=\"{ascii}\"");
+	if ascii != file_name {
+		let encoded: String = file_name
+			.bytes()
+			.map(|byte| match byte {
+				b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+					(byte as char).to_string()
+				}
+				_ => format!("%{byte:02X}"),
+			})
+			.collect();
+		value.push_str(&format!("; filename*=UTF-8''{encoded}"));
+	}
+	HeaderValue::try_from(value).unwrap()
+}
+
+#[instrument(skip_all, fields(%job_id))]
+pub(crate) async fn job_info_get<S: AppState>(
+	State(state): State<Arc<S>>,
+	auth: AuthToken,
+	Path(job_id): Path<Uuid>,
+) -> Result<Json<api::models::JobInfo>, (StatusCode, &'static str)> {
+	state.check_owner(&auth, job_id).await?;
+	state.get_job_info(job_id).await.map(Json)
+}
+
+#[instrument(skip_all, fields(%job_id))]
+pub(crate) async fn job_probe_get<S: AppState>(
+	State(state): State<Arc<S>>,
+	auth: AuthToken,
+	Path(job_id): Path<Uuid>,
+) -> Result<Json<api::models::JobProbe>, (StatusCode, &'static str)> {
+	state.check_owner(&auth, job_id).await?;
+	state.get_job_probe(job_id).await.map(Json)
+}
+
+#[instrument(skip_all, fields(%job_id))]
+pub(crate) async fn job_events_get<S: AppState>(
+	State(state): State<Arc<S>>,
+	auth: AuthToken,
+	Path(job_id): Path<Uuid>,
+) -> Result<Json<Vec<api::models::JobLogEntry>>, (StatusCode, &'static str)> {
+	state.check_owner(&auth, job_id).await?;
+	state
+		.manager()
+		.get_job(&job_id)
+		.await
+		.or(Err((StatusCode::INTERNAL_SERVER_ERROR, "Server error")))?
+		.ok_or((StatusCode::NOT_FOUND, "Job not found"))?;
+	let log = state.manager().get_job_log(&job_id);
+	Ok(Json(log.into_iter().map(Into::into).collect()))
+}
+
+#[instrument(skip_all)]
 pub(crate) async fn get_job_list<S: AppState>(
 	State(state): State<Arc<S>>,
-	_auth: AuthToken,
+	auth: AuthToken,
 ) -> Result<Json<Vec<Uuid>>, StatusCode> {
-	state
+	let jobs = state
 		.manager()
 		.get_job_list()
 		.await
-		.or(Err(StatusCode::INTERNAL_SERVER_ERROR))
-		.map(Json)
+		.or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+	if auth.is_admin(state.as_ref()).await {
+		return Ok(Json(jobs));
+	}
+	let mut owned = Vec::new();
+	for job_id in jobs {
+		if state.check_owner(&auth, job_id).await.is_ok() {
+			owned.push(job_id);
+		}
+	}
+	Ok(Json(owned))
 }
 
 #[cfg(test)]
@@ -120,9 +649,10 @@ mod test {
 			_auth_handler: LocalAuthenticator::default(),
 			_manager: manager,
 			_storage: MemStorage::default(),
+			_notifier: RecordingNotifier::default(),
 		};
 		let (code, _) = state
-			.get_job_output(Uuid::nil())
+			.get_job_output(Uuid::nil(), None)
 			.await
 			.expect_err("Should err for not found");
 		assert_eq!(code, StatusCode::NOT_FOUND)
@@ -132,16 +662,20 @@ mod test {
 	async fn client_api_get_output_before_is_available_503() {
 		let manager = LocalJobManager::default();
 		let job_id = manager
-			.create_job(JobSource {
-				input_id: Default::default(),
-				options: JobOptions {
+			.create_job(JobSource::new(
+				Default::default(),
+				JobOptions {
 					video: Options {
 						codec: None,
 						params: vec![],
 					},
 					audio: None,
+					two_pass: false,
+					container: Default::default(),
+					renditions: vec![],
+					..Default::default()
 				},
-			})
+			))
 			.await
 			.unwrap();
 		let state = GenericApp {
@@ -149,9 +683,10 @@ mod test {
 			_auth_handler: LocalAuthenticator::default(),
 			_manager: manager,
 			_storage: MemStorage::default(),
+			_notifier: RecordingNotifier::default(),
 		};
 		let (code, _) = state
-			.get_job_output(job_id)
+			.get_job_output(job_id, None)
 			.await
 			.expect_err("Should err for unavailable");
 		assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE)
@@ -167,16 +702,20 @@ mod test {
 
 		let manager = LocalJobManager::default();
 		let job_id = manager
-			.create_job(JobSource {
-				input_id: Default::default(),
-				options: JobOptions {
+			.create_job(JobSource::new(
+				Default::default(),
+				JobOptions {
 					video: Options {
 						codec: None,
 						params: vec![],
 					},
 					audio: None,
+					two_pass: false,
+					container: Default::default(),
+					renditions: vec![],
+					..Default::default()
 				},
-			})
+			))
 			.await
 			.unwrap();
 		manager
@@ -185,11 +724,12 @@ mod test {
 				TaskSource {
 					inputs: vec![Input::source()],
 					recipe: Transcode(Vec::new()),
+					..Default::default()
 				},
 			)
 			.await
 			.unwrap();
-		let allocated = manager.allocate_task().await.unwrap().unwrap();
+		let allocated = manager.allocate_task("worker").await.unwrap().unwrap();
 		manager
 			.set_task_output(&allocated.job_id, &allocated.task_id, file)
 			.await
@@ -200,8 +740,12 @@ mod test {
 			_auth_handler: LocalAuthenticator::default(),
 			_manager: manager,
 			_storage: MemStorage::default(),
+			_notifier: RecordingNotifier::default(),
 		};
-		let file_id = state.get_job_output(job_id).await.expect("Job has output");
+		let file_id = state
+			.get_job_output(job_id, None)
+			.await
+			.expect("Job has output");
 		assert_eq!(file_id, file)
 	}
 
@@ -213,6 +757,7 @@ mod test {
 			_auth_handler: LocalAuthenticator::default(),
 			_manager: manager,
 			_storage: MemStorage::default(),
+			_notifier: RecordingNotifier::default(),
 		};
 		let (code, _) = state
 			.get_task_output(Uuid::nil(), Uuid::nil())
@@ -229,19 +774,24 @@ mod test {
 			_auth_handler: LocalAuthenticator::default(),
 			_manager: manager,
 			_storage: MemStorage::default(),
+			_notifier: RecordingNotifier::default(),
 		};
 		let job_id = state
 			.manager()
-			.create_job(JobSource {
-				input_id: Default::default(),
-				options: JobOptions {
+			.create_job(JobSource::new(
+				Default::default(),
+				JobOptions {
 					video: Options {
 						codec: None,
 						params: vec![],
 					},
 					audio: None,
+					two_pass: false,
+					container: Default::default(),
+					renditions: vec![],
+					..Default::default()
 				},
-			})
+			))
 			.await
 			.unwrap();
 		state
@@ -251,11 +801,17 @@ mod test {
 				TaskSource {
 					inputs: vec![Input::source()],
 					recipe: Transcode(Vec::new()),
+					..Default::default()
 				},
 			)
 			.await
 			.unwrap();
-		let allocated = state.manager().allocate_task().await.unwrap().unwrap();
+		let allocated = state
+			.manager()
+			.allocate_task("worker")
+			.await
+			.unwrap()
+			.unwrap();
 		let (code, _) = state
 			.get_task_output(allocated.job_id, allocated.task_id)
 			.await
@@ -273,16 +829,20 @@ mod test {
 
 		let manager = LocalJobManager::default();
 		let job_id = manager
-			.create_job(JobSource {
-				input_id: Default::default(),
-				options: JobOptions {
+			.create_job(JobSource::new(
+				Default::default(),
+				JobOptions {
 					video: Options {
 						codec: None,
 						params: vec![],
 					},
 					audio: None,
+					two_pass: false,
+					container: Default::default(),
+					renditions: vec![],
+					..Default::default()
 				},
-			})
+			))
 			.await
 			.unwrap();
 		manager
@@ -291,11 +851,12 @@ mod test {
 				TaskSource {
 					inputs: vec![Input::source()],
 					recipe: Transcode(Vec::new()),
+					..Default::default()
 				},
 			)
 			.await
 			.unwrap();
-		let allocated = manager.allocate_task().await.unwrap().unwrap();
+		let allocated = manager.allocate_task("worker").await.unwrap().unwrap();
 		manager
 			.set_task_output(&allocated.job_id, &allocated.task_id, file)
 			.await
@@ -306,6 +867,7 @@ mod test {
 			_auth_handler: LocalAuthenticator::default(),
 			_manager: manager,
 			_storage: MemStorage::default(),
+			_notifier: RecordingNotifier::default(),
 		};
 		let file_id = state
 			.get_task_output(allocated.job_id, allocated.task_id)
@@ -366,16 +928,20 @@ mod test_handle {
 		use task::manager::Manager;
 		let job_id = app
 			.manager()
-			.create_job(JobSource {
-				input_id: Default::default(),
-				options: JobOptions {
+			.create_job(JobSource::new(
+				Default::default(),
+				JobOptions {
 					video: Options {
 						codec: None,
 						params: vec![],
 					},
 					audio: None,
+					two_pass: false,
+					container: Default::default(),
+					renditions: vec![],
+					..Default::default()
 				},
-			})
+			))
 			.await
 			.unwrap();
 		app.manager()
@@ -384,11 +950,17 @@ mod test_handle {
 				TaskSource {
 					inputs: vec![],
 					recipe: Recipe::Transcode(Vec::new()),
+					..Default::default()
 				},
 			)
 			.await
 			.unwrap();
-		let instance = app.manager().allocate_task().await.unwrap().unwrap();
+		let instance = app
+			.manager()
+			.allocate_task("worker")
+			.await
+			.unwrap()
+			.unwrap();
 		let code = server
 			.get(&format!(
 				"/job/{}/task/{}/output",
@@ -406,16 +978,20 @@ mod test_handle {
 		use task::manager::Manager;
 		let job_id = app
 			.manager()
-			.create_job(JobSource {
-				input_id: Default::default(),
-				options: JobOptions {
+			.create_job(JobSource::new(
+				Default::default(),
+				JobOptions {
 					video: Options {
 						codec: None,
 						params: vec![],
 					},
 					audio: None,
+					two_pass: false,
+					container: Default::default(),
+					renditions: vec![],
+					..Default::default()
 				},
-			})
+			))
 			.await
 			.unwrap();
 		app.manager()
@@ -424,11 +1000,17 @@ mod test_handle {
 				TaskSource {
 					inputs: vec![],
 					recipe: Recipe::Transcode(Vec::new()),
+					..Default::default()
 				},
 			)
 			.await
 			.unwrap();
-		let instance = app.manager().allocate_task().await.unwrap().unwrap();
+		let instance = app
+			.manager()
+			.allocate_task("worker")
+			.await
+			.unwrap()
+			.unwrap();
 		let content: Vec<u8> = WEBM_SAMPLE.iter().cloned().chain(32..98).collect();
 		let output = {
 			use crate::storage::Storage;
@@ -484,16 +1066,20 @@ mod test_handle {
 		use task::manager::Manager;
 		let id = app
 			.manager()
-			.create_job(JobSource {
-				input_id: Default::default(),
-				options: JobOptions {
+			.create_job(JobSource::new(
+				Default::default(),
+				JobOptions {
 					video: Options {
 						codec: None,
 						params: vec![],
 					},
 					audio: None,
+					two_pass: false,
+					container: Default::default(),
+					renditions: vec![],
+					..Default::default()
 				},
-			})
+			))
 			.await
 			.unwrap();
 		let array = server
@@ -545,16 +1131,20 @@ mod test_handle {
 			use task::manager::Manager;
 			let job_id = app
 				.manager()
-				.create_job(JobSource {
-					input_id: Default::default(),
-					options: JobOptions {
+				.create_job(JobSource::new(
+					Default::default(),
+					JobOptions {
 						video: Options {
 							codec: None,
 							params: vec![],
 						},
 						audio: None,
+						two_pass: false,
+						container: Default::default(),
+						renditions: vec![],
+						..Default::default()
 					},
-				})
+				))
 				.await
 				.unwrap();
 			app.manager()
@@ -563,11 +1153,17 @@ mod test_handle {
 					TaskSource {
 						inputs: vec![],
 						recipe: Recipe::Transcode(Vec::new()),
+						..Default::default()
 					},
 				)
 				.await
 				.unwrap();
-			let instance = app.manager().allocate_task().await.unwrap().unwrap();
+			let instance = app
+				.manager()
+				.allocate_task("worker")
+				.await
+				.unwrap()
+				.unwrap();
 			let code = server
 				.get(&format!("/job/{}/output", instance.job_id))
 				.add_header(AUTHORIZATION, auth)
@@ -582,16 +1178,20 @@ mod test_handle {
 			use task::manager::Manager;
 			let job_id = app
 				.manager()
-				.create_job(JobSource {
-					input_id: Default::default(),
-					options: JobOptions {
+				.create_job(JobSource::new(
+					Default::default(),
+					JobOptions {
 						video: Options {
 							codec: None,
 							params: vec![],
 						},
 						audio: None,
+						two_pass: false,
+						container: Default::default(),
+						renditions: vec![],
+						..Default::default()
 					},
-				})
+				))
 				.await
 				.unwrap();
 			app.manager()
@@ -600,11 +1200,17 @@ mod test_handle {
 					TaskSource {
 						inputs: vec![],
 						recipe: Recipe::Transcode(Vec::new()),
+						..Default::default()
 					},
 				)
 				.await
 				.unwrap();
-			let instance = app.manager().allocate_task().await.unwrap().unwrap();
+			let instance = app
+				.manager()
+				.allocate_task("worker")
+				.await
+				.unwrap()
+				.unwrap();
 			let content: Vec<u8> = WEBM_SAMPLE.iter().cloned().chain(32..98).collect();
 			let output = {
 				use crate::storage::Storage;
@@ -627,4 +1233,597 @@ mod test_handle {
 			assert_eq!(res, content)
 		}
 	}
+
+	mod partial_output {
+		use axum::http::header::CONTENT_TYPE;
+		use axum::http::HeaderValue;
+		use axum_test::TestServer;
+		use task::manager::Manager;
+		use task::{Input, Status};
+
+		use crate::storage::Storage;
+		use crate::AppStateLocal;
+
+		use super::*;
+
+		///Fake ffmpeg that prints the concat list, the argument after `-i`
+		#[cfg(unix)]
+		fn list_printing_ffmpeg() -> std::path::PathBuf {
+			use std::os::unix::fs::PermissionsExt;
+
+			let ffmpeg = std::env::temp_dir().join(format!("list-ffmpeg-{}", Uuid::new_v4()));
+			let script = "#!/bin/sh\nwhile [ \"$1\" != -i ]; do shift; done\ncat \"$2\"\n";
+			std::fs::write(&ffmpeg, script).unwrap();
+			std::fs::set_permissions(&ffmpeg, std::fs::Permissions::from_mode(0o755)).unwrap();
+			ffmpeg
+		}
+
+		///Job split in 3 segments, returns the job and the ids of the allocated segments in order
+		async fn server_with_segments(
+			app: AppStateLocal,
+		) -> (
+			TestServer,
+			std::sync::Arc<AppStateLocal>,
+			HeaderValue,
+			Uuid,
+			Vec<Uuid>,
+		) {
+			let (server, app, auth) = job_and_analyse_task_on(app).await;
+			let analysis = app
+				.manager()
+				.allocate_task("worker")
+				.await
+				.unwrap()
+				.unwrap();
+			let job_id = analysis.job_id;
+			for _ in 0..3 {
+				let transcode = TaskSource {
+					inputs: vec![Input::source()],
+					recipe: Recipe::Transcode(Vec::new()),
+					..Default::default()
+				};
+				app.manager()
+					.add_task_to_job(&job_id, transcode)
+					.await
+					.unwrap();
+			}
+			let merge = task::segment::merge(&[1, 2, 3], None);
+			app.manager().add_task_to_job(&job_id, merge).await.unwrap();
+			app.manager()
+				.update_task_status(&job_id, &analysis.task_id, Status::Finished)
+				.await
+				.unwrap();
+			for _ in 0..3 {
+				app.manager()
+					.allocate_task("worker")
+					.await
+					.unwrap()
+					.unwrap();
+			}
+			let mut allocations = app.manager().get_allocated_tasks().await.unwrap();
+			allocations.sort_by_key(|allocation| allocation.task_idx);
+			let segments = allocations
+				.into_iter()
+				.map(|allocation| allocation.task_id)
+				.collect();
+			(server, app, auth, job_id, segments)
+		}
+
+		async fn set_output(app: &AppStateLocal, job_id: Uuid, task_id: Uuid, content: &[u8]) {
+			let body = axum::body::Body::from(content.to_vec());
+			let output = app.storage().body_to_new_file(body).await.unwrap();
+			app.manager()
+				.set_task_output(&job_id, &task_id, output)
+				.await
+				.unwrap()
+				.unwrap();
+		}
+
+		#[cfg(unix)]
+		#[tokio::test]
+		async fn get_partial_joins_leading_finished_segments() {
+			let app = AppStateLocal::default().with_ffmpeg(&list_printing_ffmpeg());
+			let (server, app, auth, job_id, segments) = server_with_segments(app).await;
+			set_output(&app, job_id, segments[0], b"first").await;
+			set_output(&app, job_id, segments[2], b"third").await;
+			let res = server
+				.get(&format!("/job/{job_id}/output"))
+				.add_query_param("partial", true)
+				.add_header(AUTHORIZATION, auth)
+				.await;
+			assert_eq!(res.status_code(), StatusCode::OK);
+			assert_eq!(res.headers().get(CONTENT_TYPE).unwrap(), "video/x-matroska");
+			let list = res.text();
+			let files: Vec<_> = list.lines().collect();
+			assert_eq!(files.len(), 1, "{list}");
+			let path = files[0].trim_start_matches("file '").trim_end_matches('\'');
+			assert!(path.ends_with("0.mkv"), "{list}");
+		}
+
+		#[tokio::test]
+		async fn get_partial_without_ffmpeg_not_implemented() {
+			let app = AppStateLocal::default();
+			let (server, app, auth, job_id, segments) = server_with_segments(app).await;
+			set_output(&app, job_id, segments[0], b"first").await;
+			server
+				.get(&format!("/job/{job_id}/output"))
+				.add_query_param("partial", true)
+				.add_header(AUTHORIZATION, auth)
+				.await
+				.assert_status(StatusCode::NOT_IMPLEMENTED);
+		}
+
+		#[tokio::test]
+		async fn get_partial_without_finished_segments_unavailable() {
+			let (server, _, auth, job_id, _) = server_with_segments(AppStateLocal::default()).await;
+			let code = server
+				.get(&format!("/job/{job_id}/output"))
+				.add_query_param("partial", true)
+				.add_header(AUTHORIZATION, auth)
+				.await
+				.status_code();
+			assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE)
+		}
+
+		#[tokio::test]
+		async fn get_without_partial_unavailable() {
+			let (server, app, auth, job_id, segments) =
+				server_with_segments(AppStateLocal::default()).await;
+			set_output(&app, job_id, segments[0], b"first").await;
+			let code = server
+				.get(&format!("/job/{job_id}/output"))
+				.add_header(AUTHORIZATION, auth)
+				.await
+				.status_code();
+			assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE)
+		}
+	}
+
+	mod job_output_headers {
+		use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+		use axum::http::HeaderValue;
+
+		use task::manager::Manager;
+		use task::Container;
+
+		use crate::storage::Storage;
+
+		use super::super::attachment;
+		use super::*;
+
+		async fn server_with_finished_job(
+			name: Option<&str>,
+			container: Container,
+		) -> (axum_test::TestServer, HeaderValue, Uuid) {
+			let (server, app, auth) = test_server_state_auth().await;
+			let mut source = JobSource {
+				name: name.map(String::from),
+				..create_job_source(Uuid::nil())
+			};
+			source.options.container = container;
+			let job_id = app.manager().create_job(source).await.unwrap();
+			app.manager()
+				.add_task_to_job(
+					&job_id,
+					TaskSource {
+						inputs: vec![],
+						recipe: Recipe::Transcode(Vec::new()),
+						..Default::default()
+					},
+				)
+				.await
+				.unwrap();
+			let instance = app
+				.manager()
+				.allocate_task("worker")
+				.await
+				.unwrap()
+				.unwrap();
+			let output = {
+				let file = app.storage().create_file().await.unwrap();
+				app.storage().store_file(file).await.unwrap()
+			};
+			app.manager()
+				.set_task_output(&job_id, &instance.task_id, output)
+				.await
+				.unwrap();
+			(server, auth, job_id)
+		}
+
+		#[tokio::test]
+		async fn output_is_attachment_named_after_job() {
+			let (server, auth, job_id) =
+				server_with_finished_job(Some("my video"), Container::Matroska).await;
+			let res = server
+				.get(&format!("/job/{}/output", job_id))
+				.add_header(AUTHORIZATION, auth)
+				.await;
+			assert_eq!(
+				res.headers().get(CONTENT_DISPOSITION).unwrap(),
+				"attachment; filename=\"my video.mkv\""
+			);
+			assert_eq!(res.headers().get(CONTENT_TYPE).unwrap(), "video/x-matroska");
+		}
+
+		#[tokio::test]
+		async fn output_of_unknown_rendition_not_found() {
+			let (server, auth, job_id) = server_with_finished_job(None, Container::Matroska).await;
+			let code = server
+				.get(&format!("/job/{}/output", job_id))
+				.add_query_param("rendition", "720p")
+				.add_header(AUTHORIZATION, auth)
+				.await
+				.status_code();
+			assert_eq!(code, StatusCode::NOT_FOUND)
+		}
+
+		#[tokio::test]
+		async fn unnamed_job_output_is_named_after_job_id() {
+			let (server, auth, job_id) = server_with_finished_job(None, Container::Matroska).await;
+			let res = server
+				.get(&format!("/job/{}/output", job_id))
+				.add_header(AUTHORIZATION, auth)
+				.await;
+			let expected = format!("attachment; filename=\"{job_id}.mkv\"");
+			assert_eq!(res.headers().get(CONTENT_DISPOSITION).unwrap(), &expected);
+		}
+
+		#[tokio::test]
+		async fn output_uses_job_container() {
+			let (server, auth, job_id) =
+				server_with_finished_job(Some("clip"), Container::Mp4).await;
+			let res = server
+				.get(&format!("/job/{}/output", job_id))
+				.add_header(AUTHORIZATION, auth)
+				.await;
+			assert_eq!(
+				res.headers().get(CONTENT_DISPOSITION).unwrap(),
+				"attachment; filename=\"clip.mp4\""
+			);
+			assert_eq!(res.headers().get(CONTENT_TYPE).unwrap(), "video/mp4");
+		}
+
+		#[test]
+		fn attachment_replaces_unsafe_characters() {
+			let value = attachment("a\"b/c", "mkv");
+			assert_eq!(
+				value,
+				"attachment; filename=\"a_b_c.mkv\"; filename*=UTF-8''a%22b%2Fc.mkv"
+			);
+		}
+
+		#[test]
+		fn attachment_non_ascii_has_encoded_name() {
+			let value = attachment("vídeo", "mkv");
+			assert_eq!(
+				value,
+				"attachment; filename=\"v_deo.mkv\"; filename*=UTF-8''v%C3%ADdeo.mkv"
+			);
+		}
+	}
+
+	mod job_info {
+		use super::*;
+
+		#[tokio::test]
+		async fn get_without_auth_forbidden() {
+			let server = test_server();
+			let code = server
+				.get(&format!("/job/{}/info", Uuid::nil()))
+				.await
+				.status_code();
+			assert_eq!(code, StatusCode::FORBIDDEN)
+		}
+
+		#[tokio::test]
+		async fn get_with_auth_bad_job_not_found() {
+			let (server, auth) = test_server_auth().await;
+			let code = server
+				.get(&format!("/job/{}/info", Uuid::nil()))
+				.add_header(AUTHORIZATION, auth)
+				.await
+				.status_code();
+			assert_eq!(code, StatusCode::NOT_FOUND)
+		}
+
+		#[tokio::test]
+		async fn get_returns_job_options_and_input_size() {
+			let (server, app, auth) = test_server_state_auth().await;
+			use task::manager::Manager;
+			let input_id = {
+				use crate::storage::Storage;
+				let mut file = app.storage().create_file().await.unwrap();
+				use tokio::io::AsyncWriteExt;
+				file.write_all(&WEBM_SAMPLE).await.unwrap();
+				app.storage().store_file(file).await.unwrap()
+			};
+			let options = create_job_options();
+			let job_id = app
+				.manager()
+				.create_job(JobSource::new(input_id, options.clone()))
+				.await
+				.unwrap();
+			let info = server
+				.get(&format!("/job/{}/info", job_id))
+				.add_header(AUTHORIZATION, auth)
+				.await
+				.json::<api::models::JobInfo>();
+			assert_eq!(info.job_id, job_id.to_string());
+			assert_eq!(*info.options, options.into());
+			assert_eq!(info.input_size, WEBM_SAMPLE.len() as i64);
+		}
+
+		#[tokio::test]
+		async fn get_returns_creation_time() {
+			let (server, app, auth) = test_server_state_auth().await;
+			use task::manager::Manager;
+			let source = create_job_source(Uuid::nil());
+			let input_id = {
+				use crate::storage::Storage;
+				let file = app.storage().create_file().await.unwrap();
+				app.storage().store_file(file).await.unwrap()
+			};
+			let created_at = source.created_at;
+			let job_id = app
+				.manager()
+				.create_job(JobSource { input_id, ..source })
+				.await
+				.unwrap();
+			let info = server
+				.get(&format!("/job/{}/info", job_id))
+				.add_header(AUTHORIZATION, auth)
+				.await
+				.json::<api::models::JobInfo>();
+			let expected = created_at
+				.duration_since(std::time::UNIX_EPOCH)
+				.unwrap()
+				.as_secs_f64();
+			assert!((info.created_at - expected).abs() < 1e-3);
+		}
+	}
+
+	mod job_events {
+		use super::*;
+
+		#[tokio::test]
+		async fn get_with_auth_bad_job_not_found() {
+			let (server, auth) = test_server_auth().await;
+			let code = server
+				.get(&format!("/job/{}/events", Uuid::nil()))
+				.add_header(AUTHORIZATION, auth)
+				.await
+				.status_code();
+			assert_eq!(code, StatusCode::NOT_FOUND)
+		}
+
+		#[tokio::test]
+		async fn get_lists_allocation_with_worker_id() {
+			let (server, _, auth) = app_with_job_and_analyse_task().await;
+			let task = server
+				.get("/allocate_task")
+				.add_header(AUTHORIZATION, auth.clone())
+				.await
+				.json::<api::models::Task>();
+			let events = server
+				.get(&format!("/job/{}/events", task.job_id))
+				.add_header(AUTHORIZATION, auth.clone())
+				.await
+				.json::<Vec<api::models::JobLogEntry>>();
+			let names: Vec<_> = events.iter().map(|entry| entry.event.as_str()).collect();
+			assert_eq!(names, ["created", "task_added", "allocated"]);
+			assert_eq!(events[2].task_idx, Some(0));
+			let token = auth.to_str().unwrap();
+			let worker = events[2].worker.as_deref().unwrap();
+			assert_ne!(worker, token);
+			assert_eq!(worker, crate::api::token_id(token));
+		}
+	}
+
+	mod job_probe {
+		use task::manager::Manager;
+		use task::MediaProbe;
+
+		use super::*;
+
+		#[tokio::test]
+		async fn get_with_auth_bad_job_not_found() {
+			let (server, auth) = test_server_auth().await;
+			let code = server
+				.get(&format!("/job/{}/probe", Uuid::nil()))
+				.add_header(AUTHORIZATION, auth)
+				.await
+				.status_code();
+			assert_eq!(code, StatusCode::NOT_FOUND)
+		}
+
+		#[tokio::test]
+		async fn get_returns_source_probe_from_analysis() {
+			let (server, app, auth) = app_with_job_and_analyse_task().await;
+			let instance = app
+				.manager()
+				.allocate_task("worker")
+				.await
+				.unwrap()
+				.unwrap();
+			let probe = MediaProbe {
+				duration: Some(10.0),
+				..Default::default()
+			};
+			app.manager()
+				.set_task_probe(&instance.job_id, &instance.task_id, probe)
+				.await
+				.unwrap();
+			let res = server
+				.get(&format!("/job/{}/probe", instance.job_id))
+				.add_header(AUTHORIZATION, auth)
+				.await
+				.json::<api::models::JobProbe>();
+			assert_eq!(res.source.unwrap().duration, Some(10.0));
+			assert!(res.output.is_none())
+		}
+
+		#[tokio::test]
+		async fn get_before_analysis_returns_server_probe() {
+			let app = crate::AppStateLocal::default();
+			let job = JobSource {
+				media: Some(MediaProbe {
+					duration: Some(4.0),
+					..Default::default()
+				}),
+				..create_job_source(Uuid::new_v4())
+			};
+			let job_id = app.manager().create_job(job).await.unwrap();
+			let (server, _, auth) = test_server_state_auth_generic(std::sync::Arc::new(app)).await;
+			let res = server
+				.get(&format!("/job/{job_id}/probe"))
+				.add_header(AUTHORIZATION, auth)
+				.await
+				.json::<api::models::JobProbe>();
+			assert_eq!(res.source.unwrap().duration, Some(4.0))
+		}
+	}
+
+	mod thumbnail {
+		use axum::http::header::CONTENT_TYPE;
+		use task::manager::Manager;
+		use task::{Instance, Status, Thumbnail};
+
+		use crate::storage::Storage;
+		use crate::AppStateLocal;
+
+		use super::*;
+
+		#[tokio::test]
+		async fn get_without_auth_forbidden() {
+			let server = test_server();
+			let code = server
+				.get(&format!("/job/{}/thumbnail", Uuid::nil()))
+				.await
+				.status_code();
+			assert_eq!(code, StatusCode::FORBIDDEN)
+		}
+
+		#[tokio::test]
+		async fn post_adds_the_task_once() {
+			let (server, app, auth) = app_with_job_and_analyse_task().await;
+			let job_id = app.manager().get_job_list().await.unwrap()[0];
+			for _ in 0..2 {
+				server
+					.post(&format!("/job/{job_id}/thumbnail"))
+					.add_header(AUTHORIZATION, auth.clone())
+					.await
+					.assert_status(StatusCode::ACCEPTED);
+			}
+			let task = app.manager().get_task_source(&job_id, 1).await.unwrap();
+			assert_eq!(task.unwrap().recipe, Recipe::Thumbnail(Thumbnail::Poster));
+			let task = app.manager().get_task_source(&job_id, 2).await.unwrap();
+			assert!(task.is_none())
+		}
+
+		#[tokio::test]
+		async fn get_without_post_not_found() {
+			let (server, app, auth) = app_with_job_and_analyse_task().await;
+			let job_id = app.manager().get_job_list().await.unwrap()[0];
+			server
+				.get(&format!("/job/{job_id}/thumbnail"))
+				.add_header(AUTHORIZATION, auth)
+				.await
+				.assert_status(StatusCode::NOT_FOUND);
+			let task = app.manager().get_task_source(&job_id, 1).await.unwrap();
+			assert!(task.is_none())
+		}
+
+		#[tokio::test]
+		async fn get_running_accepted() {
+			let (server, app, auth) = app_with_job_and_analyse_task().await;
+			let job_id = app.manager().get_job_list().await.unwrap()[0];
+			let path = format!("/job/{job_id}/thumbnail");
+			server
+				.post(&path)
+				.add_header(AUTHORIZATION, auth.clone())
+				.await;
+			server
+				.get(&path)
+				.add_header(AUTHORIZATION, auth)
+				.await
+				.assert_status(StatusCode::ACCEPTED);
+		}
+
+		///Allocate the tasks until the sprite sheet one
+		async fn allocate_sprite(app: &AppStateLocal) -> Instance {
+			let mut thumbnail = None;
+			while let Some(instance) = app.manager().allocate_task("worker").await.unwrap() {
+				if matches!(instance.recipe, Recipe::Thumbnail(Thumbnail::Sprite { .. })) {
+					thumbnail = Some(instance);
+				}
+			}
+			thumbnail.expect("Should allocate the thumbnail")
+		}
+
+		#[tokio::test]
+		async fn get_finished_returns_jpeg() {
+			let (server, app, auth) = app_with_job_and_analyse_task().await;
+			let job_id = app.manager().get_job_list().await.unwrap()[0];
+			let path = format!("/job/{job_id}/thumbnail");
+			server
+				.post(&path)
+				.add_query_param("sprite", true)
+				.add_header(AUTHORIZATION, auth.clone())
+				.await;
+			let instance = allocate_sprite(app.as_ref()).await;
+			let output = {
+				let mut file = app.storage().create_file().await.unwrap();
+				use tokio::io::AsyncWriteExt;
+				file.write_all(b"jpeg").await.unwrap();
+				app.storage().store_file(file).await.unwrap()
+			};
+			app.manager()
+				.set_task_output(&job_id, &instance.task_id, output)
+				.await
+				.unwrap()
+				.unwrap();
+			let res = server
+				.get(&path)
+				.add_query_param("sprite", true)
+				.add_header(AUTHORIZATION, auth)
+				.await;
+			res.assert_status_ok();
+			assert_eq!(res.headers().get(CONTENT_TYPE).unwrap(), "image/jpeg");
+			assert_eq!(res.into_bytes().as_ref(), b"jpeg");
+		}
+
+		#[tokio::test]
+		async fn failed_unprocessable_until_posted_again() {
+			let (server, app, auth) = app_with_job_and_analyse_task().await;
+			let job_id = app.manager().get_job_list().await.unwrap()[0];
+			let path = format!("/job/{job_id}/thumbnail");
+			let post = || {
+				server
+					.post(&path)
+					.add_query_param("sprite", true)
+					.add_header(AUTHORIZATION, auth.clone())
+			};
+			let get = || {
+				server
+					.get(&path)
+					.add_query_param("sprite", true)
+					.add_header(AUTHORIZATION, auth.clone())
+			};
+			post().await.assert_status(StatusCode::ACCEPTED);
+			let instance = allocate_sprite(app.as_ref()).await;
+			app.manager()
+				.update_task_status(
+					&job_id,
+					&instance.task_id,
+					Status::Failed(Some("No frames".to_string())),
+				)
+				.await
+				.unwrap()
+				.unwrap();
+			get().await.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+			post().await.assert_status(StatusCode::ACCEPTED);
+			get().await.assert_status(StatusCode::ACCEPTED);
+			allocate_sprite(app.as_ref()).await;
+		}
+	}
 }
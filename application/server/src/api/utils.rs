@@ -65,24 +65,170 @@ pub(crate) mod parse {
 	use axum::http::header::ToStrError;
 	use axum::http::{HeaderMap, HeaderValue};
 
-	use task::{JobOptions, Options};
+	use task::{AnalysisOptions, Container, JobOptions, Options, Priority, Rendition};
 
-	pub fn parse_job_options(headers: &HeaderMap) -> Result<JobOptions, ToStrError> {
+	///Parse the job options, fails on invalid header values or an unknown container
+	///
+	///The subtitles and attachments are copied as they are, so only matroska can hold them
+	pub fn parse_job_options(headers: &HeaderMap) -> Result<JobOptions, ()> {
 		let video_codec = headers
 			.get("video_codec")
 			.map(|val| val.to_str())
-			.transpose()?
+			.transpose()
+			.or(Err(()))?
+			.map(String::from);
+		let video_params =
+			split_multiple_headers_into_strings(headers.get_all("video_param")).or(Err(()))?;
+		let audio_codec = headers
+			.get("audio_codec")
+			.map(|val| val.to_str())
+			.transpose()
+			.or(Err(()))?
+			.map(String::from);
+		let audio_params =
+			split_multiple_headers_into_strings(headers.get_all("audio_param")).or(Err(()))?;
+		let audio = (audio_codec.is_some() || !audio_params.is_empty()).then_some(Options {
+			codec: audio_codec,
+			params: audio_params,
+		});
+		let two_pass = parse_flag(headers, "two_pass")?;
+		let copy_subtitles = parse_flag(headers, "copy_subtitles")?;
+		let container = match headers.get("container") {
+			Some(val) => val
+				.to_str()
+				.ok()
+				.and_then(Container::from_extension)
+				.ok_or(())?,
+			None => Container::default(),
+		};
+		if copy_subtitles && container != Container::Matroska {
+			return Err(());
+		}
+		let input_params =
+			split_multiple_headers_into_strings(headers.get_all("input_param")).or(Err(()))?;
+		let renditions = parse_renditions(headers)?;
+		let preset = headers
+			.get("preset")
+			.map(|val| val.to_str())
+			.transpose()
+			.or(Err(()))?
 			.map(String::from);
-		let video_params = split_multiple_headers_into_strings(headers.get_all("video_param"))?;
 		Ok(JobOptions {
 			video: Options {
 				codec: video_codec,
 				params: video_params,
 			},
-			audio: None,
+			audio,
+			two_pass,
+			container,
+			preset,
+			input_params,
+			copy_subtitles,
+			renditions,
+		})
+	}
+
+	///Missing flags are false, values other than `true` or `false` are invalid
+	fn parse_flag(headers: &HeaderMap, name: &str) -> Result<bool, ()> {
+		let Some(value) = headers.get(name) else {
+			return Ok(false);
+		};
+		match value.to_str().or(Err(()))? {
+			val if val.eq_ignore_ascii_case("true") => Ok(true),
+			val if val.eq_ignore_ascii_case("false") => Ok(false),
+			_ => Err(()),
+		}
+	}
+
+	///Each rendition header holds the rendition name, followed by its video params
+	fn parse_renditions(headers: &HeaderMap) -> Result<Vec<Rendition>, ()> {
+		let mut renditions: Vec<Rendition> = Vec::new();
+		for value in headers.get_all("rendition") {
+			let mut words = value.to_str().or(Err(()))?.split_whitespace();
+			let name = words.next().ok_or(())?;
+			if renditions.iter().any(|rendition| rendition.name == name) {
+				return Err(());
+			}
+			renditions.push(Rendition {
+				name: name.to_string(),
+				params: words.map(String::from).collect(),
+			});
+		}
+		Ok(renditions)
+	}
+
+	///Parse the segmentation options, fails on invalid or out of range header values
+	pub fn parse_analysis_options(headers: &HeaderMap) -> Result<AnalysisOptions, ()> {
+		let duration = non_negative(headers, "segment_duration")?;
+		let scene_threshold = non_negative(headers, "scene_threshold")?;
+		if scene_threshold.is_some_and(|threshold| threshold > 1.0) {
+			return Err(());
+		}
+		let max_segments = headers
+			.get("max_segments")
+			.map(|val| {
+				val.to_str()
+					.ok()
+					.and_then(|val| val.parse::<u32>().ok())
+					.filter(|max| *max > 0)
+					.ok_or(())
+			})
+			.transpose()?;
+		Ok(AnalysisOptions {
+			duration,
+			scene_threshold,
+			max_segments,
 		})
 	}
 
+	///Parse the clip of the source to encode, fails unless the start is before the end
+	pub fn parse_trim(headers: &HeaderMap) -> Result<(Option<f64>, Option<f64>), ()> {
+		let start = non_negative(headers, "trim_start")?;
+		let end = non_negative(headers, "trim_end")?;
+		match (start, end) {
+			(Some(start), Some(end)) if start >= end => Err(()),
+			trim => Ok(trim),
+		}
+	}
+
+	///Parse the video and audio streams selected from the source, by their index among its
+	///streams of the same type
+	pub fn parse_streams(headers: &HeaderMap) -> Result<(Option<u32>, Option<u32>), ()> {
+		let index = |name| {
+			headers
+				.get(name)
+				.map(|val| {
+					val.to_str()
+						.ok()
+						.and_then(|val| val.parse::<u32>().ok())
+						.ok_or(())
+				})
+				.transpose()
+		};
+		Ok((index("video_stream")?, index("audio_stream")?))
+	}
+
+	///Parse the job scheduling class, jobs without it have the normal priority
+	pub fn parse_priority(headers: &HeaderMap) -> Result<Priority, ()> {
+		match headers.get("priority") {
+			Some(val) => val.to_str().ok().and_then(Priority::from_name).ok_or(()),
+			None => Ok(Priority::default()),
+		}
+	}
+
+	fn non_negative(headers: &HeaderMap, name: &str) -> Result<Option<f64>, ()> {
+		headers
+			.get(name)
+			.map(|val| {
+				val.to_str()
+					.ok()
+					.and_then(|val| val.parse::<f64>().ok())
+					.filter(|val| val.is_finite() && *val >= 0.0)
+					.ok_or(())
+			})
+			.transpose()
+	}
+
 	pub fn split_multiple_headers_into_strings<'a, I>(iter: I) -> Result<Vec<String>, ToStrError>
 	where
 		I: IntoIterator<Item = &'a HeaderValue>,
@@ -97,7 +243,12 @@ pub(crate) mod parse {
 	mod test {
 		use axum::http::{HeaderMap, HeaderValue};
 
-		use crate::api::utils::parse::{parse_job_options, split_multiple_headers_into_strings};
+		use task::{Container, Priority};
+
+		use crate::api::utils::parse::{
+			parse_analysis_options, parse_job_options, parse_priority, parse_streams, parse_trim,
+			split_multiple_headers_into_strings,
+		};
 
 		#[test]
 		fn with_empty_iterator_return_empty_vec() {
@@ -155,5 +306,353 @@ pub(crate) mod parse {
 				args.into_iter().map(String::from).collect::<Vec<_>>()
 			);
 		}
+
+		#[test]
+		fn parse_without_audio_job_options() {
+			let mut headers = HeaderMap::new();
+			headers.insert("video_codec", HeaderValue::from_static("libx264"));
+			assert_eq!(parse_job_options(&headers).unwrap().audio, None);
+		}
+
+		#[test]
+		fn parse_audio_job_options() {
+			let mut headers = HeaderMap::new();
+			headers.insert("audio_codec", HeaderValue::from_static("libopus"));
+			headers.append("audio_param", HeaderValue::from_static("-b:a"));
+			headers.append("audio_param", HeaderValue::from_static("96k"));
+			let audio = parse_job_options(&headers).unwrap().audio.unwrap();
+			assert_eq!(audio.codec.as_deref(), Some("libopus"));
+			assert_eq!(audio.params, vec!["-b:a", "96k"]);
+		}
+
+		#[test]
+		fn parse_two_pass_job_options() {
+			let mut headers = HeaderMap::new();
+			assert!(!parse_job_options(&headers).unwrap().two_pass);
+			headers.insert("two_pass", HeaderValue::from_static("true"));
+			assert!(parse_job_options(&headers).unwrap().two_pass);
+			headers.insert("two_pass", HeaderValue::from_static("false"));
+			assert!(!parse_job_options(&headers).unwrap().two_pass);
+			headers.insert("two_pass", HeaderValue::from_static("yes"));
+			assert!(parse_job_options(&headers).is_err());
+		}
+
+		#[test]
+		fn parse_copy_subtitles_job_options() {
+			let mut headers = HeaderMap::new();
+			assert!(!parse_job_options(&headers).unwrap().copy_subtitles);
+			headers.insert("copy_subtitles", HeaderValue::from_static("true"));
+			assert!(parse_job_options(&headers).unwrap().copy_subtitles);
+			headers.insert("copy_subtitles", HeaderValue::from_static("1"));
+			assert!(parse_job_options(&headers).is_err());
+		}
+
+		#[test]
+		fn parse_copy_subtitles_only_into_matroska() {
+			let mut headers = HeaderMap::new();
+			headers.insert("copy_subtitles", HeaderValue::from_static("true"));
+			headers.insert("container", HeaderValue::from_static("mp4"));
+			assert!(parse_job_options(&headers).is_err());
+			headers.insert("container", HeaderValue::from_static("webm"));
+			assert!(parse_job_options(&headers).is_err());
+		}
+
+		#[test]
+		fn parse_container_job_options() {
+			let mut headers = HeaderMap::new();
+			let container = parse_job_options(&headers).unwrap().container;
+			assert_eq!(container, Container::Matroska);
+			headers.insert("container", HeaderValue::from_static("mp4"));
+			let container = parse_job_options(&headers).unwrap().container;
+			assert_eq!(container, Container::Mp4);
+			headers.insert("container", HeaderValue::from_static("avi"));
+			assert!(parse_job_options(&headers).is_err());
+		}
+
+		#[test]
+		fn parse_preset_job_options() {
+			let mut headers = HeaderMap::new();
+			assert_eq!(parse_job_options(&headers).unwrap().preset, None);
+			headers.insert("preset", HeaderValue::from_static("x264-medium"));
+			let preset = parse_job_options(&headers).unwrap().preset;
+			assert_eq!(preset.as_deref(), Some("x264-medium"));
+		}
+
+		#[test]
+		fn parse_input_params_job_options() {
+			let mut headers = HeaderMap::new();
+			headers.append("input_param", HeaderValue::from_static("-hwaccel"));
+			headers.append("input_param", HeaderValue::from_static("auto"));
+			let params = parse_job_options(&headers).unwrap().input_params;
+			assert_eq!(params, vec!["-hwaccel", "auto"]);
+		}
+
+		#[test]
+		fn parse_renditions_job_options() {
+			let mut headers = HeaderMap::new();
+			headers.append(
+				"rendition",
+				HeaderValue::from_static("720p -vf scale=-2:720"),
+			);
+			headers.append("rendition", HeaderValue::from_static("480p"));
+			let renditions = parse_job_options(&headers).unwrap().renditions;
+			assert_eq!(renditions[0].name, "720p");
+			assert_eq!(renditions[0].params, vec!["-vf", "scale=-2:720"]);
+			assert_eq!(renditions[1].name, "480p");
+			assert!(renditions[1].params.is_empty());
+		}
+
+		#[test]
+		fn parse_renditions_with_repeated_name_fails() {
+			let mut headers = HeaderMap::new();
+			headers.append("rendition", HeaderValue::from_static("720p"));
+			headers.append("rendition", HeaderValue::from_static("720p -crf 30"));
+			assert!(parse_job_options(&headers).is_err());
+			let mut headers = HeaderMap::new();
+			headers.append("rendition", HeaderValue::from_static(" "));
+			assert!(parse_job_options(&headers).is_err());
+		}
+
+		#[test]
+		fn parse_analysis_options_headers() {
+			let mut headers = HeaderMap::new();
+			headers.insert("segment_duration", HeaderValue::from_static("25"));
+			headers.insert("scene_threshold", HeaderValue::from_static("0.4"));
+			headers.insert("max_segments", HeaderValue::from_static("16"));
+			let options = parse_analysis_options(&headers).unwrap();
+			assert_eq!(options.duration, Some(25.0));
+			assert_eq!(options.scene_threshold, Some(0.4));
+			assert_eq!(options.max_segments, Some(16));
+		}
+
+		#[test]
+		fn parse_priority_headers() {
+			let mut headers = HeaderMap::new();
+			assert_eq!(parse_priority(&headers), Ok(Priority::Normal));
+			headers.insert("priority", HeaderValue::from_static("Interactive"));
+			assert_eq!(parse_priority(&headers), Ok(Priority::Interactive));
+			headers.insert("priority", HeaderValue::from_static("batch"));
+			assert_eq!(parse_priority(&headers), Ok(Priority::Batch));
+			headers.insert("priority", HeaderValue::from_static("urgent"));
+			assert!(parse_priority(&headers).is_err());
+		}
+
+		#[test]
+		fn parse_trim_headers() {
+			let mut headers = HeaderMap::new();
+			assert_eq!(parse_trim(&headers), Ok((None, None)));
+			headers.insert("trim_start", HeaderValue::from_static("10"));
+			assert_eq!(parse_trim(&headers), Ok((Some(10.0), None)));
+			headers.insert("trim_end", HeaderValue::from_static("40.5"));
+			assert_eq!(parse_trim(&headers), Ok((Some(10.0), Some(40.5))));
+			headers.insert("trim_end", HeaderValue::from_static("10"));
+			assert!(parse_trim(&headers).is_err());
+		}
+
+		#[test]
+		fn parse_streams_headers() {
+			let mut headers = HeaderMap::new();
+			assert_eq!(parse_streams(&headers), Ok((None, None)));
+			headers.insert("audio_stream", HeaderValue::from_static("2"));
+			assert_eq!(parse_streams(&headers), Ok((None, Some(2))));
+			headers.insert("video_stream", HeaderValue::from_static("-1"));
+			assert!(parse_streams(&headers).is_err());
+		}
+
+		#[test]
+		fn parse_analysis_options_out_of_range() {
+			for (name, value) in [
+				("segment_duration", "-1"),
+				("scene_threshold", "1.5"),
+				("max_segments", "0"),
+			] {
+				let mut headers = HeaderMap::new();
+				headers.insert(name, HeaderValue::from_static(value));
+				assert!(parse_analysis_options(&headers).is_err(), "{name}: {value}");
+			}
+		}
+	}
+}
+
+pub(crate) mod digest {
+	//! Content checksum, as sent on the `Digest` header
+	use axum::http::HeaderValue;
+	use base64::engine::general_purpose::STANDARD;
+	use base64::Engine;
+	use sha2::{Digest, Sha256};
+	use tokio::io::{AsyncRead, AsyncReadExt};
+
+	pub(crate) type Sha256Digest = [u8; 32];
+
+	///Parse the sha-256 value from a `Digest` header
+	///
+	///Returns Ok(None) when the header only lists unsupported algorithms
+	pub(crate) fn parse_sha256(header: &HeaderValue) -> Result<Option<Sha256Digest>, ()> {
+		let header = header.to_str().or(Err(()))?;
+		let Some(value) = header.split(',').find_map(|entry| {
+			let (algorithm, value) = entry.trim().split_once('=')?;
+			algorithm.eq_ignore_ascii_case("sha-256").then_some(value)
+		}) else {
+			return Ok(None);
+		};
+		let decoded = STANDARD.decode(value).or(Err(()))?;
+		decoded.try_into().map(Some).or(Err(()))
+	}
+
+	pub(crate) async fn sha256_of(
+		mut read: impl AsyncRead + Unpin,
+	) -> std::io::Result<Sha256Digest> {
+		let mut hasher = Sha256::new();
+		let mut buf = vec![0; 64 * 1024];
+		loop {
+			let len = read.read(&mut buf).await?;
+			if len == 0 {
+				break;
+			}
+			hasher.update(&buf[..len]);
+		}
+		Ok(hasher.finalize().into())
+	}
+
+	///Format the digest as a `Digest` header value
+	pub(crate) fn to_header(digest: &Sha256Digest) -> HeaderValue {
+		let digest = STANDARD.encode(digest);
+		HeaderValue::try_from(format!("sha-256={digest}")).unwrap()
+	}
+
+	///Check if the `Want-Digest` header accepts sha-256
+	pub(crate) fn wants_sha256(header: &HeaderValue) -> bool {
+		header.to_str().is_ok_and(|header| {
+			header.split(',').any(|entry| {
+				let algorithm = entry.split(';').next().unwrap_or_default();
+				algorithm.trim().eq_ignore_ascii_case("sha-256")
+			})
+		})
+	}
+
+	#[cfg(test)]
+	pub(crate) fn header_for(content: &[u8]) -> HeaderValue {
+		to_header(&Sha256::digest(content).into())
+	}
+
+	#[cfg(test)]
+	mod test {
+		use axum::http::HeaderValue;
+		use sha2::{Digest, Sha256};
+
+		use crate::WEBM_SAMPLE;
+
+		use super::*;
+
+		#[test]
+		fn parse_header_for_content() {
+			let header = header_for(&WEBM_SAMPLE);
+			let parsed = parse_sha256(&header).unwrap().unwrap();
+			assert_eq!(parsed, <[u8; 32]>::from(Sha256::digest(WEBM_SAMPLE)))
+		}
+
+		#[test]
+		fn parse_picks_sha256_among_other_algorithms() {
+			let sha = header_for(&WEBM_SAMPLE);
+			let header =
+				HeaderValue::try_from(format!("md5=AAAA, {}", sha.to_str().unwrap())).unwrap();
+			assert!(parse_sha256(&header).unwrap().is_some())
+		}
+
+		#[test]
+		fn parse_unsupported_algorithm_none() {
+			let header = HeaderValue::from_static("md5=HUXZLQLMuI/KZ5KDcJPcOA==");
+			assert_eq!(parse_sha256(&header), Ok(None))
+		}
+
+		#[test]
+		fn parse_bad_base64_err() {
+			let header = HeaderValue::from_static("sha-256=not base64!");
+			assert!(parse_sha256(&header).is_err())
+		}
+
+		#[test]
+		fn parse_wrong_length_err() {
+			let header = HeaderValue::from_static("sha-256=AAAA");
+			assert!(parse_sha256(&header).is_err())
+		}
+
+		#[test]
+		fn wants_sha256_among_algorithms_with_quality() {
+			let header = HeaderValue::from_static("md5;q=0.3, SHA-256;q=1");
+			assert!(wants_sha256(&header))
+		}
+
+		#[test]
+		fn wants_other_algorithm_false() {
+			let header = HeaderValue::from_static("md5, sha-512");
+			assert!(!wants_sha256(&header))
+		}
+
+		#[tokio::test]
+		async fn sha256_of_reader_matches_content() {
+			let digest = sha256_of(WEBM_SAMPLE.as_slice()).await.unwrap();
+			assert_eq!(digest, <[u8; 32]>::from(Sha256::digest(WEBM_SAMPLE)))
+		}
+	}
+}
+
+pub(crate) mod error {
+	use axum::http::StatusCode;
+	use task::TaskError;
+
+	///Response for the errors returned by the manager
+	pub(crate) trait ErrorStatus {
+		fn status(&self) -> StatusCode {
+			self.response().0
+		}
+		///Status with a short reason, for the handlers that reply with it
+		fn response(&self) -> (StatusCode, &'static str);
+	}
+
+	impl ErrorStatus for TaskError {
+		fn response(&self) -> (StatusCode, &'static str) {
+			match self {
+				TaskError::JobNotFound => (StatusCode::NOT_FOUND, "Job not found"),
+				TaskError::TaskNotFound => (StatusCode::NOT_FOUND, "Task not found"),
+				TaskError::RenditionNotFound => (StatusCode::NOT_FOUND, "Rendition not found"),
+				TaskError::InputNotFound => (StatusCode::NOT_FOUND, "Input not found"),
+				TaskError::DependencyCycle => (
+					StatusCode::BAD_REQUEST,
+					"Dependency must be a previous task",
+				),
+				TaskError::InvalidTask(_) => (StatusCode::BAD_REQUEST, "Invalid task"),
+				TaskError::UnknownPreset(_) => (StatusCode::BAD_REQUEST, "Unknown preset"),
+				TaskError::JobNotFinished => (StatusCode::CONFLICT, "Source job is not finished"),
+				TaskError::TaskFailed => (StatusCode::CONFLICT, "Task failed"),
+				TaskError::Unsupported => (StatusCode::NOT_IMPLEMENTED, "Not implemented"),
+				TaskError::StorageUnavailable(_) => {
+					(StatusCode::SERVICE_UNAVAILABLE, "Storage unavailable")
+				}
+			}
+		}
+	}
+
+	#[cfg(test)]
+	mod test {
+		use super::*;
+
+		#[test]
+		fn not_found_errors_are_not_found() {
+			for err in [TaskError::JobNotFound, TaskError::TaskNotFound] {
+				assert_eq!(err.status(), StatusCode::NOT_FOUND)
+			}
+		}
+
+		#[test]
+		fn dependency_cycle_is_bad_request() {
+			assert_eq!(TaskError::DependencyCycle.status(), StatusCode::BAD_REQUEST)
+		}
+
+		#[test]
+		fn storage_error_is_unavailable() {
+			let err = TaskError::from(std::io::Error::other("disk full"));
+			assert_eq!(err.status(), StatusCode::SERVICE_UNAVAILABLE)
+		}
 	}
 }
@@ -2,28 +2,38 @@
 //!
 //! Define the routes used by the workers to execute tasks
 
-use std::io::ErrorKind;
+use std::convert::Infallible;
 use std::sync::Arc;
 
 use axum::body::Body;
-use axum::extract::{Path, State};
-use axum::http::StatusCode;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
 use axum::response::{IntoResponse, Response};
 use axum::Json;
 use axum_extra::headers::Range;
 use axum_extra::TypedHeader;
-use tokio::io::{AsyncRead, AsyncSeek};
+use futures::Stream;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt};
+use tracing::{instrument, warn};
 use uuid::Uuid;
 
 use task::manager::Manager;
-use task::TaskSource;
+use task::segment::{self, AnalysisArtifact};
+use task::{AnalysisOptions, Input, Instance, MediaProbe, Recipe, Resources, Status, TaskSource};
 
+use crate::api::utils::error::ErrorStatus;
 use crate::api::utils::ranged::from_reader;
-use crate::api::{AppState, AuthToken};
+use crate::api::{token_id, AppState, AuthToken};
 use crate::storage::Storage;
+use crate::webhook::{notify_job, JobEvent};
 
 trait WorkerApi {
-	async fn allocate_task(&self) -> Result<Json<api::models::Task>, impl IntoResponse>;
+	async fn allocate_task(
+		&self,
+		worker: &str,
+		capacity: &Resources,
+	) -> Result<Json<api::models::Task>, StatusCode>;
 	async fn get_task_input_file(
 		&self,
 		job_id: Uuid,
@@ -36,12 +46,25 @@ trait WorkerApi {
 		job_id: Uuid,
 		source: api::models::TaskRequest,
 	) -> Result<u32, StatusCode>;
+	///Add the transcodes of the segments listed by the analysis output
+	///
+	///Analyses without output come from workers that add the transcodes themselves. Fails with the
+	///reason when the output is not a valid [AnalysisArtifact]
+	async fn add_segments(
+		&self,
+		task: &Instance,
+		options: &AnalysisOptions,
+	) -> Result<Result<(), String>, StatusCode>;
 }
 
 impl<T: AppState> WorkerApi for T {
-	async fn allocate_task(&self) -> Result<Json<api::models::Task>, StatusCode> {
+	async fn allocate_task(
+		&self,
+		worker: &str,
+		capacity: &Resources,
+	) -> Result<Json<api::models::Task>, StatusCode> {
 		self.manager()
-			.allocate_task()
+			.allocate_task_within(worker, capacity)
 			.await
 			.map(|opt| opt.map(|val| Json(val.into())))
 			.or(Err(StatusCode::INTERNAL_SERVER_ERROR))?
@@ -58,7 +81,7 @@ impl<T: AppState> WorkerApi for T {
 			.manager()
 			.get_allocated_task_input(&job_id, &task_id, input_idx)
 			.await
-			.or(Err(StatusCode::INTERNAL_SERVER_ERROR))?
+			.map_err(|err| err.status())?
 			.ok_or(StatusCode::NOT_FOUND)?;
 		self.storage()
 			.read_file(file)
@@ -71,46 +94,136 @@ impl<T: AppState> WorkerApi for T {
 		job_id: Uuid,
 		source: api::models::TaskRequest,
 	) -> Result<u32, StatusCode> {
-		let task: TaskSource = source.try_into().or(Err(StatusCode::BAD_REQUEST))?;
+		let task: TaskSource = source
+			.try_into()
+			.or(Err(StatusCode::UNPROCESSABLE_ENTITY))?;
 		self.manager()
 			.add_task_to_job(&job_id, task)
 			.await
-			.map(Some)
-			.or_else(|err| match err.kind() {
-				ErrorKind::NotFound => Ok(None),
-				ErrorKind::InvalidInput => Err(StatusCode::BAD_REQUEST),
-				_ => Err(StatusCode::INTERNAL_SERVER_ERROR),
-			})
-			.and_then(|v| v.ok_or(StatusCode::NOT_FOUND))
+			.map_err(|err| err.status())
+	}
+
+	async fn add_segments(
+		&self,
+		task: &Instance,
+		options: &AnalysisOptions,
+	) -> Result<Result<(), String>, StatusCode> {
+		let manager = self.manager();
+		let output = manager
+			.get_allocated_task_output(&task.job_id, &task.task_id)
+			.await
+			.map_err(|err| err.status())?;
+		let Some(output) = output else {
+			return Ok(Ok(()));
+		};
+		//The transcodes were added on a previous report of the analysis status
+		let mut idx = 0;
+		while let Some(source) = manager
+			.get_task_source(&task.job_id, idx)
+			.await
+			.map_err(|err| err.status())?
+		{
+			if matches!(source.recipe, Recipe::Transcode(_)) {
+				return Ok(Ok(()));
+			}
+			idx += 1;
+		}
+		let mut content = Vec::new();
+		let mut read = self
+			.storage()
+			.read_file(output)
+			.await
+			.or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+		read.read_to_end(&mut content)
+			.await
+			.or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+		let artifact: AnalysisArtifact = match serde_json::from_slice(&content) {
+			Ok(artifact) => artifact,
+			Err(err) => return Ok(Err(format!("Invalid analysis output: {err}"))),
+		};
+		if let Err(reason) = artifact.validate() {
+			return Ok(Err(format!("Invalid analysis output: {reason}")));
+		}
+		let clip = task.inputs.first().cloned().unwrap_or_else(Input::source);
+		for transcode in segment::transcodes(&artifact, &clip, options, &task.job_options) {
+			manager
+				.add_task_to_job(&task.job_id, transcode)
+				.await
+				.map_err(|err| err.status())?;
+		}
+		Ok(Ok(()))
 	}
 }
 
+#[instrument(skip_all, fields(worker = %token_id(&worker)))]
 pub(super) async fn allocate_task<S: AppState>(
 	State(state): State<Arc<S>>,
-	_auth: AuthToken,
+	AuthToken(worker): AuthToken,
+	Query(capacity): Query<Resources>,
 ) -> Result<Json<api::models::Task>, StatusCode> {
-	state.allocate_task().await
+	state.allocate_task(&token_id(&worker), &capacity).await
 }
 
-pub(super) async fn get_task_input<S: AppState>(
+///Server-sent events stream, with an `available` event each time a task may have become available
+#[instrument(skip_all)]
+pub(super) async fn allocate_task_events<S: AppState + 'static>(
 	State(state): State<Arc<S>>,
 	_auth: AuthToken,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+	let events = futures::stream::unfold(state, |state| async move {
+		state.manager().task_available().await;
+		let event = Event::default().event("available").data("");
+		Some((Ok(event), state))
+	});
+	Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+#[instrument(skip_all, fields(%job_id, %task_id, input_idx))]
+pub(super) async fn get_task_input<S: AppState>(
+	State(state): State<Arc<S>>,
+	auth: AuthToken,
 	range: Option<TypedHeader<Range>>,
-	Path((job_id, task_id)): Path<(Uuid, Uuid)>,
+	headers: HeaderMap,
+	Path((job_id, task_id, input_idx)): Path<(Uuid, Uuid, u32)>,
 ) -> Result<Response, StatusCode> {
-	let read = state.get_task_input_file(job_id, task_id, 0).await?;
+	use crate::api::utils::digest;
+	check_task_access(state.as_ref(), &auth, job_id, task_id).await?;
+	let mut read = state
+		.get_task_input_file(job_id, task_id, input_idx)
+		.await?;
+	let wants_digest = headers.get("want-digest").is_some_and(digest::wants_sha256);
+	let content_digest = match wants_digest {
+		true => {
+			let sha = digest::sha256_of(&mut read)
+				.await
+				.or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+			read.rewind()
+				.await
+				.or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+			Some(digest::to_header(&sha))
+		}
+		false => None,
+	};
 	let ranged = from_reader(read, range.map(|TypedHeader(r)| r))
 		.await
 		.or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
-	Ok(ranged.into_response())
+	let mut res = ranged.into_response();
+	if let Some(content_digest) = content_digest {
+		res.headers_mut().insert("digest", content_digest);
+	}
+	Ok(res)
 }
 
+#[instrument(skip_all, fields(%job_id, %task_id))]
 pub(super) async fn put_task_output<S: AppState>(
 	State(state): State<Arc<S>>,
-	_auth: AuthToken,
+	auth: AuthToken,
 	Path((job_id, task_id)): Path<(Uuid, Uuid)>,
+	headers: HeaderMap,
 	body: Body,
 ) -> Result<StatusCode, StatusCode> {
+	check_task_access(state.as_ref(), &auth, job_id, task_id).await?;
+	let expected_digest = expected_digest(&headers)?;
 	state
 		.manager()
 		.get_task(&job_id, &task_id)
@@ -122,23 +235,365 @@ pub(super) async fn put_task_output<S: AppState>(
 		.body_to_new_file(body)
 		.await
 		.or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+	check_digest(state.as_ref(), file, expected_digest).await?;
+	state
+		.manager()
+		.set_task_output(&job_id, &task_id, file)
+		.await
+		.and(Ok(StatusCode::ACCEPTED))
+		.or(Err(StatusCode::INTERNAL_SERVER_ERROR))
+}
+
+///Parse the optional Digest header
+fn expected_digest(headers: &HeaderMap) -> Result<Option<[u8; 32]>, StatusCode> {
+	use crate::api::utils::digest;
+	headers
+		.get("digest")
+		.map(digest::parse_sha256)
+		.transpose()
+		.or(Err(StatusCode::BAD_REQUEST))
+		.map(Option::flatten)
+}
+
+///Compare the stored file with the expected digest, if any
+async fn check_digest<S: AppState>(
+	state: &S,
+	file: Uuid,
+	expected: Option<[u8; 32]>,
+) -> Result<(), StatusCode> {
+	use crate::api::utils::digest;
+	let Some(expected) = expected else {
+		return Ok(());
+	};
+	let stored = state
+		.storage()
+		.read_file(file)
+		.await
+		.or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+	let received = digest::sha256_of(stored)
+		.await
+		.or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+	if received != expected {
+		//The mismatching content is not kept, the worker uploads it again
+		let _ = state.storage().delete_file(file).await;
+		return Err(StatusCode::UNPROCESSABLE_ENTITY);
+	}
+	Ok(())
+}
+
+pub(crate) const UPLOAD_OFFSET: &str = "upload-offset";
+
+///Tasks are managed by the worker holding their allocation, the owner of the job, or an admin
+///
+///Tasks without allocation are not found, and the tasks of other tokens are forbidden
+async fn check_task_access<S: AppState>(
+	state: &S,
+	auth: &AuthToken,
+	job_id: Uuid,
+	task_id: Uuid,
+) -> Result<(), StatusCode> {
+	if auth.is_admin(state).await {
+		return Ok(());
+	}
+	let worker = state
+		.manager()
+		.get_task_worker(&job_id, &task_id)
+		.await
+		.map_err(|err| err.status())?
+		.ok_or(StatusCode::NOT_FOUND)?;
+	if worker == token_id(&auth.0) {
+		return Ok(());
+	}
+	let job = state
+		.manager()
+		.get_job(&job_id)
+		.await
+		.map_err(|err| err.status())?;
+	match job {
+		Some(job) if auth.owns(state, &job).await => Ok(()),
+		_ => Err(StatusCode::FORBIDDEN),
+	}
+}
+
+///Tasks are added by the owner of the job, the workers holding one of its allocations, or an admin
+///
+///Jobs of other credentials are not found
+async fn check_job_access<S: AppState>(
+	state: &S,
+	auth: &AuthToken,
+	job_id: Uuid,
+) -> Result<(), StatusCode> {
+	if auth.is_admin(state).await {
+		return Ok(());
+	}
+	let job = state
+		.manager()
+		.get_job(&job_id)
+		.await
+		.map_err(|err| err.status())?
+		.ok_or(StatusCode::NOT_FOUND)?;
+	if auth.owns(state, &job).await {
+		return Ok(());
+	}
+	let worker = token_id(&auth.0);
+	let allocations = state
+		.manager()
+		.get_allocated_tasks()
+		.await
+		.map_err(|err| err.status())?;
+	match allocations
+		.iter()
+		.any(|allocation| allocation.job_id == job_id && allocation.worker == worker)
+	{
+		true => Ok(()),
+		false => Err(StatusCode::NOT_FOUND),
+	}
+}
+
+///Get the partial output and its size, fails if the task is not allocated
+async fn partial_output<S: AppState>(
+	state: &S,
+	job_id: Uuid,
+	task_id: Uuid,
+) -> Result<(Option<Uuid>, u64), StatusCode> {
+	state
+		.manager()
+		.get_task(&job_id, &task_id)
+		.await
+		.or(Err(StatusCode::INTERNAL_SERVER_ERROR))?
+		.ok_or(StatusCode::NOT_FOUND)?;
+	let partial = state
+		.manager()
+		.get_task_partial_output(&job_id, &task_id)
+		.await
+		.or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+	let size = match partial {
+		Some(file) => state
+			.storage()
+			.file_size(file)
+			.await
+			.or(Err(StatusCode::INTERNAL_SERVER_ERROR))?,
+		None => 0,
+	};
+	Ok((partial, size))
+}
+
+///Bytes of the output already received by the resumable upload
+#[instrument(skip_all, fields(%job_id, %task_id))]
+pub(super) async fn output_upload_head<S: AppState>(
+	State(state): State<Arc<S>>,
+	auth: AuthToken,
+	Path((job_id, task_id)): Path<(Uuid, Uuid)>,
+) -> Result<impl IntoResponse, StatusCode> {
+	check_task_access(state.as_ref(), &auth, job_id, task_id).await?;
+	let (_, size) = partial_output(state.as_ref(), job_id, task_id).await?;
+	Ok([(UPLOAD_OFFSET, size.to_string())])
+}
+
+///Store an empty file as the partial output of the task
+async fn start_upload<S: AppState>(
+	state: &S,
+	job_id: Uuid,
+	task_id: Uuid,
+) -> Result<Uuid, StatusCode> {
+	let empty = state
+		.storage()
+		.create_file()
+		.await
+		.or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+	let file = state
+		.storage()
+		.store_file(empty)
+		.await
+		.or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+	let res = state
+		.manager()
+		.set_task_partial_output(&job_id, &task_id, Some(file))
+		.await;
+	if !matches!(res, Ok(Some(()))) {
+		let _ = state.storage().delete_file(file).await;
+	}
+	match res {
+		Ok(Some(())) => Ok(file),
+		Ok(None) => Err(StatusCode::NOT_FOUND),
+		Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+	}
+}
+
+///Append to the resumable upload, the content received is kept even if the request fails
+#[instrument(skip_all, fields(%job_id, %task_id))]
+pub(super) async fn output_upload_patch<S: AppState>(
+	State(state): State<Arc<S>>,
+	auth: AuthToken,
+	Path((job_id, task_id)): Path<(Uuid, Uuid)>,
+	headers: HeaderMap,
+	body: Body,
+) -> Result<impl IntoResponse, StatusCode> {
+	check_task_access(state.as_ref(), &auth, job_id, task_id).await?;
+	let offset: u64 = headers
+		.get(UPLOAD_OFFSET)
+		.and_then(|val| val.to_str().ok())
+		.and_then(|val| val.parse().ok())
+		.ok_or(StatusCode::BAD_REQUEST)?;
+	let (partial, size) = partial_output(state.as_ref(), job_id, task_id).await?;
+	if offset != size {
+		return Err(StatusCode::CONFLICT);
+	}
+	let file = match partial {
+		Some(file) => file,
+		None => start_upload(state.as_ref(), job_id, task_id).await?,
+	};
+	let complete = state
+		.storage()
+		.append_body_to_file(file, body)
+		.await
+		.or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+	let size = state
+		.storage()
+		.file_size(file)
+		.await
+		.or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+	//The worker resumes from the returned offset
+	let status = match complete {
+		true => StatusCode::NO_CONTENT,
+		false => StatusCode::REQUEST_TIMEOUT,
+	};
+	Ok((status, [(UPLOAD_OFFSET, size.to_string())]))
+}
+
+///Use the resumable upload as the task output
+#[instrument(skip_all, fields(%job_id, %task_id))]
+pub(super) async fn output_upload_post<S: AppState>(
+	State(state): State<Arc<S>>,
+	auth: AuthToken,
+	Path((job_id, task_id)): Path<(Uuid, Uuid)>,
+	headers: HeaderMap,
+) -> Result<StatusCode, StatusCode> {
+	check_task_access(state.as_ref(), &auth, job_id, task_id).await?;
+	let expected_digest = expected_digest(&headers)?;
+	let (partial, _) = partial_output(state.as_ref(), job_id, task_id).await?;
+	let file = partial.ok_or(StatusCode::CONFLICT)?;
+	if let Err(status) = check_digest(state.as_ref(), file, expected_digest).await {
+		if status == StatusCode::UNPROCESSABLE_ENTITY {
+			//The deleted upload is restarted from the beginning
+			let _ = state
+				.manager()
+				.set_task_partial_output(&job_id, &task_id, None)
+				.await;
+		}
+		return Err(status);
+	}
 	state
 		.manager()
 		.set_task_output(&job_id, &task_id, file)
 		.await
+		.or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+	state
+		.manager()
+		.set_task_partial_output(&job_id, &task_id, None)
+		.await
 		.and(Ok(StatusCode::ACCEPTED))
 		.or(Err(StatusCode::INTERNAL_SERVER_ERROR))
 }
 
+#[instrument(skip_all, fields(%job_id, %task_id))]
 pub(super) async fn task_status_post<S: AppState>(
 	State(state): State<Arc<S>>,
-	_auth: AuthToken,
+	auth: AuthToken,
 	Path((job_id, task_id)): Path<(Uuid, Uuid)>,
 	Json(body): Json<api::models::TaskStatus>,
 ) -> StatusCode {
+	if let Err(status) = check_task_access(state.as_ref(), &auth, job_id, task_id).await {
+		return status;
+	}
+	let mut status: Status = body.into();
+	let task = state.manager().get_task(&job_id, &task_id).await;
+	//The segments are added before the analysis is finished, so the server can merge them
+	if let (Status::Finished, Ok(Some(task))) = (&status, &task) {
+		if let Recipe::Analysis(options) = &task.recipe {
+			match state.add_segments(task, options).await {
+				Ok(Ok(())) => {}
+				Ok(Err(reason)) => status = Status::Failed(Some(reason)),
+				Err(code) => return code,
+			}
+		}
+	}
+	if let Status::Failed(Some(reason)) = &status {
+		warn!(%reason, "Task failed");
+	}
+	let failed = matches!(status, Status::Failed(_));
+	//Thumbnails are not part of the job output, so they don't change the job state
+	let thumbnail = matches!(
+		task,
+		Ok(Some(task)) if matches!(task.recipe, Recipe::Thumbnail(_))
+	);
+	//Only the first failure of the job is notified
+	let job_failed = match failed {
+		true => state.manager().get_job_failure(&job_id).await,
+		false => Ok(None),
+	};
+	let res = state
+		.manager()
+		.update_task_status(&job_id, &task_id, status)
+		.await;
+	if let (Ok(Some(_)), false) = (&res, thumbnail) {
+		let event = match failed {
+			true => matches!(job_failed, Ok(None)).then_some(JobEvent::Failed),
+			false => matches!(
+				state.manager().get_job_output(&job_id, None).await,
+				Ok(Some(_))
+			)
+			.then_some(JobEvent::Completed),
+		};
+		if let Some(event) = event {
+			notify_job(state.as_ref(), job_id, event).await;
+		}
+	}
+	match res {
+		Ok(Some(_)) => StatusCode::NO_CONTENT,
+		Ok(None) => StatusCode::NOT_FOUND,
+		Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+	}
+}
+
+#[instrument(skip_all, fields(%job_id, %task_id))]
+pub(super) async fn task_progress_post<S: AppState>(
+	State(state): State<Arc<S>>,
+	auth: AuthToken,
+	Path((job_id, task_id)): Path<(Uuid, Uuid)>,
+	Json(body): Json<api::models::TaskProgress>,
+) -> StatusCode {
+	if let Err(status) = check_task_access(state.as_ref(), &auth, job_id, task_id).await {
+		return status;
+	}
+	let res = state
+		.manager()
+		.update_task_progress(&job_id, &task_id, body.out_time, body.speed)
+		.await;
+	match res {
+		Ok(Some(_)) => StatusCode::NO_CONTENT,
+		Ok(None) => StatusCode::NOT_FOUND,
+		Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+	}
+}
+
+///Store the metadata probed by the worker, of the task output or of the analysed source
+#[instrument(skip_all, fields(%job_id, %task_id))]
+pub(super) async fn task_probe_put<S: AppState>(
+	State(state): State<Arc<S>>,
+	auth: AuthToken,
+	Path((job_id, task_id)): Path<(Uuid, Uuid)>,
+	Json(body): Json<api::models::MediaProbe>,
+) -> StatusCode {
+	if let Err(status) = check_task_access(state.as_ref(), &auth, job_id, task_id).await {
+		return status;
+	}
+	let Ok(probe) = MediaProbe::try_from(body) else {
+		return StatusCode::BAD_REQUEST;
+	};
 	let res = state
 		.manager()
-		.update_task_status(&job_id, &task_id, body.into())
+		.set_task_probe(&job_id, &task_id, probe)
 		.await;
 	match res {
 		Ok(Some(_)) => StatusCode::NO_CONTENT,
@@ -147,27 +602,50 @@ pub(super) async fn task_status_post<S: AppState>(
 	}
 }
 
+///Cancel the task allocation, making it available to other workers
+///
+///Only the worker holding the allocation, the owner of the job, or an admin, may cancel it
+#[instrument(skip_all, fields(%job_id, %task_id))]
+pub(super) async fn task_delete<S: AppState>(
+	State(state): State<Arc<S>>,
+	auth: AuthToken,
+	Path((job_id, task_id)): Path<(Uuid, Uuid)>,
+) -> StatusCode {
+	if let Err(status) = check_task_access(state.as_ref(), &auth, job_id, task_id).await {
+		return status;
+	}
+	let partial = state
+		.manager()
+		.get_task_partial_output(&job_id, &task_id)
+		.await;
+	match state.manager().cancel_task(&job_id, &task_id).await {
+		Ok(Some(_)) => {
+			//The next allocation starts the upload again
+			if let Ok(Some(partial)) = partial {
+				let _ = state.storage().delete_file(partial).await;
+			}
+			StatusCode::NO_CONTENT
+		}
+		Ok(None) => StatusCode::NOT_FOUND,
+		Err(err) => err.status(),
+	}
+}
+
+#[instrument(skip_all, fields(%job_id))]
 pub(super) async fn task_post<S: AppState>(
 	State(state): State<Arc<S>>,
-	_auth: AuthToken,
+	auth: AuthToken,
 	Path(job_id): Path<Uuid>,
 	Json(request): Json<api::models::TaskRequest>,
 ) -> Result<(StatusCode, String), StatusCode> {
-	let task: TaskSource = request
-		.try_into()
-		.or(Err(StatusCode::UNPROCESSABLE_ENTITY))?;
-	let result = state.manager().add_task_to_job(&job_id, task).await;
-	let idx = result.map_err(|e| match e.kind() {
-		ErrorKind::NotFound => StatusCode::NOT_FOUND,
-		_ => StatusCode::INTERNAL_SERVER_ERROR,
-	})?;
+	check_job_access(state.as_ref(), &auth, job_id).await?;
+	let idx = state.append_task_to_job(job_id, request).await?;
 	Ok((StatusCode::CREATED, idx.to_string()))
 }
 
 #[cfg(test)]
 pub(crate) mod test_util {
 	use std::future::Future;
-	use std::io::Error;
 	use std::sync::Arc;
 
 	use axum::http::HeaderValue;
@@ -176,62 +654,91 @@ pub(crate) mod test_util {
 
 	use auth_module::AuthenticationHandler;
 	use task::manager::Manager;
-	use task::{Input, Instance, JobOptions, JobSource, Options, Recipe, Status, TaskSource};
+	use task::{
+		Allocation, Input, Instance, JobOptions, JobSource, MediaProbe, Options, Recipe, Status,
+		TaskError, TaskSource,
+	};
 
 	use crate::api::AppState;
 	use crate::storage::Storage;
+	use crate::webhook::Notifier;
 	use crate::{AppStateLocal, WEBM_SAMPLE};
 
 	pub(crate) use super::super::test::*;
+	pub(crate) use crate::webhook::test_util::RecordingNotifier;
 
 	mockall::mock! {
 	pub ThisManager{}
 	impl Manager for ThisManager{
-			fn create_job(&self, job: JobSource) -> impl Future<Output=Result<Uuid, Error>> + Send;
+			fn create_job(&self, job: JobSource) -> impl Future<Output=Result<Uuid, TaskError>> + Send;
 
-			fn get_job(&self, job_id: &Uuid) -> impl Future<Output=Result<Option<JobSource>, Error>> + Send;
+			fn get_job(&self, job_id: &Uuid) -> impl Future<Output=Result<Option<JobSource>, TaskError>> + Send;
 
-			fn get_job_list(&self) -> impl Future<Output=Result<Vec<Uuid>, Error>> + Send;
+			fn get_job_list(&self) -> impl Future<Output=Result<Vec<Uuid>, TaskError>> + Send;
 
-			fn allocate_task(&self) -> impl Future<Output=Result<Option<Instance>, Error>> + Send;
+			fn allocate_task(&self, worker: &str) -> impl Future<Output=Result<Option<Instance>, TaskError>> + Send;
 
-			fn add_task_to_job(&self, job_id: &Uuid, task: TaskSource) -> impl Future<Output=Result<u32, Error>> + Send;
+			fn get_allocated_tasks(&self) -> impl Future<Output=Result<Vec<Allocation>, TaskError>> + Send;
+			fn get_task_worker(&self, job_id: &Uuid, task_id: &Uuid) -> impl Future<Output=Result<Option<String>, TaskError>> + Send;
 
-			fn get_task_source(&self, job_id: &Uuid, task_idx: u32) -> impl Future<Output=Result<Option<TaskSource>, Error>> + Send;
+			fn add_task_to_job(&self, job_id: &Uuid, task: TaskSource) -> impl Future<Output=Result<u32, TaskError>> + Send;
 
-			fn get_task(&self, job_id: &Uuid, task_id: &Uuid) -> impl Future<Output=Result<Option<Instance>, Error>> + Send;
+			fn get_task_source(&self, job_id: &Uuid, task_idx: u32) -> impl Future<Output=Result<Option<TaskSource>, TaskError>> + Send;
 
-			fn update_task_status(&self, job_id: &Uuid, task_id: &Uuid, status: Status) -> impl Future<Output=Result<Option<()>, Error>> + Send;
+			fn get_task(&self, job_id: &Uuid, task_id: &Uuid) -> impl Future<Output=Result<Option<Instance>, TaskError>> + Send;
 
-			fn set_task_output(&self, job_id: &Uuid, task_id: &Uuid, output: Uuid) -> impl Future<Output=Result<Option<()>, Error>> + Send;
+			fn update_task_status(&self, job_id: &Uuid, task_id: &Uuid, status: Status) -> impl Future<Output=Result<Option<()>, TaskError>> + Send;
 
-			fn get_task_output(&self, job_id: &Uuid, task_idx: u32) -> impl Future<Output=Result<Option<Uuid>, Error>> + Send;
+			fn update_task_progress(&self, job_id: &Uuid, task_id: &Uuid, progress: f64, speed: Option<f64>) -> impl Future<Output=Result<Option<()>, TaskError>> + Send;
 
-			fn get_allocated_task_output(&self, job_id: &Uuid, task_id: &Uuid) -> impl Future<Output=Result<Option<Uuid>, Error>> + Send;
+			fn set_task_probe(&self, job_id: &Uuid, task_id: &Uuid, probe: MediaProbe) -> impl Future<Output=Result<Option<()>, TaskError>> + Send;
 
-			fn get_allocated_task_input(&self, job_id: &Uuid, task_id: &Uuid, input_idx: u32) -> impl Future<Output = Result<Option<Uuid>, Error>> + Send;
+			fn get_task_probe(&self, job_id: &Uuid, task_idx: u32) -> impl Future<Output=Result<Option<MediaProbe>, TaskError>> + Send;
 
-			fn get_job_output(&self, job_id: &Uuid) -> impl Future<Output=Result<Option<Uuid>, Error>> + Send;
+			fn set_task_output(&self, job_id: &Uuid, task_id: &Uuid, output: Uuid) -> impl Future<Output=Result<Option<()>, TaskError>> + Send;
 
-			fn cancel_task(&self, job_id: &Uuid, task_id: &Uuid) -> impl Future<Output=Result<Option<()>, Error>> + Send;
+			fn get_task_output(&self, job_id: &Uuid, task_idx: u32) -> impl Future<Output=Result<Option<Uuid>, TaskError>> + Send;
 
-			fn delete_job(&self, job_id: &Uuid) -> impl Future<Output=Result<Option<()>, Error>> + Send;
+			fn get_task_partial_output(&self, job_id: &Uuid, task_id: &Uuid) -> impl Future<Output=Result<Option<Uuid>, TaskError>> + Send;
+
+			fn set_task_partial_output(&self, job_id: &Uuid, task_id: &Uuid, partial: Option<Uuid>) -> impl Future<Output=Result<Option<()>, TaskError>> + Send;
+
+			fn get_allocated_task_output(&self, job_id: &Uuid, task_id: &Uuid) -> impl Future<Output=Result<Option<Uuid>, TaskError>> + Send;
+
+			fn get_allocated_task_input(&self, job_id: &Uuid, task_id: &Uuid, input_idx: u32) -> impl Future<Output = Result<Option<Uuid>, TaskError>> + Send;
+
+			fn get_job_output(&self, job_id: &Uuid, rendition: Option<String>) -> impl Future<Output=Result<Option<Uuid>, TaskError>> + Send;
+
+			fn get_task_failure(&self, job_id: &Uuid, task_idx: u32) -> impl Future<Output=Result<Option<String>, TaskError>> + Send;
+
+			fn get_unfinished_tasks(&self, job_id: &Uuid) -> impl Future<Output=Result<Option<u32>, TaskError>> + Send;
+
+			fn get_job_failure(&self, job_id: &Uuid) -> impl Future<Output=Result<Option<String>, TaskError>> + Send;
+
+			fn cancel_task(&self, job_id: &Uuid, task_id: &Uuid) -> impl Future<Output=Result<Option<()>, TaskError>> + Send;
+
+			fn task_available(&self) -> impl Future<Output=()> + Send;
+
+
+			fn delete_job(&self, job_id: &Uuid) -> impl Future<Output=Result<Option<()>, TaskError>> + Send;
 
 		}
 	}
 
-	pub struct GenericApp<A: AuthenticationHandler, B: Manager, C: Storage> {
+	pub struct GenericApp<A: AuthenticationHandler, B: Manager, C: Storage, D: Notifier> {
 		pub credential: String,
 		pub _auth_handler: A,
 		pub _manager: B,
 		pub _storage: C,
+		pub _notifier: D,
 	}
 
 	impl<
 			A: AuthenticationHandler + Sync + Send,
 			B: Manager + Sync + Send,
 			C: Storage + Sync + Send,
-		> AppState for GenericApp<A, B, C>
+			D: Notifier + Sync + Send,
+		> AppState for GenericApp<A, B, C, D>
 	{
 		fn manager(&self) -> &impl Manager {
 			&self._manager
@@ -245,6 +752,10 @@ pub(crate) mod test_util {
 			&self._storage
 		}
 
+		fn notifier(&self) -> &impl Notifier {
+			&self._notifier
+		}
+
 		fn check_credential(&self, cred: &str) -> bool {
 			self.credential == cred
 		}
@@ -252,7 +763,13 @@ pub(crate) mod test_util {
 
 	pub(crate) async fn app_with_job_and_analyse_task(
 	) -> (TestServer, Arc<AppStateLocal>, HeaderValue) {
-		let app = AppStateLocal::default();
+		job_and_analyse_task_on(AppStateLocal::default()).await
+	}
+
+	///Server of the app, with a job and its analysis task
+	pub(crate) async fn job_and_analyse_task_on(
+		app: AppStateLocal,
+	) -> (TestServer, Arc<AppStateLocal>, HeaderValue) {
 		let data = axum::body::Body::from(WEBM_SAMPLE.as_slice());
 		let input = app._storage.body_to_new_file(data).await.unwrap();
 		let job = create_job_source(input);
@@ -262,7 +779,8 @@ pub(crate) mod test_util {
 				&job_id,
 				TaskSource {
 					inputs: vec![Input::source()],
-					recipe: Recipe::Analysis(None),
+					recipe: Recipe::Analysis(Default::default()),
+					..Default::default()
 				},
 			)
 			.await
@@ -277,14 +795,15 @@ pub(crate) mod test_util {
 				params: vec![],
 			},
 			audio: None,
+			two_pass: false,
+			container: Default::default(),
+			renditions: vec![],
+			..Default::default()
 		}
 	}
 
 	pub(crate) fn create_job_source(input_id: Uuid) -> JobSource {
-		JobSource {
-			input_id,
-			options: create_job_options(),
-		}
+		JobSource::new(input_id, create_job_options())
 	}
 
 	pub struct MergeRecipe(pub Vec<i32>);
@@ -308,8 +827,10 @@ mod test_allocate_task {
 	use uuid::Uuid;
 
 	use auth_module::LocalAuthenticator;
-	use task::{Input, Instance, Recipe};
+	use task::manager::Manager;
+	use task::{Input, Instance, JobSource, Recipe, Resources, TaskSource};
 
+	use crate::api::{token_id, AppState};
 	use crate::storage::MemStorage;
 
 	use super::test_util::*;
@@ -321,18 +842,26 @@ mod test_allocate_task {
 		assert_eq!(res, StatusCode::FORBIDDEN)
 	}
 
+	#[tokio::test]
+	async fn events_requires_auth() {
+		let (server, _, _) = test_server_state_auth().await;
+		let res = server.get("/allocate_task/events").await.status_code();
+		assert_eq!(res, StatusCode::FORBIDDEN)
+	}
+
 	#[tokio::test]
 	async fn with_auth_will_probe_manager() {
 		let mut mock_manager = MockThisManager::new();
 		mock_manager
 			.expect_allocate_task()
 			.times(1)
-			.returning(|| Box::pin(async { Ok(None) }));
+			.returning(|_| Box::pin(async { Ok(None) }));
 		let state = GenericApp {
 			credential: "".to_string(),
 			_auth_handler: LocalAuthenticator::default(),
 			_manager: mock_manager,
 			_storage: MemStorage::default(),
+			_notifier: RecordingNotifier::default(),
 		};
 		let (server, _, auth) = test_server_state_auth_generic(Arc::new(state)).await;
 		server
@@ -351,6 +880,7 @@ mod test_allocate_task {
 			_auth_handler: LocalAuthenticator::default(),
 			_manager: mock_manager,
 			_storage: MemStorage::default(),
+			_notifier: RecordingNotifier::default(),
 		};
 		let (server, _, _) = test_server_state_auth_generic(Arc::new(state)).await;
 		let code = server.get("/allocate_task").await.status_code();
@@ -364,14 +894,16 @@ mod test_allocate_task {
 			job_id: Uuid::from_u64_pair(1, 2),
 			task_id: Uuid::from_u64_pair(1, 3),
 			inputs: vec![Input::source()],
-			recipe: Recipe::Analysis(None),
+			recipe: Recipe::Analysis(Default::default()),
+			output: Default::default(),
+			resources: Default::default(),
 			job_options: create_job_options(),
 		};
 		let _result = instance.clone();
 		mock_manager
 			.expect_allocate_task()
 			.times(1)
-			.returning(move || {
+			.returning(move |_| {
 				let _result = _result.clone();
 				Box::pin(async { Ok(Some(_result)) })
 			});
@@ -380,6 +912,7 @@ mod test_allocate_task {
 			_auth_handler: LocalAuthenticator::default(),
 			_manager: mock_manager,
 			_storage: MemStorage::default(),
+			_notifier: RecordingNotifier::default(),
 		};
 		let (server, _, auth) = test_server_state_auth_generic(Arc::new(state)).await;
 		let res = server
@@ -391,6 +924,29 @@ mod test_allocate_task {
 		assert_eq!(got, instance);
 	}
 
+	#[tokio::test]
+	async fn allocates_to_the_authenticated_worker() {
+		let mut mock_manager = MockThisManager::new();
+		mock_manager
+			.expect_allocate_task()
+			.withf(|worker| worker == token_id("0"))
+			.times(1)
+			.returning(|_| Box::pin(async { Ok(None) }));
+		let state = GenericApp {
+			credential: "".to_string(),
+			_auth_handler: LocalAuthenticator::default(),
+			_manager: mock_manager,
+			_storage: MemStorage::default(),
+			_notifier: RecordingNotifier::default(),
+		};
+		let (server, _, auth) = test_server_state_auth_generic(Arc::new(state)).await;
+		assert_eq!(auth, "0");
+		server
+			.get("/allocate_task")
+			.add_header(AUTHORIZATION, auth)
+			.await;
+	}
+
 	#[tokio::test]
 	async fn without_task_available_returns_unavailable() {
 		let (server, _, auth) = test_server_state_auth().await;
@@ -401,20 +957,61 @@ mod test_allocate_task {
 			.status_code();
 		assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
 	}
+
+	#[tokio::test]
+	async fn allocates_tasks_fitting_the_worker_capacity() {
+		let (server, state, auth) = test_server_state_auth().await;
+		let job_id = state
+			.manager()
+			.create_job(JobSource::new(Default::default(), create_job_options()))
+			.await
+			.unwrap();
+		let task = TaskSource {
+			inputs: vec![Input::source()],
+			recipe: Recipe::Transcode(vec![]),
+			resources: Resources {
+				cpus: Some(8.0),
+				..Default::default()
+			},
+			..Default::default()
+		};
+		state
+			.manager()
+			.add_task_to_job(&job_id, task)
+			.await
+			.unwrap();
+		let small = server
+			.get("/allocate_task")
+			.add_query_param("cpus", 2)
+			.add_header(AUTHORIZATION, auth.clone())
+			.await
+			.status_code();
+		assert_eq!(small, StatusCode::SERVICE_UNAVAILABLE);
+		let large = server
+			.get("/allocate_task")
+			.add_query_param("cpus", 8)
+			.add_query_param("gpus", 0)
+			.add_header(AUTHORIZATION, auth)
+			.await
+			.status_code();
+		assert_eq!(large, StatusCode::OK);
+	}
 }
 
 #[cfg(test)]
 mod test_get_input {
 	use axum::http::header::{AUTHORIZATION, RANGE};
-	use axum::http::{HeaderValue, StatusCode};
+	use axum::http::{HeaderName, HeaderValue, StatusCode};
 	use tokio::io::AsyncReadExt;
 	use uuid::Uuid;
 
 	use task::manager::Manager;
+	use task::{Input, Recipe, Status, TaskSource};
 
 	use crate::api::test::{test_server, test_server_auth};
 	use crate::api::AppState;
 	use crate::storage::Storage;
+	use crate::WEBM_SAMPLE;
 
 	use super::test_util::*;
 
@@ -472,7 +1069,7 @@ mod test_get_input {
 		let (server, app, auth) = app_with_job_and_analyse_task().await;
 		let task = app
 			.manager()
-			.allocate_task()
+			.allocate_task("worker")
 			.await
 			.unwrap()
 			.expect("There should be a task");
@@ -491,7 +1088,7 @@ mod test_get_input {
 		let (server, app, auth) = app_with_job_and_analyse_task().await;
 		let task = app
 			.manager()
-			.allocate_task()
+			.allocate_task("worker")
 			.await
 			.unwrap()
 			.expect("There should be a task");
@@ -522,43 +1119,85 @@ mod test_get_input {
 	}
 
 	#[tokio::test]
-	async fn range_returns_partial_content() {
+	async fn want_digest_returns_content_digest() {
+		use crate::api::utils::digest;
 		let (server, app, auth) = app_with_job_and_analyse_task().await;
 		let task = app
 			.manager()
-			.allocate_task()
+			.allocate_task("worker")
 			.await
 			.unwrap()
 			.expect("There should be a task");
-		assert!(!task.inputs.is_empty(), "This task should have a input");
-		let input_id = app
-			.manager()
-			.get_job(&task.job_id)
-			.await
-			.unwrap()
-			.unwrap()
-			.input_id;
 		let path = format!("/job/{}/task/{}/input/0", task.job_id, task.task_id);
 		let response = server
 			.get(&path)
 			.add_header(AUTHORIZATION, auth)
-			.add_header(RANGE, HeaderValue::from_static("bytes=0-10"))
+			.add_header(
+				HeaderName::from_static("want-digest"),
+				HeaderValue::from_static("sha-256"),
+			)
 			.await;
-		let code = response.status_code();
-		assert_eq!(code, StatusCode::PARTIAL_CONTENT);
+		assert_eq!(
+			response.headers().get("digest"),
+			Some(&digest::header_for(&WEBM_SAMPLE))
+		);
+		assert_eq!(response.into_bytes().as_ref(), WEBM_SAMPLE.as_slice())
 	}
 
 	#[tokio::test]
-	async fn range_returns_partial_content_with_selected_range() {
+	async fn without_want_digest_no_digest() {
 		let (server, app, auth) = app_with_job_and_analyse_task().await;
 		let task = app
 			.manager()
-			.allocate_task()
+			.allocate_task("worker")
 			.await
 			.unwrap()
 			.expect("There should be a task");
-		assert!(!task.inputs.is_empty(), "This task should have a input");
-		let input_id = app
+		let path = format!("/job/{}/task/{}/input/0", task.job_id, task.task_id);
+		let response = server.get(&path).add_header(AUTHORIZATION, auth).await;
+		assert!(response.headers().get("digest").is_none())
+	}
+
+	#[tokio::test]
+	async fn range_returns_partial_content() {
+		let (server, app, auth) = app_with_job_and_analyse_task().await;
+		let task = app
+			.manager()
+			.allocate_task("worker")
+			.await
+			.unwrap()
+			.expect("There should be a task");
+		assert!(!task.inputs.is_empty(), "This task should have a input");
+		let input_id = app
+			.manager()
+			.get_job(&task.job_id)
+			.await
+			.unwrap()
+			.unwrap()
+			.input_id;
+		let size = app.storage().file_size(input_id).await.unwrap();
+		assert!(size > 10, "The input should cover the range");
+		let path = format!("/job/{}/task/{}/input/0", task.job_id, task.task_id);
+		let response = server
+			.get(&path)
+			.add_header(AUTHORIZATION, auth)
+			.add_header(RANGE, HeaderValue::from_static("bytes=0-10"))
+			.await;
+		let code = response.status_code();
+		assert_eq!(code, StatusCode::PARTIAL_CONTENT);
+	}
+
+	#[tokio::test]
+	async fn range_returns_partial_content_with_selected_range() {
+		let (server, app, auth) = app_with_job_and_analyse_task().await;
+		let task = app
+			.manager()
+			.allocate_task("worker")
+			.await
+			.unwrap()
+			.expect("There should be a task");
+		assert!(!task.inputs.is_empty(), "This task should have a input");
+		let input_id = app
 			.manager()
 			.get_job(&task.job_id)
 			.await
@@ -584,6 +1223,98 @@ mod test_get_input {
 			.unwrap();
 		assert_eq!(ret, &expected[range])
 	}
+
+	#[tokio::test]
+	async fn input_out_of_bounds_not_found() {
+		let (server, app, auth) = app_with_job_and_analyse_task().await;
+		let task = app
+			.manager()
+			.allocate_task("worker")
+			.await
+			.unwrap()
+			.expect("There should be a task");
+		let path = format!("/job/{}/task/{}/input/1", task.job_id, task.task_id);
+		let code = server
+			.get(&path)
+			.add_header(AUTHORIZATION, auth)
+			.await
+			.status_code();
+		assert_eq!(code, StatusCode::NOT_FOUND)
+	}
+
+	#[tokio::test]
+	async fn task_input_returns_the_referenced_task_output() {
+		let (server, app, auth) = app_with_job_and_analyse_task().await;
+		let analysis = app
+			.manager()
+			.allocate_task("worker")
+			.await
+			.unwrap()
+			.unwrap();
+		let transcode_idx = app
+			.manager()
+			.add_task_to_job(
+				&analysis.job_id,
+				TaskSource {
+					inputs: vec![Input::source()],
+					recipe: Recipe::Transcode(vec![]),
+					..Default::default()
+				},
+			)
+			.await
+			.unwrap();
+		app.manager()
+			.add_task_to_job(
+				&analysis.job_id,
+				TaskSource {
+					inputs: vec![Input {
+						index: transcode_idx,
+						start: None,
+						end: None,
+						..Default::default()
+					}],
+					recipe: Recipe::Merge(vec![0]),
+					..Default::default()
+				},
+			)
+			.await
+			.unwrap();
+		let transcode = app
+			.manager()
+			.allocate_task("worker")
+			.await
+			.unwrap()
+			.unwrap();
+		let output = {
+			let mut file = app.storage().create_file().await.unwrap();
+			tokio::io::AsyncWriteExt::write_all(&mut file, &WEBM_SAMPLE)
+				.await
+				.unwrap();
+			app.storage().store_file(file).await.unwrap()
+		};
+		app.manager()
+			.set_task_output(&transcode.job_id, &transcode.task_id, output)
+			.await
+			.unwrap();
+		app.manager()
+			.update_task_status(&transcode.job_id, &transcode.task_id, Status::Finished)
+			.await
+			.unwrap();
+		let merge = app
+			.manager()
+			.allocate_task("worker")
+			.await
+			.unwrap()
+			.expect("Merge should be available after the transcode finished");
+		let path = format!("/job/{}/task/{}/input/0", merge.job_id, merge.task_id);
+		let ret = server
+			.get(&path)
+			.add_header(AUTHORIZATION, auth)
+			.await
+			.into_bytes()
+			.to_vec();
+		assert_eq!(ret, WEBM_SAMPLE)
+	}
 }
 
 #[cfg(test)]
@@ -591,19 +1322,22 @@ mod test_post_input {
 	use std::sync::Arc;
 
 	use axum::http::header::AUTHORIZATION;
-	use axum::http::{HeaderValue, StatusCode};
+	use axum::http::{HeaderName, HeaderValue, StatusCode};
 	use axum_test::TestServer;
 	use tokio::io::AsyncReadExt;
 	use uuid::Uuid;
 
 	use auth_module::LocalAuthenticator;
-	use task::Status;
+	use task::manager::LocalJobManager;
+	use task::{Input, Instance, JobSource, Recipe, Status, TaskSource};
 
 	use crate::api::test::{test_server, test_server_auth, test_server_state_auth_generic};
-	use crate::api::worker::test_util::GenericApp;
 	use crate::api::worker::test_util::MockThisManager;
+	use crate::api::worker::test_util::RecordingNotifier;
+	use crate::api::worker::test_util::{create_job_source, GenericApp};
 	use crate::api::AppState;
 	use crate::storage::{MemStorage, Storage};
+	use crate::webhook::JobEvent;
 	use crate::WEBM_SAMPLE;
 
 	#[tokio::test]
@@ -632,7 +1366,7 @@ mod test_post_input {
 		let (server, app, auth) = super::test_util::app_with_job_and_analyse_task().await;
 		let instance = app
 			.manager()
-			.allocate_task()
+			.allocate_task("worker")
 			.await
 			.unwrap()
 			.expect("Should have task");
@@ -667,7 +1401,7 @@ mod test_post_input {
 		let (server, app, auth) = super::test_util::app_with_job_and_analyse_task().await;
 		let instance = app
 			.manager()
-			.allocate_task()
+			.allocate_task("worker")
 			.await
 			.unwrap()
 			.expect("Should have task");
@@ -688,7 +1422,7 @@ mod test_post_input {
 		let (server, app, auth) = super::test_util::app_with_job_and_analyse_task().await;
 		let instance = app
 			.manager()
-			.allocate_task()
+			.allocate_task("worker")
 			.await
 			.unwrap()
 			.expect("Should have task");
@@ -712,13 +1446,84 @@ mod test_post_input {
 		assert_eq!(content.as_slice(), SOURCE)
 	}
 
+	async fn put_task_output_with_digest(
+		server: &TestServer,
+		instance: &Instance,
+		auth: HeaderValue,
+		digest: HeaderValue,
+		content: &'static [u8],
+	) -> StatusCode {
+		let path = format!("/job/{}/task/{}/output", instance.job_id, instance.task_id);
+		server
+			.put(&path)
+			.add_header(AUTHORIZATION, auth)
+			.add_header(HeaderName::from_static("digest"), digest)
+			.bytes(content.into())
+			.await
+			.status_code()
+	}
+
+	#[tokio::test]
+	async fn put_with_matching_digest_accepted() {
+		use task::manager::Manager;
+		let (server, app, auth) = super::test_util::app_with_job_and_analyse_task().await;
+		let instance = app
+			.manager()
+			.allocate_task("worker")
+			.await
+			.unwrap()
+			.expect("Should have task");
+		let digest = crate::api::utils::digest::header_for(&WEBM_SAMPLE);
+		let put = put_task_output_with_digest(&server, &instance, auth, digest, &WEBM_SAMPLE).await;
+		assert_eq!(put, StatusCode::ACCEPTED);
+	}
+
+	#[tokio::test]
+	async fn put_with_mismatched_digest_unprocessable_and_no_output() {
+		use task::manager::Manager;
+		let (server, app, auth) = super::test_util::app_with_job_and_analyse_task().await;
+		let instance = app
+			.manager()
+			.allocate_task("worker")
+			.await
+			.unwrap()
+			.expect("Should have task");
+		let digest = crate::api::utils::digest::header_for(&WEBM_SAMPLE);
+		let truncated = &WEBM_SAMPLE[..100];
+		let used = app.storage().used_bytes().await.unwrap();
+		let put = put_task_output_with_digest(&server, &instance, auth, digest, truncated).await;
+		assert_eq!(put, StatusCode::UNPROCESSABLE_ENTITY);
+		let task_output = app
+			.manager()
+			.get_task_output(&instance.job_id, 0)
+			.await
+			.unwrap();
+		assert!(task_output.is_none());
+		assert_eq!(app.storage().used_bytes().await.unwrap(), used)
+	}
+
+	#[tokio::test]
+	async fn put_with_malformed_digest_bad_request() {
+		use task::manager::Manager;
+		let (server, app, auth) = super::test_util::app_with_job_and_analyse_task().await;
+		let instance = app
+			.manager()
+			.allocate_task("worker")
+			.await
+			.unwrap()
+			.expect("Should have task");
+		let digest = HeaderValue::from_static("sha-256=AAAA");
+		let put = put_task_output_with_digest(&server, &instance, auth, digest, &WEBM_SAMPLE).await;
+		assert_eq!(put, StatusCode::BAD_REQUEST);
+	}
+
 	#[tokio::test]
 	async fn status_post_returns_forbidden_without_auth() {
 		use task::manager::Manager;
 		let (server, app, _auth) = super::test_util::app_with_job_and_analyse_task().await;
 		let instance = app
 			.manager()
-			.allocate_task()
+			.allocate_task("worker")
 			.await
 			.unwrap()
 			.expect("Should have task");
@@ -733,7 +1538,7 @@ mod test_post_input {
 		let (server, app, auth) = super::test_util::app_with_job_and_analyse_task().await;
 		let instance = app
 			.manager()
-			.allocate_task()
+			.allocate_task("worker")
 			.await
 			.unwrap()
 			.expect("Should have task");
@@ -755,11 +1560,15 @@ mod test_post_input {
 			.withf(|_job, _task, status| matches!(status, Status::Finished))
 			.times(1)
 			.returning(|_job, _task, _status| Box::pin(async { Ok(None) }));
+		mock_manager
+			.expect_get_task()
+			.returning(|_job, _task| Box::pin(async { Ok(None) }));
 		let state = GenericApp {
 			credential: "".to_string(),
 			_auth_handler: LocalAuthenticator::default(),
 			_manager: mock_manager,
 			_storage: MemStorage::default(),
+			_notifier: RecordingNotifier::default(),
 		};
 		let (server, _, auth) = test_server_state_auth_generic(Arc::new(state)).await;
 		let path = format!("/job/{}/task/{}/status", Uuid::nil(), Uuid::nil());
@@ -784,6 +1593,282 @@ mod test_post_input {
 			.status_code();
 		assert_eq!(code, StatusCode::NOT_FOUND)
 	}
+
+	type WebhookApp =
+		GenericApp<LocalAuthenticator, LocalJobManager, MemStorage, RecordingNotifier>;
+
+	async fn app_with_callback_job(
+		callback: Option<&str>,
+	) -> (TestServer, Arc<WebhookApp>, HeaderValue, Instance) {
+		use task::manager::Manager;
+		let state = GenericApp {
+			credential: "".to_string(),
+			_auth_handler: LocalAuthenticator::default(),
+			_manager: LocalJobManager::default(),
+			_storage: MemStorage::default(),
+			_notifier: RecordingNotifier::default(),
+		};
+		let source = JobSource {
+			callback: callback.map(String::from),
+			..create_job_source(Uuid::nil())
+		};
+		let job_id = state._manager.create_job(source).await.unwrap();
+		state
+			._manager
+			.add_task_to_job(
+				&job_id,
+				TaskSource {
+					inputs: vec![Input::source()],
+					recipe: Recipe::Transcode(vec![]),
+					..Default::default()
+				},
+			)
+			.await
+			.unwrap();
+		let instance = state
+			._manager
+			.allocate_task("worker")
+			.await
+			.unwrap()
+			.unwrap();
+		let (server, state, auth) = test_server_state_auth_generic(Arc::new(state)).await;
+		(server, state, auth, instance)
+	}
+
+	async fn post_status(
+		server: &TestServer,
+		auth: HeaderValue,
+		instance: &Instance,
+		status: Status,
+	) {
+		let path = format!("/job/{}/task/{}/status", instance.job_id, instance.task_id);
+		server
+			.post(&path)
+			.add_header(AUTHORIZATION, auth)
+			.json(&Into::<api::models::TaskStatus>::into(status))
+			.await
+			.assert_status_success();
+	}
+
+	#[tokio::test]
+	async fn status_post_finishing_job_notifies_completed() {
+		use task::manager::Manager;
+		let callback = "http://example.com/hook";
+		let (server, app, auth, instance) = app_with_callback_job(Some(callback)).await;
+		app.manager()
+			.set_task_output(&instance.job_id, &instance.task_id, Uuid::new_v4())
+			.await
+			.unwrap();
+		post_status(&server, auth, &instance, Status::Finished).await;
+		let expected = (callback.to_string(), instance.job_id, JobEvent::Completed);
+		assert_eq!(app._notifier.events(), vec![expected])
+	}
+
+	#[tokio::test]
+	async fn status_post_failed_notifies_failed() {
+		let callback = "http://example.com/hook";
+		let (server, app, auth, instance) = app_with_callback_job(Some(callback)).await;
+		post_status(&server, auth, &instance, Status::Failed(None)).await;
+		let expected = (callback.to_string(), instance.job_id, JobEvent::Failed);
+		assert_eq!(app._notifier.events(), vec![expected])
+	}
+
+	#[tokio::test]
+	async fn status_post_failed_twice_notifies_once() {
+		let callback = "http://example.com/hook";
+		let (server, app, auth, instance) = app_with_callback_job(Some(callback)).await;
+		post_status(&server, auth.clone(), &instance, Status::Failed(None)).await;
+		let reason = Some("Retried".to_string());
+		post_status(&server, auth, &instance, Status::Failed(reason)).await;
+		let expected = (callback.to_string(), instance.job_id, JobEvent::Failed);
+		assert_eq!(app._notifier.events(), vec![expected])
+	}
+
+	#[tokio::test]
+	async fn status_post_failed_makes_job_output_unprocessable() {
+		let (server, _, auth, instance) = app_with_callback_job(None).await;
+		post_status(&server, auth.clone(), &instance, Status::Failed(None)).await;
+		let code = server
+			.get(&format!("/job/{}/output", instance.job_id))
+			.add_header(AUTHORIZATION, auth)
+			.await
+			.status_code();
+		assert_eq!(code, StatusCode::UNPROCESSABLE_ENTITY)
+	}
+
+	#[tokio::test]
+	async fn status_post_finished_without_job_output_does_not_notify() {
+		let (server, app, auth, instance) =
+			app_with_callback_job(Some("http://example.com/hook")).await;
+		post_status(&server, auth, &instance, Status::Finished).await;
+		assert!(app._notifier.events().is_empty())
+	}
+
+	#[tokio::test]
+	async fn status_post_without_callback_does_not_notify() {
+		let (server, app, auth, instance) = app_with_callback_job(None).await;
+		post_status(&server, auth, &instance, Status::Failed(None)).await;
+		assert!(app._notifier.events().is_empty())
+	}
+
+	#[tokio::test]
+	async fn progress_post_updates_allocation() {
+		use task::manager::Manager;
+		let (server, app, auth, instance) = app_with_callback_job(None).await;
+		let path = format!(
+			"/job/{}/task/{}/progress",
+			instance.job_id, instance.task_id
+		);
+		let code = server
+			.post(&path)
+			.add_header(AUTHORIZATION, auth)
+			.json(&api::models::TaskProgress {
+				out_time: 12.5,
+				speed: Some(2.0),
+			})
+			.await
+			.status_code();
+		assert_eq!(code, StatusCode::NO_CONTENT);
+		let allocation = app.manager().get_allocated_tasks().await.unwrap().remove(0);
+		assert_eq!(allocation.progress, Some(12.5));
+		assert_eq!(allocation.speed, Some(2.0));
+	}
+
+	#[tokio::test]
+	async fn task_delete_releases_allocation() {
+		use task::manager::Manager;
+		let (server, app, auth, instance) = app_with_callback_job(None).await;
+		let path = format!("/job/{}/task/{}", instance.job_id, instance.task_id);
+		let code = server
+			.delete(&path)
+			.add_header(AUTHORIZATION, auth)
+			.await
+			.status_code();
+		assert_eq!(code, StatusCode::NO_CONTENT);
+		let allocations = app.manager().get_allocated_tasks().await.unwrap();
+		assert!(allocations.is_empty());
+		let instance = app.manager().allocate_task("worker").await.unwrap();
+		assert!(instance.is_some())
+	}
+
+	#[tokio::test]
+	async fn task_delete_with_bad_task_not_found() {
+		let (server, _, auth) = super::test_util::app_with_job_and_analyse_task().await;
+		let path = format!("/job/{}/task/{}", Uuid::nil(), Uuid::nil());
+		let code = server
+			.delete(&path)
+			.add_header(AUTHORIZATION, auth)
+			.await
+			.status_code();
+		assert_eq!(code, StatusCode::NOT_FOUND)
+	}
+
+	#[tokio::test]
+	async fn task_delete_without_auth_forbidden() {
+		let (server, _, _) = super::test_util::app_with_job_and_analyse_task().await;
+		let path = format!("/job/{}/task/{}", Uuid::nil(), Uuid::nil());
+		let code = server.delete(&path).await.status_code();
+		assert_eq!(code, StatusCode::FORBIDDEN)
+	}
+
+	#[tokio::test]
+	async fn task_delete_only_by_worker_or_admin() {
+		use auth_module::AuthenticationHandler;
+		use task::manager::Manager;
+
+		use crate::api::{token_id, AppStateLocal, ADMIN_PERMISSION};
+		let state = AppStateLocal::with_cred("").with_admin_cred("admin");
+		let (server, app, worker) = test_server_state_auth_generic(Arc::new(state)).await;
+		let job_id = app
+			.manager()
+			.create_job(create_job_source(Uuid::nil()))
+			.await
+			.unwrap();
+		let task = TaskSource {
+			inputs: vec![Input::source()],
+			recipe: Recipe::Transcode(vec![]),
+			..Default::default()
+		};
+		app.manager().add_task_to_job(&job_id, task).await.unwrap();
+		let worker_id = token_id(worker.to_str().unwrap());
+		let (other, admin) = (
+			app.auth_handler().new_token().await,
+			app.auth_handler().new_token().await,
+		);
+		app.auth_handler()
+			.add(&admin, ADMIN_PERMISSION)
+			.await
+			.unwrap();
+		let delete = |token: HeaderValue, instance: &Instance| {
+			let path = format!("/job/{}/task/{}", instance.job_id, instance.task_id);
+			server.delete(&path).add_header(AUTHORIZATION, token)
+		};
+		let instance = app.manager().allocate_task(&worker_id).await.unwrap();
+		let instance = instance.unwrap();
+		let (other, admin) = (other.parse().unwrap(), admin.parse().unwrap());
+		let code = delete(other, &instance).await.status_code();
+		assert_eq!(code, StatusCode::FORBIDDEN);
+		let code = delete(admin, &instance).await.status_code();
+		assert_eq!(code, StatusCode::NO_CONTENT);
+		let instance = app.manager().allocate_task(&worker_id).await.unwrap();
+		let code = delete(worker, &instance.unwrap()).await.status_code();
+		assert_eq!(code, StatusCode::NO_CONTENT)
+	}
+
+	#[tokio::test]
+	async fn progress_post_with_bad_task_not_found() {
+		let (server, _, auth) = super::test_util::app_with_job_and_analyse_task().await;
+		let path = format!("/job/{}/task/{}/progress", Uuid::nil(), Uuid::nil());
+		let code = server
+			.post(&path)
+			.add_header(AUTHORIZATION, auth)
+			.json(&api::models::TaskProgress {
+				out_time: 1.0,
+				speed: None,
+			})
+			.await
+			.status_code();
+		assert_eq!(code, StatusCode::NOT_FOUND)
+	}
+
+	#[tokio::test]
+	async fn probe_put_stores_task_probe() {
+		use task::manager::Manager;
+		let (server, app, auth, instance) = app_with_callback_job(None).await;
+		let path = format!("/job/{}/task/{}/probe", instance.job_id, instance.task_id);
+		let code = server
+			.put(&path)
+			.add_header(AUTHORIZATION, auth)
+			.json(&api::models::MediaProbe {
+				duration: Some(4.0),
+				bit_rate: Some(64000),
+				format_name: None,
+				streams: vec![],
+			})
+			.await
+			.status_code();
+		assert_eq!(code, StatusCode::NO_CONTENT);
+		let probe = app.manager().get_task_probe(&instance.job_id, 0).await;
+		assert_eq!(probe.unwrap().unwrap().bit_rate, Some(64000));
+	}
+
+	#[tokio::test]
+	async fn probe_put_negative_bit_rate_bad_request() {
+		let (server, _, auth, instance) = app_with_callback_job(None).await;
+		let path = format!("/job/{}/task/{}/probe", instance.job_id, instance.task_id);
+		let code = server
+			.put(&path)
+			.add_header(AUTHORIZATION, auth)
+			.json(&api::models::MediaProbe {
+				duration: None,
+				bit_rate: Some(-1),
+				format_name: None,
+				streams: vec![],
+			})
+			.await
+			.status_code();
+		assert_eq!(code, StatusCode::BAD_REQUEST)
+	}
 }
 
 #[cfg(test)]
@@ -802,7 +1887,7 @@ mod test_task_post {
 		test_server, test_server_auth, test_server_state_auth, test_server_state_auth_generic,
 	};
 	use crate::api::worker::test_util::{
-		create_job_options, GenericApp, MergeRecipe, MockThisManager,
+		create_job_options, GenericApp, MergeRecipe, MockThisManager, RecordingNotifier,
 	};
 	use crate::api::worker::WorkerApi;
 	use crate::api::AppState;
@@ -815,6 +1900,8 @@ mod test_task_post {
 		let task = api::models::TaskRequest {
 			inputs: vec![Input::source().into()],
 			recipe: Box::new(MergeRecipe(vec![0]).into()),
+			output: None,
+			resources: None,
 		};
 		let err = app.append_task_to_job(Uuid::nil(), task).await;
 		assert_eq!(err.unwrap_err(), StatusCode::NOT_FOUND);
@@ -825,15 +1912,14 @@ mod test_task_post {
 		let app = AppStateLocal::default();
 		let job = app
 			.manager()
-			.create_job(JobSource {
-				input_id: Default::default(),
-				options: create_job_options(),
-			})
+			.create_job(JobSource::new(Default::default(), create_job_options()))
 			.await
 			.unwrap();
 		let task = api::models::TaskRequest {
 			inputs: vec![Input::source().into()],
 			recipe: Box::new(MergeRecipe(vec![0]).into()),
+			output: None,
+			resources: None,
 		};
 		let res = app.append_task_to_job(job, task).await;
 		assert!(res.is_ok());
@@ -844,15 +1930,14 @@ mod test_task_post {
 		let app = AppStateLocal::default();
 		let job = app
 			.manager()
-			.create_job(JobSource {
-				input_id: Default::default(),
-				options: create_job_options(),
-			})
+			.create_job(JobSource::new(Default::default(), create_job_options()))
 			.await
 			.unwrap();
 		let task = api::models::TaskRequest {
 			inputs: vec![Input::source().into()],
 			recipe: Box::new(MergeRecipe(vec![0]).into()),
+			output: None,
+			resources: None,
 		};
 		let id_1 = app.append_task_to_job(job, task.clone()).await.unwrap();
 		let id_2 = app.append_task_to_job(job, task).await.unwrap();
@@ -872,10 +1957,13 @@ mod test_task_post {
 			_auth_handler: LocalAuthenticator::default(),
 			_manager: mock_manager,
 			_storage: MemStorage::default(),
+			_notifier: RecordingNotifier::default(),
 		};
 		let task = api::models::TaskRequest {
 			inputs: vec![Input::source().into()],
 			recipe: Box::new(MergeRecipe(vec![0]).into()),
+			output: None,
+			resources: None,
 		};
 		let id = app.append_task_to_job(Uuid::nil(), task).await.unwrap();
 		assert_eq!(id, NUM);
@@ -887,6 +1975,8 @@ mod test_task_post {
 		let task = api::models::TaskRequest {
 			inputs: vec![Input::source().into()],
 			recipe: Box::new(MergeRecipe(vec![0]).into()),
+			output: None,
+			resources: None,
 		};
 		let parsed: TaskSource = task.clone().try_into().unwrap();
 		let mut mock_manager = MockThisManager::new();
@@ -900,6 +1990,7 @@ mod test_task_post {
 			_auth_handler: LocalAuthenticator::default(),
 			_manager: mock_manager,
 			_storage: MemStorage::default(),
+			_notifier: RecordingNotifier::default(),
 		};
 		let id = app.append_task_to_job(Uuid::nil(), task).await.unwrap();
 		assert_eq!(id, NUM);
@@ -944,7 +2035,9 @@ mod test_task_post {
 		let (server, auth) = test_server_auth().await;
 		let task = api::models::TaskRequest {
 			inputs: vec![Input::source().into()],
-			recipe: Box::new(MergeRecipe(vec![0, 1, 2]).into()),
+			recipe: Box::new(MergeRecipe(vec![0]).into()),
+			output: None,
+			resources: None,
 		};
 		let res = server
 			.post(&format!("/job/{}/task", Uuid::nil()))
@@ -955,12 +2048,41 @@ mod test_task_post {
 		assert_eq!(res, StatusCode::NOT_FOUND)
 	}
 
+	#[tokio::test]
+	async fn endpoint_with_unknown_output_container_unprocessable() {
+		let (server, app, auth) = test_server_state_auth().await;
+		let task = api::models::TaskRequest {
+			inputs: vec![Input::source().into()],
+			recipe: Box::new(MergeRecipe(vec![0]).into()),
+			output: Some(Box::new(api::models::TaskOutput {
+				container: Some("avi".to_string()),
+				suffix: None,
+				rendition: None,
+			})),
+			resources: None,
+		};
+		let job_id = app
+			.manager()
+			.create_job(JobSource::new(Default::default(), create_job_options()))
+			.await
+			.unwrap();
+		let res = server
+			.post(&format!("/job/{}/task", job_id))
+			.add_header(AUTHORIZATION, auth)
+			.json(&task)
+			.await
+			.status_code();
+		assert_eq!(res, StatusCode::UNPROCESSABLE_ENTITY)
+	}
+
 	#[tokio::test]
 	async fn endpoint_with_invalid_uuid_is_bad_request() {
 		let (server, auth) = test_server_auth().await;
 		let task = api::models::TaskRequest {
 			inputs: vec![Input::source().into()],
-			recipe: Box::new(MergeRecipe(vec![0, 1, 2]).into()),
+			recipe: Box::new(MergeRecipe(vec![0]).into()),
+			output: None,
+			resources: None,
 		};
 		let res = server
 			.post("/job/BAD_ID/task")
@@ -976,14 +2098,13 @@ mod test_task_post {
 		let (server, app, auth) = test_server_state_auth().await;
 		let task = api::models::TaskRequest {
 			inputs: vec![Input::source().into()],
-			recipe: Box::new(MergeRecipe(vec![0, 1, 2]).into()),
+			recipe: Box::new(MergeRecipe(vec![0]).into()),
+			output: None,
+			resources: None,
 		};
 		let job_id = app
 			.manager()
-			.create_job(JobSource {
-				input_id: Default::default(),
-				options: create_job_options(),
-			})
+			.create_job(JobSource::new(Default::default(), create_job_options()))
 			.await
 			.unwrap();
 		let res = server
@@ -1002,6 +2123,8 @@ mod test_task_post {
 		let task = api::models::TaskRequest {
 			inputs: vec![Input::source().into()],
 			recipe: Box::new(MergeRecipe(vec![0]).into()),
+			output: None,
+			resources: None,
 		};
 		let parsed: TaskSource = task.clone().try_into().unwrap();
 		let mut mock_manager = MockThisManager::new();
@@ -1018,6 +2141,7 @@ mod test_task_post {
 			_auth_handler: LocalAuthenticator::default(),
 			_manager: mock_manager,
 			_storage: MemStorage::default(),
+			_notifier: RecordingNotifier::default(),
 		};
 		let (server, _, auth) = test_server_state_auth_generic(Arc::new(app)).await;
 		let res = server
@@ -1036,11 +2160,14 @@ mod test_task_post {
 		let task = api::models::TaskRequest {
 			inputs: vec![Input::source().into()],
 			recipe: Box::new(MergeRecipe(vec![0]).into()),
+			output: None,
+			resources: None,
 		};
 		let parsed: TaskSource = task.clone().try_into().unwrap();
 		let mut mock_manager = MockThisManager::new();
 		mock_manager
 			.expect_add_task_to_job()
+			.withf(move |_, task_parsed| parsed == *task_parsed)
 			.times(1)
 			.returning(|_job, _task| Box::pin(async { Ok(NUM) }));
 		let app = GenericApp {
@@ -1048,6 +2175,7 @@ mod test_task_post {
 			_auth_handler: LocalAuthenticator::default(),
 			_manager: mock_manager,
 			_storage: MemStorage::default(),
+			_notifier: RecordingNotifier::default(),
 		};
 		let (server, _, auth) = test_server_state_auth_generic(Arc::new(app)).await;
 		let idx = server
@@ -1059,3 +2187,307 @@ mod test_task_post {
 		assert_eq!(idx, NUM)
 	}
 }
+
+#[cfg(test)]
+mod test_output_upload {
+	use std::sync::Arc;
+
+	use axum::http::header::AUTHORIZATION;
+	use axum::http::{HeaderName, HeaderValue, StatusCode};
+	use axum_test::{TestResponse, TestServer};
+	use tokio::io::AsyncReadExt;
+	use uuid::Uuid;
+
+	use task::manager::Manager;
+	use task::Instance;
+
+	use crate::api::utils::digest;
+	use crate::api::worker::test_util::app_with_job_and_analyse_task;
+	use crate::api::worker::UPLOAD_OFFSET;
+	use crate::api::AppState;
+	use crate::storage::Storage;
+	use crate::{AppStateLocal, WEBM_SAMPLE};
+
+	async fn allocated() -> (TestServer, Arc<AppStateLocal>, HeaderValue, Instance) {
+		let (server, app, auth) = app_with_job_and_analyse_task().await;
+		let instance = app
+			.manager()
+			.allocate_task("worker")
+			.await
+			.unwrap()
+			.expect("Should have task");
+		(server, app, auth, instance)
+	}
+
+	fn upload_path(instance: &Instance) -> String {
+		format!(
+			"/job/{}/task/{}/output/upload",
+			instance.job_id, instance.task_id
+		)
+	}
+
+	fn offset_of(res: &TestResponse) -> u64 {
+		res.headers()
+			.get(UPLOAD_OFFSET)
+			.unwrap()
+			.to_str()
+			.unwrap()
+			.parse()
+			.unwrap()
+	}
+
+	async fn patch(
+		server: &TestServer,
+		instance: &Instance,
+		auth: HeaderValue,
+		offset: u64,
+		content: &'static [u8],
+	) -> TestResponse {
+		server
+			.patch(&upload_path(instance))
+			.add_header(AUTHORIZATION, auth)
+			.add_header(
+				HeaderName::from_static(UPLOAD_OFFSET),
+				HeaderValue::from(offset),
+			)
+			.bytes(content.into())
+			.await
+	}
+
+	#[tokio::test]
+	async fn head_without_upload_offset_zero() {
+		let (server, _, auth, instance) = allocated().await;
+		let res = server
+			.method(axum::http::Method::HEAD, &upload_path(&instance))
+			.add_header(AUTHORIZATION, auth)
+			.await;
+		assert_eq!(res.status_code(), StatusCode::OK);
+		assert_eq!(offset_of(&res), 0)
+	}
+
+	#[tokio::test]
+	async fn head_with_bad_task_not_found() {
+		let (server, _, auth, _) = allocated().await;
+		let path = format!("/job/{id}/task/{id}/output/upload", id = Uuid::nil());
+		let res = server
+			.method(axum::http::Method::HEAD, &path)
+			.add_header(AUTHORIZATION, auth)
+			.await;
+		assert_eq!(res.status_code(), StatusCode::NOT_FOUND)
+	}
+
+	#[tokio::test]
+	async fn patch_returns_received_offset() {
+		let (server, _, auth, instance) = allocated().await;
+		let (first, _) = WEBM_SAMPLE.split_at(100);
+		let res = patch(&server, &instance, auth, 0, first).await;
+		assert_eq!(res.status_code(), StatusCode::NO_CONTENT);
+		assert_eq!(offset_of(&res), 100)
+	}
+
+	#[tokio::test]
+	async fn patch_with_wrong_offset_conflict() {
+		let (server, _, auth, instance) = allocated().await;
+		let res = patch(&server, &instance, auth, 10, &WEBM_SAMPLE).await;
+		assert_eq!(res.status_code(), StatusCode::CONFLICT)
+	}
+
+	#[tokio::test]
+	async fn patch_without_offset_bad_request() {
+		let (server, _, auth, instance) = allocated().await;
+		let code = server
+			.patch(&upload_path(&instance))
+			.add_header(AUTHORIZATION, auth)
+			.bytes(WEBM_SAMPLE.as_slice().into())
+			.await
+			.status_code();
+		assert_eq!(code, StatusCode::BAD_REQUEST)
+	}
+
+	#[tokio::test]
+	async fn resumed_upload_becomes_output_after_post() {
+		let (server, app, auth, instance) = allocated().await;
+		let (first, second) = WEBM_SAMPLE.split_at(100);
+		patch(&server, &instance, auth.clone(), 0, first).await;
+		patch(&server, &instance, auth.clone(), 100, second).await;
+		let code = server
+			.post(&upload_path(&instance))
+			.add_header(AUTHORIZATION, auth)
+			.await
+			.status_code();
+		assert_eq!(code, StatusCode::ACCEPTED);
+		let output = app
+			.manager()
+			.get_task_output(&instance.job_id, 0)
+			.await
+			.unwrap()
+			.expect("Should have output");
+		let mut content = Vec::new();
+		app.storage()
+			.read_file(output)
+			.await
+			.unwrap()
+			.read_to_end(&mut content)
+			.await
+			.unwrap();
+		assert_eq!(content, WEBM_SAMPLE)
+	}
+
+	#[tokio::test]
+	async fn resumed_upload_appends_to_the_same_file() {
+		let (server, app, auth, instance) = allocated().await;
+		let used = app.storage().used_bytes().await.unwrap();
+		let (first, second) = WEBM_SAMPLE.split_at(100);
+		patch(&server, &instance, auth.clone(), 0, first).await;
+		patch(&server, &instance, auth, 100, second).await;
+		let stored = app.storage().used_bytes().await.unwrap() - used;
+		assert_eq!(stored, WEBM_SAMPLE.len() as u64)
+	}
+
+	#[tokio::test]
+	async fn cancel_deletes_the_upload() {
+		let (server, app, auth, instance) = allocated().await;
+		let used = app.storage().used_bytes().await.unwrap();
+		patch(&server, &instance, auth.clone(), 0, &WEBM_SAMPLE).await;
+		let path = format!("/job/{}/task/{}", instance.job_id, instance.task_id);
+		let code = server
+			.delete(&path)
+			.add_header(AUTHORIZATION, auth)
+			.await
+			.status_code();
+		assert_eq!(code, StatusCode::NO_CONTENT);
+		assert_eq!(app.storage().used_bytes().await.unwrap(), used)
+	}
+
+	#[tokio::test]
+	async fn output_not_available_before_post() {
+		let (server, app, auth, instance) = allocated().await;
+		patch(&server, &instance, auth, 0, &WEBM_SAMPLE).await;
+		let output = app
+			.manager()
+			.get_task_output(&instance.job_id, 0)
+			.await
+			.unwrap();
+		assert!(output.is_none())
+	}
+
+	#[tokio::test]
+	async fn post_without_upload_conflict() {
+		let (server, _, auth, instance) = allocated().await;
+		let code = server
+			.post(&upload_path(&instance))
+			.add_header(AUTHORIZATION, auth)
+			.await
+			.status_code();
+		assert_eq!(code, StatusCode::CONFLICT)
+	}
+
+	#[tokio::test]
+	async fn post_with_wrong_digest_unprocessable() {
+		let (server, app, auth, instance) = allocated().await;
+		patch(&server, &instance, auth.clone(), 0, &WEBM_SAMPLE).await;
+		let code = server
+			.post(&upload_path(&instance))
+			.add_header(AUTHORIZATION, auth.clone())
+			.add_header(
+				HeaderName::from_static("digest"),
+				digest::header_for(b"other content"),
+			)
+			.await
+			.status_code();
+		assert_eq!(code, StatusCode::UNPROCESSABLE_ENTITY);
+		let output = app
+			.manager()
+			.get_task_output(&instance.job_id, 0)
+			.await
+			.unwrap();
+		assert!(output.is_none());
+		let res = server
+			.method(axum::http::Method::HEAD, &upload_path(&instance))
+			.add_header(AUTHORIZATION, auth)
+			.await;
+		assert_eq!(offset_of(&res), 0, "The upload should start over");
+		assert_eq!(
+			app.storage().used_bytes().await.unwrap(),
+			WEBM_SAMPLE.len() as u64,
+			"Only the job source should be stored"
+		)
+	}
+}
+
+#[cfg(test)]
+mod test_analysis_output {
+	use std::sync::Arc;
+
+	use axum::http::header::AUTHORIZATION;
+	use axum::http::StatusCode;
+
+	use task::manager::Manager;
+	use task::{Instance, Recipe, Status};
+
+	use crate::api::worker::test_util::app_with_job_and_analyse_task;
+	use crate::api::AppState;
+	use crate::storage::Storage;
+	use crate::AppStateLocal;
+
+	///Allocate the analysis, with the content as its output, and report it finished
+	async fn finish_analysis(content: &str) -> (Arc<AppStateLocal>, Instance, StatusCode) {
+		let (server, app, auth) = app_with_job_and_analyse_task().await;
+		let instance = app
+			.manager()
+			.allocate_task("worker")
+			.await
+			.unwrap()
+			.unwrap();
+		let body = axum::body::Body::from(content.to_string());
+		let output = app.storage().body_to_new_file(body).await.unwrap();
+		app.manager()
+			.set_task_output(&instance.job_id, &instance.task_id, output)
+			.await
+			.unwrap();
+		let path = format!("/job/{}/task/{}/status", instance.job_id, instance.task_id);
+		let code = server
+			.post(&path)
+			.add_header(AUTHORIZATION, auth)
+			.json(&Into::<api::models::TaskStatus>::into(Status::Finished))
+			.await
+			.status_code();
+		(app, instance, code)
+	}
+
+	#[tokio::test]
+	async fn finished_analysis_adds_transcodes_and_merge() {
+		let artifact = r#"{"duration":90.0,"cuts":[0.0,30.0,60.0]}"#;
+		let (app, instance, code) = finish_analysis(artifact).await;
+		assert_eq!(code, StatusCode::NO_CONTENT);
+		let mut recipes = Vec::new();
+		for idx in 1.. {
+			match app.manager().get_task_source(&instance.job_id, idx).await {
+				Ok(Some(task)) => recipes.push(task.recipe),
+				_ => break,
+			}
+		}
+		let transcode = Recipe::Transcode(vec![]);
+		let merge = Recipe::Merge(vec![0, 1, 2]);
+		assert_eq!(
+			recipes,
+			[transcode.clone(), transcode.clone(), transcode, merge]
+		);
+	}
+
+	#[tokio::test]
+	async fn invalid_analysis_output_fails_the_task() {
+		let (app, instance, code) = finish_analysis("not json").await;
+		assert_eq!(code, StatusCode::NO_CONTENT);
+		let allocation = app.manager().get_allocated_tasks().await.unwrap().remove(0);
+		assert!(allocation
+			.failure
+			.unwrap()
+			.starts_with("Invalid analysis output"));
+		let next = app.manager().get_task_source(&instance.job_id, 1).await;
+		assert!(matches!(next, Ok(None)));
+		let failure = app.manager().get_job_failure(&instance.job_id).await;
+		let failure = failure.unwrap().unwrap();
+		assert!(failure.starts_with("Invalid analysis output"))
+	}
+}
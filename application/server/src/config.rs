@@ -0,0 +1,459 @@
+//! Server configuration
+//!
+//! The configuration file uses toml, and every section is optional:
+//! ```toml
+//! [cors]
+//! origins = ["https://example.com", "https://*.example.com"]
+//! headers = ["x-custom-header"]
+//!
+//! [webhook]
+//! secret = "used to sign the job events"
+//! allowed_hosts = ["hooks.internal"]
+//!
+//! [storage]
+//! path = "files"
+//!
+//! [journal]
+//! path = "jobs.journal"
+//!
+//! [access_log]
+//! path = "access.log"
+//! format = "json"
+//! max_size = 10485760
+//! max_age = 86400
+//! keep = 5
+//!
+//! [quota]
+//! max_jobs = 4
+//! max_queued_tasks = 200
+//! max_bytes = 10737418240
+//!
+//! [probe]
+//! ffprobe = "/usr/bin/ffprobe"
+//! ffmpeg = "/usr/bin/ffmpeg"
+//! ```
+use std::io;
+use std::path::{Path, PathBuf};
+
+use axum::http::request::Parts;
+use axum::http::{HeaderName, HeaderValue};
+use serde::Deserialize;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+	pub cors: CorsConfig,
+	pub webhook: WebhookConfig,
+	pub storage: StorageConfig,
+	pub journal: JournalConfig,
+	pub access_log: AccessLogConfig,
+	pub quota: QuotaConfig,
+	pub probe: ProbeConfig,
+}
+
+impl Config {
+	pub fn from_file(path: &Path) -> io::Result<Self> {
+		let content = std::fs::read_to_string(path)?;
+		Self::parse(&content)
+	}
+
+	fn parse(content: &str) -> io::Result<Self> {
+		let config: Self = toml::from_str(content)
+			.map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+		if config.journal.path.is_some() && config.storage.path.is_none() {
+			let err = "The journal requires a storage path, or the restored jobs lose their files";
+			return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+		}
+		Ok(config)
+	}
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+	///Allowed origins, a leading `*.` on the host matches any subdomain
+	pub origins: Vec<String>,
+	///Extra headers allowed, in addition to the ones used by the api
+	pub headers: Vec<String>,
+	///Allow any origin and header, only intended for development
+	pub allow_all: bool,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookConfig {
+	///Key used to sign the job events, a random one is used if not set
+	pub secret: Option<String>,
+	///Callback hosts allowed even if they resolve to private, loopback or link-local addresses
+	pub allowed_hosts: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+	///Directory keeping a copy of the stored files, they are only kept in memory if not set
+	pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct JournalConfig {
+	///File recording the job changes, so the jobs survive a restart. Jobs are only kept in memory if not set
+	pub path: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogFormat {
+	///Common log format, as used by most http servers
+	#[default]
+	Common,
+	///One json object per line
+	Json,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccessLogConfig {
+	///File receiving the access log, disabled if not set
+	pub path: Option<PathBuf>,
+	pub format: AccessLogFormat,
+	///Rotate when the file would grow past this size, in bytes
+	pub max_size: Option<u64>,
+	///Rotate when the file is older than this, in seconds
+	pub max_age: Option<u64>,
+	///Rotated files kept
+	pub keep: u32,
+}
+
+impl Default for AccessLogConfig {
+	fn default() -> Self {
+		Self {
+			path: None,
+			format: Default::default(),
+			max_size: None,
+			max_age: None,
+			keep: 5,
+		}
+	}
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProbeConfig {
+	///ffprobe binary used to validate the job sources, they are not validated if not set
+	pub ffprobe: Option<PathBuf>,
+	///ffmpeg binary used to join the segments of the partial outputs, not available if not set
+	pub ffmpeg: Option<PathBuf>,
+}
+
+///Limits of each credential, shared by its tokens, unlimited if not set
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuotaConfig {
+	///Jobs without output
+	pub max_jobs: Option<u32>,
+	///Unfinished tasks of those jobs
+	pub max_queued_tasks: Option<u32>,
+	///Size of the uploaded inputs, in bytes
+	pub max_bytes: Option<u64>,
+}
+
+///Headers used by the api
+const API_HEADERS: [&str; 22] = [
+	"credentials",
+	"audio_codec",
+	"audio_param",
+	"authorization",
+	"callback_url",
+	"container",
+	"content-type",
+	"copy_subtitles",
+	"input_id",
+	"input_param",
+	"job_name",
+	"max_segments",
+	"preset",
+	"priority",
+	"rendition",
+	"scene_threshold",
+	"segment_duration",
+	"source_job",
+	"trim_end",
+	"trim_start",
+	"video_codec",
+	"video_param",
+];
+
+impl CorsConfig {
+	pub fn layer(&self) -> Result<CorsLayer, String> {
+		if self.allow_all {
+			return Ok(CorsLayer::very_permissive());
+		}
+		let headers = API_HEADERS
+			.iter()
+			.map(|header| header.to_string())
+			.chain(self.headers.iter().cloned())
+			.map(|header| {
+				HeaderName::try_from(header.as_str())
+					.map_err(|_| format!("Invalid cors header: {header}"))
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+		let origins = self
+			.origins
+			.iter()
+			.map(|origin| OriginPattern::parse(origin))
+			.collect::<Result<Vec<_>, _>>()?;
+		let allow_origin = AllowOrigin::predicate(move |origin: &HeaderValue, _: &Parts| {
+			origin
+				.to_str()
+				.is_ok_and(|origin| origins.iter().any(|pattern| pattern.matches(origin)))
+		});
+		Ok(CorsLayer::new()
+			.allow_origin(allow_origin)
+			.allow_headers(headers)
+			.allow_credentials(true))
+	}
+}
+
+#[derive(Debug, PartialEq)]
+enum OriginPattern {
+	Exact(String),
+	///Matches any subdomain, stores the scheme prefix and the domain suffix
+	Subdomain(String, String),
+}
+
+impl OriginPattern {
+	fn parse(origin: &str) -> Result<Self, String> {
+		HeaderValue::try_from(origin).map_err(|_| format!("Invalid cors origin: {origin}"))?;
+		let Some((scheme, host)) = origin.split_once("://") else {
+			return Err(format!("Invalid cors origin, missing scheme: {origin}"));
+		};
+		match host.strip_prefix("*.") {
+			Some(domain) if !domain.is_empty() && !domain.contains('*') => Ok(Self::Subdomain(
+				format!("{scheme}://"),
+				format!(".{domain}"),
+			)),
+			_ if host.contains('*') => Err(format!("Invalid cors origin wildcard: {origin}")),
+			_ => Ok(Self::Exact(origin.to_string())),
+		}
+	}
+
+	fn matches(&self, origin: &str) -> bool {
+		match self {
+			OriginPattern::Exact(exact) => exact == origin,
+			OriginPattern::Subdomain(scheme, domain) => origin
+				.strip_prefix(scheme.as_str())
+				.and_then(|host| host.strip_suffix(domain.as_str()))
+				.is_some_and(|sub| !sub.is_empty() && !sub.contains(['/', ':', '@'])),
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use axum::http::{header, Method, StatusCode};
+	use axum::routing::get;
+	use axum::Router;
+	use axum_test::TestServer;
+
+	use super::*;
+
+	fn cors_server(cors: &CorsConfig) -> TestServer {
+		let app = Router::new()
+			.route("/", get(|| async { "" }))
+			.layer(cors.layer().unwrap());
+		TestServer::new(app).unwrap()
+	}
+
+	async fn allowed_origin(server: &TestServer, origin: &'static str) -> Option<String> {
+		let res = server
+			.get("/")
+			.add_header(header::ORIGIN, HeaderValue::from_static(origin))
+			.await;
+		res.headers()
+			.get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+			.map(|value| value.to_str().unwrap().to_string())
+	}
+
+	#[test]
+	fn parse_empty_config_is_default() {
+		let config = Config::parse("").unwrap();
+		assert_eq!(config, Config::default())
+	}
+
+	#[test]
+	fn parse_cors_section() {
+		let config = Config::parse(
+			r#"
+			[cors]
+			origins = ["https://example.com"]
+			headers = ["x-extra"]
+			"#,
+		)
+		.unwrap();
+		assert_eq!(config.cors.origins, vec!["https://example.com"]);
+		assert_eq!(config.cors.headers, vec!["x-extra"]);
+		assert!(!config.cors.allow_all);
+	}
+
+	#[test]
+	fn parse_webhook_secret() {
+		let config = Config::parse("[webhook]\nsecret = \"key\"").unwrap();
+		assert_eq!(config.webhook.secret.as_deref(), Some("key"))
+	}
+
+	#[test]
+	fn parse_webhook_allowed_hosts() {
+		let config = Config::parse("[webhook]\nallowed_hosts = [\"hooks.internal\"]").unwrap();
+		assert_eq!(config.webhook.allowed_hosts, ["hooks.internal"])
+	}
+
+	#[test]
+	fn parse_journal_path() {
+		let content = "[storage]\npath = \"files\"\n[journal]\npath = \"jobs.journal\"";
+		let config = Config::parse(content).unwrap();
+		assert_eq!(config.journal.path, Some(PathBuf::from("jobs.journal")));
+		assert_eq!(config.storage.path, Some(PathBuf::from("files")))
+	}
+
+	#[test]
+	fn parse_journal_without_storage_invalid() {
+		let res = Config::parse("[journal]\npath = \"jobs.journal\"");
+		assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidData)
+	}
+
+	#[test]
+	fn parse_access_log_section() {
+		let config =
+			Config::parse("[access_log]\npath = \"access.log\"\nformat = \"json\"").unwrap();
+		assert_eq!(config.access_log.path, Some(PathBuf::from("access.log")));
+		assert_eq!(config.access_log.format, AccessLogFormat::Json);
+		assert_eq!(config.access_log.keep, 5);
+	}
+
+	#[test]
+	fn parse_quota_section() {
+		let config = Config::parse("[quota]\nmax_jobs = 2\nmax_bytes = 1024").unwrap();
+		let expected = QuotaConfig {
+			max_jobs: Some(2),
+			max_queued_tasks: None,
+			max_bytes: Some(1024),
+		};
+		assert_eq!(config.quota, expected)
+	}
+
+	#[test]
+	fn parse_probe_section() {
+		let config = Config::parse("[probe]\nffprobe = \"ffprobe\"").unwrap();
+		assert_eq!(config.probe.ffprobe, Some(PathBuf::from("ffprobe")))
+	}
+
+	#[test]
+	fn parse_unknown_field_invalid_data() {
+		let err = Config::parse("[cors]\norigin = []").unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::InvalidData)
+	}
+
+	#[test]
+	fn origin_without_scheme_is_invalid() {
+		assert!(OriginPattern::parse("example.com").is_err())
+	}
+
+	#[test]
+	fn origin_with_wildcard_outside_subdomain_is_invalid() {
+		assert!(OriginPattern::parse("https://example.*.com").is_err());
+		assert!(OriginPattern::parse("https://*.").is_err());
+	}
+
+	#[test]
+	fn subdomain_pattern_matches_only_subdomains() {
+		let pattern = OriginPattern::parse("https://*.example.com").unwrap();
+		assert!(pattern.matches("https://app.example.com"));
+		assert!(pattern.matches("https://a.b.example.com"));
+		assert!(!pattern.matches("https://example.com"));
+		assert!(!pattern.matches("http://app.example.com"));
+		assert!(!pattern.matches("https://app.example.com.evil.org"));
+		assert!(!pattern.matches("https://evil.org/.example.com"));
+	}
+
+	#[test]
+	fn invalid_header_fails_layer() {
+		let cors = CorsConfig {
+			headers: vec!["bad header".to_string()],
+			..Default::default()
+		};
+		assert!(cors.layer().is_err())
+	}
+
+	#[tokio::test]
+	async fn exact_origin_allowed() {
+		let server = cors_server(&CorsConfig {
+			origins: vec!["https://example.com".to_string()],
+			..Default::default()
+		});
+		let allowed = allowed_origin(&server, "https://example.com").await;
+		assert_eq!(allowed.as_deref(), Some("https://example.com"))
+	}
+
+	#[tokio::test]
+	async fn unlisted_origin_not_allowed() {
+		let server = cors_server(&CorsConfig {
+			origins: vec!["https://example.com".to_string()],
+			..Default::default()
+		});
+		let allowed = allowed_origin(&server, "https://other.com").await;
+		assert_eq!(allowed, None)
+	}
+
+	#[tokio::test]
+	async fn subdomain_origin_allowed() {
+		let server = cors_server(&CorsConfig {
+			origins: vec!["https://*.example.com".to_string()],
+			..Default::default()
+		});
+		let allowed = allowed_origin(&server, "https://app.example.com").await;
+		assert_eq!(allowed.as_deref(), Some("https://app.example.com"))
+	}
+
+	#[tokio::test]
+	async fn allow_all_mirrors_any_origin() {
+		let server = cors_server(&CorsConfig {
+			allow_all: true,
+			..Default::default()
+		});
+		let allowed = allowed_origin(&server, "http://localhost:5173").await;
+		assert_eq!(allowed.as_deref(), Some("http://localhost:5173"))
+	}
+
+	#[tokio::test]
+	async fn preflight_allows_configured_header() {
+		let server = cors_server(&CorsConfig {
+			origins: vec!["https://example.com".to_string()],
+			headers: vec!["x-extra".to_string()],
+			..Default::default()
+		});
+		let res = server
+			.method(Method::OPTIONS, "/")
+			.add_header(
+				header::ORIGIN,
+				HeaderValue::from_static("https://example.com"),
+			)
+			.add_header(
+				header::ACCESS_CONTROL_REQUEST_METHOD,
+				HeaderValue::from_static("GET"),
+			)
+			.await;
+		assert_eq!(res.status_code(), StatusCode::OK);
+		let headers = res
+			.headers()
+			.get(header::ACCESS_CONTROL_ALLOW_HEADERS)
+			.unwrap()
+			.to_str()
+			.unwrap()
+			.to_string();
+		assert!(headers.contains("x-extra"));
+		assert!(headers.contains("authorization"));
+	}
+}
@@ -1,4 +1,15 @@
+pub use access_log::AccessLog;
 pub use api::{make_router, AppStateLocal};
+pub use config::Config;
+pub use logging::LogFilter;
+
+mod access_log;
+mod config;
+mod logging;
+mod probe;
+mod quota;
+mod remux;
+mod webhook;
 
 mod storage;
 
@@ -0,0 +1,38 @@
+//! Log filtering that can be changed while the server runs
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+
+///Handle to replace the filter of the installed subscriber
+pub struct LogFilter {
+	handle: reload::Handle<EnvFilter, Registry>,
+}
+
+impl LogFilter {
+	///Install the global subscriber, filtered by `RUST_LOG`, or `info` when it is not set
+	pub fn init() -> Self {
+		let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into());
+		let (layer, log_filter) = Self::new(filter);
+		tracing_subscriber::registry()
+			.with(layer)
+			.with(fmt::layer())
+			.init();
+		log_filter
+	}
+
+	///Reloadable filter layer, the handle stops working when the layer is dropped
+	pub(crate) fn new(filter: EnvFilter) -> (reload::Layer<EnvFilter, Registry>, Self) {
+		let (layer, handle) = reload::Layer::new(filter);
+		(layer, Self { handle })
+	}
+
+	///Replace the filter, with the same syntax as `RUST_LOG`
+	pub fn set(&self, directives: &str) -> Result<(), String> {
+		let filter = EnvFilter::try_new(directives).map_err(|err| err.to_string())?;
+		self.handle.reload(filter).map_err(|err| err.to_string())
+	}
+
+	pub fn current(&self) -> Option<String> {
+		self.handle.with_current(|filter| filter.to_string()).ok()
+	}
+}
@@ -1,17 +1,18 @@
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::time::Duration;
 
-use axum::http::{HeaderName, HeaderValue};
 use axum::routing::Router;
 use axum_server::Handle;
 use clap::Parser;
+use tracing::info;
 
 async fn shutdown_signal(handle: Handle) {
 	// Wait for the CTRL+C signal
 	tokio::signal::ctrl_c()
 		.await
 		.expect("failed to install CTRL+C signal handler");
-	println!("Received CTRL+C");
+	info!("Received CTRL+C");
 
 	handle.graceful_shutdown(Some(Duration::from_secs(30)));
 }
@@ -20,44 +21,82 @@ async fn shutdown_signal(handle: Handle) {
 struct Args {
 	#[arg(short, long)]
 	cors_origin: Vec<String>,
+	///Allow requests from any origin, only intended for development
+	#[arg(long)]
+	cors_allow_all: bool,
+	///Toml configuration file
+	#[arg(long)]
+	config: Option<PathBuf>,
 	#[arg(short, long, default_value = "password")]
 	password: String,
+	///Admin password, when set the logins with the password only see and manage the jobs created
+	///with it
+	#[arg(long)]
+	admin_password: Option<String>,
 }
 
 #[tokio::main]
 async fn main() {
 	let args = Args::parse();
-	let api = server::make_router(server::AppStateLocal::with_cred(&args.password).into());
-	let origins: Vec<HeaderValue> = args
-		.cors_origin
-		.iter()
-		.map(|val| val.parse().unwrap())
-		.collect();
-	let headers = [
-		HeaderName::from_static("credentials"),
-		HeaderName::from_static("audio_codec"),
-		HeaderName::from_static("audio_param"),
-		HeaderName::from_static("authorization"),
-		HeaderName::from_static("content-type"),
-		HeaderName::from_static("segment_duration"),
-		HeaderName::from_static("video_codec"),
-		HeaderName::from_static("video_param"),
-	];
-	let cors = tower_http::cors::CorsLayer::new()
-		.allow_origin(origins)
-		.allow_headers(headers)
-		.allow_credentials(true);
-	let app = Router::new().nest("/api", api).layer(cors);
+	let log_filter = server::LogFilter::init();
+	let mut config = args
+		.config
+		.map(|path| server::Config::from_file(&path).expect("Failed to read config file"))
+		.unwrap_or_default();
+	let mut state = server::AppStateLocal::with_cred(&args.password).with_log_filter(log_filter);
+	if let Some(admin_password) = &args.admin_password {
+		state = state.with_admin_cred(admin_password);
+	}
+	if let Some(path) = &config.storage.path {
+		state = match state.with_storage_dir(path) {
+			Ok(state) => state,
+			Err(err) => {
+				eprintln!("Failed to open the storage {}: {err}", path.display());
+				std::process::exit(1);
+			}
+		};
+	}
+	if let Some(path) = &config.journal.path {
+		state = match state.with_journal(path).await {
+			Ok(state) => state,
+			Err(err) => {
+				eprintln!("Failed to restore the journal {}: {err}", path.display());
+				std::process::exit(1);
+			}
+		};
+	}
+	state = state.with_quotas(config.quota.clone());
+	if let Some(ffprobe) = &config.probe.ffprobe {
+		state = state.with_ffprobe(ffprobe);
+	}
+	if let Some(ffmpeg) = &config.probe.ffmpeg {
+		state = state.with_ffmpeg(ffmpeg);
+	}
+	match &config.webhook.secret {
+		Some(secret) => state = state.with_webhook_secret(secret),
+		None => info!("Webhook secret not configured, job events will use a random key"),
+	}
+	state = state.with_webhook_allowed_hosts(&config.webhook.allowed_hosts);
+	let api = server::make_router(state.into());
+	config.cors.origins.extend(args.cors_origin);
+	config.cors.allow_all |= args.cors_allow_all;
+	let cors = config.cors.layer().unwrap();
+	let mut app = Router::new().nest("/api", api).layer(cors);
+	if let Some(path) = &config.access_log.path {
+		let access_log = server::AccessLog::open(path, &config.access_log)
+			.expect("Failed to open the access log");
+		app = access_log.layer(app);
+	}
 	let handle = Handle::new();
 
 	// Spawn a task to gracefully shutdown server.
 	tokio::spawn(shutdown_signal(handle.clone()));
 
 	let addr: SocketAddr = "[::]:8888".parse().unwrap();
-	println!("listening on {}", addr);
+	info!("listening on {}", addr);
 	axum_server::bind(addr)
 		.handle(handle)
-		.serve(app.into_make_service())
+		.serve(app.into_make_service_with_connect_info::<SocketAddr>())
 		.await
 		.unwrap();
 }
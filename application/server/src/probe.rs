@@ -0,0 +1,116 @@
+//! Validate the job sources with ffprobe, before any task is scheduled
+//!
+//! The source is piped to ffprobe, so it works with any storage
+use std::io;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+use tokio::io::AsyncRead;
+use tokio::process::Command;
+
+use task::MediaProbe;
+
+///Parse the probe, a source without a video stream is not valid
+fn parse(output: &[u8]) -> io::Result<MediaProbe> {
+	let probe = task::probe::parse(output)
+		.map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+	match probe.video() {
+		Some(stream) if stream.width.is_some() && stream.height.is_some() => Ok(probe),
+		_ => Err(io::Error::new(
+			io::ErrorKind::InvalidData,
+			"Source has no video stream",
+		)),
+	}
+}
+
+///Time ffprobe has to read the source, it reads only the start of most sources
+const PROBE_TIMEOUT: Duration = Duration::from_secs(60);
+
+///Probe the source, a source ffprobe can't read fails with [io::ErrorKind::InvalidData]
+///
+///ffprobe is killed if it doesn't finish in time, failing with [io::ErrorKind::TimedOut]
+pub(crate) async fn probe(
+	ffprobe: &Path,
+	source: impl AsyncRead + Unpin,
+) -> io::Result<MediaProbe> {
+	probe_with_timeout(ffprobe, source, PROBE_TIMEOUT).await
+}
+
+async fn probe_with_timeout(
+	ffprobe: &Path,
+	source: impl AsyncRead + Unpin,
+	timeout: Duration,
+) -> io::Result<MediaProbe> {
+	tokio::time::timeout(timeout, run(ffprobe, source))
+		.await
+		.map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "ffprobe timed out"))?
+}
+
+async fn run(ffprobe: &Path, mut source: impl AsyncRead + Unpin) -> io::Result<MediaProbe> {
+	let mut child = Command::new(ffprobe)
+		.args(["-v", "error", "-of", "json"])
+		.args(["-show_entries", task::probe::SHOW_ENTRIES])
+		.arg("pipe:0")
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::null())
+		.kill_on_drop(true)
+		.spawn()?;
+	let mut stdin = child.stdin.take().expect("ffprobe stdin is piped");
+	let feed = async move {
+		//ffprobe may close its input before reading everything
+		let _ = tokio::io::copy(&mut source, &mut stdin).await;
+	};
+	let (_, output) = tokio::join!(feed, child.wait_with_output());
+	let output = output?;
+	if !output.status.success() {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidData,
+			format!("ffprobe returned: {}", output.status),
+		));
+	}
+	parse(&output.stdout)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn parse_video_stream_and_duration() {
+		let output = br#"{"programs":[],"streams":[{"index":0,"codec_type":"video","width":1920,"height":1080}],"format":{"duration":"12.500000"}}"#;
+		let probe = parse(output).unwrap();
+		assert_eq!(probe.duration, Some(12.5));
+		assert_eq!(probe.video().unwrap().width, Some(1920))
+	}
+
+	#[test]
+	fn parse_without_video_stream_invalid_data() {
+		let output = br#"{"programs":[],"streams":[{"index":0,"codec_type":"audio"}],"format":{"duration":"3.0"}}"#;
+		let err = parse(output).unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::InvalidData)
+	}
+
+	#[cfg(unix)]
+	#[tokio::test]
+	async fn failed_probe_invalid_data() {
+		let err = probe(Path::new("false"), b"not media".as_slice())
+			.await
+			.unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::InvalidData)
+	}
+
+	#[cfg(unix)]
+	#[tokio::test]
+	async fn stuck_probe_timed_out() {
+		use std::os::unix::fs::PermissionsExt;
+
+		let ffprobe = std::env::temp_dir().join(format!("stuck-ffprobe-{}", uuid::Uuid::new_v4()));
+		std::fs::write(&ffprobe, "#!/bin/sh\nexec sleep 10\n").unwrap();
+		std::fs::set_permissions(&ffprobe, std::fs::Permissions::from_mode(0o755)).unwrap();
+		let res = probe_with_timeout(&ffprobe, b"".as_slice(), Duration::from_millis(100)).await;
+		std::fs::remove_file(&ffprobe).unwrap();
+		assert_eq!(res.unwrap_err().kind(), io::ErrorKind::TimedOut)
+	}
+}
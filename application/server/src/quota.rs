@@ -0,0 +1,368 @@
+//! Limits of the jobs, queued tasks and stored bytes of each credential, shared by its tokens
+//!
+//! The usage is computed from the jobs owned by the credential, as stored by the manager, and the
+//! inputs uploaded with it. Jobs only count while their output is not available
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use axum::body::Body;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use futures::StreamExt;
+use uuid::Uuid;
+
+use task::manager::Manager;
+use task::TaskError;
+
+use crate::api::AppState;
+use crate::config::QuotaConfig;
+use crate::storage::Storage;
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum QuotaExceeded {
+	Jobs { max: u32 },
+	QueuedTasks { max: u32 },
+	Bytes { used: u64, requested: u64, max: u64 },
+}
+
+impl Display for QuotaExceeded {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match self {
+			QuotaExceeded::Jobs { max } => write!(f, "Quota of {max} unfinished jobs reached"),
+			QuotaExceeded::QueuedTasks { max } => {
+				write!(f, "Quota of {max} queued tasks reached")
+			}
+			QuotaExceeded::Bytes {
+				used,
+				requested,
+				max,
+			} => write!(
+				f,
+				"Storage quota exceeded, {used} of {max} bytes used and {requested} more requested"
+			),
+		}
+	}
+}
+
+impl IntoResponse for QuotaExceeded {
+	fn into_response(self) -> Response {
+		let status = match self {
+			QuotaExceeded::Jobs { .. } | QuotaExceeded::QueuedTasks { .. } => {
+				StatusCode::TOO_MANY_REQUESTS
+			}
+			QuotaExceeded::Bytes { .. } => StatusCode::INSUFFICIENT_STORAGE,
+		};
+		(status, self.to_string()).into_response()
+	}
+}
+
+///Credential the token logged in with, logging in again does not reset the quotas nor lose the
+///jobs it created
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Credential {
+	Admin,
+	User,
+}
+
+impl Credential {
+	///Owner recorded on the jobs created with the credential
+	pub(crate) fn owner(self) -> &'static str {
+		match self {
+			Credential::Admin => "admin",
+			Credential::User => "user",
+		}
+	}
+}
+
+///Resources used with a credential
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Usage {
+	///Jobs without output
+	pub jobs: u32,
+	///Tasks of the unfinished jobs that are not finished
+	pub queued_tasks: u32,
+	pub bytes: u64,
+	///Inputs of the jobs and the inputs uploaded, counted on the bytes
+	pub inputs: BTreeSet<Uuid>,
+}
+
+///Bytes received by a body limited to the bytes left to the usage
+pub(crate) struct BodyLimit {
+	received: Arc<AtomicU64>,
+	used: u64,
+	max: u64,
+}
+
+impl BodyLimit {
+	///The quota exceeded, when the body was cut for being longer than the bytes left
+	pub(crate) fn exceeded(&self) -> Option<QuotaExceeded> {
+		let received = self.received.load(Ordering::Relaxed);
+		(self.used.saturating_add(received) > self.max).then_some(QuotaExceeded::Bytes {
+			used: self.used,
+			requested: received,
+			max: self.max,
+		})
+	}
+}
+
+pub struct Quotas {
+	config: QuotaConfig,
+	///Inputs uploaded with each credential that no job uses yet
+	///
+	///Only kept in memory, the uploads are counted by their jobs once used
+	uploads: Mutex<HashMap<Credential, BTreeSet<Uuid>>>,
+}
+
+impl Quotas {
+	pub fn new(config: QuotaConfig) -> Self {
+		Self {
+			config,
+			uploads: Default::default(),
+		}
+	}
+
+	pub(crate) fn record_upload(&self, credential: Credential, input_id: Uuid) {
+		let mut uploads = self.uploads.lock().unwrap_or_else(|err| err.into_inner());
+		uploads.entry(credential).or_default().insert(input_id);
+	}
+
+	fn uploads(&self, credential: Credential) -> BTreeSet<Uuid> {
+		let uploads = self.uploads.lock().unwrap_or_else(|err| err.into_inner());
+		uploads.get(&credential).cloned().unwrap_or_default()
+	}
+
+	///Forget the uploads used by a job, or no longer stored
+	fn prune(&self, credential: Credential, pruned: &[Uuid]) {
+		let mut uploads = self.uploads.lock().unwrap_or_else(|err| err.into_inner());
+		if let Some(uploads) = uploads.get_mut(&credential) {
+			uploads.retain(|upload| !pruned.contains(upload));
+		}
+	}
+
+	///Fail the body once it exceeds the bytes left to the usage
+	///
+	///Chunked uploads don't declare their length, so the limit is enforced while they are received
+	pub(crate) fn limit_body(&self, usage: &Usage, body: Body) -> (Body, Option<BodyLimit>) {
+		let Some(max) = self.config.max_bytes else {
+			return (body, None);
+		};
+		let left = max.saturating_sub(usage.bytes);
+		let received = Arc::new(AtomicU64::new(0));
+		let counter = received.clone();
+		let stream = body.into_data_stream().map(move |chunk| {
+			let chunk = chunk?;
+			let len = chunk.len() as u64;
+			match counter.fetch_add(len, Ordering::Relaxed) + len > left {
+				true => Err(axum::Error::new("Storage quota exceeded")),
+				false => Ok(chunk),
+			}
+		});
+		let limit = BodyLimit {
+			received,
+			used: usage.bytes,
+			max,
+		};
+		(Body::from_stream(stream), Some(limit))
+	}
+
+	///Check if the credential may store more bytes
+	pub(crate) fn check_bytes(&self, usage: &Usage, requested: u64) -> Result<(), QuotaExceeded> {
+		match self.config.max_bytes {
+			Some(max) if usage.bytes.saturating_add(requested) > max || usage.bytes >= max => {
+				Err(QuotaExceeded::Bytes {
+					used: usage.bytes,
+					requested,
+					max,
+				})
+			}
+			_ => Ok(()),
+		}
+	}
+
+	///Check if the credential may create another job, which stores the requested bytes
+	pub(crate) fn check_job(&self, usage: &Usage, requested: u64) -> Result<(), QuotaExceeded> {
+		if let Some(max) = self.config.max_jobs.filter(|max| usage.jobs >= *max) {
+			return Err(QuotaExceeded::Jobs { max });
+		}
+		if let Some(max) = self
+			.config
+			.max_queued_tasks
+			.filter(|max| usage.queued_tasks >= *max)
+		{
+			return Err(QuotaExceeded::QueuedTasks { max });
+		}
+		self.check_bytes(usage, requested)
+	}
+
+	///Compute the resources used with the credential, from the jobs it owns and its uploads only
+	pub(crate) async fn usage<S: AppState + ?Sized>(
+		&self,
+		state: &S,
+		credential: Credential,
+	) -> Result<Usage, TaskError> {
+		let manager = state.manager();
+		let mut usage = Usage::default();
+		for job_id in manager.get_job_list().await? {
+			let Some(job) = manager.get_job(&job_id).await? else {
+				continue;
+			};
+			if job.owner.as_deref() != Some(credential.owner()) {
+				continue;
+			}
+			usage.inputs.insert(job.input_id);
+			if manager.get_job_output(&job_id, None).await?.is_some() {
+				continue;
+			}
+			usage.jobs += 1;
+			usage.queued_tasks += manager.get_unfinished_tasks(&job_id).await?.unwrap_or(0);
+		}
+		let uploads = self.uploads(credential);
+		let mut pruned: Vec<Uuid> = uploads.intersection(&usage.inputs).copied().collect();
+		for upload in uploads {
+			usage.inputs.insert(upload);
+		}
+		for input_id in &usage.inputs {
+			//Inputs no longer stored don't use space
+			match state.storage().file_size(*input_id).await {
+				Ok(size) => usage.bytes += size,
+				Err(_) => pruned.push(*input_id),
+			}
+		}
+		self.prune(credential, &pruned);
+		Ok(usage)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use task::{JobSource, Recipe, TaskSource};
+
+	use super::*;
+
+	fn quotas(
+		max_jobs: Option<u32>,
+		max_queued_tasks: Option<u32>,
+		max_bytes: Option<u64>,
+	) -> Quotas {
+		Quotas::new(QuotaConfig {
+			max_jobs,
+			max_queued_tasks,
+			max_bytes,
+		})
+	}
+
+	fn usage(jobs: u32, queued_tasks: u32, bytes: u64) -> Usage {
+		Usage {
+			jobs,
+			queued_tasks,
+			bytes,
+			inputs: BTreeSet::new(),
+		}
+	}
+
+	#[test]
+	fn without_limits_everything_allowed() {
+		let quotas = quotas(None, None, None);
+		assert_eq!(quotas.check_job(&usage(100, 1000, u64::MAX), 1), Ok(()))
+	}
+
+	#[test]
+	fn job_limit_reached() {
+		let quotas = quotas(Some(2), None, None);
+		assert_eq!(quotas.check_job(&usage(1, 0, 0), 0), Ok(()));
+		assert_eq!(
+			quotas.check_job(&usage(2, 0, 0), 0),
+			Err(QuotaExceeded::Jobs { max: 2 })
+		);
+	}
+
+	#[test]
+	fn queued_task_limit_reached() {
+		let quotas = quotas(None, Some(10), None);
+		assert_eq!(
+			quotas.check_job(&usage(1, 10, 0), 0),
+			Err(QuotaExceeded::QueuedTasks { max: 10 })
+		);
+	}
+
+	#[test]
+	fn bytes_limit_includes_requested() {
+		let quotas = quotas(None, None, Some(100));
+		assert_eq!(quotas.check_bytes(&usage(0, 0, 60), 40), Ok(()));
+		assert_eq!(
+			quotas.check_bytes(&usage(0, 0, 60), 41),
+			Err(QuotaExceeded::Bytes {
+				used: 60,
+				requested: 41,
+				max: 100
+			})
+		);
+	}
+
+	#[tokio::test]
+	async fn usage_prunes_deleted_uploads() {
+		let state = crate::api::AppStateLocal::default();
+		let quotas = quotas(None, None, None);
+		quotas.record_upload(Credential::User, Uuid::new_v4());
+		let usage = quotas.usage(&state, Credential::User).await.unwrap();
+		assert_eq!(usage.bytes, 0);
+		assert!(quotas.uploads(Credential::User).is_empty());
+	}
+
+	#[tokio::test]
+	async fn usage_counts_the_stored_jobs_of_the_credential() {
+		let state = crate::api::AppStateLocal::default();
+		let input_id = state.storage().body_to_new_file(Body::from("input")).await;
+		let input_id = input_id.unwrap();
+		for owner in ["user", "admin"] {
+			let job = JobSource {
+				owner: Some(owner.to_string()),
+				..JobSource::new(input_id, Default::default())
+			};
+			let job_id = state.manager().create_job(job).await.unwrap();
+			let task = TaskSource {
+				recipe: Recipe::Transcode(Vec::new()),
+				..Default::default()
+			};
+			state
+				.manager()
+				.add_task_to_job(&job_id, task)
+				.await
+				.unwrap();
+		}
+		let quotas = quotas(None, None, None);
+		let usage = quotas.usage(&state, Credential::User).await.unwrap();
+		assert_eq!((usage.jobs, usage.queued_tasks, usage.bytes), (1, 1, 5));
+		assert_eq!(usage.inputs, BTreeSet::from([input_id]));
+	}
+
+	#[tokio::test]
+	async fn limited_body_fails_over_the_bytes_left() {
+		let quotas = quotas(None, None, Some(10));
+		let (body, limit) = quotas.limit_body(&usage(0, 0, 4), Body::from("0123456789"));
+		assert!(axum::body::to_bytes(body, usize::MAX).await.is_err());
+		assert_eq!(
+			limit.unwrap().exceeded(),
+			Some(QuotaExceeded::Bytes {
+				used: 4,
+				requested: 10,
+				max: 10
+			})
+		);
+	}
+
+	#[test]
+	fn exceeded_status() {
+		let jobs = QuotaExceeded::Jobs { max: 1 }.into_response();
+		let bytes = QuotaExceeded::Bytes {
+			used: 1,
+			requested: 1,
+			max: 1,
+		}
+		.into_response();
+		assert_eq!(jobs.status(), StatusCode::TOO_MANY_REQUESTS);
+		assert_eq!(bytes.status(), StatusCode::INSUFFICIENT_STORAGE);
+	}
+}
@@ -0,0 +1,95 @@
+//! Join the finished segments of a job, while its output is not merged yet
+//!
+//! Matroska files can't be concatenated byte by byte, so the segments are copied to a temporary
+//! directory and ffmpeg remuxes them with the concat demuxer
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use axum::body::Bytes;
+use futures::{Stream, StreamExt};
+use tokio::io::AsyncRead;
+use tokio::process::Command;
+use tokio_util::io::ReaderStream;
+use uuid::Uuid;
+
+///Directory with the segment copies, removed once the remux stream is dropped
+struct SegmentDir(PathBuf);
+
+impl Drop for SegmentDir {
+	fn drop(&mut self) {
+		let _ = std::fs::remove_dir_all(&self.0);
+	}
+}
+
+///Line for the ffmpeg concat demuxer list
+fn concat_entry(path: &Path) -> String {
+	let path = path.to_string_lossy().replace('\'', "'\\''");
+	format!("file '{path}'\n")
+}
+
+fn concat_args(list: &Path) -> Vec<String> {
+	["-v", "error", "-f", "concat", "-safe", "0", "-i"]
+		.into_iter()
+		.map(String::from)
+		.chain([list.to_string_lossy().to_string()])
+		.chain(
+			["-map", "0", "-c", "copy", "-f", "matroska", "pipe:1"]
+				.into_iter()
+				.map(String::from),
+		)
+		.collect()
+}
+
+///Stream the segments remuxed into one matroska file, in order
+///
+///ffmpeg is killed if the stream is dropped before it finishes
+pub(crate) async fn concat(
+	ffmpeg: &Path,
+	segments: Vec<impl AsyncRead + Unpin>,
+) -> io::Result<impl Stream<Item = io::Result<Bytes>>> {
+	let dir = std::env::temp_dir().join(format!("segmented-partial-{}", Uuid::new_v4()));
+	tokio::fs::create_dir(&dir).await?;
+	let dir = SegmentDir(dir);
+	let mut list = String::new();
+	for (idx, mut segment) in segments.into_iter().enumerate() {
+		let path = dir.0.join(format!("{idx}.mkv"));
+		let mut file = tokio::fs::File::create(&path).await?;
+		tokio::io::copy(&mut segment, &mut file).await?;
+		list.push_str(&concat_entry(&path));
+	}
+	let list_path = dir.0.join("list.txt");
+	tokio::fs::write(&list_path, list).await?;
+	let mut child = Command::new(ffmpeg)
+		.args(concat_args(&list_path))
+		.stdin(Stdio::null())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::null())
+		.kill_on_drop(true)
+		.spawn()?;
+	let stdout = child.stdout.take().expect("ffmpeg stdout is piped");
+	Ok(ReaderStream::new(stdout).map(move |chunk| {
+		//The stream owns ffmpeg and the segment copies
+		let _ = (&child, &dir);
+		chunk
+	}))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn concat_entry_escapes_quotes() {
+		let entry = concat_entry(Path::new("/tmp/it's.mkv"));
+		assert_eq!(entry, "file '/tmp/it'\\''s.mkv'\n")
+	}
+
+	#[test]
+	fn concat_args_copy_every_stream_to_matroska() {
+		let args = concat_args(Path::new("list.txt"));
+		let expected = ["-f", "concat", "-safe", "0", "-i", "list.txt", "-map", "0"];
+		assert!(args.windows(8).any(|window| window == expected), "{args:?}");
+		assert!(args.ends_with(&["-c", "copy", "-f", "matroska", "pipe:1"].map(String::from)))
+	}
+}
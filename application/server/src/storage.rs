@@ -14,6 +14,20 @@ pub trait Storage: Sync {
 		&self,
 		uuid: Uuid,
 	) -> impl Future<Output = std::io::Result<impl AsyncRead + AsyncSeek + Send + Unpin + 'static>> + Send;
+	///Get the file size, in bytes
+	fn file_size(&self, uuid: Uuid) -> impl Future<Output = std::io::Result<u64>> + Send {
+		use tokio::io::AsyncSeekExt;
+		async move {
+			let mut file = self.read_file(uuid).await?;
+			file.seek(std::io::SeekFrom::End(0)).await
+		}
+	}
+	///Total size of the stored files, in bytes
+	///
+	///Storages that can't tell their size return [std::io::ErrorKind::Unsupported]
+	fn used_bytes(&self) -> impl Future<Output = std::io::Result<u64>> + Send {
+		async { Err(std::io::ErrorKind::Unsupported.into()) }
+	}
 	///Create a writer for a new file, the content may only be stored after a call to store
 	fn create_file(&self) -> impl Future<Output = std::io::Result<Self::WriteFile>> + Send;
 	///Save the file and return its id
@@ -21,6 +35,8 @@ pub trait Storage: Sync {
 		&self,
 		file: Self::WriteFile,
 	) -> impl Future<Output = std::io::Result<Uuid>> + Send;
+	///Remove the file, its id is not valid anymore
+	fn delete_file(&self, uuid: Uuid) -> impl Future<Output = std::io::Result<()>> + Send;
 	///Copy the body content to a new file
 	fn body_to_new_file(
 		&self,
@@ -31,27 +47,37 @@ pub trait Storage: Sync {
 	{
 		use futures::StreamExt;
 		use std::io;
-		use std::io::ErrorKind;
 		use tokio_util::io::StreamReader;
 		async {
 			let mut write = self.create_file().await?;
 			let body_stream = body
 				.into_data_stream()
-				.map(|res| res.map_err(|e| io::Error::new(ErrorKind::Other, e)));
+				.map(|res| res.map_err(io::Error::other));
 			let mut stream = StreamReader::new(body_stream);
 			tokio::io::copy(&mut stream, &mut write).await?;
 			self.store_file(write).await
 		}
 	}
+	///Append the body content to the end of the file, in place
+	///
+	///The received content is kept even if the body fails, returns if the body was completely
+	///received
+	fn append_body_to_file(
+		&self,
+		uuid: Uuid,
+		body: axum::body::Body,
+	) -> impl Future<Output = std::io::Result<bool>> + Send;
 }
 
 mod mem {
-	//! Memory based storage
+	//! Memory based storage, optionally copying the files to a directory
 	use std::collections::BTreeMap;
 	use std::fmt::{Debug, Formatter};
 	use std::io::{Cursor, Error, ErrorKind};
+	use std::path::{Path, PathBuf};
 	use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
+	use tokio::io::AsyncWriteExt;
 	use uuid::Uuid;
 
 	use crate::storage::Storage;
@@ -59,9 +85,46 @@ mod mem {
 	#[derive(Default)]
 	pub(crate) struct MemStorage {
 		storage: RwLock<BTreeMap<Uuid, MemReadFile>>,
+		///Directory keeping a copy of each file, to restore them after a restart
+		dir: Option<PathBuf>,
 	}
 
 	impl MemStorage {
+		///Copy the files to the directory, loading the ones already stored there
+		///
+		///Temporary files left by an interrupted store are removed
+		pub(crate) fn with_dir(dir: &Path) -> std::io::Result<Self> {
+			std::fs::create_dir_all(dir)?;
+			let mut files = BTreeMap::new();
+			for entry in std::fs::read_dir(dir)? {
+				let path = entry?.path();
+				let id = path.file_name().and_then(|name| name.to_str());
+				match id.and_then(|id| id.parse::<Uuid>().ok()) {
+					Some(id) => {
+						let content = std::fs::read(&path)?;
+						files.insert(id, MemReadFile(Arc::new(content)));
+					}
+					None if path.extension().is_some_and(|ext| ext == "tmp") => {
+						std::fs::remove_file(&path)?
+					}
+					None => {}
+				}
+			}
+			Ok(Self {
+				storage: RwLock::new(files),
+				dir: Some(dir.to_path_buf()),
+			})
+		}
+
+		///If the files survive a restart
+		pub(crate) fn is_persistent(&self) -> bool {
+			self.dir.is_some()
+		}
+
+		fn path(&self, uuid: Uuid) -> Option<PathBuf> {
+			self.dir.as_ref().map(|dir| dir.join(uuid.to_string()))
+		}
+
 		fn read(&self) -> RwLockReadGuard<'_, BTreeMap<Uuid, MemReadFile>> {
 			self.storage
 				.read()
@@ -94,6 +157,8 @@ mod mem {
 	impl Storage for MemStorage {
 		type WriteFile = Vec<u8>;
 
+		#[allow(refining_impl_trait)]
+		#[tracing::instrument(level = "debug", skip(self))]
 		async fn read_file(&self, uuid: Uuid) -> std::io::Result<Cursor<MemReadFile>> {
 			self.read()
 				.get(&uuid)
@@ -102,15 +167,77 @@ mod mem {
 				.ok_or(Error::new(ErrorKind::NotFound, "Not found"))
 		}
 
+		async fn used_bytes(&self) -> std::io::Result<u64> {
+			Ok(self.read().values().map(|file| file.0.len() as u64).sum())
+		}
+
 		async fn create_file(&self) -> std::io::Result<Self::WriteFile> {
 			Ok(Vec::new())
 		}
 
+		#[tracing::instrument(level = "debug", skip_all)]
 		async fn store_file(&self, file: Self::WriteFile) -> std::io::Result<Uuid> {
 			let id = Uuid::new_v4();
+			if let Some(path) = self.path(id) {
+				let tmp = path.with_extension("tmp");
+				let mut copy = tokio::fs::File::create(&tmp).await?;
+				copy.write_all(&file).await?;
+				copy.sync_all().await?;
+				tokio::fs::rename(&tmp, &path).await?;
+			}
+			tracing::debug!(%id, size = file.len(), "Stored file");
 			self.write().insert(id, MemReadFile(Arc::new(file)));
 			Ok(id)
 		}
+
+		#[tracing::instrument(level = "debug", skip(self))]
+		async fn delete_file(&self, uuid: Uuid) -> std::io::Result<()> {
+			self.write()
+				.remove(&uuid)
+				.ok_or(Error::new(ErrorKind::NotFound, "Not found"))?;
+			match self.path(uuid) {
+				Some(path) => tokio::fs::remove_file(path).await,
+				None => Ok(()),
+			}
+		}
+
+		#[tracing::instrument(level = "debug", skip(self, body))]
+		async fn append_body_to_file(
+			&self,
+			uuid: Uuid,
+			body: axum::body::Body,
+		) -> std::io::Result<bool> {
+			use futures::StreamExt;
+			if !self.read().contains_key(&uuid) {
+				return Err(Error::new(ErrorKind::NotFound, "Not found"));
+			}
+			let mut stream = body.into_data_stream();
+			let (mut received, mut complete) = (Vec::new(), true);
+			while let Some(chunk) = stream.next().await {
+				match chunk {
+					Ok(chunk) => received.extend_from_slice(&chunk),
+					Err(_) => {
+						complete = false;
+						break;
+					}
+				}
+			}
+			if let Some(path) = self.path(uuid) {
+				let mut copy = tokio::fs::OpenOptions::new()
+					.append(true)
+					.open(path)
+					.await?;
+				copy.write_all(&received).await?;
+				copy.sync_data().await?;
+			}
+			let mut storage = self.write();
+			let file = storage
+				.get_mut(&uuid)
+				.ok_or(Error::new(ErrorKind::NotFound, "Not found"))?;
+			//Only copied if the file is being read
+			Arc::make_mut(&mut file.0).extend_from_slice(&received);
+			Ok(complete)
+		}
 	}
 
 	#[cfg(test)]
@@ -181,5 +308,156 @@ mod mem {
 				.unwrap();
 			assert_eq!(out, input)
 		}
+
+		#[tokio::test]
+		async fn file_size_matches_written_content() {
+			let storage = MemStorage::default();
+			let mut write = storage.create_file().await.unwrap();
+			let input = &MKV_SAMPLE;
+			AsyncWriteExt::write_all(&mut write, input).await.unwrap();
+			let id = storage.store_file(write).await.unwrap();
+			let size = storage.file_size(id).await.unwrap();
+			assert_eq!(size, input.len() as u64)
+		}
+
+		#[tokio::test]
+		async fn append_body_keeps_file_content_first() {
+			let storage = MemStorage::default();
+			let id = storage
+				.body_to_new_file(axum::body::Body::from("base "))
+				.await
+				.unwrap();
+			let body = axum::body::Body::from("appended");
+			let complete = storage.append_body_to_file(id, body).await.unwrap();
+			assert!(complete);
+			let mut out = String::new();
+			let mut read = storage.read_file(id).await.unwrap();
+			AsyncReadExt::read_to_string(&mut read, &mut out)
+				.await
+				.unwrap();
+			assert_eq!(out, "base appended")
+		}
+
+		#[tokio::test]
+		async fn append_body_does_not_change_open_reads() {
+			let storage = MemStorage::default();
+			let id = storage
+				.body_to_new_file(axum::body::Body::from("base"))
+				.await
+				.unwrap();
+			let mut read = storage.read_file(id).await.unwrap();
+			let body = axum::body::Body::from(" appended");
+			storage.append_body_to_file(id, body).await.unwrap();
+			let mut out = String::new();
+			AsyncReadExt::read_to_string(&mut read, &mut out)
+				.await
+				.unwrap();
+			assert_eq!(out, "base")
+		}
+
+		#[tokio::test]
+		async fn append_broken_body_stores_received_content() {
+			let storage = MemStorage::default();
+			let file = storage.create_file().await.unwrap();
+			let id = storage.store_file(file).await.unwrap();
+			let chunks: Vec<Result<&'static str, std::io::Error>> = vec![
+				Ok("received"),
+				Err(std::io::Error::other("connection reset")),
+			];
+			let body = axum::body::Body::from_stream(futures::stream::iter(chunks));
+			let complete = storage.append_body_to_file(id, body).await.unwrap();
+			assert!(!complete);
+			assert_eq!(
+				storage.file_size(id).await.unwrap(),
+				"received".len() as u64
+			)
+		}
+
+		#[tokio::test]
+		async fn append_to_nonexistent_file_not_found() {
+			let storage = MemStorage::default();
+			let body = axum::body::Body::from("appended");
+			let res = storage.append_body_to_file(Uuid::nil(), body).await;
+			assert_eq!(res.unwrap_err().kind(), ErrorKind::NotFound)
+		}
+
+		#[tokio::test]
+		async fn used_bytes_sums_stored_files() {
+			let storage = MemStorage::default();
+			for content in [&b"1234"[..], &b"56"[..]] {
+				let mut file = storage.create_file().await.unwrap();
+				file.write_all(content).await.unwrap();
+				storage.store_file(file).await.unwrap();
+			}
+			assert_eq!(storage.used_bytes().await.unwrap(), 6)
+		}
+
+		#[tokio::test]
+		async fn delete_file_removes_the_content() {
+			let storage = MemStorage::default();
+			let file = storage.create_file().await.unwrap();
+			let id = storage.store_file(file).await.unwrap();
+			storage.delete_file(id).await.unwrap();
+			let read = storage.read_file(id).await;
+			assert_eq!(read.unwrap_err().kind(), ErrorKind::NotFound);
+		}
+
+		#[tokio::test]
+		async fn delete_nonexistent_file_not_found() {
+			let storage = MemStorage::default();
+			let res = storage.delete_file(Uuid::nil()).await;
+			assert_eq!(res.unwrap_err().kind(), ErrorKind::NotFound);
+		}
+
+		fn storage_dir() -> std::path::PathBuf {
+			std::env::temp_dir().join(format!("storage-{}", Uuid::new_v4()))
+		}
+
+		#[tokio::test]
+		async fn with_dir_restores_stored_files() {
+			let dir = storage_dir();
+			let storage = MemStorage::with_dir(&dir).unwrap();
+			let id = storage
+				.body_to_new_file(axum::body::Body::from("base"))
+				.await
+				.unwrap();
+			let body = axum::body::Body::from(" appended");
+			storage.append_body_to_file(id, body).await.unwrap();
+			let deleted = storage
+				.body_to_new_file(axum::body::Body::from("deleted"))
+				.await
+				.unwrap();
+			storage.delete_file(deleted).await.unwrap();
+			drop(storage);
+			let restored = MemStorage::with_dir(&dir).unwrap();
+			std::fs::remove_dir_all(&dir).unwrap();
+			let mut out = String::new();
+			let mut read = restored.read_file(id).await.unwrap();
+			AsyncReadExt::read_to_string(&mut read, &mut out)
+				.await
+				.unwrap();
+			assert_eq!(out, "base appended");
+			assert!(restored.read_file(deleted).await.is_err());
+		}
+
+		#[test]
+		fn with_dir_removes_temporary_files() {
+			let dir = storage_dir();
+			std::fs::create_dir_all(&dir).unwrap();
+			let tmp = dir.join(format!("{}.tmp", Uuid::new_v4()));
+			std::fs::write(&tmp, "partial").unwrap();
+			let storage = MemStorage::with_dir(&dir).unwrap();
+			let exists = tmp.exists();
+			std::fs::remove_dir_all(&dir).unwrap();
+			assert!(!exists);
+			assert!(storage.is_persistent());
+		}
+
+		#[tokio::test]
+		async fn file_size_nonexistent_file_not_found() {
+			let storage = MemStorage::default();
+			let size = storage.file_size(Uuid::nil()).await;
+			assert_eq!(size.unwrap_err().kind(), ErrorKind::NotFound);
+		}
 	}
 }
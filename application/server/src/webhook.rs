@@ -0,0 +1,356 @@
+//! Job event notifications
+//!
+//! When a job has a callback url, events are sent as a json POST, signed with a hmac-sha256 of the
+//! body on the `X-Signature-256: sha256=<hex>` header.
+//!
+//! Callbacks to private, loopback or link-local addresses are refused, unless their host is allowed
+//! in the configuration. Names are checked when they are resolved, on every delivery.
+use std::collections::BTreeSet;
+use std::future::Future;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use hmac::{Hmac, Mac};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use task::manager::Manager;
+
+use crate::api::AppState;
+
+pub(crate) const SIGNATURE_HEADER: &str = "x-signature-256";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JobEvent {
+	///The job output is available
+	Completed,
+	///A job task failed
+	Failed,
+}
+
+impl JobEvent {
+	fn as_str(&self) -> &'static str {
+		match self {
+			JobEvent::Completed => "completed",
+			JobEvent::Failed => "failed",
+		}
+	}
+}
+
+pub trait Notifier: Sync {
+	///Send the event to the callback url, delivery happens in background
+	fn notify(
+		&self,
+		callback: &str,
+		job_id: Uuid,
+		event: JobEvent,
+	) -> impl Future<Output = ()> + Send;
+	///Whether events can be sent to the callback url
+	fn accepts(&self, callback: &str) -> bool {
+		is_valid_callback(callback, &BTreeSet::new())
+	}
+}
+
+pub struct WebhookNotifier {
+	client: reqwest::Client,
+	secret: String,
+	allowed_hosts: Arc<BTreeSet<String>>,
+}
+
+impl WebhookNotifier {
+	pub fn new(secret: &str) -> Self {
+		Self::with_allowed_hosts(secret.to_string(), BTreeSet::new())
+	}
+
+	///Also send the events to these hosts, even if they resolve to private addresses
+	pub fn allowing(self, hosts: &[String]) -> Self {
+		Self::with_allowed_hosts(self.secret, hosts.iter().cloned().collect())
+	}
+
+	fn with_allowed_hosts(secret: String, allowed_hosts: BTreeSet<String>) -> Self {
+		let allowed_hosts = Arc::new(allowed_hosts);
+		//Redirects are not followed, as they could point to a private address
+		let client = reqwest::Client::builder()
+			.dns_resolver(Arc::new(PublicResolver(allowed_hosts.clone())))
+			.redirect(reqwest::redirect::Policy::none())
+			.build()
+			.unwrap();
+		Self {
+			client,
+			secret,
+			allowed_hosts,
+		}
+	}
+}
+
+impl Default for WebhookNotifier {
+	fn default() -> Self {
+		Self::new(&Uuid::new_v4().to_string())
+	}
+}
+
+impl Notifier for WebhookNotifier {
+	async fn notify(&self, callback: &str, job_id: Uuid, event: JobEvent) {
+		if !self.accepts(callback) {
+			tracing::warn!(%job_id, callback, "Refused to notify a private callback");
+			return;
+		}
+		let body = payload(job_id, event);
+		let request = self
+			.client
+			.post(callback)
+			.header(reqwest::header::CONTENT_TYPE, "application/json")
+			.header(SIGNATURE_HEADER, signature(&self.secret, body.as_bytes()))
+			.body(body);
+		let callback = callback.to_string();
+		tokio::spawn(async move {
+			let res = request.send().await.and_then(|res| res.error_for_status());
+			if let Err(err) = res {
+				tracing::warn!(%job_id, callback, "Failed to notify about the job: {err}");
+			}
+		});
+	}
+
+	fn accepts(&self, callback: &str) -> bool {
+		is_valid_callback(callback, &self.allowed_hosts)
+	}
+}
+
+///Resolve the callback hosts, keeping only the public addresses of the hosts that are not allowed
+struct PublicResolver(Arc<BTreeSet<String>>);
+
+impl Resolve for PublicResolver {
+	fn resolve(&self, name: Name) -> Resolving {
+		let allowed = self.0.contains(name.as_str());
+		Box::pin(async move {
+			let addrs = tokio::net::lookup_host((name.as_str(), 0)).await?;
+			let addrs: Vec<_> = addrs
+				.filter(|addr| allowed || is_public(addr.ip()))
+				.collect();
+			if addrs.is_empty() {
+				return Err("The callback host has no public address".into());
+			}
+			Ok(Box::new(addrs.into_iter()) as Addrs)
+		})
+	}
+}
+
+///The address is not private, loopback, link-local or otherwise reserved
+fn is_public(ip: IpAddr) -> bool {
+	match ip {
+		IpAddr::V4(ip) => {
+			let [a, b, ..] = ip.octets();
+			let shared = a == 100 && (b & 0xc0) == 64;
+			!(ip.is_private()
+				|| ip.is_loopback()
+				|| ip.is_link_local()
+				|| ip.is_unspecified()
+				|| ip.is_broadcast()
+				|| ip.is_multicast()
+				|| ip.is_documentation()
+				|| shared)
+		}
+		IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+			Some(ip) => is_public(IpAddr::V4(ip)),
+			None => {
+				let unique_local = (ip.segments()[0] & 0xfe00) == 0xfc00;
+				let link_local = (ip.segments()[0] & 0xffc0) == 0xfe80;
+				!(ip.is_loopback()
+					|| ip.is_unspecified()
+					|| ip.is_multicast()
+					|| unique_local || link_local)
+			}
+		},
+	}
+}
+
+fn payload(job_id: Uuid, event: JobEvent) -> String {
+	let event = api::models::JobEvent {
+		job_id: job_id.to_string(),
+		event: event.as_str().to_string(),
+	};
+	serde_json::to_string(&event).unwrap()
+}
+
+///Hex encoded hmac-sha256 of the body, prefixed by `sha256=`
+pub(crate) fn signature(secret: &str, body: &[u8]) -> String {
+	let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+	mac.update(body);
+	let hex: String = mac
+		.finalize()
+		.into_bytes()
+		.iter()
+		.map(|byte| format!("{byte:02x}"))
+		.collect();
+	format!("sha256={hex}")
+}
+
+///Check that the callback is an absolute http(s) url, whose host is allowed or not private
+///
+///Names are only checked when resolved, except for the local ones
+fn is_valid_callback(callback: &str, allowed_hosts: &BTreeSet<String>) -> bool {
+	let Ok(url) = reqwest::Url::parse(callback) else {
+		return false;
+	};
+	if !matches!(url.scheme(), "http" | "https") {
+		return false;
+	}
+	let Some(host) = url.host_str() else {
+		return false;
+	};
+	if allowed_hosts.contains(host) {
+		return true;
+	}
+	//Ipv6 hosts are enclosed in brackets
+	match host.trim_start_matches('[').trim_end_matches(']').parse() {
+		Ok(ip) => is_public(ip),
+		Err(_) => host != "localhost" && !host.ends_with(".localhost"),
+	}
+}
+
+///Notify the job callback, if the job has one
+pub(crate) async fn notify_job<S: AppState>(state: &S, job_id: Uuid, event: JobEvent) {
+	let callback = state
+		.manager()
+		.get_job(&job_id)
+		.await
+		.ok()
+		.flatten()
+		.and_then(|job| job.callback);
+	if let Some(callback) = callback {
+		state.notifier().notify(&callback, job_id, event).await
+	}
+}
+
+#[cfg(test)]
+pub(crate) mod test_util {
+	use std::sync::Mutex;
+
+	use uuid::Uuid;
+
+	use super::{JobEvent, Notifier};
+
+	///Notifier that only records the events
+	#[derive(Default)]
+	pub(crate) struct RecordingNotifier {
+		pub events: Mutex<Vec<(String, Uuid, JobEvent)>>,
+	}
+
+	impl RecordingNotifier {
+		pub(crate) fn events(&self) -> Vec<(String, Uuid, JobEvent)> {
+			self.events.lock().unwrap().clone()
+		}
+	}
+
+	impl Notifier for RecordingNotifier {
+		async fn notify(&self, callback: &str, job_id: Uuid, event: JobEvent) {
+			self.events
+				.lock()
+				.unwrap()
+				.push((callback.to_string(), job_id, event));
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use axum::body::Bytes;
+	use axum::http::HeaderMap;
+	use axum::routing::post;
+	use axum::Router;
+	use tokio::sync::mpsc;
+	use uuid::Uuid;
+
+	use super::*;
+
+	#[test]
+	fn signature_is_prefixed_hex_hmac() {
+		//Test vector from RFC 4231, test case 2
+		let signature = signature("Jefe", b"what do ya want for nothing?");
+		assert_eq!(
+			signature,
+			"sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+		)
+	}
+
+	#[test]
+	fn payload_has_job_and_event() {
+		let payload: serde_json::Value =
+			serde_json::from_str(&payload(Uuid::nil(), JobEvent::Failed)).unwrap();
+		assert_eq!(payload["job_id"], Uuid::nil().to_string());
+		assert_eq!(payload["event"], "failed");
+	}
+
+	#[test]
+	fn callback_must_be_http_url() {
+		let none = BTreeSet::new();
+		assert!(is_valid_callback("http://example.com:8080/hook", &none));
+		assert!(is_valid_callback("https://example.com/hook", &none));
+		assert!(!is_valid_callback("ftp://example.com/hook", &none));
+		assert!(!is_valid_callback("/relative/hook", &none));
+	}
+
+	#[test]
+	fn callback_to_private_host_rejected() {
+		let none = BTreeSet::new();
+		for callback in [
+			"http://localhost:8080/hook",
+			"http://127.0.0.1/hook",
+			"http://10.1.2.3/hook",
+			"http://192.168.0.1/hook",
+			"http://169.254.169.254/latest/meta-data",
+			"http://[::1]/hook",
+			"http://[fe80::1]/hook",
+			"http://[::ffff:127.0.0.1]/hook",
+		] {
+			assert!(!is_valid_callback(callback, &none), "{callback}");
+		}
+		assert!(is_valid_callback("http://93.184.216.34/hook", &none));
+	}
+
+	#[test]
+	fn callback_to_allowed_private_host_accepted() {
+		let allowed = BTreeSet::from(["127.0.0.1".to_string()]);
+		assert!(is_valid_callback("http://127.0.0.1:8080/hook", &allowed));
+		assert!(!is_valid_callback("http://localhost:8080/hook", &allowed));
+	}
+
+	#[tokio::test]
+	async fn resolver_rejects_names_of_private_addresses() {
+		let resolver = PublicResolver(Default::default());
+		let res = resolver.resolve("localhost".parse().unwrap()).await;
+		assert!(res.is_err());
+		let allowed = PublicResolver(Arc::new(BTreeSet::from(["localhost".to_string()])));
+		let addrs = allowed.resolve("localhost".parse().unwrap()).await;
+		assert!(addrs.unwrap().all(|addr| addr.ip().is_loopback()));
+	}
+
+	#[tokio::test]
+	async fn webhook_notifier_posts_signed_event() {
+		let (send, mut recv) = mpsc::unbounded_channel();
+		let app = Router::new().route(
+			"/hook",
+			post(move |headers: HeaderMap, body: Bytes| async move {
+				send.send((headers, body)).unwrap();
+			}),
+		);
+		let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let addr = listener.local_addr().unwrap();
+		tokio::spawn(async move { axum::serve(listener, app).await });
+
+		let notifier = WebhookNotifier::new("secret").allowing(&["127.0.0.1".to_string()]);
+		let job_id = Uuid::new_v4();
+		let callback = format!("http://{addr}/hook");
+		notifier
+			.notify(&callback, job_id, JobEvent::Completed)
+			.await;
+
+		let (headers, body) = recv.recv().await.unwrap();
+		assert_eq!(body, payload(job_id, JobEvent::Completed));
+		assert_eq!(
+			headers.get(SIGNATURE_HEADER).unwrap(),
+			signature("secret", &body).as_str()
+		);
+	}
+}
@@ -1,4 +1,4 @@
-use api::models::{AnalysisTask, CodecParams, TaskRequestRecipe, TranscodeTask};
+use api::models::{AnalysisTask, CodecParams, TaskRequestRecipe, ThumbnailTask, TranscodeTask};
 
 use super::*;
 
@@ -7,11 +7,26 @@ impl TryFrom<&api::models::Recipe> for Recipe {
 
 	fn try_from(value: &api::models::Recipe) -> Result<Self, Self::Error> {
 		let transcode = value.transcode.as_ref().map(|e| &e.options);
-		match (&value.analysis, transcode, &value.merge) {
-			(Some(s), None, None) => Ok(Recipe::Analysis(s.duration)),
-			(None, Some(opt), None) => Ok(Recipe::Transcode(opt.clone())),
-			(None, None, Some(_)) => Ok(Recipe::Merge(vec![])),
-			(_, _, _) => Err(()),
+		let recipes = (&value.analysis, transcode, &value.merge, &value.thumbnail);
+		match recipes {
+			(Some(s), None, None, None) => Ok(Recipe::Analysis(AnalysisOptions {
+				duration: s.duration,
+				scene_threshold: s.scene_threshold,
+				max_segments: s.max_segments.map(u32::try_from).transpose().or(Err(()))?,
+			})),
+			(None, Some(opt), None, None) => Ok(Recipe::Transcode(opt.clone())),
+			(None, None, Some(_), None) => Ok(Recipe::Merge(vec![])),
+			(None, None, None, Some(thumbnail)) => Ok(Recipe::Thumbnail(
+				match (thumbnail.sprite_columns, thumbnail.sprite_rows) {
+					(None, None) => Thumbnail::Poster,
+					(Some(columns), Some(rows)) => Thumbnail::Sprite {
+						columns: columns.try_into().or(Err(()))?,
+						rows: rows.try_into().or(Err(()))?,
+					},
+					_ => return Err(()),
+				},
+			)),
+			_ => Err(()),
 		}
 	}
 }
@@ -24,6 +39,17 @@ impl TryFrom<api::models::TaskInputInner> for Input {
 			index: u32::try_from(value.input).or(Err(()))?,
 			start: value.start,
 			end: value.end,
+			video_stream: value
+				.video_stream
+				.map(u32::try_from)
+				.transpose()
+				.or(Err(()))?,
+			audio_stream: value
+				.audio_stream
+				.map(u32::try_from)
+				.transpose()
+				.or(Err(()))?,
+			accurate_seek: value.accurate_seek.unwrap_or_default(),
 		})
 	}
 }
@@ -34,6 +60,13 @@ impl From<Input> for api::models::TaskInputInner {
 			input: value.index.try_into().unwrap_or(i32::MAX),
 			start: value.start,
 			end: value.end,
+			video_stream: value
+				.video_stream
+				.map(|idx| idx.try_into().unwrap_or(i32::MAX)),
+			audio_stream: value
+				.audio_stream
+				.map(|idx| idx.try_into().unwrap_or(i32::MAX)),
+			accurate_seek: Some(value.accurate_seek),
 		}
 	}
 }
@@ -48,21 +81,100 @@ impl TryFrom<api::models::Task> for Instance {
 		let inputs = inputs?;
 		let recipe = Recipe::try_from(value.recipe.as_ref())?;
 		let job_options = value.job_options.as_ref().clone().into();
+		let output = value
+			.output
+			.map(|output| (*output).try_into())
+			.transpose()?;
+		let resources = value
+			.resources
+			.map(|resources| (*resources).try_into())
+			.transpose()?;
 		Ok(Instance {
 			job_id,
 			task_id,
 			inputs,
 			recipe,
+			output: output.unwrap_or_default(),
+			resources: resources.unwrap_or_default(),
 			job_options,
 		})
 	}
 }
 
+impl TryFrom<api::models::TaskOutput> for Output {
+	type Error = ();
+
+	fn try_from(value: api::models::TaskOutput) -> Result<Self, Self::Error> {
+		let container = value
+			.container
+			.map(|container| Container::from_extension(&container).ok_or(()))
+			.transpose()?;
+		Ok(Output {
+			container,
+			suffix: value.suffix,
+			rendition: value.rendition.map(u32::try_from).transpose().or(Err(()))?,
+		})
+	}
+}
+
+impl From<Output> for api::models::TaskOutput {
+	fn from(value: Output) -> Self {
+		Self {
+			container: value
+				.container
+				.map(|container| container.extension().to_string()),
+			suffix: value.suffix,
+			rendition: value
+				.rendition
+				.map(|pos| pos.try_into().unwrap_or(i32::MAX)),
+		}
+	}
+}
+
+impl TryFrom<api::models::TaskResources> for Resources {
+	type Error = ();
+
+	fn try_from(value: api::models::TaskResources) -> Result<Self, Self::Error> {
+		Ok(Resources {
+			cpus: value.cpus,
+			memory: value.memory.map(u64::try_from).transpose().or(Err(()))?,
+			gpus: value.gpus.map(u32::try_from).transpose().or(Err(()))?,
+		})
+	}
+}
+
+impl From<Resources> for api::models::TaskResources {
+	fn from(value: Resources) -> Self {
+		Self {
+			cpus: value.cpus,
+			memory: value
+				.memory
+				.map(|memory| memory.try_into().unwrap_or(i64::MAX)),
+			gpus: value.gpus.map(|gpus| gpus.try_into().unwrap_or(i32::MAX)),
+		}
+	}
+}
+
 impl From<api::models::JobOptions> for JobOptions {
 	fn from(value: api::models::JobOptions) -> Self {
 		JobOptions {
 			video: value.video.as_ref().clone().into(),
 			audio: value.audio.map(|v| v.as_ref().clone().into()),
+			two_pass: value.two_pass.unwrap_or_default(),
+			container: value
+				.container
+				.as_deref()
+				.and_then(Container::from_extension)
+				.unwrap_or_default(),
+			preset: None,
+			input_params: value.input_params.unwrap_or_default(),
+			copy_subtitles: value.copy_subtitles.unwrap_or_default(),
+			renditions: value
+				.renditions
+				.unwrap_or_default()
+				.into_iter()
+				.map(Rendition::from)
+				.collect(),
 		}
 	}
 }
@@ -72,6 +184,35 @@ impl From<JobOptions> for api::models::JobOptions {
 		Self {
 			video: Box::new(value.video.into()),
 			audio: value.audio.map(|v| Box::new(v.clone().into())),
+			two_pass: Some(value.two_pass),
+			container: Some(value.container.extension().to_string()),
+			input_params: Some(value.input_params),
+			copy_subtitles: Some(value.copy_subtitles),
+			renditions: Some(
+				value
+					.renditions
+					.into_iter()
+					.map(api::models::Rendition::from)
+					.collect(),
+			),
+		}
+	}
+}
+
+impl From<api::models::Rendition> for Rendition {
+	fn from(value: api::models::Rendition) -> Self {
+		Self {
+			name: value.name,
+			params: value.params.unwrap_or_default(),
+		}
+	}
+}
+
+impl From<Rendition> for api::models::Rendition {
+	fn from(value: Rendition) -> Self {
+		Self {
+			name: value.name,
+			params: Some(value.params),
 		}
 	}
 }
@@ -97,15 +238,23 @@ impl From<Options> for CodecParams {
 impl From<Recipe> for api::models::Recipe {
 	fn from(value: Recipe) -> Self {
 		match value {
-			Recipe::Analysis(val) => api::models::Recipe {
-				analysis: Some(Box::new(AnalysisTask { duration: val })),
+			Recipe::Analysis(options) => api::models::Recipe {
+				analysis: Some(Box::new(AnalysisTask {
+					duration: options.duration,
+					scene_threshold: options.scene_threshold,
+					max_segments: options
+						.max_segments
+						.map(|max| i32::try_from(max).unwrap_or(i32::MAX)),
+				})),
 				transcode: None,
 				merge: None,
+				thumbnail: None,
 			},
 			Recipe::Transcode(options) => api::models::Recipe {
 				analysis: None,
 				transcode: Some(Box::new(TranscodeTask { options })),
 				merge: None,
+				thumbnail: None,
 			},
 			Recipe::Merge(val) => api::models::Recipe {
 				analysis: None,
@@ -119,7 +268,24 @@ impl From<Recipe> for api::models::Recipe {
 					}
 					.into(),
 				),
+				thumbnail: None,
 			},
+			Recipe::Thumbnail(thumbnail) => {
+				let (columns, rows) = match thumbnail {
+					Thumbnail::Poster => (None, None),
+					Thumbnail::Sprite { columns, rows } => (Some(columns), Some(rows)),
+				};
+				let to_i32 = |val: Option<u32>| val.map(|val| val.try_into().unwrap_or(i32::MAX));
+				api::models::Recipe {
+					analysis: None,
+					transcode: None,
+					merge: None,
+					thumbnail: Some(Box::new(ThumbnailTask {
+						sprite_columns: to_i32(columns),
+						sprite_rows: to_i32(rows),
+					})),
+				}
+			}
 		}
 	}
 }
@@ -140,6 +306,8 @@ impl From<Instance> for api::models::Task {
 			task_id,
 			input,
 			recipe,
+			output: Some(Box::new(value.output.into())),
+			resources: Some(Box::new(value.resources.into())),
 			job_options,
 		}
 	}
@@ -149,7 +317,8 @@ impl From<api::models::TaskStatus> for Status {
 	fn from(value: api::models::TaskStatus) -> Self {
 		match value.successfully_completed {
 			Some(true) => Status::Finished,
-			_ => Status::Running,
+			Some(false) => Status::Failed(value.reason),
+			None => Status::Running,
 		}
 	}
 }
@@ -157,12 +326,14 @@ impl From<api::models::TaskStatus> for Status {
 impl From<Status> for api::models::TaskStatus {
 	fn from(value: Status) -> Self {
 		use api::models::TaskStatus;
-		let finished = match value {
-			Status::Finished => Some(true),
-			Status::Running => None,
+		let (finished, reason) = match value {
+			Status::Finished => (Some(true), None),
+			Status::Running => (None, None),
+			Status::Failed(reason) => (Some(false), reason),
 		};
 		TaskStatus {
 			successfully_completed: finished,
+			reason,
 		}
 	}
 }
@@ -182,6 +353,166 @@ impl TryFrom<api::models::TaskRequest> for TaskSource {
 		let inputs: Result<Vec<Input>, _> =
 			value.inputs.into_iter().map(|v| v.try_into()).collect();
 		let inputs = inputs?;
-		Ok(TaskSource { inputs, recipe })
+		let output = value
+			.output
+			.map(|output| (*output).try_into())
+			.transpose()?;
+		let resources = value
+			.resources
+			.map(|resources| (*resources).try_into())
+			.transpose()?;
+		Ok(TaskSource {
+			inputs,
+			recipe,
+			output: output.unwrap_or_default(),
+			resources: resources.unwrap_or_default(),
+		})
+	}
+}
+
+impl TryFrom<TaskSource> for api::models::TaskRequest {
+	type Error = ();
+	fn try_from(value: TaskSource) -> Result<Self, Self::Error> {
+		let recipe = match value.recipe {
+			Recipe::Analysis(_) | Recipe::Thumbnail(_) => return Err(()),
+			Recipe::Transcode(options) => {
+				TaskRequestRecipe::TranscodeTask(Box::new(TranscodeTask { options }))
+			}
+			Recipe::Merge(concatenate) => {
+				TaskRequestRecipe::MergeTask(Box::new(api::models::MergeTask {
+					concatenate: concatenate
+						.into_iter()
+						.map(|v| v.try_into().unwrap_or(i32::MAX))
+						.collect(),
+				}))
+			}
+		};
+		Ok(api::models::TaskRequest {
+			inputs: value.inputs.into_iter().map(Into::into).collect(),
+			recipe: Box::new(recipe),
+			output: Some(Box::new(value.output.into())),
+			resources: Some(Box::new(value.resources.into())),
+		})
+	}
+}
+
+impl From<JobLogEntry> for api::models::JobLogEntry {
+	fn from(value: JobLogEntry) -> Self {
+		let at = value
+			.at
+			.duration_since(SystemTime::UNIX_EPOCH)
+			.unwrap_or_default()
+			.as_secs_f64();
+		let mut entry = api::models::JobLogEntry::new(at, String::new());
+		entry.task_idx = value.task_idx.map(|idx| idx.try_into().unwrap_or(i32::MAX));
+		entry.event = match value.event {
+			JobLogEvent::Created => "created",
+			JobLogEvent::TaskAdded => "task_added",
+			JobLogEvent::Allocated { worker } => {
+				entry.worker = Some(worker);
+				"allocated"
+			}
+			JobLogEvent::Progress { progress } => {
+				entry.progress = Some(progress);
+				"progress"
+			}
+			JobLogEvent::Finished => "finished",
+			JobLogEvent::Failed { reason } => {
+				entry.reason = reason;
+				"failed"
+			}
+			JobLogEvent::Cancelled => "cancelled",
+		}
+		.to_string();
+		entry
+	}
+}
+
+impl From<Allocation> for api::models::Allocation {
+	fn from(value: Allocation) -> Self {
+		let age = value
+			.allocated_at
+			.elapsed()
+			.unwrap_or_default()
+			.as_secs_f64();
+		api::models::Allocation {
+			job_id: value.job_id.to_string(),
+			task_id: value.task_id.to_string(),
+			task_idx: value.task_idx.try_into().unwrap_or(i32::MAX),
+			worker: value.worker,
+			age,
+			progress: value.progress,
+			speed: value.speed,
+			failure: value.failure,
+		}
+	}
+}
+
+impl TryFrom<api::models::MediaStream> for StreamProbe {
+	type Error = ();
+
+	fn try_from(value: api::models::MediaStream) -> Result<Self, Self::Error> {
+		Ok(StreamProbe {
+			index: value.index.try_into().or(Err(()))?,
+			codec_type: value.codec_type,
+			codec_name: value.codec_name,
+			bit_rate: value.bit_rate.map(u64::try_from).transpose().or(Err(()))?,
+			width: value.width.map(u32::try_from).transpose().or(Err(()))?,
+			height: value.height.map(u32::try_from).transpose().or(Err(()))?,
+			sample_rate: value
+				.sample_rate
+				.map(u32::try_from)
+				.transpose()
+				.or(Err(()))?,
+			channels: value.channels.map(u32::try_from).transpose().or(Err(()))?,
+		})
+	}
+}
+
+impl From<StreamProbe> for api::models::MediaStream {
+	fn from(value: StreamProbe) -> Self {
+		let int = |value: u32| value.try_into().unwrap_or(i32::MAX);
+		Self {
+			index: int(value.index),
+			codec_type: value.codec_type,
+			codec_name: value.codec_name,
+			bit_rate: value
+				.bit_rate
+				.map(|bit_rate| bit_rate.try_into().unwrap_or(i64::MAX)),
+			width: value.width.map(int),
+			height: value.height.map(int),
+			sample_rate: value.sample_rate.map(int),
+			channels: value.channels.map(int),
+		}
+	}
+}
+
+impl TryFrom<api::models::MediaProbe> for MediaProbe {
+	type Error = ();
+
+	fn try_from(value: api::models::MediaProbe) -> Result<Self, Self::Error> {
+		Ok(MediaProbe {
+			duration: value.duration,
+			bit_rate: value.bit_rate.map(u64::try_from).transpose().or(Err(()))?,
+			format_name: value.format_name,
+			streams: value
+				.streams
+				.into_iter()
+				.map(StreamProbe::try_from)
+				.collect::<Result<_, _>>()?,
+		})
+	}
+}
+
+impl From<MediaProbe> for api::models::MediaProbe {
+	fn from(value: MediaProbe) -> Self {
+		Self {
+			duration: value.duration,
+			bit_rate: value
+				.bit_rate
+				.map(|bit_rate| bit_rate.try_into().unwrap_or(i64::MAX)),
+			format_name: value.format_name,
+			streams: value.streams.into_iter().map(Into::into).collect(),
+		}
 	}
 }
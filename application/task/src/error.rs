@@ -0,0 +1,101 @@
+use std::fmt::{Display, Formatter};
+
+///Errors returned by the job manager and its database
+#[derive(Debug)]
+pub enum TaskError {
+	JobNotFound,
+	TaskNotFound,
+	///The job has no rendition with the requested name
+	RenditionNotFound,
+	///The task has no input at the requested index
+	InputNotFound,
+	///A task depends on itself, or on a task added after it
+	DependencyCycle,
+	///The task source breaks the recipe invariants
+	InvalidTask(InvalidTask),
+	///The job references a preset that does not exist
+	UnknownPreset(String),
+	///The job is chained to a job without output, or its input is not that output
+	JobNotFinished,
+	///The task failed, its allocation is kept with the failure and not released
+	TaskFailed,
+	///The operation is not supported by the manager
+	Unsupported,
+	///The persistent job storage failed
+	StorageUnavailable(std::io::Error),
+}
+
+impl Display for TaskError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match self {
+			TaskError::JobNotFound => f.write_str("Job not found"),
+			TaskError::TaskNotFound => f.write_str("Task not found"),
+			TaskError::RenditionNotFound => f.write_str("Rendition not found"),
+			TaskError::InputNotFound => f.write_str("Input not found"),
+			TaskError::DependencyCycle => f.write_str("Dependency must be a previous task"),
+			TaskError::InvalidTask(err) => write!(f, "Invalid task: {err}"),
+			TaskError::UnknownPreset(name) => write!(f, "Unknown preset: {name}"),
+			TaskError::JobNotFinished => f.write_str("Source job is not finished"),
+			TaskError::TaskFailed => f.write_str("Task failed"),
+			TaskError::Unsupported => f.write_str("Not implemented"),
+			TaskError::StorageUnavailable(err) => write!(f, "Storage unavailable: {err}"),
+		}
+	}
+}
+
+impl std::error::Error for TaskError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			TaskError::StorageUnavailable(err) => Some(err),
+			_ => None,
+		}
+	}
+}
+
+impl From<InvalidTask> for TaskError {
+	fn from(value: InvalidTask) -> Self {
+		TaskError::InvalidTask(value)
+	}
+}
+
+impl From<std::io::Error> for TaskError {
+	fn from(value: std::io::Error) -> Self {
+		TaskError::StorageUnavailable(value)
+	}
+}
+
+///Why a task source was rejected, positions are indexes in the task inputs
+#[derive(Debug, PartialEq)]
+pub enum InvalidTask {
+	///The input start is not before its end, or is negative
+	InvalidRange(usize),
+	///The merge must list every input exactly once, except the job source following them
+	MergeLength { inputs: usize, merged: usize },
+	///The merge lists an input position that does not exist, or lists it twice
+	MergePosition(u32),
+	///The input seeks accurately, which is only possible in a task with a single input
+	AccurateSeek(usize),
+}
+
+impl Display for InvalidTask {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match self {
+			InvalidTask::InvalidRange(pos) => write!(f, "input {pos} has an invalid range"),
+			InvalidTask::MergeLength { inputs, merged } => {
+				write!(f, "the merge lists {merged} of {inputs} inputs")
+			}
+			InvalidTask::MergePosition(pos) => {
+				write!(
+					f,
+					"the merge lists input {pos} more than once, or it does not exist"
+				)
+			}
+			InvalidTask::AccurateSeek(pos) => {
+				write!(
+					f,
+					"input {pos} seeks accurately, but the task has other inputs"
+				)
+			}
+		}
+	}
+}
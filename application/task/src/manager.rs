@@ -1,85 +1,157 @@
-use std::io::{Error, ErrorKind};
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Instant, SystemTime};
 
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+use tracing::{info, instrument};
 use uuid::Uuid;
 
 use crate::manager::db::local::LocalJobDb;
-use crate::{Instance, JobSource, Status, TaskSource};
+use crate::{
+	preset, segment, Allocation, Input, Instance, JobLogEntry, JobLogEvent, JobSource, MediaProbe,
+	Output, Recipe, Resources, Status, TaskError, TaskSource,
+};
+
+pub use scheduler::SchedulerStats;
 
 mod db;
+mod job_log;
+mod scheduler;
+
+///Failure recorded for the tasks that failed without a reason
+const UNKNOWN_FAILURE: &str = "Task failed";
 
 ///Interface used by the server to manage jobs and tasks
 pub trait Manager: Sync {
 	fn create_job(
 		&self,
 		job: JobSource,
-	) -> impl std::future::Future<Output = Result<Uuid, Error>> + Send;
+	) -> impl std::future::Future<Output = Result<Uuid, TaskError>> + Send;
 	fn get_job(
 		&self,
 		job_id: &Uuid,
-	) -> impl std::future::Future<Output = Result<Option<JobSource>, Error>> + Send;
-	fn get_job_list(&self) -> impl std::future::Future<Output = Result<Vec<Uuid>, Error>> + Send;
+	) -> impl std::future::Future<Output = Result<Option<JobSource>, TaskError>> + Send;
+	fn get_job_list(
+		&self,
+	) -> impl std::future::Future<Output = Result<Vec<Uuid>, TaskError>> + Send;
+	///Allocate a task to the worker, identified by an opaque id that is never its token
 	fn allocate_task(
 		&self,
-	) -> impl std::future::Future<Output = Result<Option<Instance>, Error>> + Send;
+		worker: &str,
+	) -> impl std::future::Future<Output = Result<Option<Instance>, TaskError>> + Send;
+	///Allocate a task whose resource hints fit the free capacity of the worker
+	fn allocate_task_within(
+		&self,
+		worker: &str,
+		capacity: &Resources,
+	) -> impl std::future::Future<Output = Result<Option<Instance>, TaskError>> + Send {
+		let _ = capacity;
+		self.allocate_task(worker)
+	}
+	///List the tasks allocated to workers that were not finished yet
+	fn get_allocated_tasks(
+		&self,
+	) -> impl std::future::Future<Output = Result<Vec<Allocation>, TaskError>> + Send;
+	///Worker holding the allocation of the task, as given to [Manager::allocate_task]
+	fn get_task_worker(
+		&self,
+		job_id: &Uuid,
+		task_id: &Uuid,
+	) -> impl std::future::Future<Output = Result<Option<String>, TaskError>> + Send;
 	fn add_task_to_job(
 		&self,
 		job_id: &Uuid,
 		task: TaskSource,
-	) -> impl std::future::Future<Output = Result<u32, Error>> + Send;
+	) -> impl std::future::Future<Output = Result<u32, TaskError>> + Send;
 	fn get_task_source(
 		&self,
 		job_id: &Uuid,
 		task: u32,
-	) -> impl std::future::Future<Output = Result<Option<TaskSource>, Error>> + Send;
+	) -> impl std::future::Future<Output = Result<Option<TaskSource>, TaskError>> + Send;
 	fn get_task(
 		&self,
 		job_id: &Uuid,
 		task_id: &Uuid,
-	) -> impl std::future::Future<Output = Result<Option<Instance>, Error>> + Send;
+	) -> impl std::future::Future<Output = Result<Option<Instance>, TaskError>> + Send;
 	fn update_task_status(
 		&self,
 		job_id: &Uuid,
 		task_id: &Uuid,
 		status: Status,
-	) -> impl std::future::Future<Output = Result<Option<()>, Error>> + Send;
+	) -> impl std::future::Future<Output = Result<Option<()>, TaskError>> + Send;
+	///Record the progress reported by the worker, in encoded seconds and speed relative to realtime
+	fn update_task_progress(
+		&self,
+		job_id: &Uuid,
+		task_id: &Uuid,
+		progress: f64,
+		speed: Option<f64>,
+	) -> impl std::future::Future<Output = Result<Option<()>, TaskError>> + Send;
+	///Record the metadata reported by the worker, of the task output or of the analysed job source
+	fn set_task_probe(
+		&self,
+		job_id: &Uuid,
+		task_id: &Uuid,
+		probe: MediaProbe,
+	) -> impl std::future::Future<Output = Result<Option<()>, TaskError>> + Send;
+	fn get_task_probe(
+		&self,
+		job_id: &Uuid,
+		task_idx: u32,
+	) -> impl std::future::Future<Output = Result<Option<MediaProbe>, TaskError>> + Send;
 	fn set_task_output(
 		&self,
 		job_id: &Uuid,
 		task_id: &Uuid,
 		output: Uuid,
-	) -> impl std::future::Future<Output = Result<Option<()>, Error>> + Send;
+	) -> impl std::future::Future<Output = Result<Option<()>, TaskError>> + Send;
 	fn get_task_output(
 		&self,
 		job_id: &Uuid,
 		task_idx: u32,
-	) -> impl std::future::Future<Output = Result<Option<Uuid>, Error>> + Send;
+	) -> impl std::future::Future<Output = Result<Option<Uuid>, TaskError>> + Send;
+	///Get the output being uploaded by the worker, not available as the task output yet
+	fn get_task_partial_output(
+		&self,
+		job_id: &Uuid,
+		task_id: &Uuid,
+	) -> impl std::future::Future<Output = Result<Option<Uuid>, TaskError>> + Send;
+	fn set_task_partial_output(
+		&self,
+		job_id: &Uuid,
+		task_id: &Uuid,
+		partial: Option<Uuid>,
+	) -> impl std::future::Future<Output = Result<Option<()>, TaskError>> + Send;
 	fn get_allocated_task_output(
 		&self,
 		job_id: &Uuid,
 		task_id: &Uuid,
-	) -> impl std::future::Future<Output = Result<Option<Uuid>, Error>> + Send;
+	) -> impl std::future::Future<Output = Result<Option<Uuid>, TaskError>> + Send;
+	///Get the stored input, the job input for the source, or the output of the referenced task
 	fn get_task_input(
 		&self,
 		job_id: &Uuid,
 		task_idx: u32,
 		input_idx: u32,
-	) -> impl std::future::Future<Output = Result<Option<Uuid>, Error>> + Send {
+	) -> impl std::future::Future<Output = Result<Option<Uuid>, TaskError>> + Send {
 		async move {
-			let err = || Error::new(ErrorKind::NotFound, "Input out of bounds");
-			let task = match self.get_task_source(&job_id, task_idx).await? {
+			let task = match self.get_task_source(job_id, task_idx).await? {
 				Some(task) => task,
 				None => {
 					return Ok(None);
 				}
 			};
-			let _input = task.inputs.get(input_idx as usize).ok_or_else(err)?;
+			let input = task
+				.inputs
+				.get(input_idx as usize)
+				.ok_or(TaskError::InputNotFound)?;
+			if input.index != 0 {
+				return self.get_task_output(job_id, input.index).await;
+			}
 			let job_input = self
-				.get_job(&job_id)
+				.get_job(job_id)
 				.await?
-				.ok_or(Error::new(
-					ErrorKind::NotFound,
-					"Job deleted during operation",
-				))?
+				.ok_or(TaskError::JobNotFound)?
 				.input_id;
 			Ok(Some(job_input))
 		}
@@ -89,28 +161,70 @@ pub trait Manager: Sync {
 		job_id: &Uuid,
 		task_id: &Uuid,
 		input_idx: u32,
-	) -> impl std::future::Future<Output = Result<Option<Uuid>, Error>> + Send;
-	///Get the uuid of the stored output
+	) -> impl std::future::Future<Output = Result<Option<Uuid>, TaskError>> + Send;
+	///Get the uuid of the stored output, or of the output of the named rendition
+	///
+	///The job output is only available once every rendition is finished
 	fn get_job_output(
 		&self,
 		job_id: &Uuid,
-	) -> impl std::future::Future<Output = Result<Option<Uuid>, Error>> + Send;
+		rendition: Option<String>,
+	) -> impl std::future::Future<Output = Result<Option<Uuid>, TaskError>> + Send;
+	///Reason the task failed, kept with its state while the job exists
+	fn get_task_failure(
+		&self,
+		job_id: &Uuid,
+		task_idx: u32,
+	) -> impl std::future::Future<Output = Result<Option<String>, TaskError>> + Send;
+	///Number of tasks of the job that did not finish, the failed ones included
+	fn get_unfinished_tasks(
+		&self,
+		job_id: &Uuid,
+	) -> impl std::future::Future<Output = Result<Option<u32>, TaskError>> + Send;
+	///Reason of the first failed task of the job, a failed job never finishes
+	///
+	///Thumbnails are not part of the job output, so their failures are not the job failure
+	fn get_job_failure(
+		&self,
+		job_id: &Uuid,
+	) -> impl std::future::Future<Output = Result<Option<String>, TaskError>> + Send;
 	///Cancel this task execution, will be available for allocation
+	///
+	///The reported progress and probe are kept, a failed task is not released
 	fn cancel_task(
 		&self,
 		job_id: &Uuid,
 		task_id: &Uuid,
-	) -> impl std::future::Future<Output = Result<Option<()>, Error>> + Send;
+	) -> impl std::future::Future<Output = Result<Option<()>, TaskError>> + Send;
+	///Resolves when a task may have become available for allocation
+	fn task_available(&self) -> impl std::future::Future<Output = ()> + Send;
 	///Delete the job removing all tasks, completed or pending
 	fn delete_job(
 		&self,
 		job_id: &Uuid,
-	) -> impl std::future::Future<Output = Result<Option<()>, Error>> + Send;
+	) -> impl std::future::Future<Output = Result<Option<()>, TaskError>> + Send;
+	///Counters of the task allocations and queue
+	fn scheduler_stats(&self) -> SchedulerStats {
+		SchedulerStats::default()
+	}
+	///Transitions of the job and its tasks, oldest first
+	fn get_job_log(&self, _job_id: &Uuid) -> Vec<JobLogEntry> {
+		Vec::new()
+	}
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub struct TaskState {
 	output: Option<Uuid>,
+	///Not journaled, the allocations are cancelled after a restart
+	#[serde(skip)]
+	worker: Option<String>,
+	allocated_at: Option<SystemTime>,
+	progress: Option<f64>,
+	speed: Option<f64>,
+	partial_output: Option<Uuid>,
+	failure: Option<String>,
+	probe: Option<MediaProbe>,
 }
 
 pub type LocalJobManager = JobManager<LocalJobDb<JobSource, TaskSource, TaskState>>;
@@ -119,58 +233,253 @@ impl Default for LocalJobManager {
 	fn default() -> Self {
 		LocalJobManager {
 			db: Default::default(),
+			available: Default::default(),
+			scheduler: Default::default(),
+			log: Default::default(),
+		}
+	}
+}
+
+impl LocalJobManager {
+	///Restore the jobs recorded on the journal file, and record the new changes to it
+	///
+	///Tasks that were allocated when the journal was last written are returned to the queue. The
+	///job log is kept next to the journal, with a `.log` suffix
+	pub async fn with_journal(path: &std::path::Path) -> Result<Self, TaskError> {
+		let (journal, mutations) = db::journal::FileJournal::open(path)?;
+		let db = LocalJobDb::replay(mutations, Box::new(journal))?;
+		let mut log_path = path.as_os_str().to_owned();
+		log_path.push(".log");
+		let manager = LocalJobManager {
+			db,
+			available: Default::default(),
+			scheduler: Default::default(),
+			log: job_log::JobLog::restore(log_path.as_ref())?,
+		};
+		for (job_id, idx, dependencies) in manager.db.queued_tasks() {
+			manager.scheduler.added(job_id, idx, &dependencies);
+		}
+		let requeued = manager.requeue_allocations().await?;
+		info!(path = %path.display(), requeued, "Jobs restored from the journal");
+		Ok(manager)
+	}
+}
+
+impl<DB: db::JobDb<JobSource, TaskSource, TaskState> + Sync> JobManager<DB> {
+	///Cancel every allocation, as the workers holding them are unknown after a restart
+	///
+	///Failed tasks keep their allocation and failure, they are not run again
+	async fn requeue_allocations(&self) -> Result<usize, TaskError> {
+		let mut requeued = 0;
+		for (job_id, task_id, _) in self.db.list_allocated().await? {
+			match self.cancel_task(&job_id, &task_id).await {
+				Ok(_) => requeued += 1,
+				Err(TaskError::TaskFailed) => {}
+				Err(err) => return Err(err),
+			}
+		}
+		Ok(requeued)
+	}
+
+	///Append a merge of the segments added by the analysis, for each rendition split in more than one
+	///
+	///The merge is added by the server, so it does not depend on the worker that ran the analysis.
+	///Segments already merged, by a worker that adds the merge itself, are left as they are
+	async fn merge_segments(&self, job_id: &Uuid, analysis_idx: u32) -> Result<(), TaskError> {
+		let job = self
+			.db
+			.get_job(job_id)
+			.await?
+			.ok_or(TaskError::JobNotFound)?;
+		let tasks = self
+			.db
+			.get_tasks(job_id)
+			.await?
+			.ok_or(TaskError::JobNotFound)?;
+		let merged: BTreeSet<u32> = tasks
+			.iter()
+			.filter(|task| matches!(task.recipe, Recipe::Merge(_)))
+			.flat_map(|task| task.inputs.iter().map(|input| input.index))
+			.collect();
+		//The subtitles are copied from the clip of the source that the analysis split
+		let clip = tasks
+			.get(analysis_idx as usize)
+			.and_then(|analysis| analysis.inputs.first());
+		let source = job.options.copy_subtitles.then(|| Input {
+			start: clip.and_then(|clip| clip.start),
+			end: clip.and_then(|clip| clip.end),
+			..Input::source()
+		});
+		let mut segments: BTreeMap<Option<u32>, Vec<u32>> = BTreeMap::new();
+		for (idx, task) in tasks.iter().enumerate().skip(analysis_idx as usize + 1) {
+			let idx = idx as u32;
+			if matches!(task.recipe, Recipe::Transcode(_)) && !merged.contains(&idx) {
+				segments.entry(task.output.rendition).or_default().push(idx);
+			}
+		}
+		for (rendition, transcodes) in segments {
+			if transcodes.len() < 2 {
+				continue;
+			}
+			let suffix = rendition
+				.and_then(|pos| job.options.renditions.get(pos as usize))
+				.map(|rendition| format!("-{}", rendition.name));
+			let mut merge = segment::merge(&transcodes, source.clone());
+			merge.output = Output {
+				suffix,
+				rendition,
+				..Default::default()
+			};
+			self.add_task_to_job(job_id, merge).await?;
 		}
+		Ok(())
 	}
 }
 
 pub struct JobManager<DB: db::JobDb<JobSource, TaskSource, TaskState>> {
 	db: DB,
+	///Woken when a job or task is created, a task is fulfilled or an allocation is cancelled
+	available: Notify,
+	scheduler: scheduler::Scheduler,
+	log: job_log::JobLog,
 }
 
 impl<DB: db::JobDb<JobSource, TaskSource, TaskState> + Sync> Manager for JobManager<DB> {
-	async fn create_job(&self, job: JobSource) -> Result<Uuid, Error> {
-		self.db.create_job(job).await
+	#[instrument(skip_all)]
+	async fn create_job(&self, job: JobSource) -> Result<Uuid, TaskError> {
+		if let Some(source_job) = &job.source_job {
+			let output = self.get_job_output(source_job, None).await?;
+			if output != Some(job.input_id) {
+				return Err(TaskError::JobNotFinished);
+			}
+		}
+		let job = JobSource {
+			options: preset::expand(job.options)?,
+			..job
+		};
+		let id = self.db.create_job(job).await?;
+		info!(job_id = %id, "Job created");
+		self.log.record(id, None, JobLogEvent::Created);
+		self.available.notify_waiters();
+		Ok(id)
 	}
 
-	async fn get_job(&self, job_id: &Uuid) -> Result<Option<JobSource>, Error> {
+	async fn get_job(&self, job_id: &Uuid) -> Result<Option<JobSource>, TaskError> {
 		self.db.get_job(job_id).await
 	}
 
-	async fn get_job_list(&self) -> Result<Vec<Uuid>, Error> {
+	async fn get_job_list(&self) -> Result<Vec<Uuid>, TaskError> {
 		self.db.list_job_ids().await
 	}
 
-	async fn allocate_task(&self) -> Result<Option<Instance>, Error> {
-		match self.db.allocate_task().await? {
-			Some((job_id, task_id)) => match self.db.get_allocated_task(&job_id, &task_id).await? {
-				None => Ok(None),
-				Some(task) => Ok(Some(Instance {
-					job_id,
-					task_id,
-					inputs: task.task.inputs,
-					recipe: task.task.recipe,
-					job_options: task.job.options,
-				})),
-			},
-			None => Ok(None),
+	async fn allocate_task(&self, worker: &str) -> Result<Option<Instance>, TaskError> {
+		self.allocate_task_within(worker, &Resources::default())
+			.await
+	}
+
+	#[instrument(skip(self, worker))]
+	async fn allocate_task_within(
+		&self,
+		worker: &str,
+		capacity: &Resources,
+	) -> Result<Option<Instance>, TaskError> {
+		let start = Instant::now();
+		let capacity = capacity.clone();
+		let fits = move |task: &TaskSource| task.resources.fits(&capacity);
+		let order = |a: &JobSource, b: &JobSource| {
+			(a.priority, a.created_at).cmp(&(b.priority, b.created_at))
+		};
+		let (job_id, task_id) = match self.db.allocate_task_matching(&fits, &order).await? {
+			Some(allocated) => allocated,
+			None => return Ok(None),
+		};
+		let task = match self.db.get_allocated_task(&job_id, &task_id).await? {
+			Some(task) => task,
+			None => return Ok(None),
+		};
+		let state = TaskState {
+			worker: Some(worker.to_string()),
+			allocated_at: Some(SystemTime::now()),
+			..Default::default()
+		};
+		self.db.set_task_status(&job_id, task.idx, state).await?;
+		self.scheduler.allocated(job_id, task.idx, start.elapsed());
+		info!(%job_id, %task_id, "Task allocated");
+		let worker = worker.to_string();
+		self.log
+			.record(job_id, Some(task.idx), JobLogEvent::Allocated { worker });
+		Ok(Some(Instance {
+			job_id,
+			task_id,
+			inputs: task.task.inputs,
+			recipe: task.task.recipe,
+			output: task.task.output,
+			resources: task.task.resources,
+			job_options: task.job.options,
+		}))
+	}
+
+	async fn get_allocated_tasks(&self) -> Result<Vec<Allocation>, TaskError> {
+		let mut allocations = Vec::new();
+		for (job_id, task_id, task_idx) in self.db.list_allocated().await? {
+			let state = self
+				.db
+				.get_task_status(&job_id, task_idx)
+				.await?
+				.unwrap_or_default();
+			allocations.push(Allocation {
+				job_id,
+				task_id,
+				task_idx,
+				worker: state.worker.unwrap_or_default(),
+				allocated_at: state.allocated_at.unwrap_or(SystemTime::UNIX_EPOCH),
+				progress: state.progress,
+				speed: state.speed,
+				failure: state.failure,
+			});
 		}
+		Ok(allocations)
+	}
+
+	async fn get_task_worker(
+		&self,
+		job_id: &Uuid,
+		task_id: &Uuid,
+	) -> Result<Option<String>, TaskError> {
+		let idx = match self.db.get_allocated_task(job_id, task_id).await? {
+			Some(allocated) => allocated.idx,
+			None => return Ok(None),
+		};
+		let state = self.db.get_task_status(job_id, idx).await?;
+		Ok(state.and_then(|state| state.worker))
 	}
 
-	async fn add_task_to_job(&self, job_id: &Uuid, task: TaskSource) -> Result<u32, Error> {
+	#[instrument(skip_all, fields(%job_id))]
+	async fn add_task_to_job(&self, job_id: &Uuid, task: TaskSource) -> Result<u32, TaskError> {
+		task.validate()?;
 		let deps: Vec<_> = task
 			.inputs
 			.iter()
 			.map(|input| input.index)
 			.filter(|zero| *zero != 0)
 			.collect();
-		self.db.append_task(job_id, task, deps.as_slice()).await
+		let idx = self.db.append_task(job_id, task, deps.as_slice()).await?;
+		self.scheduler.added(*job_id, idx, &deps);
+		info!(idx, ?deps, "Task added");
+		self.log.record(*job_id, Some(idx), JobLogEvent::TaskAdded);
+		self.available.notify_waiters();
+		Ok(idx)
 	}
 
-	async fn get_task_source(&self, job_id: &Uuid, task: u32) -> Result<Option<TaskSource>, Error> {
+	async fn get_task_source(
+		&self,
+		job_id: &Uuid,
+		task: u32,
+	) -> Result<Option<TaskSource>, TaskError> {
 		self.db.get_task(job_id, task).await
 	}
 
-	async fn get_task(&self, job_id: &Uuid, task_id: &Uuid) -> Result<Option<Instance>, Error> {
+	async fn get_task(&self, job_id: &Uuid, task_id: &Uuid) -> Result<Option<Instance>, TaskError> {
 		self.db
 			.get_allocated_task(job_id, task_id)
 			.await
@@ -180,56 +489,185 @@ impl<DB: db::JobDb<JobSource, TaskSource, TaskState> + Sync> Manager for JobMana
 					task_id: *task_id,
 					inputs: allocated.task.inputs,
 					recipe: allocated.task.recipe,
+					output: allocated.task.output,
+					resources: allocated.task.resources,
 					job_options: allocated.job.options,
 				})
 			})
 	}
 
+	#[instrument(skip(self, status), fields(%job_id, %task_id))]
 	async fn update_task_status(
 		&self,
 		job_id: &Uuid,
 		task_id: &Uuid,
 		status: Status,
-	) -> Result<Option<()>, Error> {
-		if let Status::Finished = status {
-			match self
-				.db
-				.get_allocated_task(job_id, task_id)
-				.await?
-				.map(|allocated| allocated.idx)
-			{
-				Some(idx) => self.db.fulfill(job_id, idx).await.map(|_| Some(())),
-				None => Ok(None),
+	) -> Result<Option<()>, TaskError> {
+		let allocated = match self.db.get_allocated_task(job_id, task_id).await? {
+			Some(allocated) => allocated,
+			None => return Ok(None),
+		};
+		let idx = allocated.idx;
+		match status {
+			Status::Finished => {
+				self.db.fulfill(job_id, idx).await?;
+				self.scheduler.fulfilled(*job_id, idx);
+				self.log.record(*job_id, Some(idx), JobLogEvent::Finished);
+				if matches!(allocated.task.recipe, Recipe::Analysis(_)) {
+					self.merge_segments(job_id, idx).await?;
+				}
+				self.available.notify_waiters();
+				Ok(Some(()))
+			}
+			Status::Failed(failure) => {
+				let state = self
+					.db
+					.get_task_status(job_id, idx)
+					.await?
+					.unwrap_or_default();
+				//The allocation is kept failed, it is not allocated again nor reported twice
+				if state.failure.is_some() {
+					return Ok(Some(()));
+				}
+				let failure = failure.unwrap_or_else(|| UNKNOWN_FAILURE.to_string());
+				let job_failed = self.get_job_failure(job_id).await?.is_some();
+				let reason = Some(failure.clone());
+				self.log.record(
+					*job_id,
+					Some(idx),
+					JobLogEvent::Failed {
+						reason: reason.clone(),
+					},
+				);
+				if !job_failed && !matches!(allocated.task.recipe, Recipe::Thumbnail(_)) {
+					self.log
+						.record(*job_id, None, JobLogEvent::Failed { reason });
+				}
+				let failure = Some(failure);
+				self.db
+					.set_task_status(job_id, idx, TaskState { failure, ..state })
+					.await
 			}
-		} else {
-			Err(Error::new(ErrorKind::Other, "Not implemented"))
+			Status::Running => Err(TaskError::Unsupported),
 		}
 	}
 
+	#[instrument(level = "debug", skip(self), fields(%job_id, %task_id))]
+	async fn update_task_progress(
+		&self,
+		job_id: &Uuid,
+		task_id: &Uuid,
+		progress: f64,
+		speed: Option<f64>,
+	) -> Result<Option<()>, TaskError> {
+		let idx = match self
+			.db
+			.get_allocated_task(job_id, task_id)
+			.await?
+			.map(|allocated| allocated.idx)
+		{
+			Some(idx) => idx,
+			None => return Ok(None),
+		};
+		let state = self
+			.db
+			.get_task_status(job_id, idx)
+			.await?
+			.unwrap_or_default();
+		self.log.progress(*job_id, idx, progress);
+		self.db
+			.set_task_progress(
+				job_id,
+				idx,
+				TaskState {
+					progress: Some(progress),
+					speed,
+					..state
+				},
+			)
+			.await
+	}
+
+	#[instrument(skip(self, probe), fields(%job_id, %task_id))]
+	async fn set_task_probe(
+		&self,
+		job_id: &Uuid,
+		task_id: &Uuid,
+		probe: MediaProbe,
+	) -> Result<Option<()>, TaskError> {
+		let idx = match self
+			.db
+			.get_allocated_task(job_id, task_id)
+			.await?
+			.map(|allocated| allocated.idx)
+		{
+			Some(idx) => idx,
+			None => return Ok(None),
+		};
+		let state = self
+			.db
+			.get_task_status(job_id, idx)
+			.await?
+			.unwrap_or_default();
+		self.db
+			.set_task_status(
+				job_id,
+				idx,
+				TaskState {
+					probe: Some(probe),
+					..state
+				},
+			)
+			.await
+	}
+
+	async fn get_task_probe(
+		&self,
+		job_id: &Uuid,
+		task_idx: u32,
+	) -> Result<Option<MediaProbe>, TaskError> {
+		Ok(self
+			.db
+			.get_task_status(job_id, task_idx)
+			.await?
+			.and_then(|status| status.probe))
+	}
+
+	#[instrument(skip(self), fields(%job_id, %task_id, %output))]
 	async fn set_task_output(
 		&self,
 		job_id: &Uuid,
 		task_id: &Uuid,
 		output: Uuid,
-	) -> Result<Option<()>, Error> {
+	) -> Result<Option<()>, TaskError> {
 		let idx = self
 			.db
 			.get_allocated_task(job_id, task_id)
 			.await?
 			.map(|a| a.idx)
 			.unwrap_or(u32::MAX /*NOT FOUND*/);
+		let state = match self.db.get_task_status(job_id, idx).await {
+			Ok(state) => state.unwrap_or_default(),
+			Err(TaskError::TaskNotFound) => return Ok(None),
+			Err(err) => return Err(err),
+		};
 		self.db
 			.set_task_status(
 				job_id,
 				idx,
 				TaskState {
 					output: Some(output),
+					..state
 				},
 			)
 			.await
 	}
 
-	async fn get_task_output(&self, job_id: &Uuid, task_idx: u32) -> Result<Option<Uuid>, Error> {
+	async fn get_task_output(
+		&self,
+		job_id: &Uuid,
+		task_idx: u32,
+	) -> Result<Option<Uuid>, TaskError> {
 		Ok(self
 			.db
 			.get_task_status(job_id, task_idx)
@@ -237,11 +675,65 @@ impl<DB: db::JobDb<JobSource, TaskSource, TaskState> + Sync> Manager for JobMana
 			.and_then(|status| status.output))
 	}
 
+	async fn get_task_partial_output(
+		&self,
+		job_id: &Uuid,
+		task_id: &Uuid,
+	) -> Result<Option<Uuid>, TaskError> {
+		let idx = match self
+			.db
+			.get_allocated_task(job_id, task_id)
+			.await?
+			.map(|allocated| allocated.idx)
+		{
+			Some(idx) => idx,
+			None => return Ok(None),
+		};
+		Ok(self
+			.db
+			.get_task_status(job_id, idx)
+			.await?
+			.and_then(|status| status.partial_output))
+	}
+
+	#[instrument(level = "debug", skip(self), fields(%job_id, %task_id))]
+	async fn set_task_partial_output(
+		&self,
+		job_id: &Uuid,
+		task_id: &Uuid,
+		partial: Option<Uuid>,
+	) -> Result<Option<()>, TaskError> {
+		let idx = match self
+			.db
+			.get_allocated_task(job_id, task_id)
+			.await?
+			.map(|allocated| allocated.idx)
+		{
+			Some(idx) => idx,
+			None => return Ok(None),
+		};
+		let state = self
+			.db
+			.get_task_status(job_id, idx)
+			.await?
+			.unwrap_or_default();
+		self.db
+			.set_task_status(
+				job_id,
+				idx,
+				TaskState {
+					partial_output: partial,
+					..state
+				},
+			)
+			.await
+	}
+
 	async fn get_allocated_task_output(
 		&self,
 		job_id: &Uuid,
 		task_id: &Uuid,
-	) -> Result<Option<Uuid>, Error> {
+	) -> Result<Option<Uuid>, TaskError> {
 		let idx = self
 			.db
 			.get_allocated_task(job_id, task_id)
@@ -256,40 +748,151 @@ impl<DB: db::JobDb<JobSource, TaskSource, TaskState> + Sync> Manager for JobMana
 		job_id: &Uuid,
 		task_id: &Uuid,
 		input_idx: u32,
-	) -> Result<Option<Uuid>, Error> {
-		let task = self.db.get_allocated_task(&job_id, &task_id).await?;
+	) -> Result<Option<Uuid>, TaskError> {
+		let task = self.db.get_allocated_task(job_id, task_id).await?;
 		Ok(match task {
 			None => None,
 			Some(allocated) => {
-				self.get_task_input(&job_id, allocated.idx, input_idx)
+				self.get_task_input(job_id, allocated.idx, input_idx)
 					.await?
 			}
 		})
 	}
 
-	async fn get_job_output(&self, job_id: &Uuid) -> Result<Option<Uuid>, Error> {
-		let last: u32 = self
+	async fn get_job_output(
+		&self,
+		job_id: &Uuid,
+		rendition: Option<String>,
+	) -> Result<Option<Uuid>, TaskError> {
+		let job = self
+			.db
+			.get_job(job_id)
+			.await?
+			.ok_or(TaskError::JobNotFound)?;
+		let tasks = self
 			.db
 			.get_tasks(job_id)
 			.await?
-			.ok_or(Error::new(ErrorKind::NotFound, "Job not found"))?
-			.len()
-			.try_into()
-			.or(Err(Error::new(ErrorKind::Other, "index out of range")))?;
-		let last_idx = match last.checked_sub(1) {
-			Some(i) => i,
+			.ok_or(TaskError::JobNotFound)?;
+		//The last task of a rendition is its merge, or its only transcode
+		let last_of = |pos: usize| {
+			tasks
+				.iter()
+				.rposition(|task| task.output.rendition == Some(pos as u32))
+		};
+		let renditions = &job.options.renditions;
+		if let Some(name) = rendition.as_deref() {
+			let pos = renditions
+				.iter()
+				.position(|rendition| rendition.name == name)
+				.ok_or(TaskError::RenditionNotFound)?;
+			return match last_of(pos) {
+				Some(idx) => self.get_task_output(job_id, idx as u32).await,
+				None => Ok(None),
+			};
+		}
+		for pos in 0..renditions.len() {
+			let finished = match last_of(pos) {
+				Some(idx) => self.get_task_output(job_id, idx as u32).await?.is_some(),
+				None => false,
+			};
+			if !finished {
+				return Ok(None);
+			}
+		}
+		//Thumbnails are added on request, after the task with the job output, and the analysis
+		//output is the artifact its transcodes are created from
+		let last = tasks
+			.iter()
+			.rposition(|task| !matches!(task.recipe, Recipe::Thumbnail(_) | Recipe::Analysis(_)));
+		let last_idx = match last {
+			Some(i) => i.try_into().unwrap_or(u32::MAX),
 			None => return Ok(None),
 		};
 		self.get_task_output(job_id, last_idx).await
 	}
 
-	async fn cancel_task(&self, job_id: &Uuid, task_id: &Uuid) -> Result<Option<()>, Error> {
-		todo!()
+	async fn get_task_failure(
+		&self,
+		job_id: &Uuid,
+		task_idx: u32,
+	) -> Result<Option<String>, TaskError> {
+		let state = self.db.get_task_status(job_id, task_idx).await?;
+		Ok(state.and_then(|state| state.failure))
+	}
+
+	async fn get_unfinished_tasks(&self, job_id: &Uuid) -> Result<Option<u32>, TaskError> {
+		let unfinished = self.db.list_unfulfilled(job_id).await?;
+		Ok(unfinished.map(|tasks| tasks.len() as u32))
+	}
+
+	async fn get_job_failure(&self, job_id: &Uuid) -> Result<Option<String>, TaskError> {
+		let tasks = self
+			.db
+			.get_tasks(job_id)
+			.await?
+			.ok_or(TaskError::JobNotFound)?;
+		for (idx, task) in tasks.iter().enumerate() {
+			if matches!(task.recipe, Recipe::Thumbnail(_)) {
+				continue;
+			}
+			let state = self.db.get_task_status(job_id, idx as u32).await?;
+			if let Some(failure) = state.and_then(|state| state.failure) {
+				return Ok(Some(failure));
+			}
+		}
+		Ok(None)
+	}
+
+	#[instrument(skip(self), fields(%job_id, %task_id))]
+	async fn cancel_task(&self, job_id: &Uuid, task_id: &Uuid) -> Result<Option<()>, TaskError> {
+		let idx = match self
+			.db
+			.get_allocated_task(job_id, task_id)
+			.await?
+			.map(|allocated| allocated.idx)
+		{
+			Some(idx) => idx,
+			None => return Ok(None),
+		};
+		let state = self
+			.db
+			.get_task_status(job_id, idx)
+			.await?
+			.unwrap_or_default();
+		if state.failure.is_some() {
+			return Err(TaskError::TaskFailed);
+		}
+		let res = self.db.deallocate(job_id, task_id).await?;
+		if res.is_some() {
+			let state = TaskState {
+				progress: state.progress,
+				probe: state.probe,
+				..Default::default()
+			};
+			self.db.set_task_status(job_id, idx, state).await?;
+			self.scheduler.cancelled(*job_id, idx);
+			self.log.record(*job_id, Some(idx), JobLogEvent::Cancelled);
+			self.available.notify_waiters();
+		}
+		Ok(res)
+	}
+
+	async fn task_available(&self) {
+		self.available.notified().await
 	}
 
-	async fn delete_job(&self, job_id: &Uuid) -> Result<Option<()>, Error> {
+	async fn delete_job(&self, _job_id: &Uuid) -> Result<Option<()>, TaskError> {
 		todo!()
 	}
+
+	fn scheduler_stats(&self) -> SchedulerStats {
+		self.scheduler.stats()
+	}
+
+	fn get_job_log(&self, job_id: &Uuid) -> Vec<JobLogEntry> {
+		self.log.get(job_id)
+	}
 }
 
 #[cfg(test)]
@@ -297,9 +900,9 @@ mod test {
 	use uuid::Uuid;
 
 	use crate::manager::db::{Allocated, JobDb, MockJobDb};
-	use crate::manager::{JobManager, Manager};
+	use crate::manager::{JobManager, LocalJobManager, Manager};
 	use crate::Recipe::{Analysis, Merge};
-	use crate::{Input, Instance, JobOptions, JobSource, Options, TaskSource};
+	use crate::{Input, Instance, JobOptions, JobSource, Options, TaskError, TaskSource};
 
 	fn default_job_options() -> JobOptions {
 		JobOptions {
@@ -308,14 +911,15 @@ mod test {
 				params: vec![],
 			},
 			audio: None,
+			two_pass: false,
+			container: Default::default(),
+			renditions: vec![],
+			..Default::default()
 		}
 	}
 
 	fn create_job_source(input_id: Uuid) -> JobSource {
-		JobSource {
-			input_id,
-			options: default_job_options(),
-		}
+		JobSource::new(input_id, default_job_options())
 	}
 
 	#[tokio::test]
@@ -327,11 +931,54 @@ mod test {
 			.with(mockall::predicate::eq(source.clone()))
 			.times(1)
 			.returning(|_| Box::pin(async { Ok(TARGET_ID) }));
-		let manager = JobManager { db: mock };
+		let manager = JobManager {
+			db: mock,
+			available: Default::default(),
+			scheduler: Default::default(),
+			log: Default::default(),
+		};
 		let id = manager.create_job(source).await.unwrap();
 		assert_eq!(id, TARGET_ID);
 	}
 
+	#[tokio::test]
+	async fn create_job_expands_the_preset() {
+		let mut source = create_job_source(Uuid::from_u64_pair(1, 1));
+		source.options.video.codec = None;
+		source.options.preset = Some("x264-medium".to_string());
+		let mut mock = MockJobDb::new();
+		mock.expect_create_job()
+			.withf(|job: &JobSource| {
+				job.options.preset.is_none()
+					&& job.options.video.codec.as_deref() == Some("libx264")
+			})
+			.times(1)
+			.returning(|_| Box::pin(async { Ok(Uuid::nil()) }));
+		let manager = JobManager {
+			db: mock,
+			available: Default::default(),
+			scheduler: Default::default(),
+			log: Default::default(),
+		};
+		manager.create_job(source).await.unwrap();
+	}
+
+	#[tokio::test]
+	async fn create_job_with_unknown_preset_fails() {
+		let mut source = create_job_source(Uuid::from_u64_pair(1, 1));
+		source.options.preset = Some("missing".to_string());
+		let mut mock = MockJobDb::new();
+		mock.expect_create_job().never();
+		let manager = JobManager {
+			db: mock,
+			available: Default::default(),
+			scheduler: Default::default(),
+			log: Default::default(),
+		};
+		let result = manager.create_job(source).await;
+		assert!(matches!(result, Err(TaskError::UnknownPreset(_))))
+	}
+
 	#[tokio::test]
 	async fn get_job_bad_id_returns_none() {
 		let mut mock = MockJobDb::new();
@@ -340,7 +987,12 @@ mod test {
 			.with(mockall::predicate::eq(TARGET_ID))
 			.times(1)
 			.returning(|_| Box::pin(async { Ok(None) }));
-		let manager = JobManager { db: mock };
+		let manager = JobManager {
+			db: mock,
+			available: Default::default(),
+			scheduler: Default::default(),
+			log: Default::default(),
+		};
 		let job = manager.get_job(&TARGET_ID).await.unwrap();
 		assert!(job.is_none());
 	}
@@ -348,11 +1000,16 @@ mod test {
 	#[tokio::test]
 	async fn allocate_task_no_available() {
 		let mut mock = MockJobDb::new();
-		mock.expect_allocate_task()
+		mock.expect_allocate_task_matching()
 			.times(1)
-			.returning(|| Box::pin(async { Ok(None) }));
-		let manager = JobManager { db: mock };
-		let instance = manager.allocate_task().await.unwrap();
+			.returning(|_, _| Box::pin(async { Ok(None) }));
+		let manager = JobManager {
+			db: mock,
+			available: Default::default(),
+			scheduler: Default::default(),
+			log: Default::default(),
+		};
+		let instance = manager.allocate_task("worker").await.unwrap();
 		assert!(instance.is_none());
 	}
 
@@ -364,10 +1021,12 @@ mod test {
 			index: 0,
 			start: None,
 			end: None,
+			..Input::source()
 		};
 		let task: TaskSource = TaskSource {
 			inputs: vec![INPUT],
-			recipe: Analysis(None),
+			recipe: Analysis(Default::default()),
+			..Default::default()
 		};
 		let job = create_job_source(Uuid::nil());
 		let target_instance = Instance {
@@ -375,13 +1034,15 @@ mod test {
 			task_id: TASK_ID,
 			inputs: task.inputs.clone(),
 			recipe: task.recipe.clone(),
+			output: task.output.clone(),
+			resources: task.resources.clone(),
 			job_options: job.options.clone(),
 		};
 		let mut mock = MockJobDb::new();
 
-		mock.expect_allocate_task()
+		mock.expect_allocate_task_matching()
 			.times(1)
-			.returning(|| Box::pin(async { Ok(Some((JOB_ID, TASK_ID))) }));
+			.returning(|_, _| Box::pin(async { Ok(Some((JOB_ID, TASK_ID))) }));
 		mock.expect_get_allocated_task()
 			.withf(|a, b| *a == JOB_ID && *b == TASK_ID)
 			.times(1)
@@ -390,15 +1051,25 @@ mod test {
 					Ok(Some(Allocated {
 						task: TaskSource {
 							inputs: vec![INPUT],
-							recipe: Analysis(None),
+							recipe: Analysis(Default::default()),
+							..Default::default()
 						},
 						idx: 0,
 						job: create_job_source(Uuid::nil()),
 					}))
 				})
 			});
-		let manager = JobManager { db: mock };
-		let instance = manager.allocate_task().await.unwrap().unwrap();
+		mock.expect_set_task_status()
+			.withf(|job_id, idx, _| *job_id == JOB_ID && *idx == 0)
+			.times(1)
+			.returning(|_, _, _| Box::pin(async { Ok(Some(())) }));
+		let manager = JobManager {
+			db: mock,
+			available: Default::default(),
+			scheduler: Default::default(),
+			log: Default::default(),
+		};
+		let instance = manager.allocate_task("worker").await.unwrap().unwrap();
 		assert_eq!(instance, target_instance);
 	}
 
@@ -410,10 +1081,12 @@ mod test {
 			index: 0,
 			start: None,
 			end: None,
+			..Input::source()
 		};
 		let task: TaskSource = TaskSource {
 			inputs: vec![INPUT],
-			recipe: Analysis(None),
+			recipe: Analysis(Default::default()),
+			..Default::default()
 		};
 		let mut mock = MockJobDb::new();
 
@@ -423,7 +1096,12 @@ mod test {
 			})
 			.times(1)
 			.returning(|_, _, _| Box::pin(async { Ok(IDX) }));
-		let manager = JobManager { db: mock };
+		let manager = JobManager {
+			db: mock,
+			available: Default::default(),
+			scheduler: Default::default(),
+			log: Default::default(),
+		};
 		let idx = manager.add_task_to_job(&JOB_ID, task).await.unwrap();
 		assert_eq!(idx, IDX);
 	}
@@ -435,15 +1113,18 @@ mod test {
 			index: 1,
 			start: None,
 			end: None,
+			..Input::source()
 		};
 		const INPUT_2: Input = Input {
 			index: 2,
 			start: None,
 			end: None,
+			..Input::source()
 		};
 		let task: TaskSource = TaskSource {
 			inputs: vec![INPUT_1, INPUT_2],
-			recipe: Merge(vec![1, 2]),
+			recipe: Merge(vec![0, 1]),
+			..Default::default()
 		};
 		let mut mock = MockJobDb::new();
 
@@ -451,7 +1132,12 @@ mod test {
 			.withf(|_job_id, _task, deps| deps.contains(&1) && deps.contains(&2))
 			.times(1)
 			.returning(|_, _, _| Box::pin(async { Ok(3) }));
-		let manager = JobManager { db: mock };
+		let manager = JobManager {
+			db: mock,
+			available: Default::default(),
+			scheduler: Default::default(),
+			log: Default::default(),
+		};
 		manager.add_task_to_job(&JOB_ID, task).await.unwrap();
 	}
 
@@ -461,10 +1147,12 @@ mod test {
 			index: 0,
 			start: None,
 			end: None,
+			..Input::source()
 		};
 		let task: TaskSource = TaskSource {
 			inputs: vec![INPUT],
-			recipe: Analysis(None),
+			recipe: Analysis(Default::default()),
+			..Default::default()
 		};
 		let mut mock = MockJobDb::new();
 
@@ -472,7 +1160,12 @@ mod test {
 			.withf(|_job_id, _task, deps| deps.is_empty())
 			.times(1)
 			.returning(|_, _, _| Box::pin(async { Ok(0) }));
-		let manager = JobManager { db: mock };
+		let manager = JobManager {
+			db: mock,
+			available: Default::default(),
+			scheduler: Default::default(),
+			log: Default::default(),
+		};
 		manager
 			.add_task_to_job(&Uuid::from_u64_pair(1, 1), task)
 			.await
@@ -485,20 +1178,22 @@ mod test {
 			index: 0,
 			start: None,
 			end: None,
+			..Input::source()
 		};
 		let task: TaskSource = TaskSource {
 			inputs: vec![INPUT],
-			recipe: Analysis(None),
+			recipe: Analysis(Default::default()),
+			..Default::default()
 		};
 
-		let db = super::db::local::LocalJobDb::default();
+		let manager = LocalJobManager::default();
+		let db = &manager.db;
 		let job_id = db
 			.create_job(create_job_source(Uuid::from_u64_pair(1, 1)))
 			.await
 			.unwrap();
 		db.append_task(&job_id, task, &[]).await.unwrap();
-		let manager = JobManager { db };
-		let instance = manager.allocate_task().await.unwrap().unwrap();
+		let instance = manager.allocate_task("worker").await.unwrap().unwrap();
 		let got = manager
 			.get_task(&job_id, &instance.task_id)
 			.await
@@ -509,15 +1204,15 @@ mod test {
 
 	#[tokio::test]
 	async fn get_task_unknown_task_returns_none() {
-		let db = super::db::local::LocalJobDb::default();
+		let manager = LocalJobManager::default();
+		let db = &manager.db;
 		let job_id = db
-			.create_job(JobSource {
-				input_id: Uuid::from_u64_pair(1, 1),
-				options: default_job_options(),
-			})
+			.create_job(JobSource::new(
+				Uuid::from_u64_pair(1, 1),
+				default_job_options(),
+			))
 			.await
 			.unwrap();
-		let manager = JobManager { db };
 		let none = manager
 			.get_task(&job_id, &Uuid::from_u64_pair(1, 2))
 			.await
@@ -527,7 +1222,7 @@ mod test {
 
 	mod task_source {
 		use crate::manager::LocalJobManager;
-		use crate::Recipe;
+		use crate::{InvalidTask, Recipe, TaskError};
 
 		use super::*;
 
@@ -543,10 +1238,7 @@ mod test {
 		async fn job_without_task_none() {
 			let manager = LocalJobManager::default();
 			let job_id = manager
-				.create_job(JobSource {
-					input_id: Default::default(),
-					options: default_job_options(),
-				})
+				.create_job(JobSource::new(Default::default(), default_job_options()))
 				.await
 				.unwrap();
 			let res = manager.get_task_source(&job_id, 0).await.unwrap();
@@ -557,15 +1249,13 @@ mod test {
 		async fn get_with_valid_job_task_returns_the_task_source() {
 			let manager = LocalJobManager::default();
 			let job_id = manager
-				.create_job(JobSource {
-					input_id: Default::default(),
-					options: default_job_options(),
-				})
+				.create_job(JobSource::new(Default::default(), default_job_options()))
 				.await
 				.unwrap();
 			let task_source = TaskSource {
 				inputs: vec![],
-				recipe: Recipe::Analysis(None),
+				recipe: Recipe::Analysis(Default::default()),
+				..Default::default()
 			};
 			let task = manager
 				.add_task_to_job(&job_id, task_source.clone())
@@ -578,45 +1268,58 @@ mod test {
 				.unwrap();
 			assert_eq!(res, task_source)
 		}
-	}
 
-	mod task_output {
-		use crate::manager::db::local::LocalJobDb;
+		#[tokio::test]
+		async fn add_invalid_task_is_rejected() {
+			let manager = LocalJobManager::default();
+			let job_id = manager
+				.create_job(JobSource::new(Default::default(), default_job_options()))
+				.await
+				.unwrap();
+			let task_source = TaskSource {
+				inputs: vec![Input::source()],
+				recipe: Recipe::Merge(vec![0, 1]),
+				..Default::default()
+			};
+			let res = manager.add_task_to_job(&job_id, task_source).await;
+			assert!(matches!(
+				res,
+				Err(TaskError::InvalidTask(InvalidTask::MergeLength { .. }))
+			));
+			let tasks = manager.db.get_tasks(&job_id).await.unwrap().unwrap();
+			assert!(tasks.is_empty())
+		}
+	}
 
+	mod task_output {
 		use super::*;
 
 		#[tokio::test]
 		async fn get_task_output_bad_job_err() {
-			let db = LocalJobDb::default();
+			let manager = LocalJobManager::default();
 			const JOB_ID: Uuid = Uuid::from_u64_pair(1, 1);
-			let manager = JobManager { db };
 			let res = manager.get_task_output(&JOB_ID, 0).await;
 			assert!(res.is_err())
 		}
 
 		#[tokio::test]
 		async fn get_task_output_bad_idx_err() {
-			let db = LocalJobDb::default();
+			let manager = LocalJobManager::default();
+			let db = &manager.db;
 			let job_id = db
-				.create_job(JobSource {
-					input_id: Default::default(),
-					options: default_job_options(),
-				})
+				.create_job(JobSource::new(Default::default(), default_job_options()))
 				.await
 				.unwrap();
-			let manager = JobManager { db };
 			let res = manager.get_task_output(&job_id, 0).await;
 			assert!(res.is_err())
 		}
 
 		#[tokio::test]
 		async fn get_task_output_before_set() {
-			let db = LocalJobDb::default();
+			let manager = LocalJobManager::default();
+			let db = &manager.db;
 			let job_id = db
-				.create_job(JobSource {
-					input_id: Default::default(),
-					options: default_job_options(),
-				})
+				.create_job(JobSource::new(Default::default(), default_job_options()))
 				.await
 				.unwrap();
 			let idx = db
@@ -624,25 +1327,23 @@ mod test {
 					&job_id,
 					TaskSource {
 						inputs: vec![],
-						recipe: Analysis(None),
+						recipe: Analysis(Default::default()),
+						..Default::default()
 					},
 					&[],
 				)
 				.await
 				.unwrap();
-			let manager = JobManager { db };
 			let output = manager.get_task_output(&job_id, idx).await.unwrap();
 			assert!(output.is_none())
 		}
 
 		#[tokio::test]
 		async fn get_task_output_after_set_equals() {
-			let db = LocalJobDb::default();
+			let manager = LocalJobManager::default();
+			let db = &manager.db;
 			let job_id = db
-				.create_job(JobSource {
-					input_id: Default::default(),
-					options: default_job_options(),
-				})
+				.create_job(JobSource::new(Default::default(), default_job_options()))
 				.await
 				.unwrap();
 			let idx = db
@@ -650,14 +1351,14 @@ mod test {
 					&job_id,
 					TaskSource {
 						inputs: vec![],
-						recipe: Analysis(None),
+						recipe: Analysis(Default::default()),
+						..Default::default()
 					},
 					&[],
 				)
 				.await
 				.unwrap();
 			let (job_id, task_id) = db.allocate_task().await.unwrap().unwrap();
-			let manager = JobManager { db };
 			let output = Uuid::from_u64_pair(1, 3);
 			manager
 				.set_task_output(&job_id, &task_id, output)
@@ -673,8 +1374,7 @@ mod test {
 
 		#[tokio::test]
 		async fn get_allocated_task_output_bad_job_err() {
-			let db = LocalJobDb::default();
-			let manager = JobManager { db };
+			let manager = LocalJobManager::default();
 			let res = manager
 				.get_allocated_task_output(&Uuid::nil(), &Uuid::nil())
 				.await;
@@ -683,15 +1383,12 @@ mod test {
 
 		#[tokio::test]
 		async fn get_allocated_task_output_bad_task_err() {
-			let db = LocalJobDb::default();
+			let manager = LocalJobManager::default();
+			let db = &manager.db;
 			let job_id = db
-				.create_job(JobSource {
-					input_id: Default::default(),
-					options: default_job_options(),
-				})
+				.create_job(JobSource::new(Default::default(), default_job_options()))
 				.await
 				.unwrap();
-			let manager = JobManager { db };
 			let res = manager
 				.get_allocated_task_output(&job_id, &Uuid::nil())
 				.await;
@@ -700,12 +1397,10 @@ mod test {
 
 		#[tokio::test]
 		async fn get_allocated_task_output_before_set() {
-			let db = LocalJobDb::default();
+			let manager = LocalJobManager::default();
+			let db = &manager.db;
 			let job_id = db
-				.create_job(JobSource {
-					input_id: Default::default(),
-					options: default_job_options(),
-				})
+				.create_job(JobSource::new(Default::default(), default_job_options()))
 				.await
 				.unwrap();
 			let idx = db
@@ -713,14 +1408,20 @@ mod test {
 					&job_id,
 					TaskSource {
 						inputs: vec![],
-						recipe: Analysis(None),
+						recipe: Analysis(Default::default()),
+						..Default::default()
 					},
 					&[],
 				)
 				.await
 				.unwrap();
-			let manager = JobManager { db };
-			let task_id = manager.allocate_task().await.unwrap().unwrap().task_id;
+			assert_eq!(idx, 0);
+			let task_id = manager
+				.allocate_task("worker")
+				.await
+				.unwrap()
+				.unwrap()
+				.task_id;
 			let output = manager
 				.get_allocated_task_output(&job_id, &task_id)
 				.await
@@ -730,12 +1431,10 @@ mod test {
 
 		#[tokio::test]
 		async fn get_allocated_task_output_after_set_equals() {
-			let db = LocalJobDb::default();
+			let manager = LocalJobManager::default();
+			let db = &manager.db;
 			let job_id = db
-				.create_job(JobSource {
-					input_id: Default::default(),
-					options: default_job_options(),
-				})
+				.create_job(JobSource::new(Default::default(), default_job_options()))
 				.await
 				.unwrap();
 			let idx = db
@@ -743,14 +1442,15 @@ mod test {
 					&job_id,
 					TaskSource {
 						inputs: vec![],
-						recipe: Analysis(None),
+						recipe: Analysis(Default::default()),
+						..Default::default()
 					},
 					&[],
 				)
 				.await
 				.unwrap();
+			assert_eq!(idx, 0);
 			let (job_id, task_id) = db.allocate_task().await.unwrap().unwrap();
-			let manager = JobManager { db };
 			let output = Uuid::from_u64_pair(1, 3);
 			manager
 				.set_task_output(&job_id, &task_id, output)
@@ -786,10 +1486,7 @@ mod test {
 		async fn job_without_task_none() {
 			let manager = LocalJobManager::default();
 			let job_id = manager
-				.create_job(JobSource {
-					input_id: Default::default(),
-					options: default_job_options(),
-				})
+				.create_job(JobSource::new(Default::default(), default_job_options()))
 				.await
 				.unwrap();
 			let task = 0;
@@ -802,10 +1499,7 @@ mod test {
 		async fn job_with_input_out_of_bounds_err() {
 			let manager = LocalJobManager::default();
 			let job_id = manager
-				.create_job(JobSource {
-					input_id: Default::default(),
-					options: default_job_options(),
-				})
+				.create_job(JobSource::new(Default::default(), default_job_options()))
 				.await
 				.unwrap();
 			let task = manager
@@ -813,7 +1507,8 @@ mod test {
 					&job_id,
 					TaskSource {
 						inputs: vec![Input::source()],
-						recipe: Recipe::Analysis(None),
+						recipe: Recipe::Analysis(Default::default()),
+						..Default::default()
 					},
 				)
 				.await
@@ -827,10 +1522,7 @@ mod test {
 			let manager = LocalJobManager::default();
 			let job_input = Uuid::from_u64_pair(123, 123);
 			let job_id = manager
-				.create_job(JobSource {
-					input_id: job_input,
-					options: default_job_options(),
-				})
+				.create_job(JobSource::new(job_input, default_job_options()))
 				.await
 				.unwrap();
 			let task = manager
@@ -838,7 +1530,8 @@ mod test {
 					&job_id,
 					TaskSource {
 						inputs: vec![Input::source()],
-						recipe: Recipe::Analysis(None),
+						recipe: Recipe::Analysis(Default::default()),
+						..Default::default()
 					},
 				)
 				.await
@@ -847,14 +1540,68 @@ mod test {
 			assert_eq!(input.unwrap(), job_input)
 		}
 
+		#[tokio::test]
+		async fn input_for_task_will_be_the_task_output() {
+			let manager = LocalJobManager::default();
+			let job_id = manager
+				.create_job(JobSource::new(Uuid::nil(), default_job_options()))
+				.await
+				.unwrap();
+			for _ in 0..2 {
+				manager
+					.add_task_to_job(
+						&job_id,
+						TaskSource {
+							inputs: vec![Input::source()],
+							recipe: Recipe::Transcode(vec![]),
+							..Default::default()
+						},
+					)
+					.await
+					.unwrap();
+			}
+			let merge = manager
+				.add_task_to_job(
+					&job_id,
+					TaskSource {
+						inputs: vec![
+							Input {
+								index: 1,
+								start: None,
+								end: None,
+								..Default::default()
+							},
+							Input {
+								index: 0,
+								start: None,
+								end: None,
+								..Default::default()
+							},
+						],
+						recipe: Recipe::Merge(vec![0, 1]),
+						..Default::default()
+					},
+				)
+				.await
+				.unwrap();
+			manager.allocate_task("worker").await.unwrap().unwrap();
+			let second = manager.allocate_task("worker").await.unwrap().unwrap();
+			let output = Uuid::from_u64_pair(4, 5);
+			manager
+				.set_task_output(&job_id, &second.task_id, output)
+				.await
+				.unwrap();
+			let input = manager.get_task_input(&job_id, merge, 0).await.unwrap();
+			assert_eq!(input, Some(output));
+			let source = manager.get_task_input(&job_id, merge, 1).await.unwrap();
+			assert_eq!(source, Some(Uuid::nil()));
+		}
+
 		#[tokio::test]
 		async fn for_invalid_allocated_task_returns_none() {
 			let manager = LocalJobManager::default();
 			let job_id = manager
-				.create_job(JobSource {
-					input_id: Default::default(),
-					options: default_job_options(),
-				})
+				.create_job(JobSource::new(Default::default(), default_job_options()))
 				.await
 				.unwrap();
 			let task = Uuid::nil();
@@ -870,10 +1617,10 @@ mod test {
 		async fn return_same_content_for_allocated_task_by_its_idx() {
 			let manager = LocalJobManager::default();
 			let job_id = manager
-				.create_job(JobSource {
-					input_id: Uuid::from_u64_pair(1, 2),
-					options: default_job_options(),
-				})
+				.create_job(JobSource::new(
+					Uuid::from_u64_pair(1, 2),
+					default_job_options(),
+				))
 				.await
 				.unwrap();
 			let task = manager
@@ -881,13 +1628,19 @@ mod test {
 					&job_id,
 					TaskSource {
 						inputs: vec![Input::source()],
-						recipe: Recipe::Analysis(None),
+						recipe: Recipe::Analysis(Default::default()),
+						..Default::default()
 					},
 				)
 				.await
 				.unwrap();
 			let idx = 0;
-			let task_id = manager.allocate_task().await.unwrap().unwrap().task_id;
+			let task_id = manager
+				.allocate_task("worker")
+				.await
+				.unwrap()
+				.unwrap()
+				.task_id;
 			let input = manager
 				.get_allocated_task_input(&job_id, &task_id, idx)
 				.await
@@ -899,107 +1652,122 @@ mod test {
 	}
 
 	mod job_output {
-		use std::io::ErrorKind;
-
-		use crate::manager::LocalJobDb;
 		use crate::Recipe::Transcode;
+		use crate::{Output, Recipe, Rendition, TaskError, Thumbnail};
 
 		use super::*;
 
 		#[tokio::test]
 		async fn get_output_invalid_job_is_not_found_err() {
-			let db = LocalJobDb::default();
-			let manager = JobManager { db };
-			let err = manager.get_job_output(&Uuid::nil()).await.unwrap_err();
-			assert_eq!(err.kind(), ErrorKind::NotFound)
+			let manager = LocalJobManager::default();
+			let err = manager
+				.get_job_output(&Uuid::nil(), None)
+				.await
+				.unwrap_err();
+			assert!(matches!(err, TaskError::JobNotFound))
 		}
 
 		#[tokio::test]
 		async fn get_output_job_not_task_returns_none() {
-			let db = LocalJobDb::default();
+			let manager = LocalJobManager::default();
+			let db = &manager.db;
 			let job_id = db
-				.create_job(JobSource {
-					input_id: Default::default(),
-					options: JobOptions {
+				.create_job(JobSource::new(
+					Default::default(),
+					JobOptions {
 						video: Options {
 							codec: None,
 							params: vec![],
 						},
 						audio: None,
+						two_pass: false,
+						container: Default::default(),
+						renditions: vec![],
+						..Default::default()
 					},
-				})
+				))
 				.await
 				.unwrap();
-			let manager = JobManager { db };
-			let res = manager.get_job_output(&job_id).await.unwrap();
+			let res = manager.get_job_output(&job_id, None).await.unwrap();
 			assert!(res.is_none())
 		}
 
 		#[tokio::test]
 		async fn get_output_job_task_not_finished() {
-			let db = LocalJobDb::default();
+			let manager = LocalJobManager::default();
+			let db = &manager.db;
 			let job_id = db
-				.create_job(JobSource {
-					input_id: Default::default(),
-					options: JobOptions {
+				.create_job(JobSource::new(
+					Default::default(),
+					JobOptions {
 						video: Options {
 							codec: None,
 							params: vec![],
 						},
 						audio: None,
+						two_pass: false,
+						container: Default::default(),
+						renditions: vec![],
+						..Default::default()
 					},
-				})
+				))
 				.await
 				.unwrap();
-			let manager = JobManager { db };
 			manager
 				.add_task_to_job(
 					&job_id,
 					TaskSource {
 						inputs: vec![Input::source()],
 						recipe: Transcode(Vec::new()),
+						..Default::default()
 					},
 				)
 				.await
 				.unwrap();
 			let allocated = manager
-				.allocate_task()
+				.allocate_task("worker")
 				.await
 				.unwrap()
 				.expect("Should allocate");
-			let res = manager.get_job_output(&job_id).await.unwrap();
+			assert_eq!(allocated.job_id, job_id);
+			let res = manager.get_job_output(&job_id, None).await.unwrap();
 			assert!(res.is_none())
 		}
 
 		#[tokio::test]
 		async fn get_output_job_with_last_task_finished_returns_task_uuid() {
-			let db = LocalJobDb::default();
+			let manager = LocalJobManager::default();
+			let db = &manager.db;
 			let job_id = db
-				.create_job(JobSource {
-					input_id: Default::default(),
-					options: JobOptions {
+				.create_job(JobSource::new(
+					Default::default(),
+					JobOptions {
 						video: Options {
 							codec: None,
 							params: vec![],
 						},
 						audio: None,
+						two_pass: false,
+						container: Default::default(),
+						renditions: vec![],
+						..Default::default()
 					},
-				})
+				))
 				.await
 				.unwrap();
-			let manager = JobManager { db };
 			manager
 				.add_task_to_job(
 					&job_id,
 					TaskSource {
 						inputs: vec![Input::source()],
 						recipe: Transcode(Vec::new()),
+						..Default::default()
 					},
 				)
 				.await
 				.unwrap();
 			let allocated = manager
-				.allocate_task()
+				.allocate_task("worker")
 				.await
 				.unwrap()
 				.expect("Should allocate");
@@ -1010,12 +1778,901 @@ mod test {
 				.unwrap()
 				.expect("Should set");
 			let res = manager
-				.get_job_output(&job_id)
+				.get_job_output(&job_id, None)
 				.await
 				.unwrap()
 				.expect("Should get the output");
 			assert_eq!(res, output)
 		}
+
+		#[tokio::test]
+		async fn get_output_of_rendition() {
+			let mut options = default_job_options();
+			options.renditions = ["720p", "480p"]
+				.map(|name| Rendition {
+					name: name.to_string(),
+					params: vec![],
+				})
+				.to_vec();
+			let manager = LocalJobManager::default();
+			let job_id = manager
+				.create_job(JobSource::new(Default::default(), options))
+				.await
+				.unwrap();
+			for rendition in [0, 1] {
+				let task = TaskSource {
+					inputs: vec![Input::source()],
+					recipe: Transcode(Vec::new()),
+					output: Output {
+						rendition: Some(rendition),
+						..Default::default()
+					},
+					..Default::default()
+				};
+				manager.add_task_to_job(&job_id, task).await.unwrap();
+			}
+			let output = Uuid::from_u64_pair(156, 895554);
+			for _ in 0..2 {
+				let allocated = manager.allocate_task("worker").await.unwrap().unwrap();
+				if allocated.output.rendition == Some(1) {
+					manager
+						.set_task_output(&allocated.job_id, &allocated.task_id, output)
+						.await
+						.unwrap()
+						.expect("Should set");
+				}
+			}
+			let res = manager
+				.get_job_output(&job_id, Some("480p".to_string()))
+				.await
+				.unwrap();
+			assert_eq!(res, Some(output));
+			let res = manager
+				.get_job_output(&job_id, Some("720p".to_string()))
+				.await
+				.unwrap();
+			assert_eq!(res, None);
+			let res = manager.get_job_output(&job_id, None).await.unwrap();
+			assert_eq!(res, None, "Every rendition must be finished");
+			let err = manager
+				.get_job_output(&job_id, Some("1080p".to_string()))
+				.await;
+			assert!(matches!(err, Err(TaskError::RenditionNotFound)))
+		}
+
+		#[tokio::test]
+		async fn get_output_skips_thumbnails() {
+			let manager = LocalJobManager::default();
+			let job_id = manager
+				.create_job(JobSource::new(Default::default(), default_job_options()))
+				.await
+				.unwrap();
+			for recipe in [Transcode(Vec::new()), Recipe::Thumbnail(Thumbnail::Poster)] {
+				let task = TaskSource {
+					inputs: vec![Input::source()],
+					recipe,
+					..Default::default()
+				};
+				manager.add_task_to_job(&job_id, task).await.unwrap();
+			}
+			let output = Uuid::from_u64_pair(156, 895554);
+			let allocated = manager.allocate_task("worker").await.unwrap().unwrap();
+			assert_eq!(allocated.recipe, Transcode(Vec::new()));
+			manager
+				.set_task_output(&allocated.job_id, &allocated.task_id, output)
+				.await
+				.unwrap()
+				.expect("Should set");
+			let res = manager.get_job_output(&job_id, None).await.unwrap();
+			assert_eq!(res, Some(output))
+		}
+	}
+
+	mod task_probe {
+		use crate::{MediaProbe, Status};
+
+		use super::*;
+
+		#[tokio::test]
+		async fn task_probe_kept_after_finished() {
+			let manager = LocalJobManager::default();
+			let job_id = manager
+				.create_job(JobSource::new(Default::default(), default_job_options()))
+				.await
+				.unwrap();
+			let task = TaskSource {
+				inputs: vec![Input::source()],
+				recipe: Analysis(Default::default()),
+				..Default::default()
+			};
+			let idx = manager.add_task_to_job(&job_id, task).await.unwrap();
+			let probe = MediaProbe {
+				duration: Some(4.0),
+				..Default::default()
+			};
+			let allocated = manager.allocate_task("worker").await.unwrap().unwrap();
+			manager
+				.set_task_probe(&job_id, &allocated.task_id, probe.clone())
+				.await
+				.unwrap()
+				.expect("Should set");
+			manager
+				.update_task_status(&job_id, &allocated.task_id, Status::Finished)
+				.await
+				.unwrap();
+			let res = manager.get_task_probe(&job_id, idx).await.unwrap();
+			assert_eq!(res, Some(probe))
+		}
+
+		#[tokio::test]
+		async fn set_task_probe_not_allocated_none() {
+			let manager = LocalJobManager::default();
+			let job_id = manager
+				.create_job(JobSource::new(Default::default(), default_job_options()))
+				.await
+				.unwrap();
+			let res = manager
+				.set_task_probe(&job_id, &Uuid::new_v4(), MediaProbe::default())
+				.await
+				.unwrap();
+			assert_eq!(res, None)
+		}
+	}
+
+	mod merge_segments {
+		use crate::manager::LocalJobManager;
+		use crate::{Output, Recipe, Rendition, Status};
+
+		use super::*;
+
+		///Job with its analysis allocated, followed by the transcodes of each rendition
+		async fn analysed_job(
+			options: JobOptions,
+			renditions: &[Option<u32>],
+		) -> (LocalJobManager, Uuid, Uuid) {
+			analysed_clip(options, Input::source(), renditions).await
+		}
+
+		///Job with the analysis of the clip allocated, followed by the transcodes of each rendition
+		async fn analysed_clip(
+			options: JobOptions,
+			clip: Input,
+			renditions: &[Option<u32>],
+		) -> (LocalJobManager, Uuid, Uuid) {
+			let manager = LocalJobManager::default();
+			let job_id = manager
+				.create_job(JobSource::new(Default::default(), options))
+				.await
+				.unwrap();
+			let analysis = TaskSource {
+				inputs: vec![clip],
+				recipe: Analysis(Default::default()),
+				..Default::default()
+			};
+			manager.add_task_to_job(&job_id, analysis).await.unwrap();
+			let allocated = manager.allocate_task("worker").await.unwrap().unwrap();
+			for &rendition in renditions {
+				let transcode = TaskSource {
+					inputs: vec![Input::source()],
+					recipe: Recipe::Transcode(Vec::new()),
+					output: Output {
+						rendition,
+						..Default::default()
+					},
+					..Default::default()
+				};
+				manager.add_task_to_job(&job_id, transcode).await.unwrap();
+			}
+			(manager, job_id, allocated.task_id)
+		}
+
+		async fn finish(manager: &LocalJobManager, job_id: Uuid, task_id: Uuid) {
+			manager
+				.update_task_status(&job_id, &task_id, Status::Finished)
+				.await
+				.unwrap()
+				.expect("Should finish");
+		}
+
+		#[tokio::test]
+		async fn finished_analysis_merges_segments() {
+			let (manager, job_id, analysis) =
+				analysed_job(default_job_options(), &[None, None, None]).await;
+			finish(&manager, job_id, analysis).await;
+			let merge = manager.get_task_source(&job_id, 4).await.unwrap().unwrap();
+			let inputs: Vec<u32> = merge.inputs.iter().map(|input| input.index).collect();
+			assert_eq!(inputs, [1, 2, 3]);
+			assert_eq!(merge.recipe, Merge(vec![0, 1, 2]));
+		}
+
+		#[tokio::test]
+		async fn merge_copies_the_subtitles_of_the_clip() {
+			let options = JobOptions {
+				copy_subtitles: true,
+				..default_job_options()
+			};
+			let clip = Input {
+				start: Some(30.0),
+				end: Some(90.0),
+				..Input::source()
+			};
+			let (manager, job_id, analysis) = analysed_clip(options, clip, &[None, None]).await;
+			finish(&manager, job_id, analysis).await;
+			let merge = manager.get_task_source(&job_id, 3).await.unwrap().unwrap();
+			let source = Input {
+				start: Some(30.0),
+				end: Some(90.0),
+				..Input::source()
+			};
+			assert_eq!(merge.inputs.last(), Some(&source));
+			assert_eq!(merge.recipe, Merge(vec![0, 1]));
+		}
+
+		#[tokio::test]
+		async fn single_segment_is_not_merged() {
+			let (manager, job_id, analysis) = analysed_job(default_job_options(), &[None]).await;
+			finish(&manager, job_id, analysis).await;
+			let task = manager.get_task_source(&job_id, 2).await.unwrap();
+			assert_eq!(task, None)
+		}
+
+		#[tokio::test]
+		async fn segments_merged_by_the_worker_are_not_merged_again() {
+			let (manager, job_id, analysis) =
+				analysed_job(default_job_options(), &[None, None]).await;
+			let merge = crate::segment::merge(&[1, 2], None);
+			manager.add_task_to_job(&job_id, merge).await.unwrap();
+			finish(&manager, job_id, analysis).await;
+			let task = manager.get_task_source(&job_id, 4).await.unwrap();
+			assert_eq!(task, None)
+		}
+
+		#[tokio::test]
+		async fn each_rendition_has_its_merge() {
+			let options = JobOptions {
+				renditions: ["720p", "480p"]
+					.map(|name| Rendition {
+						name: name.to_string(),
+						params: vec![],
+					})
+					.to_vec(),
+				..default_job_options()
+			};
+			let renditions = [Some(0), Some(0), Some(1), Some(1)];
+			let (manager, job_id, analysis) = analysed_job(options, &renditions).await;
+			finish(&manager, job_id, analysis).await;
+			for (idx, rendition, suffix) in [(5, 0, "-720p"), (6, 1, "-480p")] {
+				let merge = manager
+					.get_task_source(&job_id, idx)
+					.await
+					.unwrap()
+					.unwrap();
+				assert_eq!(merge.output.rendition, Some(rendition));
+				assert_eq!(merge.output.suffix.as_deref(), Some(suffix));
+			}
+		}
+	}
+
+	mod journal {
+		use crate::manager::LocalJobManager;
+		use crate::{JobLogEvent, Status};
+
+		use super::*;
+
+		fn journal_path() -> std::path::PathBuf {
+			std::env::temp_dir().join(format!("restart-{}.journal", Uuid::new_v4()))
+		}
+
+		fn remove_journal(path: &std::path::Path) {
+			std::fs::remove_file(path).unwrap();
+			let mut log = path.as_os_str().to_owned();
+			log.push(".log");
+			std::fs::remove_file(log).unwrap();
+		}
+
+		#[tokio::test]
+		async fn restart_restores_queue_and_job_log() {
+			let path = journal_path();
+			let manager = LocalJobManager::with_journal(&path).await.unwrap();
+			let job_id = manager
+				.create_job(create_job_source(Uuid::nil()))
+				.await
+				.unwrap();
+			let task = TaskSource {
+				inputs: vec![Input::source()],
+				recipe: Analysis(Default::default()),
+				..Default::default()
+			};
+			manager.add_task_to_job(&job_id, task).await.unwrap();
+			drop(manager);
+
+			let restarted = LocalJobManager::with_journal(&path).await.unwrap();
+			let events: Vec<_> = restarted
+				.get_job_log(&job_id)
+				.into_iter()
+				.map(|entry| entry.event)
+				.collect();
+			let stats = restarted.scheduler_stats();
+			remove_journal(&path);
+			assert_eq!(events, [JobLogEvent::Created, JobLogEvent::TaskAdded]);
+			assert_eq!(stats.ready_tasks, 1);
+		}
+
+		#[tokio::test]
+		async fn restart_returns_allocated_task_to_queue() {
+			let path = journal_path();
+			let manager = LocalJobManager::with_journal(&path).await.unwrap();
+			let job_id = manager
+				.create_job(create_job_source(Uuid::nil()))
+				.await
+				.unwrap();
+			let task = TaskSource {
+				inputs: vec![Input::source()],
+				recipe: Analysis(Default::default()),
+				..Default::default()
+			};
+			manager.add_task_to_job(&job_id, task).await.unwrap();
+			let before = manager.allocate_task("worker").await.unwrap().unwrap();
+			drop(manager);
+
+			let restarted = LocalJobManager::with_journal(&path).await.unwrap();
+			let allocations = restarted.get_allocated_tasks().await.unwrap();
+			let after = restarted.allocate_task("worker").await.unwrap();
+			remove_journal(&path);
+			assert!(allocations.is_empty());
+			let after = after.expect("Task should be allocated again");
+			assert_eq!(after.job_id, job_id);
+			assert_ne!(after.task_id, before.task_id);
+		}
+
+		#[tokio::test]
+		async fn restart_keeps_failed_task() {
+			let path = journal_path();
+			let manager = LocalJobManager::with_journal(&path).await.unwrap();
+			let job_id = manager
+				.create_job(create_job_source(Uuid::nil()))
+				.await
+				.unwrap();
+			let task = TaskSource {
+				inputs: vec![Input::source()],
+				recipe: Analysis(Default::default()),
+				..Default::default()
+			};
+			manager.add_task_to_job(&job_id, task).await.unwrap();
+			let instance = manager.allocate_task("worker").await.unwrap().unwrap();
+			let status = Status::Failed(Some("Broken".to_string()));
+			manager
+				.update_task_status(&job_id, &instance.task_id, status)
+				.await
+				.unwrap();
+			drop(manager);
+
+			let restarted = LocalJobManager::with_journal(&path).await.unwrap();
+			let failure = restarted.get_job_failure(&job_id).await.unwrap();
+			let task_failure = restarted.get_task_failure(&job_id, 0).await.unwrap();
+			let after = restarted.allocate_task("worker").await.unwrap();
+			remove_journal(&path);
+			assert_eq!(failure.as_deref(), Some("Broken"));
+			assert_eq!(task_failure, failure);
+			assert!(after.is_none())
+		}
+	}
+
+	mod allocations {
+		use std::time::SystemTime;
+
+		use crate::manager::LocalJobManager;
+		use crate::{MediaProbe, Status, TaskError};
+
+		use super::*;
+
+		async fn manager_with_task() -> (LocalJobManager, Uuid) {
+			let manager = LocalJobManager::default();
+			let job_id = manager
+				.create_job(create_job_source(Uuid::nil()))
+				.await
+				.unwrap();
+			manager
+				.add_task_to_job(
+					&job_id,
+					TaskSource {
+						inputs: vec![Input::source()],
+						recipe: Analysis(Default::default()),
+						..Default::default()
+					},
+				)
+				.await
+				.unwrap();
+			(manager, job_id)
+		}
+
+		#[tokio::test]
+		async fn without_allocation_is_empty() {
+			let (manager, _) = manager_with_task().await;
+			let allocations = manager.get_allocated_tasks().await.unwrap();
+			assert!(allocations.is_empty())
+		}
+
+		#[tokio::test]
+		async fn contains_allocated_task() {
+			let (manager, job_id) = manager_with_task().await;
+			let instance = manager.allocate_task("worker").await.unwrap().unwrap();
+			let allocations = manager.get_allocated_tasks().await.unwrap();
+			assert_eq!(allocations.len(), 1);
+			assert_eq!(allocations[0].job_id, job_id);
+			assert_eq!(allocations[0].task_id, instance.task_id);
+			assert_eq!(allocations[0].task_idx, 0);
+		}
+
+		#[tokio::test]
+		async fn records_worker_and_allocation_time() {
+			let (manager, _) = manager_with_task().await;
+			let before = SystemTime::now();
+			manager.allocate_task("worker 1").await.unwrap().unwrap();
+			let allocation = manager.get_allocated_tasks().await.unwrap().remove(0);
+			assert_eq!(allocation.worker, "worker 1");
+			assert!(allocation.allocated_at >= before);
+		}
+
+		#[tokio::test]
+		async fn output_keeps_allocation_info() {
+			let (manager, _) = manager_with_task().await;
+			let instance = manager.allocate_task("worker 1").await.unwrap().unwrap();
+			manager
+				.set_task_output(&instance.job_id, &instance.task_id, Uuid::nil())
+				.await
+				.unwrap();
+			let allocation = manager.get_allocated_tasks().await.unwrap().remove(0);
+			assert_eq!(allocation.worker, "worker 1");
+		}
+
+		#[tokio::test]
+		async fn failed_task_status_is_accepted() {
+			let (manager, _) = manager_with_task().await;
+			let instance = manager.allocate_task("worker").await.unwrap().unwrap();
+			let res = manager
+				.update_task_status(&instance.job_id, &instance.task_id, Status::Failed(None))
+				.await
+				.unwrap();
+			assert_eq!(res, Some(()))
+		}
+
+		#[tokio::test]
+		async fn failure_reason_is_listed_with_allocation() {
+			let (manager, _) = manager_with_task().await;
+			let instance = manager.allocate_task("worker").await.unwrap().unwrap();
+			let reason = "Unknown encoder 'libx625'".to_string();
+			manager
+				.update_task_status(
+					&instance.job_id,
+					&instance.task_id,
+					Status::Failed(Some(reason.clone())),
+				)
+				.await
+				.unwrap();
+			let allocation = manager.get_allocated_tasks().await.unwrap().remove(0);
+			assert_eq!(allocation.failure, Some(reason));
+		}
+
+		#[tokio::test]
+		async fn failed_task_fails_the_job_once() {
+			use crate::JobLogEvent;
+			let (manager, job_id) = manager_with_task().await;
+			let instance = manager.allocate_task("worker").await.unwrap().unwrap();
+			assert_eq!(manager.get_job_failure(&job_id).await.unwrap(), None);
+			for reason in ["First", "Second"] {
+				let status = Status::Failed(Some(reason.to_string()));
+				manager
+					.update_task_status(&instance.job_id, &instance.task_id, status)
+					.await
+					.unwrap();
+			}
+			let failure = manager.get_job_failure(&job_id).await.unwrap();
+			assert_eq!(failure.as_deref(), Some("First"));
+			let job_failures = manager
+				.get_job_log(&job_id)
+				.into_iter()
+				.filter(|entry| entry.task_idx.is_none())
+				.filter(|entry| matches!(entry.event, JobLogEvent::Failed { .. }))
+				.count();
+			assert_eq!(job_failures, 1);
+		}
+
+		#[tokio::test]
+		async fn failed_task_without_reason_fails_the_job() {
+			let (manager, job_id) = manager_with_task().await;
+			let instance = manager.allocate_task("worker").await.unwrap().unwrap();
+			manager
+				.update_task_status(&instance.job_id, &instance.task_id, Status::Failed(None))
+				.await
+				.unwrap();
+			let failure = manager.get_job_failure(&job_id).await.unwrap();
+			assert_eq!(failure.as_deref(), Some(crate::manager::UNKNOWN_FAILURE));
+		}
+
+		#[tokio::test]
+		async fn scheduler_stats_count_allocated_and_finished_tasks() {
+			let (manager, _) = manager_with_task().await;
+			assert_eq!(manager.scheduler_stats().ready_tasks, 1);
+			let instance = manager.allocate_task("worker").await.unwrap().unwrap();
+			manager
+				.update_task_status(&instance.job_id, &instance.task_id, Status::Finished)
+				.await
+				.unwrap();
+			let stats = manager.scheduler_stats();
+			assert_eq!(stats.ready_tasks, 0);
+			assert_eq!(stats.allocations, 1);
+			assert_eq!(stats.completed, 1);
+		}
+
+		#[tokio::test]
+		async fn job_log_records_transitions_in_order() {
+			use crate::JobLogEvent;
+			let (manager, job_id) = manager_with_task().await;
+			let instance = manager.allocate_task("worker").await.unwrap().unwrap();
+			manager
+				.update_task_status(&instance.job_id, &instance.task_id, Status::Finished)
+				.await
+				.unwrap();
+			let events: Vec<_> = manager
+				.get_job_log(&job_id)
+				.into_iter()
+				.map(|entry| entry.event)
+				.collect();
+			let worker = "worker".to_string();
+			let expected = [
+				JobLogEvent::Created,
+				JobLogEvent::TaskAdded,
+				JobLogEvent::Allocated { worker },
+				JobLogEvent::Finished,
+			];
+			assert_eq!(events, expected);
+		}
+
+		#[tokio::test]
+		async fn failed_status_for_unknown_task_none() {
+			let (manager, job_id) = manager_with_task().await;
+			let res = manager
+				.update_task_status(&job_id, &Uuid::nil(), Status::Failed(None))
+				.await
+				.unwrap();
+			assert_eq!(res, None)
+		}
+
+		#[tokio::test]
+		async fn records_reported_progress() {
+			let (manager, _) = manager_with_task().await;
+			let instance = manager.allocate_task("worker 1").await.unwrap().unwrap();
+			let res = manager
+				.update_task_progress(&instance.job_id, &instance.task_id, 12.5, Some(2.0))
+				.await
+				.unwrap();
+			assert_eq!(res, Some(()));
+			let allocation = manager.get_allocated_tasks().await.unwrap().remove(0);
+			assert_eq!(allocation.progress, Some(12.5));
+			assert_eq!(allocation.speed, Some(2.0));
+			assert_eq!(allocation.worker, "worker 1");
+		}
+
+		#[tokio::test]
+		async fn progress_for_unknown_task_none() {
+			let (manager, job_id) = manager_with_task().await;
+			let res = manager
+				.update_task_progress(&job_id, &Uuid::nil(), 1.0, None)
+				.await
+				.unwrap();
+			assert_eq!(res, None)
+		}
+
+		#[tokio::test]
+		async fn cancelled_task_is_allocated_again() {
+			let (manager, _) = manager_with_task().await;
+			let instance = manager.allocate_task("worker 1").await.unwrap().unwrap();
+			let res = manager
+				.cancel_task(&instance.job_id, &instance.task_id)
+				.await
+				.unwrap();
+			assert_eq!(res, Some(()));
+			assert!(manager.get_allocated_tasks().await.unwrap().is_empty());
+			let instance = manager.allocate_task("worker 2").await.unwrap();
+			assert!(instance.is_some());
+			let allocation = manager.get_allocated_tasks().await.unwrap().remove(0);
+			assert_eq!(allocation.worker, "worker 2");
+		}
+
+		const WAKE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+		#[tokio::test]
+		async fn create_job_wakes_waiting_workers() {
+			let manager = LocalJobManager::default();
+			let create = manager.create_job(create_job_source(Uuid::nil()));
+			let woken = tokio::time::timeout(WAKE_TIMEOUT, async {
+				tokio::join!(manager.task_available(), create)
+			})
+			.await;
+			assert!(woken.is_ok())
+		}
+
+		#[tokio::test]
+		async fn cancel_task_wakes_waiting_workers() {
+			let (manager, _) = manager_with_task().await;
+			let instance = manager.allocate_task("worker").await.unwrap().unwrap();
+			let cancel = manager.cancel_task(&instance.job_id, &instance.task_id);
+			let woken = tokio::time::timeout(WAKE_TIMEOUT, async {
+				tokio::join!(manager.task_available(), cancel)
+			})
+			.await;
+			assert!(woken.is_ok())
+		}
+
+		#[tokio::test]
+		async fn progress_does_not_wake_waiting_workers() {
+			let (manager, _) = manager_with_task().await;
+			let instance = manager.allocate_task("worker").await.unwrap().unwrap();
+			let progress =
+				manager.update_task_progress(&instance.job_id, &instance.task_id, 1.0, None);
+			let woken = tokio::time::timeout(WAKE_TIMEOUT, async {
+				tokio::join!(manager.task_available(), progress)
+			})
+			.await;
+			assert!(woken.is_err())
+		}
+
+		#[tokio::test]
+		async fn cancel_unknown_task_none() {
+			let (manager, job_id) = manager_with_task().await;
+			let res = manager.cancel_task(&job_id, &Uuid::nil()).await.unwrap();
+			assert_eq!(res, None)
+		}
+
+		#[tokio::test]
+		async fn cancel_finished_task_none() {
+			let (manager, _) = manager_with_task().await;
+			let instance = manager.allocate_task("worker").await.unwrap().unwrap();
+			manager
+				.update_task_status(&instance.job_id, &instance.task_id, Status::Finished)
+				.await
+				.unwrap();
+			let res = manager
+				.cancel_task(&instance.job_id, &instance.task_id)
+				.await
+				.unwrap();
+			assert_eq!(res, None)
+		}
+
+		#[tokio::test]
+		async fn cancel_keeps_task_probe() {
+			let (manager, job_id) = manager_with_task().await;
+			let instance = manager.allocate_task("worker").await.unwrap().unwrap();
+			let probe = MediaProbe {
+				duration: Some(2.0),
+				..Default::default()
+			};
+			manager
+				.set_task_probe(&job_id, &instance.task_id, probe.clone())
+				.await
+				.unwrap();
+			manager
+				.cancel_task(&job_id, &instance.task_id)
+				.await
+				.unwrap();
+			let kept = manager.get_task_probe(&job_id, 0).await.unwrap();
+			assert_eq!(kept, Some(probe))
+		}
+
+		#[tokio::test]
+		async fn cancel_failed_task_keeps_failure() {
+			let (manager, job_id) = manager_with_task().await;
+			let instance = manager.allocate_task("worker").await.unwrap().unwrap();
+			let status = Status::Failed(Some("Broken".to_string()));
+			manager
+				.update_task_status(&job_id, &instance.task_id, status)
+				.await
+				.unwrap();
+			let res = manager.cancel_task(&job_id, &instance.task_id).await;
+			assert!(matches!(res, Err(TaskError::TaskFailed)));
+			let failure = manager.get_job_failure(&job_id).await.unwrap();
+			assert_eq!(failure.as_deref(), Some("Broken"));
+			assert!(manager.allocate_task("worker").await.unwrap().is_none())
+		}
+
+		#[tokio::test]
+		async fn task_worker_is_allocation_worker() {
+			let (manager, job_id) = manager_with_task().await;
+			let instance = manager.allocate_task("worker").await.unwrap().unwrap();
+			let worker = manager
+				.get_task_worker(&job_id, &instance.task_id)
+				.await
+				.unwrap();
+			assert_eq!(worker.as_deref(), Some("worker"));
+			let unknown = manager.get_task_worker(&job_id, &Uuid::nil()).await;
+			assert_eq!(unknown.unwrap(), None)
+		}
+
+		#[tokio::test]
+		async fn partial_output_is_not_task_output() {
+			let (manager, job_id) = manager_with_task().await;
+			let instance = manager.allocate_task("worker").await.unwrap().unwrap();
+			let partial = Uuid::from_u64_pair(1, 5);
+			let res = manager
+				.set_task_partial_output(&job_id, &instance.task_id, Some(partial))
+				.await
+				.unwrap();
+			assert_eq!(res, Some(()));
+			let stored = manager
+				.get_task_partial_output(&job_id, &instance.task_id)
+				.await
+				.unwrap();
+			assert_eq!(stored, Some(partial));
+			let output = manager.get_task_output(&job_id, 0).await.unwrap();
+			assert_eq!(output, None)
+		}
+
+		#[tokio::test]
+		async fn partial_output_for_unknown_task_none() {
+			let (manager, job_id) = manager_with_task().await;
+			let res = manager
+				.set_task_partial_output(&job_id, &Uuid::nil(), Some(Uuid::nil()))
+				.await
+				.unwrap();
+			assert_eq!(res, None)
+		}
+
+		#[tokio::test]
+		async fn finished_task_is_not_listed() {
+			let (manager, _) = manager_with_task().await;
+			let instance = manager.allocate_task("worker").await.unwrap().unwrap();
+			manager
+				.update_task_status(&instance.job_id, &instance.task_id, Status::Finished)
+				.await
+				.unwrap();
+			let allocations = manager.get_allocated_tasks().await.unwrap();
+			assert!(allocations.is_empty())
+		}
+	}
+
+	mod chained_job {
+		use crate::manager::LocalJobManager;
+		use crate::{Recipe, Status, TaskError};
+
+		use super::*;
+
+		const OUTPUT: Uuid = Uuid::from_u64_pair(4, 4);
+
+		///Job with a single transcode, returning its id and the transcode run
+		async fn job_with_transcode(manager: &LocalJobManager) -> (Uuid, Uuid) {
+			let job_id = manager
+				.create_job(create_job_source(Uuid::nil()))
+				.await
+				.unwrap();
+			let transcode = TaskSource {
+				inputs: vec![Input::source()],
+				recipe: Recipe::Transcode(Vec::new()),
+				..Default::default()
+			};
+			manager.add_task_to_job(&job_id, transcode).await.unwrap();
+			let allocated = manager.allocate_task("worker").await.unwrap().unwrap();
+			(job_id, allocated.task_id)
+		}
+
+		async fn finished_job(manager: &LocalJobManager) -> Uuid {
+			let (job_id, task_id) = job_with_transcode(manager).await;
+			manager
+				.set_task_output(&job_id, &task_id, OUTPUT)
+				.await
+				.unwrap();
+			manager
+				.update_task_status(&job_id, &task_id, Status::Finished)
+				.await
+				.unwrap();
+			job_id
+		}
+
+		fn chained(input_id: Uuid, source_job: Uuid) -> JobSource {
+			JobSource {
+				source_job: Some(source_job),
+				..create_job_source(input_id)
+			}
+		}
+
+		#[tokio::test]
+		async fn chained_to_finished_job_reads_its_output() {
+			let manager = LocalJobManager::default();
+			let source_job = finished_job(&manager).await;
+			let job_id = manager
+				.create_job(chained(OUTPUT, source_job))
+				.await
+				.unwrap();
+			let job = manager.get_job(&job_id).await.unwrap().unwrap();
+			assert_eq!(job.input_id, OUTPUT)
+		}
+
+		#[tokio::test]
+		async fn chained_to_unfinished_job_fails() {
+			let manager = LocalJobManager::default();
+			let (source_job, _) = job_with_transcode(&manager).await;
+			let err = manager
+				.create_job(chained(OUTPUT, source_job))
+				.await
+				.unwrap_err();
+			assert!(matches!(err, TaskError::JobNotFinished));
+			assert_eq!(manager.get_job_list().await.unwrap().len(), 1)
+		}
+
+		#[tokio::test]
+		async fn chained_with_other_input_fails() {
+			let manager = LocalJobManager::default();
+			let source_job = finished_job(&manager).await;
+			let err = manager
+				.create_job(chained(Uuid::nil(), source_job))
+				.await
+				.unwrap_err();
+			assert!(matches!(err, TaskError::JobNotFinished))
+		}
+
+		#[tokio::test]
+		async fn chained_to_missing_job_not_found() {
+			let manager = LocalJobManager::default();
+			let err = manager
+				.create_job(chained(OUTPUT, Uuid::nil()))
+				.await
+				.unwrap_err();
+			assert!(matches!(err, TaskError::JobNotFound))
+		}
+	}
+
+	mod priority {
+		use std::time::{Duration, SystemTime};
+
+		use crate::manager::LocalJobManager;
+		use crate::Priority;
+
+		use super::*;
+
+		async fn add_job(manager: &LocalJobManager, priority: Priority, created_at: u64) -> Uuid {
+			let source = JobSource {
+				priority,
+				created_at: SystemTime::UNIX_EPOCH + Duration::from_secs(created_at),
+				..create_job_source(Uuid::nil())
+			};
+			let job_id = manager.create_job(source).await.unwrap();
+			manager
+				.add_task_to_job(
+					&job_id,
+					TaskSource {
+						inputs: vec![Input::source()],
+						recipe: Analysis(Default::default()),
+						..Default::default()
+					},
+				)
+				.await
+				.unwrap();
+			job_id
+		}
+
+		#[tokio::test]
+		async fn interactive_job_allocated_before_older_batch_job() {
+			let manager = LocalJobManager::default();
+			let batch = add_job(&manager, Priority::Batch, 1).await;
+			let normal = add_job(&manager, Priority::Normal, 2).await;
+			let interactive = add_job(&manager, Priority::Interactive, 3).await;
+			let mut allocated = Vec::new();
+			while let Some(instance) = manager.allocate_task("worker").await.unwrap() {
+				allocated.push(instance.job_id);
+			}
+			assert_eq!(allocated, [interactive, normal, batch])
+		}
+
+		#[tokio::test]
+		async fn same_priority_allocated_in_creation_order() {
+			let manager = LocalJobManager::default();
+			let second = add_job(&manager, Priority::Batch, 20).await;
+			let first = add_job(&manager, Priority::Batch, 10).await;
+			let instance = manager.allocate_task("worker").await.unwrap().unwrap();
+			assert_eq!(instance.job_id, first);
+			let instance = manager.allocate_task("worker").await.unwrap().unwrap();
+			assert_eq!(instance.job_id, second);
+		}
 	}
 
 	mod list_job {
@@ -1034,16 +2691,20 @@ mod test {
 		async fn list_jobs_contains_created_job_id() {
 			let manager = LocalJobManager::default();
 			let id = manager
-				.create_job(JobSource {
-					input_id: Default::default(),
-					options: JobOptions {
+				.create_job(JobSource::new(
+					Default::default(),
+					JobOptions {
 						video: Options {
 							codec: None,
 							params: vec![],
 						},
 						audio: None,
+						two_pass: false,
+						container: Default::default(),
+						renditions: vec![],
+						..Default::default()
 					},
-				})
+				))
 				.await
 				.unwrap();
 			let res = manager.get_job_list().await.unwrap();
@@ -19,19 +19,31 @@
 //!
 //! Those operations could be implemented with 3 tables
 //! - Jobs:
-//! 	At least job_id
+//!   At least job_id
 //! - Tasks:
-//! 	At least job_id and task_number
+//!   At least job_id and task_number
 //! - TaskInstances:
-//! 	At least job_id, task_number and instance_id
+//!   At least job_id, task_number and instance_id
+//!
 //! Additionally:
 //! - TaskDependencies
-//! 	job_id, task_number, dependency_task_number
+//!   job_id, task_number, dependency_task_number
 
+use std::cmp::Ordering;
 use std::future::Future;
 
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::TaskError;
+
+pub(crate) mod journal;
+
+///Accepts the tasks that can be allocated
+pub type TaskFilter<TASK> = dyn Fn(&TASK) -> bool + Sync;
+///Orders the jobs, the tasks of the first one are allocated first
+pub type JobOrder<JOB> = dyn Fn(&JOB, &JOB) -> Ordering + Sync;
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Allocated<JOB: Sync, TASK: Sync> {
 	pub task: TASK,
@@ -39,30 +51,66 @@ pub struct Allocated<JOB: Sync, TASK: Sync> {
 	pub idx: u32,
 }
 
+///A change to the database, appended to the journal before being applied
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Mutation<JOB, TASK, STATUS> {
+	CreateJob {
+		id: Uuid,
+		job: JOB,
+	},
+	AppendTask {
+		job_id: Uuid,
+		task: TASK,
+		dependencies: Vec<u32>,
+	},
+	Allocate {
+		job_id: Uuid,
+		task_idx: u32,
+		run_id: Uuid,
+	},
+	Deallocate {
+		job_id: Uuid,
+		task_idx: u32,
+	},
+	Fulfill {
+		job_id: Uuid,
+		task_idx: u32,
+	},
+	SetStatus {
+		job_id: Uuid,
+		task_idx: u32,
+		status: STATUS,
+	},
+}
+
+///Append-only record of the database mutations, used to rebuild the database after a restart
+pub trait Journal<JOB, TASK, STATUS>: Send + Sync {
+	fn append(&self, mutation: &Mutation<JOB, TASK, STATUS>) -> std::io::Result<()>;
+	///Replace the recorded mutations with ones rebuilding the same database
+	fn compact(&self, mutations: &[Mutation<JOB, TASK, STATUS>]) -> std::io::Result<()>;
+}
+
 #[cfg_attr(test, mockall::automock)]
 pub trait JobDb<JOB: Sync, TASK: Sync, STATUS: Sync>: Sync {
-	fn get_job(
-		&self,
-		id: &Uuid,
-	) -> impl Future<Output = Result<Option<JOB>, std::io::Error>> + Send;
-	fn create_job(&self, job: JOB) -> impl Future<Output = Result<Uuid, std::io::Error>> + Send;
-	fn list_job_ids(&self) -> impl Future<Output = Result<Vec<Uuid>, std::io::Error>> + Send;
+	fn get_job(&self, id: &Uuid) -> impl Future<Output = Result<Option<JOB>, TaskError>> + Send;
+	fn create_job(&self, job: JOB) -> impl Future<Output = Result<Uuid, TaskError>> + Send;
+	fn list_job_ids(&self) -> impl Future<Output = Result<Vec<Uuid>, TaskError>> + Send;
 	/// Append task to job and return the task index
 	fn append_task(
 		&self,
 		job_id: &Uuid,
 		task: TASK,
 		dep: &[u32],
-	) -> impl Future<Output = Result<u32, std::io::Error>> + Send;
+	) -> impl Future<Output = Result<u32, TaskError>> + Send;
 	fn get_tasks(
 		&self,
 		job_id: &Uuid,
-	) -> impl Future<Output = Result<Option<Vec<TASK>>, std::io::Error>> + Send;
+	) -> impl Future<Output = Result<Option<Vec<TASK>>, TaskError>> + Send;
 	fn get_task(
 		&self,
 		job_id: &Uuid,
 		task_idx: u32,
-	) -> impl Future<Output = Result<Option<TASK>, std::io::Error>> + Send {
+	) -> impl Future<Output = Result<Option<TASK>, TaskError>> + Send {
 		async move {
 			let tasks = self.get_tasks(job_id).await?;
 			Ok(tasks.and_then(|tasks| tasks.into_iter().nth(task_idx as usize)))
@@ -72,54 +120,164 @@ pub trait JobDb<JOB: Sync, TASK: Sync, STATUS: Sync>: Sync {
 		&self,
 		job_id: &Uuid,
 		task_id: &Uuid,
-	) -> impl Future<Output = Result<Option<Allocated<JOB, TASK>>, std::io::Error>> + Send;
+	) -> impl Future<Output = Result<Option<Allocated<JOB, TASK>>, TaskError>> + Send;
 
 	fn allocate_task(
 		&self,
-	) -> impl Future<Output = Result<Option<(Uuid, Uuid)>, std::io::Error>> + Send;
+	) -> impl Future<Output = Result<Option<(Uuid, Uuid)>, TaskError>> + Send {
+		async move {
+			self.allocate_task_matching(&|_| true, &|_, _| Ordering::Equal)
+				.await
+		}
+	}
+	///Allocate the available task accepted by `fits`, of the first job in `order`
+	///
+	///Tasks of the same job are allocated in the order they were appended
+	fn allocate_task_matching(
+		&self,
+		fits: &TaskFilter<TASK>,
+		order: &JobOrder<JOB>,
+	) -> impl Future<Output = Result<Option<(Uuid, Uuid)>, TaskError>> + Send;
+	///Remove the allocation of a task that was not fulfilled, allowing it to be allocated again
+	fn deallocate(
+		&self,
+		job_id: &Uuid,
+		task_id: &Uuid,
+	) -> impl Future<Output = Result<Option<()>, TaskError>> + Send;
+	///Indexes of the tasks of the job that were not fulfilled, None if the job does not exist
+	fn list_unfulfilled(
+		&self,
+		job_id: &Uuid,
+	) -> impl Future<Output = Result<Option<Vec<u32>>, TaskError>> + Send;
+	///List the allocated tasks that were not fulfilled yet, as (job_id, task_id, task_idx)
+	fn list_allocated(
+		&self,
+	) -> impl Future<Output = Result<Vec<(Uuid, Uuid, u32)>, TaskError>> + Send;
 	///Mark the task as finished, allowing tasks that depend on this task to run
 	fn fulfill(
 		&self,
 		job_id: &Uuid,
 		task_idx: u32,
-	) -> impl Future<Output = Result<(), std::io::Error>> + Send;
+	) -> impl Future<Output = Result<(), TaskError>> + Send;
 	fn get_task_status(
 		&self,
 		job_id: &Uuid,
 		task_idx: u32,
-	) -> impl Future<Output = Result<Option<STATUS>, std::io::Error>> + Send;
+	) -> impl Future<Output = Result<Option<STATUS>, TaskError>> + Send;
 	fn set_task_status(
 		&self,
 		job_id: &Uuid,
 		task_idx: u32,
 		status: STATUS,
-	) -> impl Future<Output = Result<Option<()>, std::io::Error>> + Send;
+	) -> impl Future<Output = Result<Option<()>, TaskError>> + Send;
+	///Set the status after a progress report, which is not worth keeping after a restart
+	fn set_task_progress(
+		&self,
+		job_id: &Uuid,
+		task_idx: u32,
+		status: STATUS,
+	) -> impl Future<Output = Result<Option<()>, TaskError>> + Send {
+		self.set_task_status(job_id, task_idx, status)
+	}
 }
 
 pub(crate) mod local {
 	use std::collections::{BTreeSet, HashMap};
 	use std::io::{Error, ErrorKind};
-	use std::sync::{Mutex, MutexGuard};
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::sync::{mpsc, Mutex, MutexGuard};
 
+	use tokio::sync::oneshot;
+	use tracing::{instrument, warn};
 	use uuid::Uuid;
 
-	use super::{Allocated, JobDb};
+	use super::{Allocated, JobDb, JobOrder, Journal, Mutation, TaskFilter};
+	use crate::TaskError;
 
 	struct Entry<TASK, STATUS> {
 		task: TASK,
 		run_id: Option<Uuid>,
 		dependencies: BTreeSet<u32>,
 		status: Option<STATUS>,
+		fulfilled: bool,
 	}
 
 	type LocalMap<JOB, TASK, STATUS> = HashMap<Uuid, (JOB, Vec<Entry<TASK, STATUS>>)>;
 
+	///Mutations appended before the journal is compacted
+	const COMPACT_AFTER: usize = 10_000;
+
+	enum Write<JOB, TASK, STATUS> {
+		///Append the mutation, sending it back once it is on the disk
+		Append(
+			Mutation<JOB, TASK, STATUS>,
+			oneshot::Sender<Result<Mutation<JOB, TASK, STATUS>, Error>>,
+		),
+		Compact(Vec<Mutation<JOB, TASK, STATUS>>),
+	}
+
+	///Writes the journal on its own thread, the runtime never waits for the disk
+	struct JournalWriter<JOB, TASK, STATUS> {
+		writes: mpsc::Sender<Write<JOB, TASK, STATUS>>,
+	}
+
+	impl<JOB: Send + 'static, TASK: Send + 'static, STATUS: Send + 'static>
+		JournalWriter<JOB, TASK, STATUS>
+	{
+		///Write in order, until the writer is dropped
+		fn spawn(journal: Box<dyn Journal<JOB, TASK, STATUS>>) -> Result<Self, Error> {
+			let (writes, received) = mpsc::channel();
+			std::thread::Builder::new()
+				.name("journal".to_string())
+				.spawn(move || {
+					for write in received {
+						match write {
+							Write::Append(mutation, written) => {
+								let res = journal.append(&mutation).map(|()| mutation);
+								let _ = written.send(res);
+							}
+							Write::Compact(mutations) => {
+								if let Err(err) = journal.compact(&mutations) {
+									warn!(%err, "Failed to compact the journal");
+								}
+							}
+						}
+					}
+				})?;
+			Ok(Self { writes })
+		}
+	}
+
+	impl<JOB, TASK, STATUS> JournalWriter<JOB, TASK, STATUS> {
+		async fn append(
+			&self,
+			mutation: Mutation<JOB, TASK, STATUS>,
+		) -> Result<Mutation<JOB, TASK, STATUS>, Error> {
+			let stopped = || Error::other("Journal writer stopped");
+			let (written, res) = oneshot::channel();
+			self.writes
+				.send(Write::Append(mutation, written))
+				.map_err(|_| stopped())?;
+			res.await.map_err(|_| stopped())?
+		}
+
+		fn compact(&self, mutations: Vec<Mutation<JOB, TASK, STATUS>>) {
+			let _ = self.writes.send(Write::Compact(mutations));
+		}
+	}
+
 	pub struct LocalJobDb<
 		JOB: Sync + Send + Clone,
 		TASK: Sync + Send + Clone,
 		STATUS: Sync + Send + Clone,
 	> {
 		jobs: Mutex<LocalMap<JOB, TASK, STATUS>>,
+		journal: Option<JournalWriter<JOB, TASK, STATUS>>,
+		///Held from reading the mutation inputs until it is applied, the journal keeps the order
+		///the mutations are applied in while the database lock is free during the write
+		commits: tokio::sync::Mutex<()>,
+		///Mutations appended since the journal was compacted
+		appended: AtomicUsize,
 	}
 
 	impl<JOB: Sync + Send + Clone, TASK: Sync + Send + Clone, STATUS: Sync + Send + Clone> Default
@@ -128,58 +286,220 @@ pub(crate) mod local {
 		fn default() -> Self {
 			Self {
 				jobs: Mutex::new(Default::default()),
+				journal: None,
+				commits: Default::default(),
+				appended: AtomicUsize::new(0),
+			}
+		}
+	}
+
+	impl<
+			JOB: Sync + Send + Clone + 'static,
+			TASK: Sync + Send + Clone + 'static,
+			STATUS: Sync + Send + Clone + 'static,
+		> LocalJobDb<JOB, TASK, STATUS>
+	{
+		///Rebuild the database from the journaled mutations, and journal the new ones
+		///
+		///The journal is compacted to the rebuilt database. The new mutations are written by a
+		///thread of the journal
+		pub fn replay(
+			mutations: impl IntoIterator<Item = Mutation<JOB, TASK, STATUS>>,
+			journal: Box<dyn Journal<JOB, TASK, STATUS>>,
+		) -> Result<Self, Error> {
+			let mut jobs = LocalMap::default();
+			for mutation in mutations {
+				apply(&mut jobs, mutation)
+					.ok_or_else(|| Error::new(ErrorKind::InvalidData, "Journal is inconsistent"))?;
 			}
+			journal.compact(&snapshot(&jobs))?;
+			Ok(Self {
+				jobs: Mutex::new(jobs),
+				journal: Some(JournalWriter::spawn(journal)?),
+				commits: Default::default(),
+				appended: AtomicUsize::new(0),
+			})
 		}
 	}
 
 	impl<JOB: Sync + Send + Clone, TASK: Sync + Send + Clone, STATUS: Sync + Send + Clone>
 		LocalJobDb<JOB, TASK, STATUS>
 	{
+		///Tasks waiting to be allocated, with the indexes of their unfinished dependencies
+		pub(crate) fn queued_tasks(&self) -> Vec<(Uuid, u32, Vec<u32>)> {
+			let jobs = self.lock();
+			let tasks = jobs.iter().flat_map(|(job_id, (_, tasks))| {
+				let queued = tasks
+					.iter()
+					.enumerate()
+					.filter(|(_, entry)| entry.run_id.is_none() && !entry.fulfilled);
+				queued.map(|(idx, entry)| {
+					let dependencies = entry.dependencies.iter().copied().collect();
+					(*job_id, idx as u32, dependencies)
+				})
+			});
+			tasks.collect()
+		}
+
 		fn lock(&self) -> MutexGuard<'_, LocalMap<JOB, TASK, STATUS>> {
 			self.jobs
 				.lock()
 				.unwrap_or_else(|poison| poison.into_inner())
 		}
+
+		///Journal the mutation, and only apply it if it was recorded
+		///
+		///The caller holds the commits lock since reading the database for the mutation, the
+		///database lock is not held while the journal is written
+		async fn commit(&self, mutation: Mutation<JOB, TASK, STATUS>) -> Result<(), TaskError> {
+			let Some(journal) = &self.journal else {
+				return apply(&mut self.lock(), mutation).ok_or(TaskError::TaskNotFound);
+			};
+			let mutation = journal.append(mutation).await?;
+			let mut jobs = self.lock();
+			apply(&mut jobs, mutation).ok_or(TaskError::TaskNotFound)?;
+			if self.appended.fetch_add(1, Ordering::Relaxed) + 1 >= COMPACT_AFTER {
+				self.appended.store(0, Ordering::Relaxed);
+				journal.compact(snapshot(&jobs));
+			}
+			Ok(())
+		}
+	}
+
+	fn entry<JOB, TASK, STATUS>(
+		jobs: &mut LocalMap<JOB, TASK, STATUS>,
+		job_id: Uuid,
+		task_idx: u32,
+	) -> Option<&mut Entry<TASK, STATUS>> {
+		jobs.get_mut(&job_id)
+			.and_then(|(_, tasks)| tasks.get_mut(task_idx as usize))
+	}
+
+	///Mutations rebuilding the jobs, from an empty database
+	fn snapshot<JOB: Clone, TASK: Clone, STATUS: Clone>(
+		jobs: &LocalMap<JOB, TASK, STATUS>,
+	) -> Vec<Mutation<JOB, TASK, STATUS>> {
+		let mut mutations = Vec::new();
+		for (&job_id, (job, tasks)) in jobs {
+			let job = job.clone();
+			mutations.push(Mutation::CreateJob { id: job_id, job });
+			for (task_idx, entry) in tasks.iter().enumerate() {
+				let task_idx = task_idx as u32;
+				mutations.push(Mutation::AppendTask {
+					job_id,
+					task: entry.task.clone(),
+					dependencies: entry.dependencies.iter().copied().collect(),
+				});
+				if let Some(run_id) = entry.run_id {
+					mutations.push(Mutation::Allocate {
+						job_id,
+						task_idx,
+						run_id,
+					});
+				}
+				if entry.fulfilled {
+					mutations.push(Mutation::Fulfill { job_id, task_idx });
+				}
+				if let Some(status) = entry.status.clone() {
+					mutations.push(Mutation::SetStatus {
+						job_id,
+						task_idx,
+						status,
+					});
+				}
+			}
+		}
+		mutations
+	}
+
+	fn apply<JOB, TASK, STATUS>(
+		jobs: &mut LocalMap<JOB, TASK, STATUS>,
+		mutation: Mutation<JOB, TASK, STATUS>,
+	) -> Option<()> {
+		match mutation {
+			Mutation::CreateJob { id, job } => {
+				jobs.insert(id, (job, Vec::new()));
+			}
+			Mutation::AppendTask {
+				job_id,
+				task,
+				dependencies,
+			} => jobs.get_mut(&job_id)?.1.push(Entry {
+				task,
+				run_id: None,
+				dependencies: BTreeSet::from_iter(dependencies),
+				status: None,
+				fulfilled: false,
+			}),
+			Mutation::Allocate {
+				job_id,
+				task_idx,
+				run_id,
+			} => entry(jobs, job_id, task_idx)?.run_id = Some(run_id),
+			Mutation::Deallocate { job_id, task_idx } => {
+				entry(jobs, job_id, task_idx)?.run_id = None
+			}
+			Mutation::Fulfill { job_id, task_idx } => {
+				let tasks = &mut jobs.get_mut(&job_id)?.1;
+				tasks.get_mut(task_idx as usize)?.fulfilled = true;
+				for task in tasks.iter_mut().skip(task_idx as usize) {
+					task.dependencies.remove(&task_idx);
+				}
+			}
+			Mutation::SetStatus {
+				job_id,
+				task_idx,
+				status,
+			} => entry(jobs, job_id, task_idx)?.status = Some(status),
+		}
+		Some(())
 	}
 
 	impl<JOB: Sync + Send + Clone, TASK: Sync + Send + Clone, STATUS: Sync + Send + Clone>
 		JobDb<JOB, TASK, STATUS> for LocalJobDb<JOB, TASK, STATUS>
 	{
-		async fn get_job(&self, id: &Uuid) -> Result<Option<JOB>, Error> {
+		async fn get_job(&self, id: &Uuid) -> Result<Option<JOB>, TaskError> {
 			let job = self.lock().get(id).map(|(job, _)| job).cloned();
 			Ok(job)
 		}
 
-		async fn create_job(&self, job: JOB) -> Result<Uuid, Error> {
-			let key = Uuid::new_v4();
-			self.lock().insert(key, (job, Default::default()));
-			Ok(key)
+		#[instrument(level = "debug", skip_all)]
+		async fn create_job(&self, job: JOB) -> Result<Uuid, TaskError> {
+			let _commit = self.commits.lock().await;
+			let id = Uuid::new_v4();
+			self.commit(Mutation::CreateJob { id, job }).await?;
+			Ok(id)
 		}
 
-		async fn list_job_ids(&self) -> Result<Vec<Uuid>, Error> {
+		async fn list_job_ids(&self) -> Result<Vec<Uuid>, TaskError> {
 			Ok(self.lock().keys().cloned().collect())
 		}
 
-		async fn append_task(&self, job_id: &Uuid, task: TASK, dep: &[u32]) -> Result<u32, Error> {
-			let mut guard = self.lock();
-			let job = match guard.get_mut(job_id).map(|(_, tasks)| tasks) {
-				None => return Err(Error::new(ErrorKind::NotFound, "Job not found")),
-				Some(tasks) => tasks,
+		#[instrument(level = "debug", skip(self, task), fields(%job_id))]
+		async fn append_task(
+			&self,
+			job_id: &Uuid,
+			task: TASK,
+			dep: &[u32],
+		) -> Result<u32, TaskError> {
+			let _commit = self.commits.lock().await;
+			let idx = match self.lock().get(job_id).map(|(_, tasks)| tasks) {
+				None => return Err(TaskError::JobNotFound),
+				Some(tasks) => tasks.len(),
 			};
-			let idx = job.len();
 			if dep.iter().any(|x| x >= &(idx as u32)) {
-				return Err(Error::new(ErrorKind::NotFound, "Dependency not found"));
+				return Err(TaskError::DependencyCycle);
 			}
-			job.push(Entry {
+			let mutation = Mutation::AppendTask {
+				job_id: *job_id,
 				task,
-				run_id: None,
-				dependencies: BTreeSet::from_iter(dep.iter().cloned()),
-				status: None,
-			});
+				dependencies: dep.to_vec(),
+			};
+			self.commit(mutation).await?;
 			Ok(idx as u32)
 		}
 
-		async fn get_tasks(&self, job_id: &Uuid) -> Result<Option<Vec<TASK>>, Error> {
+		async fn get_tasks(&self, job_id: &Uuid) -> Result<Option<Vec<TASK>>, TaskError> {
 			Ok(self
 				.lock()
 				.get(job_id)
@@ -190,7 +510,7 @@ pub(crate) mod local {
 			&self,
 			job_id: &Uuid,
 			task_id: &Uuid,
-		) -> Result<Option<Allocated<JOB, TASK>>, Error> {
+		) -> Result<Option<Allocated<JOB, TASK>>, TaskError> {
 			let guard = self.lock();
 			let job = match guard.get(job_id) {
 				None => {
@@ -211,80 +531,308 @@ pub(crate) mod local {
 			Ok(task)
 		}
 
-		async fn allocate_task(&self) -> Result<Option<(Uuid, Uuid)>, Error> {
-			let mut binding = self.lock();
-			let available = binding
-				.iter_mut()
-				.flat_map(|(job_id, (_, tasks))| {
+		#[instrument(level = "debug", skip_all)]
+		async fn allocate_task_matching(
+			&self,
+			fits: &TaskFilter<TASK>,
+			order: &JobOrder<JOB>,
+		) -> Result<Option<(Uuid, Uuid)>, TaskError> {
+			let _commit = self.commits.lock().await;
+			let available = self
+				.lock()
+				.iter()
+				.filter_map(|(job_id, (job, tasks))| {
 					tasks
-						.iter_mut()
-						.filter(|entry| entry.run_id.is_none() && entry.dependencies.is_empty())
-						.map(|task| (*job_id, task))
+						.iter()
+						.position(|entry| {
+							entry.run_id.is_none()
+								&& entry.dependencies.is_empty()
+								&& fits(&entry.task)
+						})
+						.map(|idx| (job_id, job, idx as u32))
 				})
-				.next();
+				.min_by(|(_, a, _), (_, b, _)| order(a, b))
+				.map(|(&job_id, _, task_idx)| (job_id, task_idx));
 			match available {
 				None => Ok(None),
-				Some((job_id, available)) => {
-					let id = Uuid::new_v4();
-					available.run_id = Some(id);
-					Ok(Some((job_id, id)))
+				Some((job_id, task_idx)) => {
+					let run_id = Uuid::new_v4();
+					let mutation = Mutation::Allocate {
+						job_id,
+						task_idx,
+						run_id,
+					};
+					self.commit(mutation).await?;
+					Ok(Some((job_id, run_id)))
 				}
 			}
 		}
 
-		async fn fulfill(&self, job_id: &Uuid, task_idx: u32) -> Result<(), Error> {
-			let mut binding = self.lock();
-			let job = binding
-				.get_mut(job_id)
-				.map(|job| {
-					let found_task = job.1.len() > task_idx as usize;
-					found_task.then_some(job)
+		#[instrument(level = "debug", skip(self), fields(%job_id, %task_id))]
+		async fn deallocate(&self, job_id: &Uuid, task_id: &Uuid) -> Result<Option<()>, TaskError> {
+			let _commit = self.commits.lock().await;
+			let task_idx = self.lock().get(job_id).and_then(|(_, tasks)| {
+				tasks
+					.iter()
+					.position(|entry| entry.run_id.as_ref() == Some(task_id) && !entry.fulfilled)
+			});
+			let task_idx = match task_idx {
+				Some(idx) => idx as u32,
+				None => return Ok(None),
+			};
+			let mutation = Mutation::Deallocate {
+				job_id: *job_id,
+				task_idx,
+			};
+			self.commit(mutation).await.map(Some)
+		}
+
+		async fn list_unfulfilled(&self, job_id: &Uuid) -> Result<Option<Vec<u32>>, TaskError> {
+			let unfulfilled = self.lock().get(job_id).map(|(_, tasks)| {
+				let unfulfilled = tasks
+					.iter()
+					.enumerate()
+					.filter(|(_, entry)| !entry.fulfilled);
+				unfulfilled.map(|(idx, _)| idx as u32).collect()
+			});
+			Ok(unfulfilled)
+		}
+
+		async fn list_allocated(&self) -> Result<Vec<(Uuid, Uuid, u32)>, TaskError> {
+			let binding = self.lock();
+			let allocated = binding
+				.iter()
+				.flat_map(|(job_id, (_, tasks))| {
+					tasks
+						.iter()
+						.enumerate()
+						.filter(|(_, entry)| !entry.fulfilled)
+						.filter_map(|(idx, entry)| entry.run_id.map(|id| (*job_id, id, idx as u32)))
 				})
-				.unwrap_or_default()
-				.ok_or_else(|| Error::new(ErrorKind::NotFound, "Task_not_found"))?;
-			for entry in job.1.iter_mut().skip(task_idx as usize) {
-				entry.dependencies.remove(&task_idx);
+				.collect();
+			Ok(allocated)
+		}
+
+		#[instrument(level = "debug", skip(self), fields(%job_id))]
+		async fn fulfill(&self, job_id: &Uuid, task_idx: u32) -> Result<(), TaskError> {
+			let _commit = self.commits.lock().await;
+			let found_task = self
+				.lock()
+				.get(job_id)
+				.is_some_and(|(_, tasks)| tasks.len() > task_idx as usize);
+			if !found_task {
+				return Err(TaskError::TaskNotFound);
 			}
-			Ok(())
+			let mutation = Mutation::Fulfill {
+				job_id: *job_id,
+				task_idx,
+			};
+			self.commit(mutation).await
 		}
 
 		async fn get_task_status(
 			&self,
 			job_id: &Uuid,
 			task_idx: u32,
-		) -> Result<Option<STATUS>, Error> {
+		) -> Result<Option<STATUS>, TaskError> {
 			let binding = self.lock();
 			let task = binding
 				.get(job_id)
 				.map(|(_, tasks)| tasks.get(task_idx as usize))
 				.unwrap_or_default()
 				.map(|entry| entry.status.clone());
-			task.ok_or_else(|| Error::new(ErrorKind::NotFound, "Task not found"))
+			task.ok_or(TaskError::TaskNotFound)
 		}
 
+		#[instrument(level = "debug", skip(self, status), fields(%job_id))]
 		async fn set_task_status(
 			&self,
 			job_id: &Uuid,
 			task_idx: u32,
 			status: STATUS,
-		) -> Result<Option<()>, Error> {
-			let mut binding = self.lock();
-			let task = binding
-				.get_mut(job_id)
-				.map(|(_, tasks)| tasks.get_mut(task_idx as usize))
-				.unwrap_or_default();
-			Ok(task.map(|entry| entry.status.insert(status)).and(Some(())))
+		) -> Result<Option<()>, TaskError> {
+			let _commit = self.commits.lock().await;
+			let found_task = self
+				.lock()
+				.get(job_id)
+				.is_some_and(|(_, tasks)| tasks.len() > task_idx as usize);
+			if !found_task {
+				return Ok(None);
+			}
+			let mutation = Mutation::SetStatus {
+				job_id: *job_id,
+				task_idx,
+				status,
+			};
+			self.commit(mutation).await.map(Some)
+		}
+
+		///Only kept in memory, the allocations are cancelled after a restart
+		async fn set_task_progress(
+			&self,
+			job_id: &Uuid,
+			task_idx: u32,
+			status: STATUS,
+		) -> Result<Option<()>, TaskError> {
+			let mutation = Mutation::SetStatus {
+				job_id: *job_id,
+				task_idx,
+				status,
+			};
+			Ok(apply(&mut self.lock(), mutation))
 		}
 	}
 
 	#[cfg(test)]
 	mod test {
+		use std::cmp::Ordering;
 		use std::io::ErrorKind;
 
 		use uuid::Uuid;
 
 		use super::JobDb;
 		use super::LocalJobDb;
+		use super::{Journal, Mutation};
+		use crate::TaskError;
+
+		type Recorded = std::sync::Arc<std::sync::Mutex<Vec<Mutation<String, String, u32>>>>;
+
+		#[derive(Default)]
+		struct RecordingJournal(Recorded);
+
+		impl Journal<String, String, u32> for RecordingJournal {
+			fn append(&self, mutation: &Mutation<String, String, u32>) -> std::io::Result<()> {
+				self.0.lock().unwrap().push(mutation.clone());
+				Ok(())
+			}
+
+			fn compact(&self, mutations: &[Mutation<String, String, u32>]) -> std::io::Result<()> {
+				*self.0.lock().unwrap() = mutations.to_vec();
+				Ok(())
+			}
+		}
+
+		struct FailingJournal;
+
+		impl Journal<String, String, u32> for FailingJournal {
+			fn append(&self, _mutation: &Mutation<String, String, u32>) -> std::io::Result<()> {
+				Err(std::io::Error::other("Disk full"))
+			}
+
+			fn compact(&self, _mutations: &[Mutation<String, String, u32>]) -> std::io::Result<()> {
+				Ok(())
+			}
+		}
+
+		async fn db_with_journal() -> (LocalJobDb<String, String, u32>, Recorded) {
+			let journal = RecordingJournal::default();
+			let recorded = journal.0.clone();
+			let db = LocalJobDb::replay(Vec::new(), Box::new(journal)).unwrap();
+			(db, recorded)
+		}
+
+		#[tokio::test]
+		async fn replay_restores_fulfilled_dependencies_and_allocations() {
+			let (db, recorded) = db_with_journal().await;
+			let job_id = db.create_job("JOB".to_string()).await.unwrap();
+			db.append_task(&job_id, "first".to_string(), &[])
+				.await
+				.unwrap();
+			db.append_task(&job_id, "second".to_string(), &[0])
+				.await
+				.unwrap();
+			let (_, first_run) = db.allocate_task().await.unwrap().unwrap();
+			db.set_task_status(&job_id, 0, 7).await.unwrap();
+			db.fulfill(&job_id, 0).await.unwrap();
+			let mutations = recorded.lock().unwrap().clone();
+
+			let restored =
+				LocalJobDb::replay(mutations, Box::new(RecordingJournal::default())).unwrap();
+			let allocated = restored
+				.get_allocated_task(&job_id, &first_run)
+				.await
+				.unwrap();
+			assert_eq!(allocated.map(|task| task.idx), Some(0));
+			assert_eq!(restored.get_task_status(&job_id, 0).await.unwrap(), Some(7));
+			let (_, second_run) = restored
+				.allocate_task()
+				.await
+				.unwrap()
+				.expect("Dependency was fulfilled");
+			let allocated = restored
+				.get_allocated_task(&job_id, &second_run)
+				.await
+				.unwrap();
+			assert_eq!(allocated.map(|task| task.idx), Some(1));
+		}
+
+		#[tokio::test]
+		async fn replay_of_missing_job_invalid_data() {
+			let mutation = Mutation::Fulfill {
+				job_id: Uuid::nil(),
+				task_idx: 0,
+			};
+			let journal = Box::new(RecordingJournal::default());
+			let res = LocalJobDb::replay(vec![mutation], journal);
+			assert_eq!(
+				res.err().map(|err| err.kind()),
+				Some(ErrorKind::InvalidData)
+			);
+		}
+
+		#[tokio::test]
+		async fn replay_compacts_to_the_same_database() {
+			let (db, recorded) = db_with_journal().await;
+			let job_id = db.create_job("JOB".to_string()).await.unwrap();
+			db.append_task(&job_id, "first".to_string(), &[])
+				.await
+				.unwrap();
+			db.append_task(&job_id, "second".to_string(), &[0])
+				.await
+				.unwrap();
+			for status in 0..10 {
+				db.set_task_status(&job_id, 0, status).await.unwrap();
+			}
+			db.fulfill(&job_id, 0).await.unwrap();
+			let mutations = recorded.lock().unwrap().clone();
+
+			let journal = RecordingJournal::default();
+			let compacted = journal.0.clone();
+			let restored = LocalJobDb::replay(mutations, Box::new(journal)).unwrap();
+			assert_eq!(compacted.lock().unwrap().len(), 5);
+			assert_eq!(restored.get_task_status(&job_id, 0).await.unwrap(), Some(9));
+			assert_eq!(restored.queued_tasks(), [(job_id, 1, vec![])]);
+		}
+
+		#[tokio::test]
+		async fn progress_not_journaled() {
+			let (db, recorded) = db_with_journal().await;
+			let job_id = db.create_job("JOB".to_string()).await.unwrap();
+			db.append_task(&job_id, "first".to_string(), &[])
+				.await
+				.unwrap();
+			let res = db.set_task_progress(&job_id, 0, 5).await.unwrap();
+			assert!(res.is_some());
+			assert_eq!(db.get_task_status(&job_id, 0).await.unwrap(), Some(5));
+			assert_eq!(recorded.lock().unwrap().len(), 2);
+		}
+
+		#[tokio::test]
+		async fn failed_deallocate_not_journaled() {
+			let (db, recorded) = db_with_journal().await;
+			let job_id = db.create_job("JOB".to_string()).await.unwrap();
+			let res = db.deallocate(&job_id, &Uuid::nil()).await.unwrap();
+			assert!(res.is_none());
+			assert_eq!(recorded.lock().unwrap().len(), 1);
+		}
+
+		#[tokio::test]
+		async fn unrecorded_mutation_not_applied() {
+			let db = LocalJobDb::replay(Vec::new(), Box::new(FailingJournal)).unwrap();
+			let res = db.create_job("JOB".to_string()).await;
+			assert!(matches!(res, Err(TaskError::StorageUnavailable(_))));
+			assert!(db.list_job_ids().await.unwrap().is_empty())
+		}
 
 		#[tokio::test]
 		async fn get_nonexistent_job_none() {
@@ -309,7 +857,7 @@ pub(crate) mod local {
 			let first_task = manager
 				.append_task(&Uuid::from_u64_pair(1, 2), task, &[])
 				.await;
-			assert_eq!(first_task.unwrap_err().kind(), ErrorKind::NotFound)
+			assert!(matches!(first_task, Err(TaskError::JobNotFound)))
 		}
 
 		#[tokio::test]
@@ -418,6 +966,62 @@ pub(crate) mod local {
 			assert!(!allocation_id.is_nil())
 		}
 
+		#[tokio::test]
+		async fn allocate_task_matching_skips_rejected_tasks() {
+			let manager = LocalJobDb::<String, String, ()>::default();
+			let job_id = manager.create_job("Job 1".to_string()).await.unwrap();
+			for task in ["Large", "Small"] {
+				manager
+					.append_task(&job_id, task.to_string(), &[])
+					.await
+					.unwrap();
+			}
+			let (_, run_id) = manager
+				.allocate_task_matching(&|task| task != "Large", &|_, _| Ordering::Equal)
+				.await
+				.unwrap()
+				.unwrap();
+			let allocated = manager.get_allocated_task(&job_id, &run_id).await.unwrap();
+			assert_eq!(allocated.unwrap().task, "Small");
+			let none = manager
+				.allocate_task_matching(&|task| task != "Large", &|_, _| Ordering::Equal)
+				.await
+				.unwrap();
+			assert!(none.is_none())
+		}
+
+		#[tokio::test]
+		async fn allocate_task_matching_follows_job_order() {
+			let manager = LocalJobDb::<String, String, ()>::default();
+			for job in ["Job 2", "Job 1", "Job 3"] {
+				let job_id = manager.create_job(job.to_string()).await.unwrap();
+				for task in ["Task 1", "Task 2"] {
+					manager
+						.append_task(&job_id, format!("{job} {task}"), &[])
+						.await
+						.unwrap();
+				}
+			}
+			let mut allocated = Vec::new();
+			while let Some((job_id, run_id)) = manager
+				.allocate_task_matching(&|_| true, &|a, b| a.cmp(b))
+				.await
+				.unwrap()
+			{
+				let task = manager.get_allocated_task(&job_id, &run_id).await.unwrap();
+				allocated.push(task.unwrap().task);
+			}
+			let expected = [
+				"Job 1 Task 1",
+				"Job 1 Task 2",
+				"Job 2 Task 1",
+				"Job 2 Task 2",
+				"Job 3 Task 1",
+				"Job 3 Task 2",
+			];
+			assert_eq!(allocated, expected)
+		}
+
 		#[tokio::test]
 		async fn allocate_more_than_available_return_none() {
 			let manager = LocalJobDb::<String, String, ()>::default();
@@ -576,6 +1180,83 @@ pub(crate) mod local {
 			assert_eq!(allocated.idx, task_idx);
 		}
 
+		#[tokio::test]
+		async fn list_allocated_without_allocations_is_empty() {
+			let manager = LocalJobDb::<String, String, ()>::default();
+			let job_id = manager.create_job("Job 1".to_string()).await.unwrap();
+			manager
+				.append_task(&job_id, "Task 1".to_string(), &[])
+				.await
+				.unwrap();
+			let allocated = manager.list_allocated().await.unwrap();
+			assert!(allocated.is_empty())
+		}
+
+		#[tokio::test]
+		async fn list_allocated_contains_allocated_task() {
+			let manager = LocalJobDb::<String, String, ()>::default();
+			let job_id = manager.create_job("Job 1".to_string()).await.unwrap();
+			let task_idx = manager
+				.append_task(&job_id, "Task 1".to_string(), &[])
+				.await
+				.unwrap();
+			let (_, task_id) = manager.allocate_task().await.unwrap().unwrap();
+			let allocated = manager.list_allocated().await.unwrap();
+			assert_eq!(allocated, [(job_id, task_id, task_idx)])
+		}
+
+		#[tokio::test]
+		async fn list_allocated_skips_fulfilled_task() {
+			let manager = LocalJobDb::<String, String, ()>::default();
+			let job_id = manager.create_job("Job 1".to_string()).await.unwrap();
+			let task_idx = manager
+				.append_task(&job_id, "Task 1".to_string(), &[])
+				.await
+				.unwrap();
+			manager.allocate_task().await.unwrap().unwrap();
+			manager.fulfill(&job_id, task_idx).await.unwrap();
+			let allocated = manager.list_allocated().await.unwrap();
+			assert!(allocated.is_empty())
+		}
+
+		#[tokio::test]
+		async fn deallocate_allows_allocating_again() {
+			let manager = LocalJobDb::<String, String, ()>::default();
+			let job_id = manager.create_job("Job 1".to_string()).await.unwrap();
+			manager
+				.append_task(&job_id, "Task 1".to_string(), &[])
+				.await
+				.unwrap();
+			let (_, task_id) = manager.allocate_task().await.unwrap().unwrap();
+			let res = manager.deallocate(&job_id, &task_id).await.unwrap();
+			assert_eq!(res, Some(()));
+			assert!(manager.list_allocated().await.unwrap().is_empty());
+			let (_, new_id) = manager.allocate_task().await.unwrap().unwrap();
+			assert_ne!(new_id, task_id)
+		}
+
+		#[tokio::test]
+		async fn deallocate_unknown_task_none() {
+			let manager = LocalJobDb::<String, String, ()>::default();
+			let job_id = manager.create_job("Job 1".to_string()).await.unwrap();
+			let res = manager.deallocate(&job_id, &Uuid::nil()).await.unwrap();
+			assert_eq!(res, None)
+		}
+
+		#[tokio::test]
+		async fn deallocate_fulfilled_task_none() {
+			let manager = LocalJobDb::<String, String, ()>::default();
+			let job_id = manager.create_job("Job 1".to_string()).await.unwrap();
+			let task_idx = manager
+				.append_task(&job_id, "Task 1".to_string(), &[])
+				.await
+				.unwrap();
+			let (_, task_id) = manager.allocate_task().await.unwrap().unwrap();
+			manager.fulfill(&job_id, task_idx).await.unwrap();
+			let res = manager.deallocate(&job_id, &task_id).await.unwrap();
+			assert_eq!(res, None)
+		}
+
 		#[tokio::test]
 		async fn get_task_status_before_set_returns_none() {
 			let manager = LocalJobDb::<String, String, ()>::default();
@@ -0,0 +1,172 @@
+//! Journal stored as a file, with one json encoded record per line
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::{Journal, Mutation};
+
+pub(crate) struct FileJournal {
+	path: PathBuf,
+	file: Mutex<File>,
+}
+
+impl FileJournal {
+	///Open the journal, creating it if needed, and read the records already written
+	///
+	///A partially written last line, from a crash during the append, is discarded
+	pub(crate) fn open<T: DeserializeOwned>(path: &Path) -> Result<(Self, Vec<T>), Error> {
+		let file = OpenOptions::new()
+			.read(true)
+			.append(true)
+			.create(true)
+			.open(path)?;
+		let mut records = Vec::new();
+		let mut complete = 0;
+		let mut reader = BufReader::new(&file);
+		let mut line = String::new();
+		while reader.read_line(&mut line)? != 0 {
+			if !line.ends_with('\n') {
+				break;
+			}
+			let record = serde_json::from_str(&line)
+				.map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+			records.push(record);
+			complete += line.len() as u64;
+			line.clear();
+		}
+		file.set_len(complete)?;
+		let journal = Self {
+			path: path.to_path_buf(),
+			file: Mutex::new(file),
+		};
+		Ok((journal, records))
+	}
+
+	fn lock(&self) -> MutexGuard<'_, File> {
+		self.file.lock().unwrap_or_else(|err| err.into_inner())
+	}
+
+	///Append the record, waiting for it to reach the disk if `sync`
+	pub(crate) fn write<T: Serialize>(&self, record: &T, sync: bool) -> Result<(), Error> {
+		let mut line = serde_json::to_vec(record).map_err(Error::other)?;
+		line.push(b'\n');
+		let mut file = self.lock();
+		file.write_all(&line)?;
+		match sync {
+			true => file.sync_data(),
+			false => Ok(()),
+		}
+	}
+
+	///Replace the journal with the records, through a new file renamed over it
+	pub(crate) fn rewrite<T: Serialize>(
+		&self,
+		records: impl IntoIterator<Item = T>,
+	) -> Result<(), Error> {
+		let mut file = self.lock();
+		let tmp = self.path.with_extension("compact");
+		let mut writer = BufWriter::new(File::create(&tmp)?);
+		for record in records {
+			serde_json::to_writer(&mut writer, &record).map_err(Error::other)?;
+			writer.write_all(b"\n")?;
+		}
+		writer
+			.into_inner()
+			.map_err(|err| err.into_error())?
+			.sync_all()?;
+		std::fs::rename(&tmp, &self.path)?;
+		*file = OpenOptions::new().append(true).open(&self.path)?;
+		Ok(())
+	}
+}
+
+impl<JOB, TASK, STATUS> Journal<JOB, TASK, STATUS> for FileJournal
+where
+	Mutation<JOB, TASK, STATUS>: Serialize,
+{
+	fn append(&self, mutation: &Mutation<JOB, TASK, STATUS>) -> Result<(), Error> {
+		self.write(mutation, true)
+	}
+
+	fn compact(&self, mutations: &[Mutation<JOB, TASK, STATUS>]) -> Result<(), Error> {
+		self.rewrite(mutations)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::io::Write;
+
+	use uuid::Uuid;
+
+	use super::*;
+
+	type TestMutation = Mutation<String, String, u32>;
+
+	fn journal_path(name: &str) -> std::path::PathBuf {
+		let path = std::env::temp_dir().join(format!("{name}-{}.journal", Uuid::new_v4()));
+		let _ = std::fs::remove_file(&path);
+		path
+	}
+
+	#[test]
+	fn open_reads_appended_mutations() {
+		let path = journal_path("append");
+		let mutation = TestMutation::CreateJob {
+			id: Uuid::nil(),
+			job: "job".to_string(),
+		};
+		let (journal, recorded) = FileJournal::open::<TestMutation>(&path).unwrap();
+		assert!(recorded.is_empty());
+		journal.append(&mutation).unwrap();
+		drop(journal);
+		let (_, recorded) = FileJournal::open::<TestMutation>(&path).unwrap();
+		std::fs::remove_file(&path).unwrap();
+		assert_eq!(recorded, [mutation]);
+	}
+
+	#[test]
+	fn open_discards_partial_line() {
+		let path = journal_path("partial");
+		let mutation = TestMutation::Fulfill {
+			job_id: Uuid::nil(),
+			task_idx: 0,
+		};
+		let (journal, _) = FileJournal::open::<TestMutation>(&path).unwrap();
+		journal.append(&mutation).unwrap();
+		drop(journal);
+		let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+		file.write_all(b"{\"Fulfill\":").unwrap();
+		drop(file);
+		let (journal, recorded) = FileJournal::open::<TestMutation>(&path).unwrap();
+		journal.append(&mutation).unwrap();
+		drop(journal);
+		let (_, after) = FileJournal::open::<TestMutation>(&path).unwrap();
+		std::fs::remove_file(&path).unwrap();
+		assert_eq!(recorded.len(), 1);
+		assert_eq!(after.len(), 2);
+	}
+
+	#[test]
+	fn compact_replaces_the_records() {
+		let path = journal_path("compact");
+		let mutation = |task_idx| TestMutation::Fulfill {
+			job_id: Uuid::nil(),
+			task_idx,
+		};
+		let (journal, _) = FileJournal::open::<TestMutation>(&path).unwrap();
+		journal.append(&mutation(0)).unwrap();
+		journal.append(&mutation(1)).unwrap();
+		journal.compact(&[mutation(2)]).unwrap();
+		journal.append(&mutation(3)).unwrap();
+		drop(journal);
+		let (_, recorded) = FileJournal::open::<TestMutation>(&path).unwrap();
+		std::fs::remove_file(&path).unwrap();
+		assert_eq!(recorded, [mutation(2), mutation(3)]);
+	}
+}
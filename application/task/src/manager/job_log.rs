@@ -0,0 +1,237 @@
+//! Timeline of the job and task transitions, kept for debugging slow or failed jobs
+
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::manager::db::journal::FileJournal;
+use crate::{JobLogEntry, JobLogEvent};
+
+///Encoded seconds between the recorded progress reports of a task
+const PROGRESS_MILESTONE: f64 = 60.0;
+///Entries kept per job, the oldest are dropped first
+const MAX_JOB_ENTRIES: usize = 1000;
+///Time after the last transition a job log is kept
+const RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+///Entries appended to the file before it is compacted
+const COMPACT_AFTER: usize = 10_000;
+
+type Logs = HashMap<Uuid, Vec<JobLogEntry>>;
+
+#[derive(Default)]
+pub(crate) struct JobLog {
+	entries: Mutex<Logs>,
+	///File the entries are appended to, without waiting for the disk
+	journal: Option<FileJournal>,
+	///Entries appended since the file was compacted
+	appended: AtomicUsize,
+}
+
+impl JobLog {
+	///Restore the entries recorded on the file, and record the new ones to it
+	pub(crate) fn restore(path: &Path) -> io::Result<Self> {
+		let (journal, records) = FileJournal::open::<(Uuid, JobLogEntry)>(path)?;
+		let mut logs = Logs::new();
+		for (job_id, entry) in records {
+			logs.entry(job_id).or_default().push(entry);
+		}
+		expire(&mut logs, SystemTime::now());
+		for entries in logs.values_mut() {
+			truncate(entries, MAX_JOB_ENTRIES);
+		}
+		journal.rewrite(records_of(&logs))?;
+		Ok(Self {
+			entries: Mutex::new(logs),
+			journal: Some(journal),
+			appended: AtomicUsize::new(0),
+		})
+	}
+
+	fn lock(&self) -> MutexGuard<'_, Logs> {
+		self.entries.lock().unwrap_or_else(|err| err.into_inner())
+	}
+
+	pub(crate) fn record(&self, job_id: Uuid, task_idx: Option<u32>, event: JobLogEvent) {
+		self.push(&mut self.lock(), job_id, task_idx, event);
+	}
+
+	///Append the entry, expiring the idle job logs when a new one starts
+	fn push(&self, logs: &mut Logs, job_id: Uuid, task_idx: Option<u32>, event: JobLogEvent) {
+		let entry = JobLogEntry {
+			at: SystemTime::now(),
+			task_idx,
+			event,
+		};
+		if let Some(journal) = &self.journal {
+			if let Err(err) = journal.write(&(job_id, &entry), false) {
+				warn!(%err, "Failed to record the job log");
+			}
+		}
+		if !logs.contains_key(&job_id) {
+			expire(logs, entry.at);
+		}
+		let entries = logs.entry(job_id).or_default();
+		truncate(entries, MAX_JOB_ENTRIES - 1);
+		entries.push(entry);
+		self.compact(logs);
+	}
+
+	///Rewrite the file with the kept entries, after enough were appended
+	fn compact(&self, logs: &Logs) {
+		let Some(journal) = &self.journal else {
+			return;
+		};
+		if self.appended.fetch_add(1, Ordering::Relaxed) + 1 < COMPACT_AFTER {
+			return;
+		}
+		self.appended.store(0, Ordering::Relaxed);
+		if let Err(err) = journal.rewrite(records_of(logs)) {
+			warn!(%err, "Failed to compact the job log");
+		}
+	}
+
+	///Record the progress of the first report, and after each milestone since the allocation
+	pub(crate) fn progress(&self, job_id: Uuid, task_idx: u32, progress: f64) {
+		let mut logs = self.lock();
+		let last = logs.get(&job_id).and_then(|entries| {
+			entries
+				.iter()
+				.rev()
+				.filter(|entry| entry.task_idx == Some(task_idx))
+				.find_map(|entry| match entry.event {
+					JobLogEvent::Progress { progress } => Some(Some(progress)),
+					JobLogEvent::Allocated { .. } => Some(None),
+					_ => None,
+				})
+				.flatten()
+		});
+		match last {
+			Some(last) if progress < last + PROGRESS_MILESTONE => {}
+			_ => {
+				let event = JobLogEvent::Progress { progress };
+				self.push(&mut logs, job_id, Some(task_idx), event)
+			}
+		}
+	}
+
+	pub(crate) fn get(&self, job_id: &Uuid) -> Vec<JobLogEntry> {
+		self.lock().get(job_id).cloned().unwrap_or_default()
+	}
+}
+
+///Drop the logs without transitions for the retention time
+fn expire(logs: &mut Logs, now: SystemTime) {
+	logs.retain(|_, entries| {
+		let last = entries.last().map_or(now, |entry| entry.at);
+		now.duration_since(last).unwrap_or_default() < RETENTION
+	});
+}
+
+///Drop the oldest entries, keeping at most `max`
+fn truncate(entries: &mut Vec<JobLogEntry>, max: usize) {
+	if entries.len() > max {
+		entries.drain(..entries.len() - max);
+	}
+}
+
+fn records_of(logs: &Logs) -> impl Iterator<Item = (&Uuid, &JobLogEntry)> {
+	logs.iter()
+		.flat_map(|(job_id, entries)| entries.iter().map(move |entry| (job_id, entry)))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn progress_entries(log: &JobLog) -> Vec<f64> {
+		log.get(&Uuid::nil())
+			.into_iter()
+			.filter_map(|entry| match entry.event {
+				JobLogEvent::Progress { progress } => Some(progress),
+				_ => None,
+			})
+			.collect()
+	}
+
+	#[test]
+	fn record_keeps_order() {
+		let log = JobLog::default();
+		log.record(Uuid::nil(), None, JobLogEvent::Created);
+		log.record(Uuid::nil(), Some(0), JobLogEvent::TaskAdded);
+		let events: Vec<_> = log
+			.get(&Uuid::nil())
+			.into_iter()
+			.map(|entry| entry.event)
+			.collect();
+		assert_eq!(events, [JobLogEvent::Created, JobLogEvent::TaskAdded])
+	}
+
+	#[test]
+	fn progress_only_recorded_on_milestones() {
+		let log = JobLog::default();
+		for progress in [1.0, 30.0, 61.0, 90.0, 121.0] {
+			log.progress(Uuid::nil(), 0, progress);
+		}
+		assert_eq!(progress_entries(&log), [1.0, 61.0, 121.0])
+	}
+
+	#[test]
+	fn record_drops_oldest_entries_over_limit() {
+		let log = JobLog::default();
+		log.record(Uuid::nil(), None, JobLogEvent::Created);
+		for _ in 0..MAX_JOB_ENTRIES {
+			log.record(Uuid::nil(), Some(0), JobLogEvent::Cancelled);
+		}
+		let entries = log.get(&Uuid::nil());
+		assert_eq!(entries.len(), MAX_JOB_ENTRIES);
+		assert_eq!(entries[0].event, JobLogEvent::Cancelled)
+	}
+
+	#[test]
+	fn new_job_expires_idle_logs() {
+		let log = JobLog::default();
+		let idle = JobLogEntry {
+			at: SystemTime::now() - RETENTION,
+			task_idx: None,
+			event: JobLogEvent::Created,
+		};
+		log.lock().insert(Uuid::nil(), vec![idle]);
+		log.record(Uuid::max(), None, JobLogEvent::Created);
+		assert!(log.get(&Uuid::nil()).is_empty());
+		assert_eq!(log.get(&Uuid::max()).len(), 1)
+	}
+
+	#[test]
+	fn restore_reads_recorded_entries() {
+		let path = std::env::temp_dir().join(format!("job-{}.log", Uuid::new_v4()));
+		let log = JobLog::restore(&path).unwrap();
+		log.record(Uuid::nil(), None, JobLogEvent::Created);
+		log.progress(Uuid::nil(), 0, 1.0);
+		drop(log);
+		let restored = JobLog::restore(&path).unwrap();
+		std::fs::remove_file(&path).unwrap();
+		let events: Vec<_> = restored
+			.get(&Uuid::nil())
+			.into_iter()
+			.map(|entry| entry.event)
+			.collect();
+		let progress = JobLogEvent::Progress { progress: 1.0 };
+		assert_eq!(events, [JobLogEvent::Created, progress])
+	}
+
+	#[test]
+	fn progress_recorded_again_after_new_allocation() {
+		let log = JobLog::default();
+		log.progress(Uuid::nil(), 0, 10.0);
+		let worker = "worker".to_string();
+		log.record(Uuid::nil(), Some(0), JobLogEvent::Allocated { worker });
+		log.progress(Uuid::nil(), 0, 5.0);
+		assert_eq!(progress_entries(&log), [10.0, 5.0])
+	}
+}
@@ -0,0 +1,184 @@
+//! Scheduler performance counters, recorded by the manager to diagnose allocation bottlenecks
+
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+
+///Snapshot of the scheduler counters
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SchedulerStats {
+	///Tasks allocated to workers
+	pub allocations: u64,
+	///Time spent allocating tasks, in seconds
+	pub allocation_seconds: f64,
+	///Tasks ready to be allocated, with all dependencies finished
+	pub ready_tasks: u64,
+	///Time the allocated tasks waited after becoming ready, in seconds
+	pub queue_seconds: f64,
+	///Tasks finished by workers
+	pub completed: u64,
+	///Finished tasks per second, since the manager was created
+	pub tasks_per_second: f64,
+	///Tasks finished by workers in the last hour
+	pub completed_last_hour: u64,
+}
+
+const HOUR: Duration = Duration::from_secs(60 * 60);
+
+struct Queued {
+	///Dependencies not finished yet
+	pending: BTreeSet<u32>,
+	ready_since: Option<Instant>,
+}
+
+#[derive(Default)]
+struct Counters {
+	///Tasks not allocated yet, by job and task index
+	queued: HashMap<(Uuid, u32), Queued>,
+	allocations: u64,
+	allocation_time: Duration,
+	queue_time: Duration,
+	completed: u64,
+	///When the tasks finished in the last hour were finished
+	recent: VecDeque<Instant>,
+}
+
+impl Counters {
+	fn forget_older_than_hour(&mut self, now: Instant) {
+		while let Some(finished) = self.recent.front() {
+			if now.duration_since(*finished) < HOUR {
+				break;
+			}
+			self.recent.pop_front();
+		}
+	}
+}
+
+pub(crate) struct Scheduler {
+	started: Instant,
+	counters: Mutex<Counters>,
+}
+
+impl Default for Scheduler {
+	fn default() -> Self {
+		Self {
+			started: Instant::now(),
+			counters: Default::default(),
+		}
+	}
+}
+
+impl Scheduler {
+	fn lock(&self) -> MutexGuard<'_, Counters> {
+		self.counters.lock().unwrap_or_else(|err| err.into_inner())
+	}
+
+	pub(crate) fn added(&self, job_id: Uuid, idx: u32, deps: &[u32]) {
+		let pending: BTreeSet<u32> = deps.iter().copied().collect();
+		let ready_since = pending.is_empty().then(Instant::now);
+		let queued = Queued {
+			pending,
+			ready_since,
+		};
+		self.lock().queued.insert((job_id, idx), queued);
+	}
+
+	pub(crate) fn allocated(&self, job_id: Uuid, idx: u32, latency: Duration) {
+		let mut counters = self.lock();
+		counters.allocations += 1;
+		counters.allocation_time += latency;
+		let waited = counters
+			.queued
+			.remove(&(job_id, idx))
+			.and_then(|queued| queued.ready_since)
+			.map(|ready| ready.elapsed())
+			.unwrap_or_default();
+		counters.queue_time += waited;
+	}
+
+	pub(crate) fn fulfilled(&self, job_id: Uuid, idx: u32) {
+		let mut counters = self.lock();
+		counters.completed += 1;
+		let now = Instant::now();
+		counters.recent.push_back(now);
+		counters.forget_older_than_hour(now);
+		let dependents = counters
+			.queued
+			.iter_mut()
+			.filter(|((job, _), _)| *job == job_id)
+			.map(|(_, queued)| queued);
+		for queued in dependents {
+			if queued.pending.remove(&idx) && queued.pending.is_empty() {
+				queued.ready_since = Some(now);
+			}
+		}
+	}
+
+	///The allocation was cancelled, the task is ready again
+	pub(crate) fn cancelled(&self, job_id: Uuid, idx: u32) {
+		self.added(job_id, idx, &[]);
+	}
+
+	pub(crate) fn stats(&self) -> SchedulerStats {
+		let mut counters = self.lock();
+		counters.forget_older_than_hour(Instant::now());
+		let ready_tasks = counters
+			.queued
+			.values()
+			.filter(|queued| queued.ready_since.is_some())
+			.count();
+		SchedulerStats {
+			allocations: counters.allocations,
+			allocation_seconds: counters.allocation_time.as_secs_f64(),
+			ready_tasks: ready_tasks as u64,
+			queue_seconds: counters.queue_time.as_secs_f64(),
+			completed: counters.completed,
+			tasks_per_second: counters.completed as f64 / self.started.elapsed().as_secs_f64(),
+			completed_last_hour: counters.recent.len() as u64,
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn added_without_dependencies_is_ready() {
+		let scheduler = Scheduler::default();
+		scheduler.added(Uuid::nil(), 0, &[]);
+		assert_eq!(scheduler.stats().ready_tasks, 1)
+	}
+
+	#[test]
+	fn dependent_is_ready_after_dependencies_fulfilled() {
+		let scheduler = Scheduler::default();
+		scheduler.added(Uuid::nil(), 2, &[0, 1]);
+		scheduler.fulfilled(Uuid::nil(), 0);
+		assert_eq!(scheduler.stats().ready_tasks, 0);
+		scheduler.fulfilled(Uuid::nil(), 1);
+		assert_eq!(scheduler.stats().ready_tasks, 1)
+	}
+
+	#[test]
+	fn fulfilled_counts_in_last_hour() {
+		let scheduler = Scheduler::default();
+		scheduler.fulfilled(Uuid::nil(), 0);
+		let stats = scheduler.stats();
+		assert_eq!(stats.completed, 1);
+		assert_eq!(stats.completed_last_hour, 1)
+	}
+
+	#[test]
+	fn allocated_leaves_the_queue() {
+		let scheduler = Scheduler::default();
+		scheduler.added(Uuid::nil(), 0, &[]);
+		scheduler.allocated(Uuid::nil(), 0, Duration::from_millis(5));
+		let stats = scheduler.stats();
+		assert_eq!(stats.ready_tasks, 0);
+		assert_eq!(stats.allocations, 1);
+		assert_eq!(stats.allocation_seconds, 0.005)
+	}
+}
@@ -0,0 +1,159 @@
+//! Named encode presets, that jobs reference instead of listing the codec and its params
+//!
+//! The manager expands the preset when the job is created, so workers only see concrete options
+
+use crate::{JobOptions, Options, TaskError};
+
+struct Preset {
+	name: &'static str,
+	codec: &'static str,
+	params: &'static [&'static str],
+}
+
+const PRESETS: [Preset; 6] = [
+	Preset {
+		name: "x264-medium",
+		codec: "libx264",
+		params: &["-preset", "medium", "-crf", "23"],
+	},
+	Preset {
+		name: "x264-slow",
+		codec: "libx264",
+		params: &["-preset", "slow", "-crf", "20"],
+	},
+	Preset {
+		name: "x265-medium",
+		codec: "libx265",
+		params: &["-preset", "medium", "-crf", "28"],
+	},
+	Preset {
+		name: "vp9-good",
+		codec: "libvpx-vp9",
+		params: &["-deadline", "good", "-b:v", "0", "-crf", "31"],
+	},
+	Preset {
+		name: "av1-svt-q30",
+		codec: "libsvtav1",
+		params: &["-preset", "6", "-crf", "30"],
+	},
+	Preset {
+		name: "av1-svt-q60",
+		codec: "libsvtav1",
+		params: &["-preset", "8", "-crf", "60"],
+	},
+];
+
+///Names of the available presets
+pub fn names() -> impl Iterator<Item = &'static str> {
+	PRESETS.iter().map(|preset| preset.name)
+}
+
+///Video codec and params of the preset
+pub fn get(name: &str) -> Option<Options> {
+	let preset = PRESETS.iter().find(|preset| preset.name == name)?;
+	Some(Options {
+		codec: Some(preset.codec.to_string()),
+		params: preset
+			.params
+			.iter()
+			.map(|param| param.to_string())
+			.collect(),
+	})
+}
+
+///Replace the preset referenced by the job with its codec and params
+///
+///The job codec takes precedence, and the job params follow the preset ones so they override them.
+///The preset params only apply to the preset codec, so they are left out when the job codec is another one
+pub fn expand(options: JobOptions) -> Result<JobOptions, TaskError> {
+	let Some(name) = &options.preset else {
+		return Ok(options);
+	};
+	let preset = get(name).ok_or_else(|| TaskError::UnknownPreset(name.clone()))?;
+	let other_codec = options.video.codec.is_some() && options.video.codec != preset.codec;
+	let preset_params = if other_codec {
+		Vec::new()
+	} else {
+		preset.params
+	};
+	let video = Options {
+		codec: options.video.codec.or(preset.codec),
+		params: preset_params
+			.into_iter()
+			.chain(options.video.params)
+			.collect(),
+	};
+	Ok(JobOptions {
+		video,
+		preset: None,
+		..options
+	})
+}
+
+#[cfg(test)]
+mod test {
+	use crate::Container;
+
+	use super::*;
+
+	fn job_options(preset: Option<&str>, codec: Option<&str>, params: &[&str]) -> JobOptions {
+		JobOptions {
+			video: Options {
+				codec: codec.map(String::from),
+				params: params.iter().map(|param| param.to_string()).collect(),
+			},
+			audio: None,
+			two_pass: false,
+			container: Container::Matroska,
+			preset: preset.map(String::from),
+			renditions: vec![],
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn every_preset_has_a_codec() {
+		for name in names() {
+			assert!(get(name).unwrap().codec.is_some(), "{name}")
+		}
+	}
+
+	#[test]
+	fn expand_without_preset_keeps_options() {
+		let options = job_options(None, Some("libx264"), &["-crf", "20"]);
+		assert_eq!(expand(options.clone()).unwrap(), options)
+	}
+
+	#[test]
+	fn expand_uses_preset_codec_and_params() {
+		let expanded = expand(job_options(Some("x264-medium"), None, &[])).unwrap();
+		let expected = job_options(None, Some("libx264"), &["-preset", "medium", "-crf", "23"]);
+		assert_eq!(expanded, expected)
+	}
+
+	#[test]
+	fn expand_appends_job_params_after_preset() {
+		let expanded = expand(job_options(Some("av1-svt-q60"), None, &["-g", "240"])).unwrap();
+		assert_eq!(expanded.video.params[4..], ["-g", "240"])
+	}
+
+	#[test]
+	fn expand_same_codec_keeps_preset_params() {
+		let expanded = expand(job_options(Some("x265-medium"), Some("libx265"), &[])).unwrap();
+		let expected = job_options(None, Some("libx265"), &["-preset", "medium", "-crf", "28"]);
+		assert_eq!(expanded, expected)
+	}
+
+	#[test]
+	fn expand_other_codec_drops_preset_params() {
+		let options = job_options(Some("x264-medium"), Some("libvpx-vp9"), &["-crf", "30"]);
+		let expected = job_options(None, Some("libvpx-vp9"), &["-crf", "30"]);
+		assert_eq!(expand(options).unwrap(), expected)
+	}
+
+	#[test]
+	fn expand_unknown_preset_fails() {
+		let result = expand(job_options(Some("missing"), None, &[]));
+		assert!(matches!(result, Err(TaskError::UnknownPreset(name)) if name == "missing"))
+	}
+}
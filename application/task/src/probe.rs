@@ -0,0 +1,108 @@
+//! Parse the ffprobe json output into a [MediaProbe]
+//!
+//! The server probes the job sources and the workers probe their inputs and outputs, both read the
+//! entries listed in [SHOW_ENTRIES]
+
+use serde::Deserialize;
+
+use crate::{MediaProbe, StreamProbe};
+
+///Value of the `-show_entries` argument, with every field of the [MediaProbe]
+pub const SHOW_ENTRIES: &str = "format=duration,bit_rate,format_name:stream=index,codec_type,codec_name,bit_rate,width,height,sample_rate,channels";
+
+#[derive(Deserialize)]
+struct Output {
+	#[serde(default)]
+	streams: Vec<Stream>,
+	format: Option<Format>,
+}
+
+///ffprobe prints most numbers of the json output as strings
+#[derive(Deserialize)]
+struct Stream {
+	index: u32,
+	codec_type: Option<String>,
+	codec_name: Option<String>,
+	bit_rate: Option<String>,
+	width: Option<u32>,
+	height: Option<u32>,
+	sample_rate: Option<String>,
+	channels: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct Format {
+	duration: Option<String>,
+	bit_rate: Option<String>,
+	format_name: Option<String>,
+}
+
+///Parse the output of ffprobe run with `-of json` and the [SHOW_ENTRIES]
+pub fn parse(output: &[u8]) -> Result<MediaProbe, serde_json::Error> {
+	let output: Output = serde_json::from_slice(output)?;
+	let format = output.format;
+	let streams = output
+		.streams
+		.into_iter()
+		.map(|stream| StreamProbe {
+			index: stream.index,
+			codec_type: stream.codec_type,
+			codec_name: stream.codec_name,
+			bit_rate: stream.bit_rate.and_then(|rate| rate.parse().ok()),
+			width: stream.width,
+			height: stream.height,
+			sample_rate: stream.sample_rate.and_then(|rate| rate.parse().ok()),
+			channels: stream.channels,
+		})
+		.collect();
+	Ok(MediaProbe {
+		duration: format
+			.as_ref()
+			.and_then(|format| format.duration.as_ref()?.parse().ok()),
+		bit_rate: format
+			.as_ref()
+			.and_then(|format| format.bit_rate.as_ref()?.parse().ok()),
+		format_name: format.and_then(|format| format.format_name),
+		streams,
+	})
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn parse_converts_string_numbers() {
+		let output = br#"{
+			"programs": [],
+			"streams": [
+				{"index": 0, "codec_name": "vp9", "codec_type": "video", "width": 1280, "height": 720},
+				{"index": 1, "codec_name": "opus", "codec_type": "audio", "sample_rate": "48000", "channels": 2, "bit_rate": "96000"}
+			],
+			"format": {"format_name": "matroska,webm", "duration": "10.010000", "bit_rate": "1200000"}
+		}"#;
+		let probe = parse(output).unwrap();
+		assert_eq!(probe.duration, Some(10.01));
+		assert_eq!(probe.bit_rate, Some(1200000));
+		assert_eq!(probe.format_name.as_deref(), Some("matroska,webm"));
+		assert_eq!(probe.streams[0].width, Some(1280));
+		assert_eq!(probe.streams[1].sample_rate, Some(48000));
+		assert_eq!(probe.streams[1].bit_rate, Some(96000));
+	}
+
+	#[test]
+	fn parse_without_duration() {
+		let output = br#"{"streams":[{"index":0,"width":8,"height":8}],"format":{}}"#;
+		assert_eq!(parse(output).unwrap().duration, None)
+	}
+
+	#[test]
+	fn video_is_the_first_video_stream() {
+		let output = br#"{"streams":[
+			{"index":0,"codec_type":"audio"},
+			{"index":1,"codec_type":"video","width":640,"height":360}
+		]}"#;
+		let probe = parse(output).unwrap();
+		assert_eq!(probe.video().map(|stream| stream.index), Some(1))
+	}
+}
@@ -0,0 +1,364 @@
+//! Split the job source into segments, from the analysis of the job source
+//!
+//! The analysis task uploads an [AnalysisArtifact] as its output, the server creates the transcodes
+//! of the segments from it
+
+use serde::{Deserialize, Serialize};
+
+use crate::{AnalysisOptions, Container, Input, JobOptions, Output, Recipe, TaskSource};
+
+///Segment duration used when the job does not specify one
+pub const DEFAULT_SEGMENT_DURATION: f64 = 30.0;
+
+///Output of the analysis task, uploaded as json like `{"duration":61.5,"cuts":[0.0,4.2,10.01]}`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AnalysisArtifact {
+	///Duration of the job source, in seconds
+	pub duration: f64,
+	///Timestamps the source may be cut at, its keyframes or scene changes, in seconds
+	pub cuts: Vec<f64>,
+}
+
+impl AnalysisArtifact {
+	///Check the durations are numbers ffmpeg accepts
+	pub fn validate(&self) -> Result<(), &'static str> {
+		if !self.duration.is_finite() || self.duration <= 0.0 {
+			return Err("the duration must be positive");
+		}
+		if self.cuts.iter().any(|cut| !cut.is_finite()) {
+			return Err("the cuts must be finite");
+		}
+		Ok(())
+	}
+}
+
+///Split the source at the keyframes, each segment will be at least `target` seconds long, except
+///the last one
+///
+///A non-positive target results in a single segment
+pub fn split(duration: f64, keyframes: &[f64], target: f64) -> Vec<Input> {
+	if target <= 0.0 {
+		return vec![Input::source()];
+	}
+	let mut cuts = Vec::new();
+	let mut last = 0.0;
+	for &keyframe in keyframes {
+		if keyframe - last >= target && keyframe < duration {
+			cuts.push(keyframe);
+			last = keyframe;
+		}
+	}
+	let starts = std::iter::once(None).chain(cuts.iter().copied().map(Some));
+	let ends = cuts.iter().copied().map(Some).chain(std::iter::once(None));
+	starts
+		.zip(ends)
+		.map(|(start, end)| Input {
+			index: 0,
+			start,
+			end,
+			..Default::default()
+		})
+		.collect()
+}
+
+///Split the clip of the source between `start` and `end`, like [split] splits the whole source
+pub fn split_clip(
+	start: Option<f64>,
+	end: Option<f64>,
+	duration: f64,
+	keyframes: &[f64],
+	target: f64,
+) -> Vec<Input> {
+	let offset = start.unwrap_or(0.0);
+	let length = end.map_or(duration, |end| end.min(duration)) - offset;
+	let keyframes: Vec<f64> = keyframes.iter().map(|keyframe| keyframe - offset).collect();
+	split(length, &keyframes, target)
+		.into_iter()
+		.map(|segment| Input {
+			start: segment.start.map(|start| start + offset).or(start),
+			end: segment.end.map(|end| end + offset).or(end),
+			..segment
+		})
+		.collect()
+}
+
+///Enlarge the target duration so splitting the source creates at most `max_segments` segments
+pub fn limit_target(duration: f64, target: f64, max_segments: Option<u32>) -> f64 {
+	match max_segments {
+		Some(max) if target > 0.0 => target.max(duration / max.max(1) as f64),
+		_ => target,
+	}
+}
+
+///Create the task that concatenates the outputs of the tasks, in order
+///
+///The source input, when given, follows the tasks so the merge can copy its subtitles. It should
+///have the range of the clip that was split, for the subtitles to match the segments
+pub fn merge(tasks: &[u32], source: Option<Input>) -> TaskSource {
+	TaskSource {
+		inputs: tasks
+			.iter()
+			.map(|&index| Input {
+				index,
+				..Input::source()
+			})
+			.chain(source)
+			.collect(),
+		recipe: Recipe::Merge((0..tasks.len() as u32).collect()),
+		..Default::default()
+	}
+}
+
+///Create the transcodes of the segments of the analysed clip, for each rendition of the job
+///
+///Segments are encoded to matroska when they are merged, the transcode of a single segment writes
+///the output of the job, or of the rendition
+pub fn transcodes(
+	artifact: &AnalysisArtifact,
+	clip: &Input,
+	options: &AnalysisOptions,
+	job: &JobOptions,
+) -> Vec<TaskSource> {
+	let mut cuts = artifact.cuts.clone();
+	cuts.sort_by(f64::total_cmp);
+	let duration = artifact.duration;
+	let clip_duration =
+		clip.end.map_or(duration, |end| end.min(duration)) - clip.start.unwrap_or(0.0);
+	let target = limit_target(
+		clip_duration,
+		options.duration.unwrap_or(DEFAULT_SEGMENT_DURATION),
+		options.max_segments,
+	);
+	let segments = split_clip(clip.start, clip.end, duration, &cuts, target);
+	let merged = segments.len() > 1;
+	//Each rendition is encoded by its own chain of transcodes and merge
+	let renditions: Vec<_> = match job.renditions.len() as u32 {
+		0 => vec![None],
+		count => (0..count).map(Some).collect(),
+	};
+	let mut tasks = Vec::with_capacity(renditions.len() * segments.len());
+	for rendition in renditions {
+		let name = rendition
+			.and_then(|pos| job.renditions.get(pos as usize))
+			.map(|rendition| format!("-{}", rendition.name))
+			.unwrap_or_default();
+		for (idx, segment) in segments.iter().enumerate() {
+			let output = match merged {
+				true => Output {
+					container: Some(Container::Matroska),
+					suffix: Some(format!("{name}-segment{idx}")),
+					rendition,
+				},
+				false => Output {
+					suffix: rendition.is_some().then(|| name.clone()),
+					rendition,
+					..Default::default()
+				},
+			};
+			//The segments read the streams selected for the clip
+			let input = Input {
+				video_stream: clip.video_stream,
+				audio_stream: clip.audio_stream,
+				..segment.clone()
+			};
+			tasks.push(TaskSource {
+				inputs: vec![input],
+				recipe: Recipe::Transcode(Default::default()),
+				output,
+				..Default::default()
+			});
+		}
+	}
+	tasks
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	const KEYFRAMES: [f64; 6] = [0.0, 4.0, 8.0, 12.0, 16.0, 20.0];
+
+	#[test]
+	fn non_positive_target_single_segment() {
+		assert_eq!(split(22.0, &KEYFRAMES, 0.0), vec![Input::source()]);
+		assert_eq!(split(22.0, &KEYFRAMES, -1.0), vec![Input::source()]);
+	}
+
+	#[test]
+	fn target_longer_than_duration_single_segment() {
+		assert_eq!(split(22.0, &KEYFRAMES, 30.0), vec![Input::source()]);
+	}
+
+	#[test]
+	fn without_keyframes_single_segment() {
+		assert_eq!(split(22.0, &[], 5.0), vec![Input::source()]);
+	}
+
+	#[test]
+	fn cuts_at_first_keyframe_after_target() {
+		let segments = split(22.0, &KEYFRAMES, 7.0);
+		let bounds: Vec<_> = segments.iter().map(|s| (s.start, s.end)).collect();
+		assert_eq!(
+			bounds,
+			vec![
+				(None, Some(8.0)),
+				(Some(8.0), Some(16.0)),
+				(Some(16.0), None)
+			]
+		);
+	}
+
+	#[test]
+	fn segments_read_the_source() {
+		let segments = split(22.0, &KEYFRAMES, 4.0);
+		assert_eq!(segments.len(), 6);
+		assert!(segments.iter().all(|s| s.index == 0));
+	}
+
+	#[test]
+	fn split_clip_cuts_inside_the_clip() {
+		let segments = split_clip(Some(5.0), Some(18.0), 22.0, &KEYFRAMES, 4.0);
+		let bounds: Vec<_> = segments.iter().map(|s| (s.start, s.end)).collect();
+		assert_eq!(
+			bounds,
+			vec![
+				(Some(5.0), Some(12.0)),
+				(Some(12.0), Some(16.0)),
+				(Some(16.0), Some(18.0))
+			]
+		);
+	}
+
+	#[test]
+	fn split_clip_without_trim_is_split() {
+		assert_eq!(
+			split_clip(None, None, 22.0, &KEYFRAMES, 7.0),
+			split(22.0, &KEYFRAMES, 7.0)
+		);
+	}
+
+	#[test]
+	fn limit_target_respects_max_segments() {
+		let target = limit_target(22.0, 4.0, Some(2));
+		assert_eq!(target, 11.0);
+		assert_eq!(split(22.0, &KEYFRAMES, target).len(), 2);
+		assert_eq!(limit_target(22.0, 4.0, Some(10)), 4.0);
+		assert_eq!(limit_target(22.0, 0.0, Some(2)), 0.0);
+	}
+
+	#[test]
+	fn merge_concatenates_tasks_in_order() {
+		let merge = merge(&[3, 1, 2], None);
+		let inputs: Vec<_> = merge.inputs.iter().map(|i| i.index).collect();
+		assert_eq!(inputs, vec![3, 1, 2]);
+		assert_eq!(merge.recipe, Recipe::Merge(vec![0, 1, 2]));
+	}
+
+	#[test]
+	fn merge_with_source_appends_it_unlisted() {
+		let merge = merge(&[1, 2], Some(Input::source()));
+		let inputs: Vec<_> = merge.inputs.iter().map(|i| i.index).collect();
+		assert_eq!(inputs, vec![1, 2, 0]);
+		assert_eq!(merge.recipe, Recipe::Merge(vec![0, 1]));
+		assert_eq!(merge.validate(), Ok(()));
+	}
+
+	fn job_options(renditions: &[&str]) -> JobOptions {
+		JobOptions {
+			video: crate::Options {
+				codec: None,
+				params: vec![],
+			},
+			audio: None,
+			two_pass: false,
+			container: Container::Mp4,
+			renditions: renditions
+				.iter()
+				.map(|name| crate::Rendition {
+					name: name.to_string(),
+					params: vec![],
+				})
+				.collect(),
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn artifact_from_documented_json() {
+		let json = r#"{"duration":61.5,"cuts":[0.0,4.2,10.01]}"#;
+		let artifact: AnalysisArtifact = serde_json::from_str(json).unwrap();
+		assert_eq!(artifact.duration, 61.5);
+		assert_eq!(artifact.cuts, [0.0, 4.2, 10.01]);
+		assert_eq!(artifact.validate(), Ok(()));
+	}
+
+	#[test]
+	fn artifact_without_duration_invalid() {
+		let artifact = AnalysisArtifact {
+			duration: 0.0,
+			cuts: vec![],
+		};
+		assert!(artifact.validate().is_err())
+	}
+
+	#[test]
+	fn transcodes_of_merged_segments_are_matroska() {
+		let artifact = AnalysisArtifact {
+			duration: 22.0,
+			cuts: KEYFRAMES.into_iter().rev().collect(),
+		};
+		let options = AnalysisOptions {
+			duration: Some(7.0),
+			..Default::default()
+		};
+		let tasks = transcodes(&artifact, &Input::source(), &options, &job_options(&[]));
+		let ends: Vec<_> = tasks.iter().map(|task| task.inputs[0].end).collect();
+		assert_eq!(ends, [Some(8.0), Some(16.0), None]);
+		assert_eq!(tasks[1].output.container, Some(Container::Matroska));
+		assert_eq!(tasks[1].output.suffix.as_deref(), Some("-segment1"));
+	}
+
+	#[test]
+	fn transcodes_read_the_clip_streams() {
+		let artifact = AnalysisArtifact {
+			duration: 22.0,
+			cuts: KEYFRAMES.to_vec(),
+		};
+		let options = AnalysisOptions {
+			duration: Some(7.0),
+			..Default::default()
+		};
+		let clip = Input {
+			video_stream: Some(1),
+			audio_stream: Some(2),
+			..Input::source()
+		};
+		let tasks = transcodes(&artifact, &clip, &options, &job_options(&[]));
+		assert_eq!(tasks.len(), 3);
+		for task in tasks {
+			let input = &task.inputs[0];
+			assert_eq!((input.video_stream, input.audio_stream), (Some(1), Some(2)));
+		}
+	}
+
+	#[test]
+	fn single_segment_writes_each_rendition_output() {
+		let artifact = AnalysisArtifact {
+			duration: 22.0,
+			cuts: KEYFRAMES.to_vec(),
+		};
+		let tasks = transcodes(
+			&artifact,
+			&Input::source(),
+			&Default::default(),
+			&job_options(&["720p", "480p"]),
+		);
+		let outputs: Vec<_> = tasks.into_iter().map(|task| task.output).collect();
+		let expected = [(0, "-720p"), (1, "-480p")].map(|(rendition, suffix)| Output {
+			container: None,
+			suffix: Some(suffix.to_string()),
+			rendition: Some(rendition),
+		});
+		assert_eq!(outputs, expected)
+	}
+}
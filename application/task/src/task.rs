@@ -1,76 +1,651 @@
 //! #Task crate
 //! This crate defines the tasks, and includes the task runner under a feature, and the job/task manager trait
 
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 #[cfg_attr(test, derive(Debug, PartialEq))]
 pub struct JobSource {
 	pub input_id: Uuid,
 	pub options: JobOptions,
+	pub created_at: SystemTime,
+	///Url notified when the job output is available, or the job fails
+	pub callback: Option<String>,
+	///Name used for the output file
+	pub name: Option<String>,
+	///Start of the clip of the source that is encoded, in seconds
+	#[serde(default)]
+	pub start: Option<f64>,
+	///End of the clip of the source that is encoded, in seconds
+	#[serde(default)]
+	pub end: Option<f64>,
+	///Credential that created the job, `admin` or `user`
+	#[serde(default)]
+	pub owner: Option<String>,
+	///Source properties, when probed by the server
+	#[serde(default)]
+	pub media: Option<MediaProbe>,
+	///Scheduling class, tasks of higher priority jobs are allocated first
+	#[serde(default)]
+	pub priority: Priority,
+	///Finished job whose output is the source of this job, the input_id is that output
+	#[serde(default)]
+	pub source_job: Option<Uuid>,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+impl JobSource {
+	///Creates a job source, timestamped with the current time
+	pub fn new(input_id: Uuid, options: JobOptions) -> Self {
+		Self {
+			input_id,
+			options,
+			created_at: SystemTime::now(),
+			callback: None,
+			name: None,
+			start: None,
+			end: None,
+			owner: None,
+			media: None,
+			priority: Priority::default(),
+			source_job: None,
+		}
+	}
+}
+
+///Scheduling class of a job, ordered from the first allocated
+///
+///Interactive jobs, like previews and clips, skip ahead of the queued normal and batch jobs. Jobs
+///in the same class are allocated in their creation order
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Priority {
+	Interactive,
+	#[default]
+	Normal,
+	Batch,
+}
+
+impl Priority {
+	pub fn from_name(name: &str) -> Option<Self> {
+		match name.to_ascii_lowercase().as_str() {
+			"interactive" => Some(Priority::Interactive),
+			"normal" => Some(Priority::Normal),
+			"batch" => Some(Priority::Batch),
+			_ => None,
+		}
+	}
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			Priority::Interactive => "interactive",
+			Priority::Normal => "normal",
+			Priority::Batch => "batch",
+		}
+	}
+}
+
+///Metadata of a media file, as reported by ffprobe
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MediaProbe {
+	///Duration in seconds
+	pub duration: Option<f64>,
+	///Overall bitrate, in bits per second
+	pub bit_rate: Option<u64>,
+	///Container format names, as listed by ffprobe
+	pub format_name: Option<String>,
+	pub streams: Vec<StreamProbe>,
+}
+
+impl MediaProbe {
+	///First video stream
+	pub fn video(&self) -> Option<&StreamProbe> {
+		self.streams
+			.iter()
+			.find(|stream| stream.codec_type.as_deref() == Some("video"))
+	}
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct StreamProbe {
+	pub index: u32,
+	///video, audio, subtitle, data or attachment
+	pub codec_type: Option<String>,
+	pub codec_name: Option<String>,
+	///Bits per second, when the container reports it for the stream
+	pub bit_rate: Option<u64>,
+	pub width: Option<u32>,
+	pub height: Option<u32>,
+	pub sample_rate: Option<u32>,
+	pub channels: Option<u32>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct JobOptions {
 	pub video: Options,
 	pub audio: Option<Options>,
+	///Encode the video in two passes, the first one only collects statistics
+	pub two_pass: bool,
+	pub container: Container,
+	///Name of the encode preset, expanded into the video codec and params when the job is created
+	#[serde(default)]
+	pub preset: Option<String>,
+	///ffmpeg options placed before each input, like `-hwaccel` or `-analyzeduration`
+	#[serde(default)]
+	pub input_params: Vec<String>,
+	///Copy the subtitle and attachment streams of the source into the merged output
+	#[serde(default)]
+	pub copy_subtitles: bool,
+	///Outputs encoded from the same source, each with its own transcode and merge tasks
+	///
+	///Without renditions the job has a single output, encoded with the video options
+	#[serde(default)]
+	pub renditions: Vec<Rendition>,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+///One of the job outputs, like a step of a resolution ladder
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Rendition {
+	pub name: String,
+	///Video params following the job ones, like `-vf scale=-2:720`
+	pub params: Vec<String>,
+}
+
+///Container of the task outputs
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Container {
+	#[default]
+	Matroska,
+	Mp4,
+	Webm,
+}
+
+impl Container {
+	///Parse the container from its file extension
+	pub fn from_extension(extension: &str) -> Option<Self> {
+		match extension.to_ascii_lowercase().as_str() {
+			"mkv" => Some(Container::Matroska),
+			"mp4" => Some(Container::Mp4),
+			"webm" => Some(Container::Webm),
+			_ => None,
+		}
+	}
+
+	pub fn extension(&self) -> &'static str {
+		match self {
+			Container::Matroska => "mkv",
+			Container::Mp4 => "mp4",
+			Container::Webm => "webm",
+		}
+	}
+
+	///Name of the ffmpeg muxer
+	pub fn format(&self) -> &'static str {
+		match self {
+			Container::Matroska => "matroska",
+			Container::Mp4 => "mp4",
+			Container::Webm => "webm",
+		}
+	}
+
+	pub fn content_type(&self) -> &'static str {
+		match self {
+			Container::Matroska => "video/x-matroska",
+			Container::Mp4 => "video/mp4",
+			Container::Webm => "video/webm",
+		}
+	}
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct TaskSource {
 	///Here, the input should be the task id, or 0 for the job source
 	pub inputs: Vec<Input>,
 	pub recipe: Recipe,
+	#[serde(default)]
+	pub output: Output,
+	#[serde(default)]
+	pub resources: Resources,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+impl TaskSource {
+	///Check the recipe invariants, the manager rejects invalid tasks before queueing them
+	///
+	///Inputs referencing the task itself, or a later task, are rejected when the task is added
+	pub fn validate(&self) -> Result<(), InvalidTask> {
+		for (pos, input) in self.inputs.iter().enumerate() {
+			let negative = [input.start, input.end]
+				.into_iter()
+				.flatten()
+				.any(|time| !time.is_finite() || time < 0.0);
+			let reversed =
+				matches!((input.start, input.end), (Some(start), Some(end)) if start >= end);
+			if negative || reversed {
+				return Err(InvalidTask::InvalidRange(pos));
+			}
+			if input.accurate_seek && self.inputs.len() > 1 {
+				return Err(InvalidTask::AccurateSeek(pos));
+			}
+		}
+		match &self.recipe {
+			Recipe::Merge(order) => {
+				//The job source may follow the merged inputs, to copy its subtitles and attachments
+				let merged = match self.inputs.split_last() {
+					Some((last, merged)) if last.index == 0 && merged.len() == order.len() => {
+						merged.len()
+					}
+					_ => self.inputs.len(),
+				};
+				if order.len() != merged {
+					return Err(InvalidTask::MergeLength {
+						inputs: self.inputs.len(),
+						merged: order.len(),
+					});
+				}
+				let mut listed = vec![false; order.len()];
+				for &pos in order {
+					match listed.get_mut(pos as usize) {
+						Some(seen) if !*seen => *seen = true,
+						_ => return Err(InvalidTask::MergePosition(pos)),
+					}
+				}
+				Ok(())
+			}
+			_ => Ok(()),
+		}
+	}
+}
+
+///Resources used by a task, or available on a worker, unset fields are not limited
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Resources {
+	pub cpus: Option<f64>,
+	///Memory in bytes
+	pub memory: Option<u64>,
+	pub gpus: Option<u32>,
+}
+
+impl Resources {
+	///Whether a task with these hints fits the capacity
+	pub fn fits(&self, capacity: &Resources) -> bool {
+		fn within<T: PartialOrd>(hint: Option<T>, capacity: Option<T>) -> bool {
+			match (hint, capacity) {
+				(Some(hint), Some(capacity)) => hint <= capacity,
+				_ => true,
+			}
+		}
+		within(self.cpus, capacity.cpus)
+			&& within(self.memory, capacity.memory)
+			&& within(self.gpus, capacity.gpus)
+	}
+}
+
+///Format of the task output, the job container is used when not set
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Output {
+	pub container: Option<Container>,
+	///Appended to the output file name, before the extension
+	pub suffix: Option<String>,
+	///Position of the job rendition the output belongs to
+	#[serde(default)]
+	pub rendition: Option<u32>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct Options {
 	pub codec: Option<String>,
 	pub params: Vec<String>,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+///Controls how the analysis splits the job source into segments
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AnalysisOptions {
+	///Target segment duration in seconds, 0 means a single segment
+	pub duration: Option<f64>,
+	///Cut at scene changes scoring above this threshold, between 0 and 1, instead of at keyframes
+	pub scene_threshold: Option<f64>,
+	///Longer segments are used if the target duration would create more than this
+	pub max_segments: Option<u32>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Recipe {
-	///Determines how long the tasks segments should be
-	Analysis(Option<f64>),
+	///Determines how the source is split into segments
+	Analysis(AnalysisOptions),
 	///Extra options for transcoding
 	Transcode(Vec<String>),
 	Merge(Vec<u32>),
+	///Preview image of the input, uploaded as jpeg. Not part of the job output
+	Thumbnail(Thumbnail),
+}
+
+impl Default for Recipe {
+	fn default() -> Self {
+		Recipe::Transcode(Vec::new())
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Thumbnail {
+	///A single frame, from the input start
+	Poster,
+	///Frames evenly spaced over the input range, tiled in a grid
+	Sprite { columns: u32, rows: u32 },
 }
 
 #[derive(Clone)]
 pub enum Status {
 	Finished,
 	Running,
+	///With the reason reported by the worker
+	Failed(Option<String>),
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct Input {
 	pub index: u32,
 	pub start: Option<f64>,
 	pub end: Option<f64>,
+	///Index of the video stream to read, among the input video streams
+	#[serde(default)]
+	pub video_stream: Option<u32>,
+	///Index of the audio stream to read, among the input audio streams
+	///
+	///Selecting any stream maps only the selected ones, leaving out subtitles and attachments
+	#[serde(default)]
+	pub audio_stream: Option<u32>,
+	///Seek by decoding from the input start and discarding until the range start, instead of
+	///seeking the input to the nearest keyframe. Slower, but the range boundaries don't drift
+	///
+	///The range is applied to the output, so only a task with a single input can use it
+	#[serde(default)]
+	pub accurate_seek: bool,
 }
 
 impl Input {
-	pub fn source() -> Input {
+	pub const fn source() -> Input {
 		Input {
 			index: 0,
 			start: None,
 			end: None,
+			video_stream: None,
+			audio_stream: None,
+			accurate_seek: false,
 		}
 	}
 }
 
 ///An allocated task
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Instance {
 	pub job_id: Uuid,
 	pub task_id: Uuid,
 	pub inputs: Vec<Input>,
 	pub recipe: Recipe,
+	pub output: Output,
+	pub resources: Resources,
 	pub job_options: JobOptions,
 }
 
+impl Instance {
+	///Container of the task output
+	pub fn container(&self) -> Container {
+		self.output.container.unwrap_or(self.job_options.container)
+	}
+
+	///Rendition of the job encoded by the task
+	pub fn rendition(&self) -> Option<&Rendition> {
+		let pos = self.output.rendition?;
+		self.job_options.renditions.get(pos as usize)
+	}
+}
+
+///A task currently allocated to a worker
+#[derive(Clone, Debug, PartialEq)]
+pub struct Allocation {
+	pub job_id: Uuid,
+	pub task_id: Uuid,
+	pub task_idx: u32,
+	pub worker: String,
+	pub allocated_at: SystemTime,
+	///Last progress reported by the worker, as encoded seconds
+	pub progress: Option<f64>,
+	///Last encoding speed reported by the worker, relative to realtime
+	pub speed: Option<f64>,
+	///Reason reported by the worker, when the task failed
+	pub failure: Option<String>,
+}
+
+///A job or task transition, recorded in the job log
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JobLogEntry {
+	pub at: SystemTime,
+	///Task the transition refers to, none for the job itself
+	pub task_idx: Option<u32>,
+	pub event: JobLogEvent,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum JobLogEvent {
+	Created,
+	TaskAdded,
+	Allocated {
+		worker: String,
+	},
+	///Encoded seconds reported by the worker
+	Progress {
+		progress: f64,
+	},
+	Finished,
+	Failed {
+		reason: Option<String>,
+	},
+	///The allocation was cancelled, the task will be allocated again
+	Cancelled,
+}
+
 mod conversion;
+mod error;
+
+pub use error::{InvalidTask, TaskError};
 
 pub mod manager;
+pub mod preset;
+pub mod probe;
+pub mod segment;
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn instance_serde_round_trip() {
+		let instance = Instance {
+			job_id: Uuid::from_u64_pair(1, 2),
+			task_id: Uuid::from_u64_pair(1, 3),
+			inputs: vec![Input::source()],
+			recipe: Recipe::Transcode(vec!["-crf".to_string(), "30".to_string()]),
+			output: Output {
+				container: Some(Container::Webm),
+				suffix: None,
+				rendition: Some(0),
+			},
+			resources: Resources {
+				cpus: Some(2.0),
+				memory: None,
+				gpus: Some(1),
+			},
+			job_options: JobOptions {
+				video: Options {
+					codec: Some("libsvtav1".to_string()),
+					params: vec![],
+				},
+				audio: None,
+				two_pass: false,
+				container: Container::Mp4,
+				renditions: vec![Rendition {
+					name: "720p".to_string(),
+					params: vec!["-vf".to_string(), "scale=-2:720".to_string()],
+				}],
+				..Default::default()
+			},
+		};
+		assert_eq!(instance.rendition().unwrap().name, "720p");
+		let json = serde_json::to_string(&instance).unwrap();
+		assert_eq!(serde_json::from_str::<Instance>(&json).unwrap(), instance)
+	}
+
+	fn merge(inputs: usize, order: Vec<u32>) -> TaskSource {
+		TaskSource {
+			inputs: (1..=inputs as u32)
+				.map(|index| Input {
+					index,
+					..Input::source()
+				})
+				.collect(),
+			recipe: Recipe::Merge(order),
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn validate_merge_lists_every_input_once() {
+		assert_eq!(merge(2, vec![1, 0]).validate(), Ok(()));
+		assert_eq!(
+			merge(2, vec![0]).validate(),
+			Err(InvalidTask::MergeLength {
+				inputs: 2,
+				merged: 1
+			})
+		);
+		assert_eq!(
+			merge(2, vec![0, 0]).validate(),
+			Err(InvalidTask::MergePosition(0))
+		);
+		assert_eq!(
+			merge(2, vec![0, 2]).validate(),
+			Err(InvalidTask::MergePosition(2))
+		);
+	}
+
+	#[test]
+	fn validate_merge_followed_by_the_source() {
+		let mut task = merge(2, vec![1, 0]);
+		task.inputs.push(Input::source());
+		assert_eq!(task.validate(), Ok(()));
+		task.inputs.push(Input::source());
+		assert_eq!(
+			task.validate(),
+			Err(InvalidTask::MergeLength {
+				inputs: 4,
+				merged: 2
+			})
+		);
+	}
+
+	#[test]
+	fn validate_input_range() {
+		let mut task = merge(1, vec![0]);
+		task.inputs[0].start = Some(4.0);
+		task.inputs[0].end = Some(8.0);
+		assert_eq!(task.validate(), Ok(()));
+		task.inputs[0].end = Some(4.0);
+		assert_eq!(task.validate(), Err(InvalidTask::InvalidRange(0)));
+		task.inputs[0].start = Some(-1.0);
+		task.inputs[0].end = None;
+		assert_eq!(task.validate(), Err(InvalidTask::InvalidRange(0)));
+	}
+
+	#[test]
+	fn validate_accurate_seek_needs_a_single_input() {
+		let mut task = merge(1, vec![0]);
+		task.inputs[0].accurate_seek = true;
+		assert_eq!(task.validate(), Ok(()));
+		let mut task = merge(2, vec![0, 1]);
+		task.inputs[1].accurate_seek = true;
+		assert_eq!(task.validate(), Err(InvalidTask::AccurateSeek(1)));
+	}
+
+	#[test]
+	fn resources_fit_the_limited_capacity() {
+		let hint = Resources {
+			cpus: Some(4.0),
+			memory: Some(1 << 30),
+			gpus: None,
+		};
+		assert!(hint.fits(&Resources::default()));
+		let capacity = Resources {
+			cpus: Some(4.0),
+			memory: Some(1 << 29),
+			gpus: Some(0),
+		};
+		assert!(!hint.fits(&capacity));
+		let capacity = Resources {
+			memory: None,
+			..capacity
+		};
+		assert!(hint.fits(&capacity));
+	}
+
+	#[test]
+	fn thumbnail_recipe_api_round_trip() {
+		for thumbnail in [
+			Thumbnail::Poster,
+			Thumbnail::Sprite {
+				columns: 4,
+				rows: 3,
+			},
+		] {
+			let recipe = Recipe::Thumbnail(thumbnail);
+			let converted = api::models::Recipe::from(recipe.clone());
+			assert_eq!(Recipe::try_from(&converted), Ok(recipe))
+		}
+	}
+
+	#[test]
+	fn media_probe_api_round_trip() {
+		let probe = MediaProbe {
+			duration: Some(12.5),
+			bit_rate: Some(4_000_000),
+			format_name: Some("matroska,webm".to_string()),
+			streams: vec![
+				StreamProbe {
+					index: 0,
+					codec_type: Some("video".to_string()),
+					codec_name: Some("vp9".to_string()),
+					width: Some(1920),
+					height: Some(1080),
+					..Default::default()
+				},
+				StreamProbe {
+					index: 1,
+					codec_type: Some("audio".to_string()),
+					codec_name: Some("opus".to_string()),
+					sample_rate: Some(48000),
+					channels: Some(2),
+					..Default::default()
+				},
+			],
+		};
+		let converted = api::models::MediaProbe::from(probe.clone());
+		assert_eq!(MediaProbe::try_from(converted), Ok(probe))
+	}
+
+	#[test]
+	fn task_source_without_newer_fields_deserializes() {
+		let json = r#"{"inputs":[{"index":0,"start":null,"end":4.0}],"recipe":{"Merge":[0]}}"#;
+		let source: TaskSource = serde_json::from_str(json).unwrap();
+		assert_eq!(source.inputs[0].video_stream, None);
+		assert_eq!(source.output, Output::default());
+		assert_eq!(source.resources, Resources::default())
+	}
+
+	#[test]
+	fn job_options_without_newer_fields_deserializes() {
+		let json = r#"{"video":{"codec":"libx264","params":[]},"audio":null,"two_pass":false,"container":"Mp4"}"#;
+		let options: JobOptions = serde_json::from_str(json).unwrap();
+		assert_eq!(options.preset, None);
+		assert!(options.input_params.is_empty())
+	}
+}